@@ -0,0 +1,176 @@
+use crate::{TaskRef, STATE_JOIN_INTEREST, STATE_OUTPUT_READY, STATE_PANICKED, STATE_RUNNING};
+
+use core::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    ptr,
+    task::{Context, Poll},
+};
+
+/// JoinError is returned by a [JoinHandle] when the task did not run to
+/// completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted via [JoinHandle::abort] or [AbortHandle::abort].
+    Aborted,
+    /// The future of the task panicked, only reported with the `std`
+    /// feature, without it the panic unwinds through the executor.
+    Panicked,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Aborted => write!(f, "task was aborted"),
+            Self::Panicked => write!(f, "task panicked"),
+        }
+    }
+}
+
+/// AbortHandle aborts a task without joining it, see [JoinHandle::abort].
+///
+/// An AbortHandle remembers the generation of the [crate::TaskStorage] it was
+/// made for, hence it does nothing once the task has finished even if the
+/// storage has been reused by another task since.
+#[derive(Clone, Copy)]
+pub struct AbortHandle {
+    task: TaskRef,
+    generation: u32,
+}
+
+impl AbortHandle {
+    pub(crate) fn new(task: TaskRef) -> Self {
+        Self {
+            task,
+            generation: task.generation(),
+        }
+    }
+
+    /// abort aborts the task if it is still running, see [JoinHandle::abort].
+    pub fn abort(&self) {
+        if self.task.generation() == self.generation {
+            self.task.abort();
+        }
+    }
+}
+
+/// JoinHandle is returned when a task is spawned and resolves to the output
+/// of the task once it completes.
+///
+/// The output is kept inline in the [crate::TaskStorage] of the task hence
+/// joining a task does not require any memory allocation. The storage is
+/// handed back to its [crate::TaskPool] only once the output has been taken
+/// or the JoinHandle has been dropped.
+///
+/// Dropping a JoinHandle detaches the task, it keeps running and its output
+/// is dropped as soon as it is ready.
+pub struct JoinHandle<T> {
+    /// task is `None` once the output has been taken
+    task: Option<TaskRef>,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(task: TaskRef) -> Self {
+        Self {
+            task: Some(task),
+            _output: PhantomData,
+        }
+    }
+
+    /// is_finished returns true if the task has completed or was aborted
+    pub fn is_finished(&self) -> bool {
+        match self.task {
+            Some(task) => task.state() & STATE_RUNNING == 0,
+            None => true,
+        }
+    }
+
+    /// abort asks the task to stop, its future is dropped (without being
+    /// polled again) at the next scheduling point and the JoinHandle resolves
+    /// to [JoinError::Aborted].
+    ///
+    /// Aborting a task that has already finished does nothing.
+    pub fn abort(&self) {
+        if let Some(task) = self.task {
+            task.abort();
+        }
+    }
+
+    /// abort_handle returns an [AbortHandle] of the task, it can abort the
+    /// task even after the JoinHandle is gone.
+    pub fn abort_handle(&self) -> Option<AbortHandle> {
+        self.task.map(AbortHandle::new)
+    }
+}
+
+impl<T> Unpin for JoinHandle<T> {}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let task = self.task.expect("JoinHandle polled after completion");
+        let header = task.header();
+        let state = task.state();
+
+        if state & STATE_RUNNING == 0 {
+            // A finished task without an output was aborted or panicked
+            let output = if state & STATE_OUTPUT_READY != 0 {
+                // # Safety
+                // STATE_OUTPUT_READY guarantees that the output slot was written
+                // by a future whose output type is T.
+                Ok(unsafe { ptr::read(header.output_ptr as *mut T) })
+            } else if state & STATE_PANICKED != 0 {
+                Err(JoinError::Panicked)
+            } else {
+                Err(JoinError::Aborted)
+            };
+
+            task.set_state(state & !(STATE_JOIN_INTEREST | STATE_OUTPUT_READY));
+            self.task = None;
+
+            // # Safety
+            // The future is gone and we just moved the output out.
+            unsafe { task.release() };
+
+            return Poll::Ready(output);
+        }
+
+        unsafe {
+            header.join_waker.get().replace(Some(ctx.waker().clone()));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        let Some(task) = self.task.take() else {
+            return;
+        };
+
+        let header = task.header();
+        let state = task.state();
+
+        unsafe {
+            header.join_waker.get().replace(None);
+        }
+        task.set_state(state & !(STATE_JOIN_INTEREST | STATE_OUTPUT_READY));
+
+        if state & STATE_RUNNING == 0 {
+            // # Safety
+            // The task has already finished and nobody else is going to
+            // touch the output hence it is dropped and the storage released.
+            unsafe {
+                if state & STATE_OUTPUT_READY != 0 {
+                    ptr::drop_in_place(header.output_ptr as *mut T);
+                }
+                task.release();
+            }
+        }
+    }
+}
@@ -1,17 +1,49 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+mod join;
 mod queue;
 mod util;
 mod waker;
 
 use core::future::Future;
+use core::marker::PhantomData;
 use core::mem;
 use core::pin::Pin;
-use core::task::{Context, Poll};
+use core::task::{Context, Poll, Waker};
 use core::{cell::UnsafeCell, ptr::NonNull};
 use queue::{TaskFreeList, TaskQueue};
 use util::UninitCell;
 
+pub use join::{AbortHandle, JoinError, JoinHandle};
+
+/// STATE_RUNNING is set while the future of the task is stored in its
+/// [TaskStorage] and has not yet completed.
+pub(crate) const STATE_RUNNING: u8 = 1 << 0;
+
+/// STATE_JOIN_INTEREST is set while a [JoinHandle] for the task is alive
+/// and hence the output of the task must be preserved.
+pub(crate) const STATE_JOIN_INTEREST: u8 = 1 << 1;
+
+/// STATE_OUTPUT_READY is set once the output of the task has been written
+/// to its [TaskStorage] and is waiting to be taken by the [JoinHandle].
+pub(crate) const STATE_OUTPUT_READY: u8 = 1 << 2;
+
+/// STATE_ABORTED is set when the task has been asked to abort, its future
+/// is dropped instead of being polled the next time it is scheduled.
+pub(crate) const STATE_ABORTED: u8 = 1 << 3;
+
+/// STATE_RUN_QUEUED is set while the task is in the queue of an executor,
+/// it ensures that a task is never enqueued twice.
+pub(crate) const STATE_RUN_QUEUED: u8 = 1 << 4;
+
+/// STATE_SYSTEM is set for the system tasks, the executor does not wait for
+/// them to finish before it stops.
+pub(crate) const STATE_SYSTEM: u8 = 1 << 5;
+
+/// STATE_PANICKED is set when the future of the task panicked, it is only
+/// ever set with the `std` feature which catches the panics of the tasks.
+pub(crate) const STATE_PANICKED: u8 = 1 << 6;
+
 /// TaskHeader contains the raw data regarding any task, the tasks are an abstraction on top of
 /// futures and hence the task header contains the raw data that is required to run a future.
 pub(crate) struct TaskHeader {
@@ -49,6 +81,22 @@ pub(crate) struct TaskHeader {
     /// This should be None if a [TaskPool] was not used to create
     /// this Task (eg. Direct [TaskStorage] usage)
     task_pool_finalizer_fn: Option<unsafe fn(*const (), TaskRef)>,
+
+    /// state holds the `STATE_*` flags of the task.
+    state: UnsafeCell<u8>,
+
+    /// output_ptr is a mut pointer to the slot in the [TaskStorage] where
+    /// the output of the future is written once it completes.
+    ///
+    /// This pointer can NEVER be null (other than before getting initialized)
+    output_ptr: *mut (),
+
+    /// join_waker is the waker of the task awaiting the [JoinHandle], if any.
+    join_waker: UnsafeCell<Option<Waker>>,
+
+    /// generation is bumped every time a new future is written to the
+    /// storage, an [AbortHandle] only aborts the generation it was made for.
+    generation: UnsafeCell<u32>,
 }
 
 /// TaskRef just holds a pointer to TaskHeader
@@ -87,6 +135,59 @@ impl TaskRef {
         self.ptr.as_ptr()
     }
 
+    /// state returns the current `STATE_*` flags of the task
+    pub(crate) fn state(&self) -> u8 {
+        unsafe { *self.header().state.get() }
+    }
+
+    /// set_state overwrites the `STATE_*` flags of the task
+    pub(crate) fn set_state(&self, state: u8) {
+        unsafe {
+            self.header().state.get().replace(state);
+        }
+    }
+
+    /// generation returns the generation of the future held by the storage
+    pub(crate) fn generation(&self) -> u32 {
+        unsafe { *self.header().generation.get() }
+    }
+
+    /// release marks the [TaskStorage] of a finished task free for use again
+    /// by handing it back to the [TaskPool] it was taken from (if any).
+    ///
+    /// # Safety
+    /// The future of the task must have been dropped and its output, if any,
+    /// must have been taken or dropped.
+    pub(crate) unsafe fn release(self) {
+        let header = self.header();
+        if let Some(task_pool_finalizer) = header.task_pool_finalizer_fn {
+            task_pool_finalizer(header.task_pool_ptr, self);
+        }
+    }
+
+    /// abort asks the task to stop, its future is dropped (without being
+    /// polled again) at the next scheduling point and the [TaskStorage] of
+    /// the task is released just like for a task that ran to completion.
+    ///
+    /// A [JoinHandle] of an aborted task resolves to [JoinError::Aborted].
+    /// Aborting a task that has already finished does nothing.
+    ///
+    /// The storage of a finished task can be reused by another task, hence
+    /// abort is only exposed through [JoinHandle] and [AbortHandle].
+    pub(crate) fn abort(&self) {
+        let state = self.state();
+        if state & STATE_RUNNING == 0 {
+            return;
+        }
+
+        self.set_state(state | STATE_ABORTED);
+
+        // # Safety
+        // If the task was never spawned then it has no executor yet and it
+        // will be dropped as soon as it gets spawned.
+        unsafe { self.enqueue_self() }
+    }
+
     pub(crate) unsafe fn enqueue_self(mut self) {
         let header = self.ptr.as_mut();
         let ex = *header.executor.get();
@@ -106,6 +207,62 @@ impl TaskRef {
     }
 }
 
+/// SpawnToken is a [TaskRef] which remembers the output type of the task it
+/// points to.
+///
+/// A SpawnToken is returned when a task is prepared and is consumed by
+/// [Executor::spawn_task], which hands back a [JoinHandle] for the output.
+/// Dropping a SpawnToken without spawning it drops the future of the task and
+/// hands its [TaskStorage] back to its [TaskPool].
+#[must_use = "dropping a SpawnToken drops the task without running it"]
+pub struct SpawnToken<T> {
+    task: TaskRef,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<T> SpawnToken<T> {
+    fn new(task: TaskRef) -> Self {
+        Self {
+            task,
+            _output: PhantomData,
+        }
+    }
+
+    /// task_ref returns the untyped [TaskRef] of the prepared task
+    pub fn task_ref(&self) -> TaskRef {
+        self.task
+    }
+
+    /// abort_handle returns an [AbortHandle] of the prepared task
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle::new(self.task)
+    }
+
+    /// into_task_ref consumes the token without dropping the task
+    fn into_task_ref(self) -> TaskRef {
+        let task = self.task;
+        mem::forget(self);
+        task
+    }
+}
+
+impl<T> Drop for SpawnToken<T> {
+    fn drop(&mut self) {
+        let task = self.task;
+        task.set_state(task.state() | STATE_ABORTED);
+
+        // # Safety
+        // The task was never spawned hence nothing else refers to it, polling
+        // an aborted task drops its future without polling it.
+        unsafe {
+            if let Some(poll) = task.header().poll_fn {
+                poll(task);
+            }
+            task.release();
+        }
+    }
+}
+
 /// Wake a task by `TaskRef`.
 ///
 /// You can obtain a `TaskRef` from a `Waker` using [`task_from_waker`].
@@ -119,11 +276,16 @@ pub fn wake_task(task: TaskRef) {
 pub struct TaskStorage<F: Future + 'static> {
     raw: TaskHeader,
     future: UninitCell<F>,
+    output: UninitCell<F::Output>,
 }
 
-impl<F: Future + 'static> TaskStorage<F> {
-    const NEW: Self = Self::new();
+impl<F: Future + 'static> Default for TaskStorage<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl<F: Future + 'static> TaskStorage<F> {
     pub const fn new() -> Self {
         Self {
             raw: TaskHeader {
@@ -134,12 +296,17 @@ impl<F: Future + 'static> TaskStorage<F> {
                 task_pool_ptr: core::ptr::null(),
                 task_pool_finalizer_fn: None,
                 task_storage_ptr: core::ptr::null_mut(),
+                state: UnsafeCell::new(0),
+                output_ptr: core::ptr::null_mut(),
+                join_waker: UnsafeCell::new(None),
+                generation: UnsafeCell::new(0),
             },
             future: UninitCell::uninit(),
+            output: UninitCell::uninit(),
         }
     }
 
-    pub fn prepare_task(&'static mut self, future: impl FnOnce() -> F) -> TaskRef {
+    pub fn prepare_task(&'static mut self, future: impl FnOnce() -> F) -> SpawnToken<F::Output> {
         // # Safety
         // This is safe to do because this is essentially a `ptr::write`
         // which is sound so for as long as the destination is valid and
@@ -148,25 +315,79 @@ impl<F: Future + 'static> TaskStorage<F> {
             self.future.write(future());
         }
 
+        self.prepare_header();
+
+        SpawnToken::new(TaskRef::new(self))
+    }
+
+    /// prepare_header (re)initializes the [TaskHeader] of the storage right
+    /// after a new future has been written into it.
+    fn prepare_header(&mut self) {
+        // A stale wakeup of the previous task could have left the storage
+        // in the queue of the executor, that must not be forgotten.
+        let run_queued = *self.raw.state.get_mut() & STATE_RUN_QUEUED;
+
         self.raw.task_storage_ptr = self as *mut _ as *mut ();
         self.raw.poll_fn = Some(TaskStorage::<F>::poll);
+        self.raw.output_ptr = unsafe { self.output.as_mut_ptr() } as *mut ();
+        self.raw.state = UnsafeCell::new(STATE_RUNNING | run_queued);
+        self.raw.join_waker = UnsafeCell::new(None);
 
-        TaskRef::new(self)
+        let generation = self.raw.generation.get_mut();
+        *generation = generation.wrapping_add(1);
     }
 
     unsafe fn poll(p: TaskRef) -> bool {
         let this = &mut *(p.as_ptr() as *mut TaskStorage<F>);
         let mut res = false;
 
+        if p.state() & STATE_ABORTED != 0 {
+            this.future.drop_in_place();
+            p.set_state(p.state() & !(STATE_RUNNING | STATE_ABORTED));
+
+            if let Some(join_waker) = this.raw.join_waker.get().replace(None) {
+                join_waker.wake();
+            }
+
+            return true;
+        }
+
         let future = Pin::new_unchecked(this.future.as_mut());
         let waker = waker::from_task(p);
         let mut ctx = Context::from_waker(&waker);
-        match future.poll(&mut ctx) {
-            Poll::Ready(_) => {
+        match Self::poll_future(future, &mut ctx) {
+            Some(Poll::Ready(output)) => {
                 this.future.drop_in_place();
+
+                // The task could have aborted itself right before completing
+                let state = p.state() & !(STATE_RUNNING | STATE_ABORTED);
+                if state & STATE_JOIN_INTEREST != 0 {
+                    // Someone is waiting for the output hence keep it in the
+                    // storage till the JoinHandle takes it.
+                    this.output.write(output);
+                    p.set_state(state | STATE_OUTPUT_READY);
+
+                    if let Some(join_waker) = this.raw.join_waker.get().replace(None) {
+                        join_waker.wake();
+                    }
+                } else {
+                    p.set_state(state);
+                    mem::drop(output);
+                }
+
+                res = true;
+            }
+            Some(Poll::Pending) => {}
+            None => {
+                this.future.drop_in_place();
+                p.set_state((p.state() & !(STATE_RUNNING | STATE_ABORTED)) | STATE_PANICKED);
+
+                if let Some(join_waker) = this.raw.join_waker.get().replace(None) {
+                    join_waker.wake();
+                }
+
                 res = true;
             }
-            Poll::Pending => {}
         }
 
         // the compiler is emitting a virtual call for waker drop, but we know
@@ -175,6 +396,20 @@ impl<F: Future + 'static> TaskStorage<F> {
 
         res
     }
+
+    /// poll_future polls the future of the task, it returns `None` if the
+    /// future panicked.
+    #[cfg(feature = "std")]
+    fn poll_future(future: Pin<&mut F>, ctx: &mut Context<'_>) -> Option<Poll<F::Output>> {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.poll(ctx))).ok()
+    }
+
+    /// poll_future polls the future of the task, without the `std` feature
+    /// a panic unwinds through the executor.
+    #[cfg(not(feature = "std"))]
+    fn poll_future(future: Pin<&mut F>, ctx: &mut Context<'_>) -> Option<Poll<F::Output>> {
+        Some(future.poll(ctx))
+    }
 }
 
 /// Raw storage that can hold up to N tasks of the same type.
@@ -186,19 +421,28 @@ pub struct TaskPool<F: Future + 'static, const N: usize> {
     exhaust_list_cnt: usize,
 }
 
+impl<F: Future + 'static, const N: usize> Default for TaskPool<F, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<F: Future + 'static, const N: usize> TaskPool<F, N> {
     /// Create a new TaskPool, with all tasks in non-spawned state.
     pub const fn new() -> Self {
         Self {
-            pool: [TaskStorage::NEW; N],
+            pool: [const { TaskStorage::new() }; N],
             free_list: TaskFreeList::new(),
             exhaust_list_cnt: 0,
         }
     }
 
     /// prepare_task consumes a future, stores it in one of the available [TaskStorage] and
-    /// returns a [TaskRef] which points to a [TaskHeader] which points to the give future.
-    pub fn prepare_task(&'static mut self, future: impl FnOnce() -> F) -> Option<TaskRef> {
+    /// returns a [SpawnToken] which points to a [TaskHeader] which points to the give future.
+    pub fn prepare_task(
+        &'static mut self,
+        future: impl FnOnce() -> F,
+    ) -> Option<SpawnToken<F::Output>> {
         let self_ptr = self as *const _ as *const ();
 
         let storage = if self.exhaust_list_cnt < N {
@@ -220,12 +464,11 @@ impl<F: Future + 'static, const N: usize> TaskPool<F, N> {
             unsafe {
                 storage.future.write(future());
 
-                storage.raw.task_storage_ptr = storage as *mut _ as *mut ();
-                storage.raw.poll_fn = Some(TaskStorage::<F>::poll);
+                storage.prepare_header();
                 storage.raw.task_pool_ptr = self_ptr;
                 storage.raw.task_pool_finalizer_fn = Some(TaskPool::<F, N>::finalize);
 
-                Some(TaskRef::from_ptr(&storage.raw))
+                Some(SpawnToken::new(TaskRef::from_ptr(&storage.raw)))
             }
         } else {
             None
@@ -236,7 +479,7 @@ impl<F: Future + 'static, const N: usize> TaskPool<F, N> {
     /// [TaskStorage]. This [TaskStorage] is then marked free for use again.
     ///
     /// It is intended that the executor should invoke this function once a task [Future]
-    /// is completed.
+    /// is completed and its output is no longer needed.
     unsafe fn finalize(task_pool: *const (), t: TaskRef) {
         let task_pool = task_pool as *const TaskPool<F, N>;
 
@@ -254,6 +497,12 @@ pub struct Executor {
     spawned: UnsafeCell<u64>,
 }
 
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Executor {
     /// new creates a new instance of executor
     pub const fn new() -> Self {
@@ -263,12 +512,18 @@ impl Executor {
         }
     }
 
-    /// spawn_task consumes a [TaskRef] and enqueues it for running
+    /// spawn_task consumes a [SpawnToken] and enqueues it for running
     ///
-    /// This function relies on a TaskRef to already exist which can be
+    /// This function relies on a SpawnToken to already exist which can be
     /// created via static TaskStorage. This ensures that no dynamic memory
     /// allocation happens but this also makes this interface harder to consume
-    pub fn spawn_task(&'static self, t: TaskRef) {
+    ///
+    /// The returned [JoinHandle] resolves to the output of the task. Dropping
+    /// it detaches the task and its output is dropped as soon as it is ready.
+    pub fn spawn_task<T>(&'static self, t: SpawnToken<T>) -> JoinHandle<T> {
+        let task = t.into_task_ref();
+        task.set_state(task.state() | STATE_JOIN_INTEREST);
+
         // Increment the total spawned task here and not in the
         // enqueue function as that is shared by wakeup mechanism
         // as well.
@@ -277,7 +532,24 @@ impl Executor {
             *spawned += 1;
         }
 
-        self.enqueue(t);
+        self.enqueue(task);
+
+        JoinHandle::new(task)
+    }
+
+    /// spawn_system_task is [Executor::spawn_task] for the tasks that serve
+    /// the other tasks (cache eviction, write-back, etc.) and typically run
+    /// forever.
+    ///
+    /// System tasks are not counted as spawned tasks, hence the executor stops
+    /// once all the other tasks are done even if the system tasks are not.
+    pub fn spawn_system_task<T>(&'static self, t: SpawnToken<T>) -> JoinHandle<T> {
+        let task = t.into_task_ref();
+        task.set_state(task.state() | STATE_JOIN_INTEREST | STATE_SYSTEM);
+
+        self.enqueue(task);
+
+        JoinHandle::new(task)
     }
 
     /// run starts a busy loop and keep polling the tasks forever
@@ -285,23 +557,34 @@ impl Executor {
         loop {
             // Drain the user tasks
             self.task_queue.drain(|mut taskptr| {
+                taskptr.set_state(taskptr.state() & !STATE_RUN_QUEUED);
+
+                // A finished task can still be woken up by a stale waker,
+                // its future is gone hence there is nothing to poll.
+                if taskptr.state() & STATE_RUNNING == 0 {
+                    return;
+                }
+
                 let task = taskptr.mut_header();
 
                 if let Some(poll) = task.poll_fn {
+                    let system = taskptr.state() & STATE_SYSTEM != 0;
                     // # Safety: Implied
                     let finished = unsafe { poll(TaskRef::from_ptr(taskptr.as_ptr())) };
                     if finished {
-                        if let Some(task_pool_finalizer) = task.task_pool_finalizer_fn {
+                        // If a JoinHandle is still around then it will release
+                        // the storage once it is done with the output.
+                        if taskptr.state() & STATE_JOIN_INTEREST == 0 {
                             // # Safety: Implied
-                            unsafe {
-                                task_pool_finalizer(task.task_pool_ptr, TaskRef::from_ptr(taskptr.as_ptr()))
-                            }
+                            unsafe { TaskRef::from_ptr(taskptr.as_ptr()).release() }
                         }
 
-                        let queued = self.spawned.get();
-                        assert!(!queued.is_null());
+                        if !system {
+                            let queued = self.spawned.get();
+                            assert!(!queued.is_null());
 
-                        unsafe { *queued -= 1; }
+                            unsafe { *queued -= 1; }
+                        }
                     }
                 }
             });
@@ -321,9 +604,225 @@ impl Executor {
     }
 
     pub(crate) fn enqueue(&'static self, t: TaskRef) {
+        let state = t.state();
+        if state & STATE_RUN_QUEUED != 0 {
+            return;
+        }
+
+        t.set_state(state | STATE_RUN_QUEUED);
+
         unsafe {
             t.header().executor.get().replace(Some(self));
             self.task_queue.enqueue(t);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::future::{self, Ready};
+    use std::{boxed::Box, cell::Cell, rc::Rc};
+
+    fn executor() -> &'static Executor {
+        Box::leak(Box::new(Executor::new()))
+    }
+
+    fn spawn<F: Future + 'static>(ex: &'static Executor, fut: F) -> JoinHandle<F::Output> {
+        let storage = Box::leak(Box::new(TaskStorage::new()));
+        ex.spawn_task(storage.prepare_task(|| fut))
+    }
+
+    fn run(ex: &'static Executor) {
+        ex.run(None::<fn()>);
+    }
+
+    #[test]
+    fn join_returns_output() {
+        let ex = executor();
+        let out = Rc::new(Cell::new(None));
+
+        let task = spawn(ex, async { 42 });
+        let o = out.clone();
+        drop(spawn(ex, async move { o.set(Some(task.await)) }));
+        run(ex);
+
+        assert_eq!(out.get(), Some(Ok(42)));
+    }
+
+    #[test]
+    fn join_after_completion() {
+        let ex = executor();
+        let task = spawn(ex, async { "done" });
+        run(ex);
+        assert!(task.is_finished());
+
+        let out = Rc::new(Cell::new(None));
+        let o = out.clone();
+        drop(spawn(ex, async move { o.set(Some(task.await)) }));
+        run(ex);
+
+        assert_eq!(out.get(), Some(Ok("done")));
+    }
+
+    #[test]
+    fn detached_output_is_dropped() {
+        let ex = executor();
+        let value = Rc::new(());
+
+        let v = value.clone();
+        drop(spawn(ex, async move { v }));
+        run(ex);
+
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    /// join_later spawns a task which stores the output of `task` in the
+    /// returned cell.
+    fn join_later<T: 'static>(
+        ex: &'static Executor,
+        task: JoinHandle<T>,
+    ) -> Rc<Cell<Option<Result<T, JoinError>>>> {
+        let out = Rc::new(Cell::new(None));
+        let o = out.clone();
+        drop(spawn(ex, async move { o.set(Some(task.await)) }));
+        out
+    }
+
+    #[test]
+    fn abort_pending_task() {
+        let ex = executor();
+        let value = Rc::new(());
+
+        let v = value.clone();
+        let task = spawn(ex, async move {
+            future::pending::<()>().await;
+            drop(v);
+        });
+        task.abort();
+        let out = join_later(ex, task);
+        run(ex);
+
+        assert_eq!(out.take(), Some(Err(JoinError::Aborted)));
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn abort_before_spawn() {
+        let ex = executor();
+        let storage = Box::leak(Box::new(TaskStorage::new()));
+        let token = storage.prepare_task(|| async { 1 });
+
+        token.abort_handle().abort();
+        let out = join_later(ex, ex.spawn_task(token));
+        run(ex);
+
+        assert_eq!(out.take(), Some(Err(JoinError::Aborted)));
+    }
+
+    #[test]
+    fn abort_after_completion() {
+        let ex = executor();
+        let task = spawn(ex, async { 1 });
+        run(ex);
+
+        task.abort();
+        let out = join_later(ex, task);
+        run(ex);
+
+        assert_eq!(out.take(), Some(Ok(1)));
+    }
+
+    #[test]
+    fn abort_self_and_complete() {
+        let ex = executor();
+        let storage = Box::leak(Box::new(TaskStorage::new()));
+        let handle = Rc::new(Cell::new(None::<AbortHandle>));
+
+        let h = handle.clone();
+        let token = storage.prepare_task(|| async move {
+            h.get().unwrap().abort();
+            1
+        });
+        let task_ref = token.task_ref();
+        handle.set(Some(token.abort_handle()));
+
+        let out = join_later(ex, ex.spawn_task(token));
+        run(ex);
+
+        assert_eq!(out.take(), Some(Ok(1)));
+        assert_eq!(task_ref.state() & STATE_ABORTED, 0);
+    }
+
+    #[test]
+    fn stale_abort_handle() {
+        let pool: *mut TaskPool<Ready<u32>, 1> = Box::leak(Box::new(TaskPool::new()));
+        let ex = executor();
+
+        let token = unsafe { &mut *pool }.prepare_task(|| future::ready(1)).unwrap();
+        let stale = token.abort_handle();
+        drop(token);
+
+        // The slot is reused by another task which the stale handle must not
+        // abort.
+        let token = unsafe { &mut *pool }.prepare_task(|| future::ready(2));
+        let task = ex.spawn_task(token.unwrap());
+        stale.abort();
+        let out = join_later(ex, task);
+        run(ex);
+
+        assert_eq!(out.take(), Some(Ok(2)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn panic_is_join_error() {
+        let ex = executor();
+        let task = spawn(ex, async {
+            panic!("task panicked");
+        });
+        let out = join_later(ex, task);
+        run(ex);
+
+        assert_eq!(out.take(), Some(Err(JoinError::Panicked)));
+    }
+
+    #[test]
+    fn dropped_spawn_token_releases_slot() {
+        let pool: *mut TaskPool<Ready<Rc<()>>, 1> = Box::leak(Box::new(TaskPool::new()));
+        let value = Rc::new(());
+
+        let token = unsafe { &mut *pool }.prepare_task(|| future::ready(value.clone()));
+        assert!(token.is_some());
+        assert!(unsafe { &mut *pool }.prepare_task(|| future::ready(value.clone())).is_none());
+
+        drop(token);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let ex = executor();
+        let token = unsafe { &mut *pool }.prepare_task(|| future::ready(value.clone()));
+        let task = ex.spawn_task(token.expect("slot was not released"));
+        run(ex);
+        assert!(task.is_finished());
+    }
+
+    #[test]
+    fn system_tasks_are_not_counted() {
+        let ex = executor();
+        let storage = Box::leak(Box::new(TaskStorage::new()));
+        let system = ex.spawn_system_task(storage.prepare_task(future::pending::<()>));
+
+        // The executor stops once the other tasks are done
+        let task = spawn(ex, async { 1 });
+        run(ex);
+        assert!(task.is_finished());
+        assert!(!system.is_finished());
+
+        system.abort();
+        let out = join_later(ex, system);
+        run(ex);
+        assert_eq!(out.take(), Some(Err(JoinError::Aborted)));
+    }
+}
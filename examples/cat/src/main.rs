@@ -3,27 +3,20 @@
 use std::env;
 
 use reika::executor::PerThreadExecutor;
+use reika::reactor::bufio::BufReader;
 use reika::reactor::io;
 
 async fn read_file(path: &str) {
     let file = io::File::open(path).await.unwrap();
+    let mut reader = BufReader::new(file);
 
-    let mut buf = [0; 4096];
-
-    loop {
-        let read = file.read(&mut buf).await.unwrap();
-
-        print!(
-            "{}",
-            std::str::from_utf8(&buf[0..(read as usize)]).expect("expected to get valid utf8")
-        );
-
-        if read < buf.len() as _ {
-            break;
-        }
+    let mut line = String::new();
+    while reader.read_line(&mut line).await.unwrap() > 0 {
+        print!("{}", line);
+        line.clear();
     }
 
-    file.close().await.unwrap();
+    reader.into_inner().close().await.unwrap();
 }
 
 #[reika::macros::task]
@@ -3,25 +3,30 @@
 use std::env;
 
 use reika::executor::PerThreadExecutor;
+use reika::reactor::bufio::{BufReader, BufWriter};
 use reika::reactor::io;
 
 async fn copy_file(src: &str, dest: &str) {
     let src = io::File::open(src).await.unwrap();
     let dest = io::File::options().create(true).write(true).open(dest).await.unwrap();
 
-    let mut buf = [0; 4096];
+    let mut reader = BufReader::new(src);
+    let mut writer = BufWriter::new(dest);
 
     loop {
-        let read = src.read(&mut buf).await.unwrap();
-        let _ = dest.write(&mut buf[0..(read as usize)]).await.unwrap();
-
-        if read < buf.len() as _ {
+        let buf = reader.fill_buf().await.unwrap();
+        if buf.is_empty() {
             break;
         }
+
+        let read = buf.len();
+        writer.write_all(buf).await.unwrap();
+        reader.consume(read);
     }
+    writer.flush().await.unwrap();
 
-    src.close().await.unwrap();
-    dest.close().await.unwrap();
+    reader.into_inner().close().await.unwrap();
+    writer.into_inner().close().await.unwrap();
 }
 
 #[reika::macros::task]
@@ -40,7 +45,7 @@ fn main() {
     PerThreadExecutor::spawn_task(entry().unwrap());
 
     PerThreadExecutor::run(Some(|| {
-        if reika::reactor::PerThreadReactor::flush(0, 0, false).is_err() {
+        if reika::reactor::PerThreadReactor::flush().is_err() {
             println!("oops, reactor failed");
         }
     }));
@@ -35,8 +35,9 @@ async fn main() {
         .unwrap();
     println!("Listening on 127.0.0.1:2310");
 
+    let mut incoming = listener.incoming();
     loop {
-        let connection = listener.accept().await.unwrap();
+        let connection = incoming.next().await.unwrap();
         loop {
             match connection_pool(connection) {
                 Some(task) => {
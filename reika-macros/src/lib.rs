@@ -1,3 +1,7 @@
+// The FromMeta derive expands #[darling(default)] into a match which clippy
+// would rather see as unwrap_or_default.
+#![allow(clippy::manual_unwrap_or_default)]
+
 extern crate proc_macro;
 
 use proc_macro::TokenStream as TS;
@@ -42,7 +46,11 @@ struct ReplicateArgs {
 #[derive(Debug, FromMeta)]
 struct EntryArgs {
     #[darling(default)]
-    replicate: Option<syn::LitInt>
+    replicate: Option<syn::LitInt>,
+    /// config is an expression evaluating to a `ReactorConfig` (or a
+    /// reference to one), it is evaluated by every replica.
+    #[darling(default)]
+    config: Option<syn::Expr>,
 }
 
 fn task_pool_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStream> {
@@ -51,11 +59,7 @@ fn task_pool_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, Tok
     let pool_size_env = match &args.pool_size_env {
         Some(lit) => {
             let env = lit.value().to_string();
-            if let Ok(var) = std::env::var(env) {
-                Some(var)
-            } else {
-                None
-            }
+            std::env::var(env).ok()
         }
         None => None,
     };
@@ -85,8 +89,12 @@ fn task_pool_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, Tok
             }
         },
         Some(Expr::Macro(macr)) => Expr::Macro(macr.clone()),
+        Some(Expr::Path(path)) => Expr::Path(path.clone()),
         Some(_) => {
-            let err = syn::Error::new(Span::call_site(), "only literals and macros are allowed");
+            let err = syn::Error::new(
+                Span::call_site(),
+                "only literals, constants and macros are allowed",
+            );
             return Err(syn::Error::to_compile_error(&err));
         }
         None => {
@@ -122,20 +130,15 @@ fn task_pool_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, Tok
         let err = syn::Error::new_spanned(&f.sig, "task functions must not be variadic");
         return Err(syn::Error::to_compile_error(&err));
     }
-    match &f.sig.output {
-        ReturnType::Default => {}
+    // Tasks that never return are joined as if they returned `()`, the
+    // `!` gets coerced when the inner future is awaited.
+    let (output, never_returns): (Type, bool) = match &f.sig.output {
+        ReturnType::Default => (parse_quote! { () }, false),
         ReturnType::Type(_, ty) => match &**ty {
-            Type::Tuple(tuple) if tuple.elems.is_empty() => {}
-            Type::Never(_) => {}
-            _ => {
-                let err = syn::Error::new_spanned(
-                    &f.sig,
-                    "task functions must either not return a value, return `()` or return `!`",
-                );
-                return Err(syn::Error::to_compile_error(&err));
-            }
+            Type::Never(_) => (parse_quote! { () }, true),
+            ty => (ty.clone(), false),
         },
-    }
+    };
 
     let mut arg_names = Vec::new();
     let mut fargs = f.sig.inputs.clone();
@@ -171,12 +174,18 @@ fn task_pool_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, Tok
     task_inner.vis = syn::Visibility::Inherited;
     task_inner.sig.ident = task_inner_ident.clone();
 
+    let task_future = if never_returns {
+        quote! { async move { #task_inner_ident(#(#arg_names,)*).await } }
+    } else {
+        quote! { #task_inner_ident(#(#arg_names,)*) }
+    };
+
     let mut task_outer: ItemFn = parse_quote! {
-        #visibility fn #task_ident(#fargs) -> Option<::reika::executor::core::TaskRef> {
-            type Fut = impl ::core::future::Future + 'static;
+        #visibility fn #task_ident(#fargs) -> Option<::reika::executor::core::SpawnToken<#output>> {
+            type Fut = impl ::core::future::Future<Output = #output> + 'static;
             const POOL_SIZE: usize = #pool_size;
             static mut POOL: ::reika::executor::core::TaskPool<Fut, POOL_SIZE> = ::reika::executor::core::TaskPool::new();
-            unsafe { POOL.prepare_task(move || #task_inner_ident(#(#arg_names,)*)) }
+            unsafe { POOL.prepare_task(move || #task_future) }
         }
     };
 
@@ -312,11 +321,20 @@ fn replicate_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, Tok
     Ok(result)
 }
 
-fn entry_run(args: &[NestedMeta], mut f: syn::ItemFn) -> Result<TokenStream, TokenStream> {
+fn entry_run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStream> {
     let args = EntryArgs::from_list(args).map_err(|e| e.write_errors())?;
     let replicate = args.replicate.unwrap_or(LitInt::new("1", Span::call_site()));
     let replicate = replicate.base10_parse::<usize>().unwrap();
 
+    // The reactor is configured before the first op of the thread settles on
+    // the default one.
+    let configure = args.config.map(|config| {
+        quote! {
+            ::reika::reactor::PerThreadReactor::configure(&#config)
+                .expect("failed to configure reika reactor");
+        }
+    });
+
     if f.sig.asyncness.is_none() {
         let err = syn::Error::new_spanned(&f.sig, "entry must be marked async");
         return Err(syn::Error::to_compile_error(&err));
@@ -403,6 +421,8 @@ fn entry_run(args: &[NestedMeta], mut f: syn::ItemFn) -> Result<TokenStream, Tok
 
         let outer_fn_definition: ItemFn = parse_quote! {
             fn #outer_fn_ident() {
+                #configure
+
                 type Fut = impl ::core::future::Future + 'static;
                 const POOL_SIZE: usize = 1;
                 static mut POOL: ::reika::executor::core::TaskPool<Fut, POOL_SIZE> = ::reika::executor::core::TaskPool::new();
@@ -441,37 +461,90 @@ pub fn task(args: TS, item: TS) -> TS {
     task_pool_run(&args.meta, f).unwrap_or_else(|x| x).into()
 }
 
-#[proc_macro_derive(Future)]
+/// Future implements [std::future::Future] for the structs holding a
+/// `reactor` and a `req` (a `ReactorRequest`), the output is the result of
+/// the request.
+///
+/// The result is an `i32` unless the struct is annotated with the type it is
+/// to be converted to, eg. `#[output(usize)]`.
+///
+/// The fields annotated with `#[keep]` hold memory the request refers to,
+/// like its path or its address. They are `Option`s which are dropped only
+/// once the backend is done with the request, even if the future is dropped
+/// while it is in-flight.
+///
+/// The struct also gets a `with_deadline` method which bounds the request,
+/// see `ReactorRequest::set_deadline`.
+#[proc_macro_derive(Future, attributes(output, keep))]
 pub fn derive_future(input: TS) -> TS {
     let syn::DeriveInput {
-        ident, generics, ..
+        ident,
+        generics,
+        attrs,
+        data,
+        ..
     } = syn::parse_macro_input!(input);
 
-    let inner = quote! {
-        type Output = ::std::io::Result<i32>;
+    // The fields marked with #[keep] hold memory the op refers to, they are
+    // Options which are taken and released once the backend is done with
+    // the op.
+    let keep: Vec<_> = match &data {
+        syn::Data::Struct(data) => data
+            .fields
+            .iter()
+            .filter(|field| field.attrs.iter().any(|attr| attr.path().is_ident("keep")))
+            .filter_map(|field| field.ident.clone())
+            .collect(),
+        _ => Vec::new(),
+    };
 
-        fn poll(
-            mut self: ::std::pin::Pin<&mut Self>,
-            ctx: &mut ::std::task::Context<'_>,
-        ) -> ::std::task::Poll<Self::Output> {
-            if let Some(return_val) = self.req.return_val {
-                if return_val < 0 {
-                    return ::std::task::Poll::Ready(Err(::std::io::Error::from_raw_os_error(-return_val)));
-                }
+    let output: Option<Type> = match attrs.iter().find(|attr| attr.path().is_ident("output")) {
+        Some(attr) => match attr.parse_args() {
+            Ok(ty) => Some(ty),
+            Err(err) => return err.to_compile_error().into(),
+        },
+        None => None,
+    };
 
-                return ::std::task::Poll::Ready(Ok(return_val));
+    let inner = match output {
+        Some(ty) => quote! {
+            type Output = ::std::io::Result<#ty>;
+
+            fn poll(
+                mut self: ::std::pin::Pin<&mut Self>,
+                ctx: &mut ::std::task::Context<'_>,
+            ) -> ::std::task::Poll<Self::Output> {
+                let this = &mut *self;
+
+                unsafe { this.req.poll(this.reactor, ctx) }
+                    .map(|res| res.map(|val| val as #ty))
             }
+        },
+        None => quote! {
+            type Output = ::std::io::Result<i32>;
 
-            self.req.waker = Some(ctx.waker().clone());
+            fn poll(
+                mut self: ::std::pin::Pin<&mut Self>,
+                ctx: &mut ::std::task::Context<'_>,
+            ) -> ::std::task::Poll<Self::Output> {
+                let this = &mut *self;
 
-            unsafe {
-                if self.reactor.submit(&mut self.req).is_err() {
-                    // enqueue immediately
-                    ctx.waker().wake_by_ref();
-                }
+                unsafe { this.req.poll(this.reactor, ctx) }
             }
+        },
+    };
 
-            ::std::task::Poll::Pending
+    // Dropping the future before it completes must not leave the backend
+    // with a dangling request, the backend cancels it instead.
+    let drop = if keep.is_empty() {
+        quote! {
+            self.req.cancel(self.reactor);
+        }
+    } else {
+        quote! {
+            let kept = ( #( self.#keep.take(), )* );
+            self.req
+                .cancel_then(self.reactor, ::std::boxed::Box::new(move || drop(kept)));
         }
     };
 
@@ -479,6 +552,25 @@ pub fn derive_future(input: TS) -> TS {
         impl #generics ::std::future::Future for #ident #generics {
             #inner
         }
+
+        impl #generics ::std::ops::Drop for #ident #generics {
+            fn drop(&mut self) {
+                #drop
+            }
+        }
+
+        #[allow(dead_code)]
+        impl #generics #ident #generics {
+            /// with_deadline cancels the request if it is still in-flight at
+            /// the deadline, it then fails with `ErrorKind::TimedOut`.
+            ///
+            /// # Panics
+            /// Panics if the request has already been submitted.
+            pub fn with_deadline(mut self, deadline: ::std::time::Instant) -> Self {
+                self.req.set_deadline(deadline);
+                self
+            }
+        }
     };
 
     output.into()
@@ -0,0 +1,849 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io as stdio,
+    os::fd::RawFd,
+    rc::Rc,
+    sync::atomic::AtomicU16,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::slab::Slab;
+
+/// CURRENT_POSITION can be used as the offset of [Op::Read] and [Op::Write]
+/// (and their vectored variants) to read from (or write to) the current file
+/// position instead of an explicit one.
+pub const CURRENT_POSITION: u64 = u64::MAX;
+
+/// Fd is the file an op operates on, either a file descriptor of the process
+/// or an index into the fixed file table of the backend (see
+/// [Backend::register_files]).
+///
+/// The fixed files spare the kernel from looking up (and reference counting)
+/// the file on every op, which pays off for the files that see a lot of IO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fd {
+    Raw(RawFd),
+    Fixed(u32),
+}
+
+impl Fd {
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, Fd::Fixed(_))
+    }
+}
+
+impl From<RawFd> for Fd {
+    fn from(fd: RawFd) -> Self {
+        Fd::Raw(fd)
+    }
+}
+
+/// Op describes a single IO operation independent of the [Backend] which is
+/// going to execute it.
+///
+/// The result of every op is an `i32` following the io_uring (and syscall)
+/// convention: a negative value is a negated `errno` and anything else is the
+/// op specific return value (bytes transferred, new file descriptor, ...).
+///
+/// Ops hold raw pointers to the memory they operate on, see [Backend::submit]
+/// for the guarantees that the submitter needs to provide.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Op {
+    Nop,
+    Read {
+        fd: Fd,
+        buf: *mut u8,
+        len: u32,
+        offset: u64,
+    },
+    Write {
+        fd: Fd,
+        buf: *const u8,
+        len: u32,
+        offset: u64,
+    },
+    /// Readv is [Op::Read] scattering into the `len` iovecs at `iovecs`.
+    /// `flags` are the flags of preadv2(2), like `RWF_NOWAIT`.
+    Readv {
+        fd: Fd,
+        iovecs: *const libc::iovec,
+        len: u32,
+        offset: u64,
+        flags: i32,
+    },
+    /// Writev is [Op::Write] gathering from the `len` iovecs at `iovecs`.
+    /// `flags` are the flags of pwritev2(2), like `RWF_DSYNC`.
+    Writev {
+        fd: Fd,
+        iovecs: *const libc::iovec,
+        len: u32,
+        offset: u64,
+        flags: i32,
+    },
+    /// OpenAt opens the file at `path`, if `fixed` is set then the file is
+    /// installed into a free slot of the fixed file table and the result is
+    /// the index of the slot rather than a file descriptor.
+    OpenAt {
+        dirfd: RawFd,
+        path: *const libc::c_char,
+        flags: i32,
+        mode: u32,
+        fixed: bool,
+    },
+    Close {
+        fd: Fd,
+    },
+    Fsync {
+        fd: Fd,
+        datasync: bool,
+    },
+    Fallocate {
+        fd: Fd,
+        offset: u64,
+        len: u64,
+        mode: i32,
+    },
+    Socket {
+        domain: i32,
+        socket_type: i32,
+        protocol: i32,
+    },
+    SetSockOpt {
+        fd: RawFd,
+        level: i32,
+        name: i32,
+        value: i32,
+    },
+    Bind {
+        fd: RawFd,
+        addr: *const libc::sockaddr,
+        addrlen: libc::socklen_t,
+    },
+    Listen {
+        fd: RawFd,
+        backlog: i32,
+    },
+    /// Accept accepts a connection, `fixed` works like it does for
+    /// [Op::OpenAt].
+    Accept {
+        fd: Fd,
+        fixed: bool,
+    },
+    Connect {
+        fd: Fd,
+        addr: *const libc::sockaddr,
+        addrlen: libc::socklen_t,
+    },
+    Recv {
+        fd: Fd,
+        buf: *mut u8,
+        len: u32,
+    },
+    Send {
+        fd: Fd,
+        buf: *const u8,
+        len: u32,
+    },
+    /// ReadFixed is [Op::Read] into a buffer registered via
+    /// [Backend::register_buffers], `buf` lies within the buffer `buf_index`.
+    ReadFixed {
+        fd: Fd,
+        buf: *mut u8,
+        len: u32,
+        offset: u64,
+        buf_index: u16,
+    },
+    /// WriteFixed is [Op::Write] from a buffer registered via
+    /// [Backend::register_buffers], `buf` lies within the buffer `buf_index`.
+    WriteFixed {
+        fd: Fd,
+        buf: *const u8,
+        len: u32,
+        offset: u64,
+        buf_index: u16,
+    },
+    /// AcceptMulti accepts connections till it is cancelled, completing once
+    /// per connection. It is a multishot op, see [Backend::submit_multishot].
+    AcceptMulti {
+        fd: Fd,
+        fixed: bool,
+    },
+    /// RecvMulti receives into the buffers provided by the buffer group
+    /// `buf_group` (see [Backend::register_buf_ring]) till the connection is
+    /// closed, completing once per buffer. It is a multishot op.
+    RecvMulti {
+        fd: Fd,
+        buf_group: u16,
+    },
+    /// SendZc is [Op::Send] without copying the data, the kernel refers to
+    /// the buffer till the data is acknowledged. It completes with the result
+    /// first and then once more, with a notification, when it releases the
+    /// buffer. It is a multishot op.
+    SendZc {
+        fd: Fd,
+        buf: *const u8,
+        len: u32,
+    },
+    /// SendMsg sends the data gathered from the iovecs of `msg`, to the
+    /// address in its `msg_name` if it has one.
+    SendMsg {
+        fd: Fd,
+        msg: *const libc::msghdr,
+    },
+    /// RecvMsg receives into the iovecs of `msg`, the address of the sender
+    /// is written to its `msg_name` if it has one. `flags` are the flags of
+    /// recvmsg(2), like `MSG_CMSG_CLOEXEC`.
+    RecvMsg {
+        fd: Fd,
+        msg: *mut libc::msghdr,
+        flags: u32,
+    },
+    /// Timeout completes with `-ETIME` once the time pointed by `ts` has
+    /// elapsed since its submission.
+    Timeout {
+        ts: *const Timespec,
+    },
+}
+
+/// Timespec is a duration laid out like the `__kernel_timespec` of the
+/// kernel, as used by [Op::Timeout].
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl From<Duration> for Timespec {
+    fn from(duration: Duration) -> Self {
+        Self {
+            tv_sec: duration.as_secs() as i64,
+            tv_nsec: duration.subsec_nanos() as i64,
+        }
+    }
+}
+
+impl From<Timespec> for Duration {
+    fn from(ts: Timespec) -> Self {
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    }
+}
+
+/// Completion is a result of a multishot op, along with the id of the
+/// provided buffer it picked if it picked one.
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    pub result: i32,
+    pub buf_id: Option<u16>,
+}
+
+impl Completion {
+    pub fn new(result: i32) -> Self {
+        Self {
+            result,
+            buf_id: None,
+        }
+    }
+}
+
+/// BufRingEntry is an entry of a provided buffer ring, laid out like the
+/// `io_uring_buf` of the kernel.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct BufRingEntry {
+    pub addr: u64,
+    pub len: u32,
+    pub bid: u16,
+    /// resv of the first entry is the tail of the ring, see
+    /// [BufRingEntry::tail].
+    pub resv: u16,
+}
+
+impl BufRingEntry {
+    /// tail returns the tail of the ring, the index past the last buffer
+    /// that was provided.
+    ///
+    /// # Safety
+    /// `ring` must point to the first entry of a valid ring.
+    pub unsafe fn tail<'a>(ring: *mut BufRingEntry) -> &'a AtomicU16 {
+        &*(std::ptr::addr_of_mut!((*ring).resv) as *const AtomicU16)
+    }
+}
+
+/// Backend is the interface between the reika ops and whatever is executing
+/// them, for example the io_uring based [crate::Reactor].
+///
+/// A backend is single threaded, it is installed per thread via
+/// [crate::PerThreadReactor::install] and all the ops issued on that thread
+/// are submitted to it.
+///
+/// Every submitted op is identified by a token, [Inflight] can be used by the
+/// implementations to keep track of the tokens and their completions.
+pub trait Backend {
+    /// submit queues the op for execution and returns the token identifying it.
+    ///
+    /// # Safety
+    /// The memory referred by the op must stay valid till the op completes or
+    /// till its cancellation has been processed by the backend.
+    unsafe fn submit(&self, op: &Op) -> stdio::Result<usize>;
+
+    /// submit_with_timeout is like [Backend::submit] but the op is cancelled
+    /// if it has not completed once the time pointed by `ts` has elapsed
+    /// since the submission. The op then completes with `-ECANCELED` (or
+    /// `-ETIME`).
+    ///
+    /// # Safety
+    /// Same as [Backend::submit], `ts` is part of the memory referred by the
+    /// op.
+    unsafe fn submit_with_timeout(&self, op: &Op, ts: *const Timespec) -> stdio::Result<usize>;
+
+    /// submit_multishot is like [Backend::submit] for the multishot ops,
+    /// which complete any number of times till they finish. Their results
+    /// are collected via [Backend::poll_next].
+    ///
+    /// # Safety
+    /// Same as [Backend::submit].
+    unsafe fn submit_multishot(&self, op: &Op) -> stdio::Result<usize>;
+
+    /// poll_op returns the result of the op if it has completed, otherwise the
+    /// waker of `ctx` is woken once it does.
+    ///
+    /// The token is no longer valid once the result has been returned.
+    fn poll_op(&self, token: usize, ctx: &mut Context<'_>) -> Poll<i32>;
+
+    /// poll_next returns the next result of a multishot op, otherwise the
+    /// waker of `ctx` is woken once there is one. None is returned once the
+    /// op has finished and all its results have been collected.
+    ///
+    /// The token is no longer valid once None has been returned.
+    fn poll_next(&self, token: usize, ctx: &mut Context<'_>) -> Poll<Option<Completion>>;
+
+    /// cancel_op is called once nobody is interested in the result of the op
+    /// anymore. If the op is still in-flight then the backend should try to
+    /// cancel it and discard its completion.
+    ///
+    /// The token is no longer valid after this call.
+    fn cancel_op(&self, token: usize);
+
+    /// cancel_multishot is [Backend::cancel_op] for the multishot ops, the
+    /// results which were not collected, including the ones arriving till
+    /// the op finishes, are handed to `discard`. It lets the owner get back
+    /// what the results hold on to, like provided buffers.
+    ///
+    /// `discard` must not use the backend.
+    fn cancel_multishot(&self, token: usize, discard: Box<dyn FnMut(Completion)>);
+
+    /// cancel_op_then is like [Backend::cancel_op] but `release` is called
+    /// once the op no longer accesses its memory, that is once it completes
+    /// (right away if it already has). It lets the owner of the memory reuse
+    /// it safely.
+    ///
+    /// `release` must not use the backend.
+    fn cancel_op_then(&self, token: usize, release: Box<dyn FnOnce()>);
+
+    /// cancel_op_and_wait is like [Backend::cancel_op] but it returns only
+    /// once the op no longer accesses its memory, it blocks the thread till
+    /// then. It is for the ops on borrowed memory, which may be reused as
+    /// soon as the borrow ends.
+    fn cancel_op_and_wait(&self, token: usize) {
+        let done = Rc::new(Cell::new(false));
+        let released = done.clone();
+        self.cancel_op_then(token, Box::new(move || released.set(true)));
+
+        while !done.get() {
+            // The memory is freed once we return, hence there is no way out
+            // if the backend cannot tell when the op is done with it.
+            if self.wait(None).is_err() {
+                std::process::abort();
+            }
+        }
+    }
+
+    /// register_buffers registers the buffers used by the fixed ops
+    /// ([Op::ReadFixed], [Op::WriteFixed]), the index of a buffer is its
+    /// position in `bufs`. The buffers can be registered only once.
+    ///
+    /// # Safety
+    /// The buffers must stay valid for as long as the backend.
+    unsafe fn register_buffers(&self, bufs: &[libc::iovec]) -> stdio::Result<()>;
+
+    /// register_files registers an empty fixed file table with room for
+    /// `count` files, the slots are filled by the ops opening files with
+    /// `fixed` set and freed by closing [Fd::Fixed]. The table can be
+    /// registered only once.
+    fn register_files(&self, count: u32) -> stdio::Result<()>;
+
+    /// register_buf_ring registers the ring of `entries` provided buffers as
+    /// the buffer group `group`. The ops selecting a buffer of the group take
+    /// it from the head of the ring while the owner of the ring hands the
+    /// buffers back by advancing its tail.
+    ///
+    /// # Safety
+    /// The ring, and the buffers it refers to, must stay valid for as long as
+    /// the backend. `entries` must be a power of two.
+    unsafe fn register_buf_ring(
+        &self,
+        ring: *mut BufRingEntry,
+        entries: u16,
+        group: u16,
+    ) -> stdio::Result<()>;
+
+    /// poll_completions pushes the queued ops to the device and processes the
+    /// completions that are available without blocking.
+    fn poll_completions(&self) -> stdio::Result<()>;
+
+    /// wait blocks till at least one completion is available (or `timeout`
+    /// elapses) and processes the available completions.
+    fn wait(&self, timeout: Option<Duration>) -> stdio::Result<()>;
+
+    /// wake makes a blocked, or otherwise the next, call to [Backend::wait]
+    /// return immediately.
+    fn wake(&self);
+
+    /// inflight returns the number of ops that have been submitted and have
+    /// not completed yet.
+    fn inflight(&self) -> usize;
+
+    /// ring_fd returns the file descriptor of the io_uring instance behind
+    /// the backend, if there is one.
+    fn ring_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// clock returns the current time as seen by the backend, the timeouts
+    /// elapse against it.
+    fn clock(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Inflight keeps track of the state of submitted ops on behalf of a
+/// [Backend], the tokens that it hands out are stable till the op is
+/// collected or cancelled.
+///
+/// The state is owned by the backend rather than by the future of the op so
+/// that a completion arriving after the future was dropped never touches
+/// freed memory.
+pub struct Inflight {
+    ops: Slab<Lifecycle>,
+    /// pending is the number of ops waiting for their completion
+    pending: usize,
+}
+
+enum Lifecycle {
+    /// Submitted, nobody is waiting for the completion yet.
+    Submitted,
+    /// Submitted and the waker is to be woken on completion.
+    Waiting(Waker),
+    /// Completed with the given result, waiting to be collected.
+    Completed(i32),
+    /// A multishot op, the results wait in the queue to be collected.
+    Streaming {
+        results: VecDeque<Completion>,
+        waker: Option<Waker>,
+        finished: bool,
+    },
+    /// The owner lost interest before completion, the completion is discarded
+    /// and the release function (if any) is called then.
+    Ignored(Option<Box<dyn FnOnce()>>),
+    /// The owner of a multishot op lost interest, the results are handed to
+    /// the discard function till the op finishes.
+    Discarding(Box<dyn FnMut(Completion)>),
+}
+
+impl Inflight {
+    pub const fn new() -> Self {
+        Self {
+            ops: Slab::new(),
+            pending: 0,
+        }
+    }
+
+    /// insert starts tracking a newly submitted op and returns its token
+    pub fn insert(&mut self) -> usize {
+        self.pending += 1;
+        self.ops.insert(Lifecycle::Submitted)
+    }
+
+    /// insert_multishot starts tracking a newly submitted multishot op and
+    /// returns its token
+    pub fn insert_multishot(&mut self) -> usize {
+        self.pending += 1;
+        self.ops.insert(Lifecycle::Streaming {
+            results: VecDeque::new(),
+            waker: None,
+            finished: false,
+        })
+    }
+
+    /// insert_completed tracks an op that completed right away, for example
+    /// because the backend executed it synchronously.
+    pub fn insert_completed(&mut self, result: i32) -> usize {
+        self.ops.insert(Lifecycle::Completed(result))
+    }
+
+    /// abandon forgets about an op that could not be submitted after all
+    pub fn abandon(&mut self, token: usize) {
+        self.pending -= 1;
+        self.ops.remove(token);
+    }
+
+    /// complete records the result of the op and wakes up its owner, a
+    /// multishot op is finished by it.
+    ///
+    /// # Panics
+    /// Panics if the token is unknown or the op has already completed.
+    pub fn complete(&mut self, token: usize, result: i32) {
+        self.complete_with(token, Completion::new(result))
+    }
+
+    /// complete_with is [Inflight::complete] for the completions which may
+    /// have picked a provided buffer, the buffer is only kept for the
+    /// multishot ops.
+    pub fn complete_with(&mut self, token: usize, completion: Completion) {
+        let lifecycle = self
+            .ops
+            .get_mut(token)
+            .expect("completion for an unknown op");
+
+        match lifecycle {
+            Lifecycle::Streaming { finished, .. } => {
+                assert!(!*finished, "op completed twice");
+                *finished = true;
+
+                self.push(token, completion);
+                self.pending -= 1;
+                return;
+            }
+            Lifecycle::Discarding(discard) => {
+                discard(completion);
+
+                self.ops.remove(token);
+                self.pending -= 1;
+                return;
+            }
+            _ => {}
+        }
+
+        let result = completion.result;
+        match std::mem::replace(lifecycle, Lifecycle::Completed(result)) {
+            Lifecycle::Submitted => {}
+            Lifecycle::Waiting(waker) => waker.wake(),
+            Lifecycle::Ignored(release) => {
+                self.ops.remove(token);
+                if let Some(release) = release {
+                    release();
+                }
+            }
+            Lifecycle::Completed(_) => unreachable!("op completed twice"),
+            Lifecycle::Streaming { .. } | Lifecycle::Discarding(_) => unreachable!(),
+        }
+
+        self.pending -= 1;
+    }
+
+    /// push records a result of a multishot op which keeps going, the
+    /// results of a cancelled op are discarded.
+    ///
+    /// # Panics
+    /// Panics if the token is unknown or the op is not multishot.
+    pub fn push(&mut self, token: usize, completion: Completion) {
+        let lifecycle = self
+            .ops
+            .get_mut(token)
+            .expect("completion for an unknown op");
+
+        match lifecycle {
+            Lifecycle::Streaming { results, waker, .. } => {
+                results.push_back(completion);
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+            Lifecycle::Discarding(discard) => discard(completion),
+            Lifecycle::Ignored(_) => {}
+            _ => panic!("multiple completions for a single shot op"),
+        }
+    }
+
+    /// poll implements [Backend::poll_op]
+    pub fn poll(&mut self, token: usize, ctx: &mut Context<'_>) -> Poll<i32> {
+        let lifecycle = self.ops.get_mut(token).expect("polling an unknown op");
+
+        match lifecycle {
+            Lifecycle::Completed(result) => {
+                let result = *result;
+                self.ops.remove(token);
+                Poll::Ready(result)
+            }
+            Lifecycle::Waiting(waker) if waker.will_wake(ctx.waker()) => Poll::Pending,
+            Lifecycle::Streaming { .. } => panic!("polling a multishot op for a single result"),
+            _ => {
+                *lifecycle = Lifecycle::Waiting(ctx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// poll_next implements [Backend::poll_next]
+    pub fn poll_next(&mut self, token: usize, ctx: &mut Context<'_>) -> Poll<Option<Completion>> {
+        let lifecycle = self.ops.get_mut(token).expect("polling an unknown op");
+
+        let Lifecycle::Streaming {
+            results,
+            waker,
+            finished,
+        } = lifecycle
+        else {
+            panic!("polling a single shot op for multiple results");
+        };
+
+        if let Some(result) = results.pop_front() {
+            return Poll::Ready(Some(result));
+        }
+
+        if *finished {
+            self.ops.remove(token);
+            return Poll::Ready(None);
+        }
+
+        match waker {
+            Some(waker) if waker.will_wake(ctx.waker()) => {}
+            _ => *waker = Some(ctx.waker().clone()),
+        }
+
+        Poll::Pending
+    }
+
+    /// cancel implements the bookkeeping part of [Backend::cancel_op], it
+    /// returns true if the op is still in-flight and hence the backend should
+    /// try to cancel it.
+    pub fn cancel(&mut self, token: usize) -> bool {
+        self.cancel_with(token, None)
+    }
+
+    /// cancel_then implements the bookkeeping part of
+    /// [Backend::cancel_op_then], see [Inflight::cancel].
+    pub fn cancel_then(&mut self, token: usize, release: Box<dyn FnOnce()>) -> bool {
+        self.cancel_with(token, Some(release))
+    }
+
+    fn cancel_with(&mut self, token: usize, release: Option<Box<dyn FnOnce()>>) -> bool {
+        let lifecycle = self.ops.get_mut(token).expect("cancelling an unknown op");
+
+        if let Lifecycle::Completed(_) | Lifecycle::Streaming { finished: true, .. } = lifecycle {
+            self.ops.remove(token);
+            if let Some(release) = release {
+                release();
+            }
+
+            return false;
+        }
+
+        *lifecycle = Lifecycle::Ignored(release);
+        true
+    }
+
+    /// cancel_multishot implements the bookkeeping part of
+    /// [Backend::cancel_multishot], see [Inflight::cancel].
+    pub fn cancel_multishot(
+        &mut self,
+        token: usize,
+        mut discard: Box<dyn FnMut(Completion)>,
+    ) -> bool {
+        let lifecycle = self.ops.get_mut(token).expect("cancelling an unknown op");
+
+        let Lifecycle::Streaming {
+            results, finished, ..
+        } = lifecycle
+        else {
+            panic!("cancelling a single shot op as multishot");
+        };
+
+        results.drain(..).for_each(&mut discard);
+
+        if *finished {
+            self.ops.remove(token);
+            return false;
+        }
+
+        *lifecycle = Lifecycle::Discarding(discard);
+        true
+    }
+
+    /// pending returns the number of ops waiting for their completion
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl Default for Inflight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::Wake,
+    };
+
+    use super::*;
+
+    /// Wakes counts the wakeups of a waker
+    #[derive(Default)]
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn waker() -> (Arc<Wakes>, Waker) {
+        let wakes = Arc::new(Wakes::default());
+        (wakes.clone(), Waker::from(wakes))
+    }
+
+    #[test]
+    fn single_shot_lifecycle() {
+        let mut inflight = Inflight::new();
+        let (wakes, waker) = waker();
+        let mut ctx = Context::from_waker(&waker);
+
+        // Submitted, then Waiting once polled
+        let token = inflight.insert();
+        assert_eq!(inflight.pending(), 1);
+        assert!(inflight.poll(token, &mut ctx).is_pending());
+
+        // Completed wakes the owner, which collects the result
+        inflight.complete(token, 42);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(inflight.pending(), 0);
+        assert_eq!(inflight.poll(token, &mut ctx), Poll::Ready(42));
+
+        // Completed before anybody polled it
+        let token = inflight.insert();
+        inflight.complete(token, -libc::EIO);
+        assert_eq!(inflight.poll(token, &mut ctx), Poll::Ready(-libc::EIO));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+
+        let token = inflight.insert_completed(7);
+        assert_eq!(inflight.pending(), 0);
+        assert_eq!(inflight.poll(token, &mut ctx), Poll::Ready(7));
+    }
+
+    #[test]
+    fn cancelled_op_releases_once() {
+        let mut inflight = Inflight::new();
+        let released = Rc::new(Cell::new(0));
+        let mut ctx = Context::from_waker(Waker::noop());
+
+        let token = inflight.insert();
+        assert!(inflight.poll(token, &mut ctx).is_pending());
+
+        // Ignored till the completion arrives, which then releases the memory
+        let r = released.clone();
+        assert!(inflight.cancel_then(token, Box::new(move || r.set(r.get() + 1))));
+        assert_eq!(released.get(), 0);
+        assert_eq!(inflight.pending(), 1);
+
+        inflight.complete(token, -libc::ECANCELED);
+        assert_eq!(released.get(), 1);
+        assert_eq!(inflight.pending(), 0);
+
+        // The token is reused by the next op, whose completion belongs to it
+        // alone
+        let reused = inflight.insert();
+        assert_eq!(reused, token);
+        inflight.complete(reused, 3);
+        assert_eq!(inflight.poll(reused, &mut ctx), Poll::Ready(3));
+        assert_eq!(released.get(), 1);
+
+        // An op which has already completed is released right away
+        let token = inflight.insert();
+        inflight.complete(token, 0);
+        let r = released.clone();
+        assert!(!inflight.cancel_then(token, Box::new(move || r.set(r.get() + 1))));
+        assert_eq!(released.get(), 2);
+
+        // A plain cancel has nothing to release
+        let token = inflight.insert();
+        assert!(inflight.cancel(token));
+        inflight.complete(token, -libc::ECANCELED);
+        assert_eq!(inflight.pending(), 0);
+        assert_eq!(released.get(), 2);
+    }
+
+    #[test]
+    fn multishot_lifecycle() {
+        let mut inflight = Inflight::new();
+        let (wakes, waker) = waker();
+        let mut ctx = Context::from_waker(&waker);
+
+        let token = inflight.insert_multishot();
+        assert!(inflight.poll_next(token, &mut ctx).is_pending());
+
+        inflight.push(token, Completion::new(1));
+        inflight.push(token, Completion::new(2));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(inflight.pending(), 1);
+
+        let next =
+            |inflight: &mut Inflight, ctx: &mut Context<'_>| match inflight.poll_next(token, ctx) {
+                Poll::Ready(next) => next.map(|completion| completion.result),
+                Poll::Pending => panic!("results are missing"),
+            };
+        assert_eq!(next(&mut inflight, &mut ctx), Some(1));
+        assert_eq!(next(&mut inflight, &mut ctx), Some(2));
+
+        // Streaming finishes with its last result
+        inflight.complete_with(token, Completion::new(0));
+        assert_eq!(inflight.pending(), 0);
+        assert_eq!(next(&mut inflight, &mut ctx), Some(0));
+        assert_eq!(next(&mut inflight, &mut ctx), None);
+    }
+
+    #[test]
+    fn cancelled_multishot_discards() {
+        let mut inflight = Inflight::new();
+        let discarded = Rc::new(RefCell::new(Vec::new()));
+
+        let token = inflight.insert_multishot();
+        inflight.push(token, Completion::new(1));
+
+        // Discarding hands over the results which were not collected, and
+        // the ones arriving till the op finishes
+        let d = discarded.clone();
+        let discard =
+            Box::new(move |completion: Completion| d.borrow_mut().push(completion.result));
+        assert!(inflight.cancel_multishot(token, discard));
+        assert_eq!(*discarded.borrow(), [1]);
+
+        inflight.push(token, Completion::new(2));
+        inflight.complete_with(token, Completion::new(-libc::ECANCELED));
+        assert_eq!(*discarded.borrow(), [1, 2, -libc::ECANCELED]);
+        assert_eq!(inflight.pending(), 0);
+
+        assert_eq!(inflight.insert(), token);
+    }
+
+    #[test]
+    fn abandoned_op_is_forgotten() {
+        let mut inflight = Inflight::new();
+
+        let token = inflight.insert();
+        inflight.abandon(token);
+        assert_eq!(inflight.pending(), 0);
+        assert_eq!(inflight.insert(), token);
+    }
+}
@@ -0,0 +1,234 @@
+//! config holds the setup of the io_uring instance behind a [Reactor].
+//!
+//! ```ignore
+//! PerThreadReactor::configure(ReactorConfig::new().entries(1024).sqpoll(2000))?;
+//! ```
+
+use std::{io as stdio, os::fd::RawFd};
+
+use crate::Reactor;
+
+/// ReactorConfig configures the ring of a [Reactor], the defaults match the
+/// reactor that a thread gets when it issues an op without configuring one.
+#[derive(Debug, Clone, Copy)]
+pub struct ReactorConfig {
+    pub(crate) entries: u32,
+    pub(crate) cq_entries: Option<u32>,
+
+    pub(crate) sqpoll_idle: Option<u32>,
+    pub(crate) sqpoll_cpu: Option<u32>,
+    pub(crate) iopoll: bool,
+    pub(crate) defer_taskrun: bool,
+    pub(crate) attach_wq: Option<RawFd>,
+}
+
+impl ReactorConfig {
+    pub fn new() -> Self {
+        Self {
+            entries: 512,
+            cq_entries: None,
+            sqpoll_idle: None,
+            sqpoll_cpu: None,
+            iopoll: false,
+            defer_taskrun: false,
+            attach_wq: None,
+        }
+    }
+
+    /// entries sets the size of the submission queue, the kernel rounds it
+    /// up to a power of two.
+    pub fn entries(&mut self, entries: u32) -> &mut Self {
+        self.entries = entries;
+        self
+    }
+
+    /// cq_entries sets the size of the completion queue, by default it is
+    /// twice the size of the submission queue.
+    pub fn cq_entries(&mut self, entries: u32) -> &mut Self {
+        self.cq_entries = Some(entries);
+        self
+    }
+
+    /// sqpoll makes a kernel thread poll the submission queue, the thread
+    /// goes to sleep once it has been idle for `idle_ms` milliseconds.
+    pub fn sqpoll(&mut self, idle_ms: u32) -> &mut Self {
+        self.sqpoll_idle = Some(idle_ms);
+        self
+    }
+
+    /// sqpoll_cpu pins the polling thread of [ReactorConfig::sqpoll] to the
+    /// CPU.
+    pub fn sqpoll_cpu(&mut self, cpu: u32) -> &mut Self {
+        self.sqpoll_cpu = Some(cpu);
+        self
+    }
+
+    /// iopoll busy polls the device for the completions instead of waiting
+    /// for interrupts.
+    ///
+    /// NOTE: Such a ring only takes reads and writes of files opened for
+    /// direct I/O (see [crate::io::OpenOptions::direct]) on devices
+    /// supporting polling (like NVMe), hence it is meant for threads
+    /// dedicated to storage.
+    pub fn iopoll(&mut self, iopoll: bool) -> &mut Self {
+        self.iopoll = iopoll;
+        self
+    }
+
+    /// defer_taskrun defers the completion work of the kernel till the
+    /// reactor asks for the completions, rather than interrupting the thread
+    /// whenever an op completes.
+    pub fn defer_taskrun(&mut self, defer_taskrun: bool) -> &mut Self {
+        self.defer_taskrun = defer_taskrun;
+        self
+    }
+
+    /// attach_wq shares the async worker pool (and with SQPOLL, the polling
+    /// thread) of the ring `ring_fd` instead of creating new ones. It lets
+    /// sibling threads share the kernel threads, see
+    /// [crate::PerThreadReactor::ring_fd].
+    pub fn attach_wq(&mut self, ring_fd: RawFd) -> &mut Self {
+        self.attach_wq = Some(ring_fd);
+        self
+    }
+
+    /// build sets up the ring and returns the reactor driving it
+    pub fn build(&self) -> stdio::Result<Reactor> {
+        if self.sqpoll_idle.is_some() && self.defer_taskrun {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidInput,
+                "defer_taskrun cannot be combined with sqpoll",
+            ));
+        }
+
+        if self.sqpoll_idle.is_none() && self.sqpoll_cpu.is_some() {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidInput,
+                "sqpoll_cpu requires sqpoll",
+            ));
+        }
+
+        Reactor::with_config(self)
+    }
+}
+
+impl Default for ReactorConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        os::fd::FromRawFd,
+        pin::pin,
+        task::{Context, Poll, Waker},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{io::File, PerThreadReactor};
+
+    /// round_trip reads what was written to a pipe through the reactor of the
+    /// current thread.
+    fn round_trip() -> Vec<u8> {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let file = unsafe { File::from_raw_fd(fds[0]) };
+        assert_eq!(
+            unsafe { libc::write(fds[1], b"data".as_ptr() as *const _, 4) },
+            4
+        );
+
+        let reactor = unsafe { PerThreadReactor::this() };
+        let mut ctx = Context::from_waker(Waker::noop());
+        let mut buf = [0; 8];
+        let n = {
+            let mut read = pin!(file.read(&mut buf));
+            loop {
+                if let Poll::Ready(res) = read.as_mut().poll(&mut ctx) {
+                    break res.unwrap();
+                }
+                reactor.wait(Some(Duration::from_millis(10))).unwrap();
+            }
+        };
+
+        unsafe { libc::close(fds[1]) };
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn conflicting_options_are_rejected() {
+        let err = ReactorConfig::new()
+            .sqpoll(100)
+            .defer_taskrun(true)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidInput);
+
+        let err = ReactorConfig::new().sqpoll_cpu(0).build().err().unwrap();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn configured_reactor_runs_the_ops() {
+        std::thread::spawn(|| {
+            PerThreadReactor::configure(
+                ReactorConfig::new()
+                    .entries(8)
+                    .cq_entries(64)
+                    .defer_taskrun(true),
+            )
+            .unwrap();
+            assert_eq!(round_trip(), b"data");
+
+            // The thread has settled on its reactor
+            let err = PerThreadReactor::configure(&ReactorConfig::new()).unwrap_err();
+            assert_eq!(err.kind(), stdio::ErrorKind::AlreadyExists);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn configure_after_the_first_op_fails() {
+        std::thread::spawn(|| {
+            assert_eq!(round_trip(), b"data");
+
+            let err = PerThreadReactor::configure(ReactorConfig::new().entries(8)).unwrap_err();
+            assert_eq!(err.kind(), stdio::ErrorKind::AlreadyExists);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn sqpoll_rings_share_the_workqueue() {
+        let (fd_tx, fd_rx) = std::sync::mpsc::channel();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+
+        // The reactor of a thread lives as long as the thread, the first one
+        // is kept around till its sibling is done with the ring
+        let first = std::thread::spawn(move || {
+            PerThreadReactor::configure(ReactorConfig::new().sqpoll(10)).unwrap();
+            fd_tx.send(PerThreadReactor::ring_fd().unwrap()).unwrap();
+            assert_eq!(round_trip(), b"data");
+            let _ = done_rx.recv();
+        });
+
+        let ring_fd = fd_rx.recv().unwrap();
+        let sibling = std::thread::spawn(move || {
+            PerThreadReactor::configure(ReactorConfig::new().sqpoll(10).attach_wq(ring_fd))
+                .unwrap();
+            assert_eq!(round_trip(), b"data");
+        });
+
+        let res = sibling.join();
+        drop(done_tx);
+        first.join().unwrap();
+        res.unwrap();
+    }
+}
@@ -1,18 +1,80 @@
 #![cfg(target_os = "linux")]
+pub mod backend;
+pub mod config;
 pub mod error;
 mod ops;
+pub mod sim;
+mod slab;
 pub use ops::*;
 
 extern crate libc;
 
+use backend::{Backend, BufRingEntry, Completion, Fd, Inflight, Op, Timespec};
+use config::ReactorConfig;
 use io_uring::{squeue, IoUring};
-use std::{cell::UnsafeCell, io as stdio, task::Waker};
+use std::{
+    cell::{OnceCell, UnsafeCell},
+    io as stdio,
+    os::fd::{AsRawFd, RawFd},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// TIMEOUT_USER_DATA is the user data of the timeouts submitted by
+/// [Reactor::run_for_ns] to bound the event loop.
+const TIMEOUT_USER_DATA: u64 = 0;
+
+/// CANCEL_USER_DATA is the user data of the cancellation requests submitted
+/// on behalf of dropped requests, their completions are discarded.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+/// WAKE_USER_DATA is the user data of the read armed on the eventfd used by
+/// [Reactor::wake].
+const WAKE_USER_DATA: u64 = u64::MAX - 1;
+
+/// LINK_TIMEOUT_USER_DATA is the user data of the timeouts linked to the
+/// requests with a deadline, their completions are discarded.
+const LINK_TIMEOUT_USER_DATA: u64 = u64::MAX - 2;
+
+/// IORING_ENTER_GETEVENTS asks io_uring_enter to run the pending completion
+/// work, see [Reactor::run_task_work].
+const IORING_ENTER_GETEVENTS: u32 = 1;
 
 pub struct PerThreadReactor;
 
 impl PerThreadReactor {
     thread_local! {
-        static REACTOR: stdio::Result<Reactor> = Reactor::new(512);
+        static REACTOR: OnceCell<Box<dyn Backend>> = const { OnceCell::new() };
+    }
+
+    /// install sets the backend which is going to execute the ops issued on the
+    /// current thread.
+    ///
+    /// It needs to be called before the first op is issued on the thread, otherwise
+    /// the thread has already settled on the default io_uring [Reactor] and the
+    /// given backend is handed back.
+    pub fn install(backend: Box<dyn Backend>) -> Result<(), Box<dyn Backend>> {
+        Self::REACTOR.with(|reactor| reactor.set(backend))
+    }
+
+    /// configure builds a [Reactor] from the config and installs it, see
+    /// [PerThreadReactor::install].
+    pub fn configure(config: &ReactorConfig) -> stdio::Result<()> {
+        let reactor = config.build()?;
+
+        Self::install(Box::new(reactor)).map_err(|_| {
+            stdio::Error::new(
+                stdio::ErrorKind::AlreadyExists,
+                "the thread already has a reactor",
+            )
+        })
+    }
+
+    /// ring_fd returns the file descriptor of the ring of the current thread,
+    /// None if its backend is not io_uring based.
+    pub fn ring_fd() -> Option<RawFd> {
+        let reactor = unsafe { Self::this() };
+        reactor.ring_fd()
     }
 
     /// this returns a static reference to the reactor
@@ -21,103 +83,633 @@ impl PerThreadReactor {
     /// # Safety
     /// The consumer of the function needs to ensure that the returned reference
     /// does NOT outlive the thread (that is, it should not be sent to other threads!)
-    pub(crate) unsafe fn this() -> &'static Reactor {
-        Self::REACTOR.with(|reactor_res: &stdio::Result<Reactor>| {
-            let reactor = reactor_res.as_ref().unwrap();
+    pub(crate) unsafe fn this() -> &'static dyn Backend {
+        Self::REACTOR.with(|reactor: &OnceCell<Box<dyn Backend>>| {
+            let reactor = reactor.get_or_init(|| {
+                Box::new(
+                    ReactorConfig::new()
+                        .build()
+                        .expect("failed to initialize the reactor"),
+                )
+            });
 
-            _make_static(reactor)
+            _make_static(reactor.as_ref())
         })
     }
 
-    /// submit takes a reference to request and submits the squeue entry part of it to
-    /// the underlying IO Ring.
-    ///
-    /// # Safety
-    /// It needs to be ensured the the [Request] and the data referred by the request lives
-    /// at least for as long as the request is in the queue.
-    pub(crate) unsafe fn submit(req: &mut ReactorRequest) -> stdio::Result<()> {
-        let reactor = Self::this();
-        reactor.submit(req)
+    /// flush pushes the queued requests to the backend and processes the
+    /// available completions without blocking.
+    pub fn flush() -> stdio::Result<()> {
+        let reactor = unsafe { Self::this() };
+        reactor.poll_completions()
     }
 
-    pub fn flush(want: usize, timeouts: usize, etime: bool) -> stdio::Result<(usize, bool)> {
+    /// register_files registers a fixed file table with room for `count`
+    /// files with the backend of the current thread, see
+    /// [Backend::register_files].
+    pub fn register_files(count: u32) -> stdio::Result<()> {
         let reactor = unsafe { Self::this() };
-        reactor.flush(want, timeouts, etime)
+        reactor.register_files(count)
     }
 
+    /// run flushes the backend and if there are no requests in-flight it
+    /// waits for at most `ns` nanoseconds for something to happen.
     pub fn run(ns: u32) -> stdio::Result<()> {
         let reactor = unsafe { Self::this() };
-        reactor.run(ns)
+        reactor.poll_completions()?;
+
+        if reactor.inflight() == 0 {
+            reactor.wait(Some(Duration::from_nanos(ns as u64)))
+        } else {
+            Ok(())
+        }
     }
 
+    /// run_for_ns waits for at most `ns` nanoseconds for completions.
     pub fn run_for_ns(ns: u32) -> stdio::Result<()> {
         let reactor = unsafe { Self::this() };
-        reactor.run_for_ns(ns)
+        reactor.wait(Some(Duration::from_nanos(ns as u64)))
     }
 }
 
-pub struct Reactor {
-    ring: UnsafeCell<IoUring>,
-    req_queued: UnsafeCell<usize>,
-}
-
+/// ReactorRequest is embedded into every op future, it remembers the op
+/// and tracks it while it is in-flight on a [Backend].
 pub struct ReactorRequest {
-    pub(crate) sentry: squeue::Entry,
+    pub(crate) op: Op,
     pub(crate) return_val: Option<i32>,
-    pub(crate) waker: Option<Waker>,
+
+    /// token identifies the request on the backend while it is in-flight.
+    pub(crate) token: Option<usize>,
+
+    /// deadline is the time (on the clock of the backend) at which the
+    /// request is cancelled if it is still in-flight.
+    deadline: Option<Instant>,
+    /// timeout is the time left till the deadline at the submission, it is
+    /// pointed by the timeout linked to the op.
+    timeout: Option<Box<Timespec>>,
 }
 
 impl ReactorRequest {
-    pub fn new(sentry: squeue::Entry) -> Self {
+    pub fn new(op: Op) -> Self {
         Self {
-            sentry,
+            op,
             return_val: None,
-            waker: None,
+            token: None,
+            deadline: None,
+            timeout: None,
+        }
+    }
+
+    /// set_deadline bounds the request, if it has not completed by the
+    /// deadline then the backend cancels it and the request fails with
+    /// [stdio::ErrorKind::TimedOut].
+    ///
+    /// # Panics
+    /// Panics if the request has already been submitted.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        assert!(
+            self.token.is_none() && self.return_val.is_none(),
+            "request has already been submitted"
+        );
+
+        self.deadline = Some(deadline);
+    }
+
+    /// submit submits the op to the backend, linked to a timeout if the
+    /// request has a deadline.
+    ///
+    /// # Safety
+    /// Same as [Backend::submit].
+    unsafe fn submit(&mut self, backend: &dyn Backend) -> stdio::Result<usize> {
+        let Some(deadline) = self.deadline else {
+            return backend.submit(&self.op);
+        };
+
+        let timeout = self.timeout.insert(Box::new(Timespec::from(
+            deadline.saturating_duration_since(backend.clock()),
+        )));
+
+        backend.submit_with_timeout(&self.op, &**timeout)
+    }
+
+    /// poll submits the request the first time it is called and afterwards checks
+    /// whether the request has completed. `ctx` is woken once the completion arrives.
+    ///
+    /// # Safety
+    /// Same as [Backend::submit].
+    pub unsafe fn poll(
+        &mut self,
+        backend: &dyn Backend,
+        ctx: &mut Context<'_>,
+    ) -> Poll<stdio::Result<i32>> {
+        if let Some(return_val) = self.return_val {
+            // The op is cancelled by the kernel once the linked timeout fires
+            if self.deadline.is_some()
+                && (return_val == -libc::ECANCELED || return_val == -libc::ETIME)
+            {
+                return Poll::Ready(Err(stdio::ErrorKind::TimedOut.into()));
+            }
+
+            if return_val < 0 {
+                return Poll::Ready(Err(stdio::Error::from_raw_os_error(-return_val)));
+            }
+
+            return Poll::Ready(Ok(return_val));
+        }
+
+        let token = match self.token {
+            Some(token) => token,
+            None => match self.submit(backend) {
+                Ok(token) => {
+                    self.token = Some(token);
+                    token
+                }
+                Err(_) => {
+                    // enqueue immediately
+                    ctx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            },
+        };
+
+        match backend.poll_op(token, ctx) {
+            Poll::Ready(return_val) => {
+                self.token = None;
+                self.return_val = Some(return_val);
+
+                self.poll(backend, ctx)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// cancel is to be called when a request is dropped. If the request is still
+    /// in-flight then the backend is asked to cancel it and its completion, whenever
+    /// it arrives, is discarded.
+    ///
+    /// NOTE: Cancellation is asynchronous, the backend may still access the memory
+    /// referred by the op till the cancellation has been processed. The timeout
+    /// linked to the op is kept alive till then.
+    pub fn cancel(&mut self, backend: &dyn Backend) {
+        if let Some(token) = self.token.take() {
+            match self.timeout.take() {
+                Some(timeout) => backend.cancel_op_then(token, Box::new(move || drop(timeout))),
+                None => backend.cancel_op(token),
+            }
+        }
+    }
+
+    /// cancel_and_wait is [ReactorRequest::cancel] for the requests referring
+    /// to borrowed memory, it returns only once the backend is done with the
+    /// memory, see [Backend::cancel_op_and_wait].
+    pub fn cancel_and_wait(&mut self, backend: &dyn Backend) {
+        if let Some(token) = self.token.take() {
+            backend.cancel_op_and_wait(token);
+        }
+    }
+
+    /// cancel_then is like [ReactorRequest::cancel] but `release` is called
+    /// once the backend is done with the memory referred by the op, right
+    /// away if the request is not in-flight.
+    pub fn cancel_then(&mut self, backend: &dyn Backend, release: Box<dyn FnOnce()>) {
+        match self.token.take() {
+            Some(token) => {
+                let timeout = self.timeout.take();
+                backend.cancel_op_then(
+                    token,
+                    Box::new(move || {
+                        release();
+                        drop(timeout);
+                    }),
+                )
+            }
+            None => release(),
         }
     }
 }
 
+/// MultishotRequest is the [ReactorRequest] of the multishot ops, a single
+/// submission yields any number of results.
+pub struct MultishotRequest {
+    pub(crate) op: Op,
+    pub(crate) token: Option<usize>,
+}
+
+impl MultishotRequest {
+    pub fn new(op: Op) -> Self {
+        Self { op, token: None }
+    }
+
+    /// poll_next submits the request if it is not armed and returns its next
+    /// result. The kernel may finish a multishot op at any point (for
+    /// example when the completion queue overflows), the request is then
+    /// armed again. Once it has failed, it is armed again by the next poll,
+    /// hence the caller must not poll it right away if the failure persists
+    /// (see [crate::net::RecvStream]).
+    ///
+    /// # Safety
+    /// Same as [Backend::submit].
+    pub unsafe fn poll_next(
+        &mut self,
+        backend: &dyn Backend,
+        ctx: &mut Context<'_>,
+    ) -> Poll<stdio::Result<Completion>> {
+        let token = match self.token {
+            Some(token) => token,
+            None => match backend.submit_multishot(&self.op) {
+                Ok(token) => {
+                    self.token = Some(token);
+                    token
+                }
+                Err(_) => {
+                    // enqueue immediately
+                    ctx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            },
+        };
+
+        match backend.poll_next(token, ctx) {
+            Poll::Ready(Some(completion)) if completion.result < 0 => {
+                Poll::Ready(Err(stdio::Error::from_raw_os_error(-completion.result)))
+            }
+            Poll::Ready(Some(completion)) => Poll::Ready(Ok(completion)),
+            Poll::Ready(None) => {
+                self.token = None;
+                self.poll_next(backend, ctx)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// cancel is to be called when the request is dropped, see
+    /// [ReactorRequest::cancel].
+    pub fn cancel(&mut self, backend: &dyn Backend) {
+        if let Some(token) = self.token.take() {
+            backend.cancel_op(token);
+        }
+    }
+
+    /// cancel_with is like [MultishotRequest::cancel] but the results that
+    /// were not collected are handed to `discard`, see
+    /// [Backend::cancel_multishot].
+    pub fn cancel_with(&mut self, backend: &dyn Backend, discard: Box<dyn FnMut(Completion)>) {
+        if let Some(token) = self.token.take() {
+            backend.cancel_multishot(token, discard);
+        }
+    }
+}
+
+pub struct Reactor {
+    ring: UnsafeCell<IoUring>,
+
+    /// inflight tracks every request that the kernel knows about, the user
+    /// data of a request is its token plus one.
+    inflight: UnsafeCell<Inflight>,
+
+    /// wake_fd is an eventfd, a read on it is kept armed in the ring while
+    /// waiting so that [Reactor::wake] can interrupt the wait.
+    wake_fd: RawFd,
+    wake_armed: UnsafeCell<bool>,
+    wake_buf: Box<UnsafeCell<u64>>,
+
+    /// defer_taskrun is set if the ring runs the completion work only when
+    /// asked to.
+    defer_taskrun: bool,
+}
+
 impl Reactor {
     pub fn new(entries: u32) -> stdio::Result<Self> {
+        ReactorConfig::new().entries(entries).build()
+    }
+
+    /// with_config sets up the ring as configured, the config is expected
+    /// to be validated by [ReactorConfig::build].
+    pub(crate) fn with_config(config: &ReactorConfig) -> stdio::Result<Self> {
+        let mut builder = IoUring::builder();
+        builder.setup_single_issuer();
+
+        match config.sqpoll_idle {
+            Some(idle) => {
+                builder.setup_sqpoll(idle);
+                if let Some(cpu) = config.sqpoll_cpu {
+                    builder.setup_sqpoll_cpu(cpu);
+                }
+            }
+            // The kernel rejects COOP_TASKRUN along with SQPOLL, the polling
+            // thread never interrupts us anyway.
+            None => {
+                builder.setup_coop_taskrun();
+            }
+        }
+
+        if let Some(cq_entries) = config.cq_entries {
+            builder.setup_cqsize(cq_entries);
+        }
+        if config.iopoll {
+            builder.setup_iopoll();
+        }
+        if config.defer_taskrun {
+            builder.setup_defer_taskrun();
+        }
+        if let Some(ring_fd) = config.attach_wq {
+            builder.setup_attach_wq(ring_fd);
+        }
+
         let ring: io_uring::IoUring<io_uring::squeue::Entry, io_uring::cqueue::Entry> =
-            IoUring::builder()
-                .setup_coop_taskrun()
-                .setup_single_issuer()
-                .build(entries)?;
+            builder.build(config.entries)?;
+
+        let wake_fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if wake_fd < 0 {
+            return Err(stdio::Error::last_os_error());
+        }
+
         Ok(Self {
             ring: UnsafeCell::new(ring),
-            req_queued: UnsafeCell::new(0),
+            inflight: UnsafeCell::new(Inflight::new()),
+            wake_fd,
+            wake_armed: UnsafeCell::new(false),
+            wake_buf: Box::new(UnsafeCell::new(0)),
+            defer_taskrun: config.defer_taskrun,
         })
     }
 
-    /// submit takes a reference to request and submits the squeue entry part of it to
-    /// the underlying IO Ring.
+    /// push pushes the entries to the submission queue, if the queue is full
+    /// then the queue is flushed to the kernel and the push is tried once more.
+    ///
+    /// The entries are pushed next to each other, hence they can be linked.
     ///
     /// # Safety
-    /// It needs to be ensured the the [Request] and the data referred by the request lives
-    /// at least for as long as the request is in the queue.
-    pub unsafe fn submit(&'static self, req: &mut ReactorRequest) -> stdio::Result<()> {
-        let mutreq = self.req_queued.get().as_mut().unwrap();
-        *mutreq += 1;
-
+    /// The data referred by the entries must live till the entries complete.
+    unsafe fn push(&self, sentries: &[squeue::Entry]) -> stdio::Result<()> {
         let mutring = self.ring.get().as_mut().unwrap();
 
-        req.sentry = req.sentry.clone().user_data(req as *mut _ as u64);
+        if mutring.submission().push_multiple(sentries).is_ok() {
+            return Ok(());
+        }
+
+        mutring.submit()?;
 
         mutring
             .submission()
-            .push(&req.sentry)
-            .map_err(|_| stdio::Error::new(stdio::ErrorKind::Other, "failed to submit IO"))?;
+            .push_multiple(sentries)
+            .map_err(|_| stdio::Error::other("failed to submit IO"))
+    }
+
+    /// prepare translates the op to the squeue entry executing it, `None` is
+    /// returned for the ops which have no io_uring counterpart and are executed
+    /// synchronously by [Reactor::execute] instead.
+    fn prepare(op: &Op) -> Option<squeue::Entry> {
+        use io_uring::{opcode, types};
+
+        // target builds the entry against either types::Fd or types::Fixed,
+        // they do not share a nameable type.
+        macro_rules! target {
+            ($fd:expr, |$target:ident| $build:expr) => {
+                match $fd {
+                    Fd::Raw(fd) => {
+                        let $target = types::Fd(fd);
+                        $build
+                    }
+                    Fd::Fixed(index) => {
+                        let $target = types::Fixed(index);
+                        $build
+                    }
+                }
+            };
+        }
+
+        // The slot of a file opened as fixed is allocated by the kernel
+        let file_index = |fixed: bool| fixed.then(types::DestinationSlot::auto_target);
+
+        let sentry = match *op {
+            Op::Nop => opcode::Nop::new().build(),
+            Op::Read {
+                fd,
+                buf,
+                len,
+                offset,
+            } => target!(fd, |fd| opcode::Read::new(fd, buf, len)
+                .offset(offset)
+                .build()),
+            Op::Write {
+                fd,
+                buf,
+                len,
+                offset,
+            } => target!(fd, |fd| opcode::Write::new(fd, buf, len)
+                .offset(offset)
+                .build()),
+            Op::Readv {
+                fd,
+                iovecs,
+                len,
+                offset,
+                flags,
+            } => target!(fd, |fd| opcode::Readv::new(fd, iovecs, len)
+                .offset(offset)
+                .rw_flags(flags)
+                .build()),
+            Op::Writev {
+                fd,
+                iovecs,
+                len,
+                offset,
+                flags,
+            } => target!(fd, |fd| opcode::Writev::new(fd, iovecs, len)
+                .offset(offset)
+                .rw_flags(flags)
+                .build()),
+            Op::ReadFixed {
+                fd,
+                buf,
+                len,
+                offset,
+                buf_index,
+            } => target!(fd, |fd| opcode::ReadFixed::new(fd, buf, len, buf_index)
+                .offset(offset)
+                .build()),
+            Op::WriteFixed {
+                fd,
+                buf,
+                len,
+                offset,
+                buf_index,
+            } => target!(fd, |fd| opcode::WriteFixed::new(fd, buf, len, buf_index)
+                .offset(offset)
+                .build()),
+            Op::OpenAt {
+                dirfd,
+                path,
+                flags,
+                mode,
+                fixed,
+            } => opcode::OpenAt::new(types::Fd(dirfd), path)
+                .file_index(file_index(fixed))
+                .flags(flags)
+                .mode(mode)
+                .build(),
+            Op::Close { fd } => target!(fd, |fd| opcode::Close::new(fd).build()),
+            Op::Fsync { fd, datasync } => target!(fd, |fd| {
+                let fsync_op = opcode::Fsync::new(fd);
+                if datasync {
+                    fsync_op.flags(types::FsyncFlags::DATASYNC).build()
+                } else {
+                    fsync_op.build()
+                }
+            }),
+            Op::Fallocate {
+                fd,
+                offset,
+                len,
+                mode,
+            } => target!(fd, |fd| opcode::Fallocate::new(fd, len)
+                .offset(offset)
+                .mode(mode)
+                .build()),
+            Op::Socket {
+                domain,
+                socket_type,
+                protocol,
+            } => opcode::Socket::new(domain, socket_type, protocol).build(),
+            Op::Accept { fd, fixed } => target!(fd, |fd| opcode::Accept::new(
+                fd,
+                std::ptr::null_mut(),
+                std::ptr::null_mut()
+            )
+            .file_index(file_index(fixed))
+            .build()),
+            Op::Connect { fd, addr, addrlen } => {
+                target!(fd, |fd| opcode::Connect::new(fd, addr, addrlen).build())
+            }
+            Op::AcceptMulti { fd, fixed } => target!(fd, |fd| opcode::AcceptMulti::new(fd)
+                .allocate_file_index(fixed)
+                .build()),
+            Op::RecvMulti { fd, buf_group } => {
+                target!(fd, |fd| opcode::RecvMulti::new(fd, buf_group).build())
+            }
+            Op::Recv { fd, buf, len } => target!(fd, |fd| opcode::Recv::new(fd, buf, len).build()),
+            Op::SendZc { fd, buf, len } => {
+                target!(fd, |fd| opcode::SendZc::new(fd, buf, len).build())
+            }
+            Op::Send { fd, buf, len } => target!(fd, |fd| opcode::Send::new(fd, buf, len).build()),
+            Op::SendMsg { fd, msg } => target!(fd, |fd| opcode::SendMsg::new(fd, msg).build()),
+            Op::RecvMsg { fd, msg, flags } => {
+                target!(fd, |fd| opcode::RecvMsg::new(fd, msg).flags(flags).build())
+            }
+            // # Safety
+            // backend::Timespec has the layout of __kernel_timespec, just like
+            // types::Timespec.
+            Op::Timeout { ts } => opcode::Timeout::new(ts as *const types::Timespec).build(),
+            Op::SetSockOpt { .. } | Op::Bind { .. } | Op::Listen { .. } => return None,
+        };
+
+        Some(sentry)
+    }
+
+    /// execute runs the ops that [Reactor::prepare] cannot translate with
+    /// plain syscalls and returns their result.
+    ///
+    /// # Safety
+    /// The memory referred by the op must be valid.
+    unsafe fn execute(op: &Op) -> i32 {
+        let res = match *op {
+            Op::SetSockOpt {
+                fd,
+                level,
+                name,
+                value,
+            } => libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const _ as *const libc::c_void,
+                std::mem::size_of::<i32>() as _,
+            ),
+            Op::Bind { fd, addr, addrlen } => libc::bind(fd, addr, addrlen),
+            Op::Listen { fd, backlog } => libc::listen(fd, backlog),
+            _ => unreachable!("op can be submitted to the ring"),
+        };
+
+        if res < 0 {
+            -stdio::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+        } else {
+            res
+        }
+    }
+
+    /// push_cancel asks the kernel to cancel the in-flight request
+    fn push_cancel(&self, token: usize) {
+        let cancel_op = io_uring::opcode::AsyncCancel::new(token as u64 + 1)
+            .build()
+            .user_data(CANCEL_USER_DATA);
+
+        // If the cancellation cannot be submitted then the request runs to
+        // completion and is discarded then.
+        let _ = unsafe { self.push(&[cancel_op]) };
+    }
+
+    /// arm_wake makes sure that a read is pending on the wake eventfd
+    fn arm_wake(&self) -> stdio::Result<()> {
+        let armed = unsafe { self.wake_armed.get().as_mut().unwrap() };
+        if *armed {
+            return Ok(());
+        }
+
+        let read_op = io_uring::opcode::Read::new(
+            io_uring::types::Fd(self.wake_fd),
+            self.wake_buf.get() as *mut u8,
+            std::mem::size_of::<u64>() as u32,
+        )
+        .build()
+        .user_data(WAKE_USER_DATA);
+
+        // # Safety
+        // The buffer is owned by the reactor and the eventfd is only closed once
+        // the reactor is dropped.
+        unsafe { self.push(&[read_op])? };
+        *armed = true;
 
         Ok(())
     }
 
     pub fn flush(&self, want: usize, timeouts: usize, etime: bool) -> stdio::Result<(usize, bool)> {
         self.flush_submissions(want, timeouts, etime)?;
+        self.run_task_work()?;
         self.flush_completions(0, timeouts, etime)
     }
+
+    /// run_task_work posts the completions held back by DEFER_TASKRUN, the
+    /// kernel otherwise does so only while we wait for completions.
+    fn run_task_work(&self) -> stdio::Result<()> {
+        if !self.defer_taskrun {
+            return Ok(());
+        }
+
+        let mutring = unsafe { self.ring.get().as_mut().unwrap() };
+
+        // # Safety
+        // No submissions and no arguments, it merely reaps the completions.
+        let res = unsafe {
+            mutring
+                .submitter()
+                .enter::<libc::sigset_t>(0, 0, IORING_ENTER_GETEVENTS, None)
+        };
+
+        match res {
+            Err(err)
+                if !matches!(
+                    err.raw_os_error(),
+                    Some(libc::EINTR) | Some(libc::EBUSY) | Some(libc::EAGAIN)
+                ) =>
+            {
+                Err(err)
+            }
+            _ => Ok(()),
+        }
+    }
     pub fn run(&self, ns: u32) -> stdio::Result<()> {
-        self.flush(0, 0, false);
+        self.flush(0, 0, false)?;
 
         if !self.requires_reaping() {
             self.run_for_ns(ns)
@@ -146,7 +738,7 @@ impl Reactor {
             timeout_ts.nsec(ts.tv_nsec as u32 + ns);
 
             let timeout_op = io_uring::opcode::Timeout::new(&timeout_ts as *const _).build();
-            let timeout_op = timeout_op.user_data(0);
+            let timeout_op = timeout_op.user_data(TIMEOUT_USER_DATA);
             timeouts += 1; // indicates submitting a timeout op
 
             unsafe {
@@ -171,9 +763,9 @@ impl Reactor {
     }
 
     fn requires_reaping(&self) -> bool {
-        let mutreq = unsafe { self.req_queued.get().as_mut().unwrap() };
+        let inflight = unsafe { self.inflight.get().as_ref().unwrap() };
 
-        *mutreq > 0
+        inflight.pending() > 0
     }
 
     fn flush_submissions(
@@ -217,29 +809,41 @@ impl Reactor {
         let mut timeouts = timeouts;
         let mut etime = etime;
 
-        let mutreq = unsafe { self.req_queued.get().as_mut().unwrap() };
         let mutself = unsafe { self.ring.get().as_mut().unwrap() };
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
 
         loop {
             for cqe in mutself.completion() {
                 let udata = cqe.user_data();
-                if udata == 0 {
-                    timeouts -= 1;
-                    if -cqe.result() == libc::ETIME {
-                        etime = true;
+                match udata {
+                    TIMEOUT_USER_DATA => {
+                        timeouts -= 1;
+                        if -cqe.result() == libc::ETIME {
+                            etime = true;
+                        }
                     }
-                } else {
-                    unsafe {
-                        let req = udata as *mut ReactorRequest;
-                        req.as_mut().unwrap().return_val = Some(cqe.result());
-                        req.as_ref().unwrap().waker.as_ref().unwrap().wake_by_ref();
+                    CANCEL_USER_DATA | LINK_TIMEOUT_USER_DATA => {}
+                    WAKE_USER_DATA => unsafe {
+                        self.wake_armed.get().replace(false);
+                    },
+                    _ => {
+                        let completion = Completion {
+                            result: cqe.result(),
+                            buf_id: io_uring::cqueue::buffer_select(cqe.flags()),
+                        };
+
+                        // The multishot ops keep going as long as there are
+                        // more completions to come
+                        if io_uring::cqueue::more(cqe.flags()) {
+                            inflight.push((udata - 1) as usize, completion);
+                        } else {
+                            inflight.complete_with((udata - 1) as usize, completion);
+                        }
+                        collected += 1;
                     }
-                    collected += 1;
                 }
             }
 
-            *mutreq -= collected;
-
             // Keep looping till we collect at least `want` completions
             if collected >= want {
                 return Ok((timeouts, etime));
@@ -248,6 +852,437 @@ impl Reactor {
     }
 }
 
-unsafe fn _make_static<T>(i: &T) -> &'static T {
+impl Backend for Reactor {
+    unsafe fn submit(&self, op: &Op) -> stdio::Result<usize> {
+        let inflight = self.inflight.get().as_mut().unwrap();
+
+        let Some(sentry) = Self::prepare(op) else {
+            return Ok(inflight.insert_completed(Self::execute(op)));
+        };
+
+        let token = inflight.insert();
+        let sentry = sentry.user_data(token as u64 + 1);
+
+        if let Err(err) = self.push(&[sentry]) {
+            inflight.abandon(token);
+            return Err(err);
+        }
+
+        Ok(token)
+    }
+
+    unsafe fn submit_with_timeout(&self, op: &Op, ts: *const Timespec) -> stdio::Result<usize> {
+        let inflight = self.inflight.get().as_mut().unwrap();
+
+        // The ops executed right away are never late
+        let Some(sentry) = Self::prepare(op) else {
+            return Ok(inflight.insert_completed(Self::execute(op)));
+        };
+
+        let token = inflight.insert();
+        let sentry = sentry
+            .user_data(token as u64 + 1)
+            .flags(squeue::Flags::IO_LINK);
+
+        // # Safety
+        // backend::Timespec has the layout of __kernel_timespec, just like
+        // types::Timespec.
+        let timeout_op = io_uring::opcode::LinkTimeout::new(ts as *const io_uring::types::Timespec)
+            .build()
+            .user_data(LINK_TIMEOUT_USER_DATA);
+
+        if let Err(err) = self.push(&[sentry, timeout_op]) {
+            inflight.abandon(token);
+            return Err(err);
+        }
+
+        Ok(token)
+    }
+
+    unsafe fn submit_multishot(&self, op: &Op) -> stdio::Result<usize> {
+        let inflight = self.inflight.get().as_mut().unwrap();
+
+        let Some(sentry) = Self::prepare(op) else {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidInput,
+                "op is not multishot",
+            ));
+        };
+
+        let token = inflight.insert_multishot();
+        let sentry = sentry.user_data(token as u64 + 1);
+
+        if let Err(err) = self.push(&[sentry]) {
+            inflight.abandon(token);
+            return Err(err);
+        }
+
+        Ok(token)
+    }
+
+    fn poll_op(&self, token: usize, ctx: &mut Context<'_>) -> Poll<i32> {
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
+        inflight.poll(token, ctx)
+    }
+
+    fn poll_next(&self, token: usize, ctx: &mut Context<'_>) -> Poll<Option<Completion>> {
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
+        inflight.poll_next(token, ctx)
+    }
+
+    fn cancel_op(&self, token: usize) {
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
+        if inflight.cancel(token) {
+            self.push_cancel(token);
+        }
+    }
+
+    fn cancel_multishot(&self, token: usize, discard: Box<dyn FnMut(Completion)>) {
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
+        if inflight.cancel_multishot(token, discard) {
+            self.push_cancel(token);
+        }
+    }
+
+    fn cancel_op_then(&self, token: usize, release: Box<dyn FnOnce()>) {
+        let inflight = unsafe { self.inflight.get().as_mut().unwrap() };
+        if inflight.cancel_then(token, release) {
+            self.push_cancel(token);
+        }
+    }
+
+    unsafe fn register_buffers(&self, bufs: &[libc::iovec]) -> stdio::Result<()> {
+        let mutring = self.ring.get().as_mut().unwrap();
+        mutring.submitter().register_buffers(bufs)
+    }
+
+    fn register_files(&self, count: u32) -> stdio::Result<()> {
+        let mutring = unsafe { self.ring.get().as_mut().unwrap() };
+        mutring.submitter().register_files_sparse(count)
+    }
+
+    unsafe fn register_buf_ring(
+        &self,
+        ring: *mut BufRingEntry,
+        entries: u16,
+        group: u16,
+    ) -> stdio::Result<()> {
+        let mutring = self.ring.get().as_mut().unwrap();
+        mutring
+            .submitter()
+            .register_buf_ring(ring as u64, entries, group)
+    }
+
+    fn poll_completions(&self) -> stdio::Result<()> {
+        self.flush(0, 0, false)?;
+        Ok(())
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> stdio::Result<()> {
+        self.arm_wake()?;
+
+        let mutring = unsafe { self.ring.get().as_mut().unwrap() };
+
+        let res = match timeout {
+            Some(timeout) => {
+                let ts = io_uring::types::Timespec::new()
+                    .sec(timeout.as_secs())
+                    .nsec(timeout.subsec_nanos());
+                let args = io_uring::types::SubmitArgs::new().timespec(&ts);
+
+                mutring.submitter().submit_with_args(1, &args)
+            }
+            None => mutring.submit_and_wait(1),
+        };
+
+        if let Err(err) = res {
+            match err.raw_os_error() {
+                Some(libc::ETIME) | Some(libc::EINTR) | Some(libc::EBUSY) | Some(libc::EAGAIN) => {}
+                _ => return Err(err),
+            }
+        }
+
+        self.flush_completions(0, 0, false)?;
+        Ok(())
+    }
+
+    fn wake(&self) {
+        // An eventfd takes 8 byte writes which add to its counter, a full
+        // counter (EAGAIN) is already a pending wake.
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                self.wake_fd,
+                &one as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+
+    fn inflight(&self) -> usize {
+        let inflight = unsafe { self.inflight.get().as_ref().unwrap() };
+        inflight.pending()
+    }
+
+    fn ring_fd(&self) -> Option<RawFd> {
+        let ring = unsafe { self.ring.get().as_ref().unwrap() };
+        Some(ring.as_raw_fd())
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.wake_fd);
+        }
+    }
+}
+
+unsafe fn _make_static<T: ?Sized>(i: &T) -> &'static T {
     std::mem::transmute(i)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, os::fd::FromRawFd, pin::pin, task::Waker};
+
+    use super::*;
+    use crate::{
+        aligned::AlignedBuf,
+        backend::CURRENT_POSITION,
+        io::File,
+        net::{TcpListner, TcpStream, UnixDatagram, UnixListener, UnixStream},
+    };
+
+    /// block_on drives the future on the reactor of the current thread
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let reactor = unsafe { PerThreadReactor::this() };
+        let mut ctx = Context::from_waker(Waker::noop());
+        let mut fut = pin!(fut);
+
+        loop {
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut ctx) {
+                return output;
+            }
+            reactor.wait(Some(Duration::from_millis(10))).unwrap();
+        }
+    }
+
+    /// pipe returns the read and the write end of a pipe
+    fn pipe() -> (File, RawFd) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        (unsafe { File::from_raw_fd(fds[0]) }, fds[1])
+    }
+
+    #[test]
+    fn dropped_read_waits_for_cancellation() {
+        let (file, tx) = pipe();
+        let reactor = unsafe { PerThreadReactor::this() };
+        let mut ctx = Context::from_waker(Waker::noop());
+
+        let mut buf = [0u8; 4];
+        {
+            let mut read = pin!(file.read(&mut buf));
+            assert!(read.as_mut().poll(&mut ctx).is_pending());
+            PerThreadReactor::flush().unwrap();
+            assert_eq!(reactor.inflight(), 1);
+        }
+        // The kernel is done with the buffer once the read is dropped
+        assert_eq!(reactor.inflight(), 0);
+
+        assert_eq!(unsafe { libc::write(tx, b"data".as_ptr() as *const _, 4) }, 4);
+        assert_eq!(buf, [0; 4]);
+        unsafe { libc::close(tx) };
+    }
+
+    #[test]
+    fn dropped_deadline_keeps_its_timeout() {
+        let (file, tx) = pipe();
+        let reactor = unsafe { PerThreadReactor::this() };
+        let mut ctx = Context::from_waker(Waker::noop());
+
+        // The read is not flushed, the kernel reads the timespec of the
+        // linked timeout only once the dropped request reaches it
+        let buf: &'static mut [u8; 4] = Box::leak(Box::new([0; 4]));
+        let mut req = ReactorRequest::new(Op::Read {
+            fd: Fd::Raw(file.as_raw_fd()),
+            buf: buf.as_mut_ptr(),
+            len: buf.len() as u32,
+            offset: CURRENT_POSITION,
+        });
+        req.set_deadline(reactor.clock() + Duration::from_secs(60));
+        assert!(unsafe { req.poll(reactor, &mut ctx) }.is_pending());
+        req.cancel(reactor);
+        drop(req);
+
+        // A freed timespec would be handed out again and garbled
+        let scribble = Box::new(Timespec {
+            tv_sec: -1,
+            tv_nsec: -1,
+        });
+
+        PerThreadReactor::flush().unwrap();
+        while reactor.inflight() > 0 {
+            reactor.wait(Some(Duration::from_millis(10))).unwrap();
+        }
+        drop(scribble);
+
+        // The reactor keeps working, the next deadline fires
+        let mut buf = [0u8; 4];
+        let res = block_on(
+            file.read(&mut buf)
+                .with_deadline(reactor.clock() + Duration::from_millis(10)),
+        );
+        assert_eq!(res.unwrap_err().kind(), stdio::ErrorKind::TimedOut);
+
+        unsafe { libc::close(tx) };
+    }
+
+    #[test]
+    fn send_zc_waits_for_the_notification() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut stream = block_on(TcpStream::connect(&addr)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        // The kernel posts the result of the send first, the buffer is
+        // handed back only once the notification releasing it arrives
+        let data: Vec<u8> = (0..16384).map(|idx| idx as u8).collect();
+        let (res, buf) = block_on(stream.send_zc(data.clone()));
+        assert_eq!(res.unwrap(), data.len());
+        assert_eq!(buf, data);
+
+        let reactor = unsafe { PerThreadReactor::this() };
+        assert_eq!(reactor.inflight(), 0);
+
+        let mut received = vec![0; data.len()];
+        std::io::Read::read_exact(&mut peer, &mut received).unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn connect_over_ipv6() {
+        // The port is free once the probe is gone
+        let probe = std::net::TcpListener::bind("[::1]:0").unwrap();
+        let addr = probe.local_addr().unwrap().to_string();
+        drop(probe);
+
+        let listener = block_on(TcpListner::bind(&addr, 16)).unwrap();
+        let mut client = block_on(TcpStream::connect(&addr)).unwrap();
+        let server = block_on(listener.accept()).unwrap();
+
+        block_on(client.send(b"hello")).unwrap();
+        let mut buf = [0; 8];
+        let n = block_on(server.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    /// temp_path returns a path in the temporary directory for a file or a
+    /// unix socket of the test, which must not exist yet.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("reika-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn unix_stream_passes_fds() {
+        let path = temp_path("stream");
+        let listener = block_on(UnixListener::bind(&path, 16)).unwrap();
+        let mut client = block_on(UnixStream::connect(&path)).unwrap();
+        let server = block_on(listener.accept()).unwrap();
+
+        let (file, tx) = pipe();
+        assert_eq!(block_on(client.send_with_fds(b"fd", &[tx])).unwrap(), 2);
+        unsafe { libc::close(tx) };
+
+        let mut buf = [0; 8];
+        let (n, fds) = block_on(server.recv_with_fds(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"fd");
+        assert_eq!(fds.len(), 1);
+
+        // The received descriptor refers to the same pipe
+        assert_eq!(
+            unsafe { libc::write(fds[0], b"data".as_ptr() as *const _, 4) },
+            4
+        );
+        unsafe { libc::close(fds[0]) };
+
+        let mut buf = [0; 8];
+        let n = block_on(file.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"data");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unix_datagram_reports_the_sender() {
+        let server_path = temp_path("dgram-server");
+        let client_path = temp_path("dgram-client");
+        let server = block_on(UnixDatagram::bind(&server_path)).unwrap();
+        let client = block_on(UnixDatagram::bind(&client_path)).unwrap();
+        let unbound = block_on(UnixDatagram::unbound()).unwrap();
+
+        let mut buf = [0; 8];
+        block_on(client.send_to(b"named", &server_path)).unwrap();
+        let (n, from) = block_on(server.recv_from(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"named");
+        assert_eq!(from.unwrap().to_str(), Some(client_path.as_str()));
+
+        block_on(unbound.send_to(b"anon", &server_path)).unwrap();
+        let (n, from) = block_on(server.recv_from(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"anon");
+        assert!(from.is_none());
+
+        // The descriptors travel along the datagrams as well
+        block_on(client.connect(&server_path)).unwrap();
+        let (file, tx) = pipe();
+        block_on(client.send_with_fds(b"fd", &[tx])).unwrap();
+        unsafe { libc::close(tx) };
+        let (_, fds) = block_on(server.recv_with_fds(&mut buf)).unwrap();
+        assert_eq!(fds.len(), 1);
+        unsafe { libc::close(fds[0]) };
+
+        // The pipe is closed once every write end is gone
+        assert_eq!(block_on(file.read(&mut buf)).unwrap(), 0);
+
+        let _ = std::fs::remove_file(&server_path);
+        let _ = std::fs::remove_file(&client_path);
+    }
+
+    #[test]
+    fn direct_io_round_trip() {
+        let path = temp_path("direct");
+        let opened = block_on(
+            File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .direct(true)
+                .open(&path),
+        );
+        // Not every filesystem supports direct I/O, tmpfs does not
+        let file = match opened {
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return,
+            res => res.unwrap(),
+        };
+        let align = file.alignment().unwrap();
+
+        let mut buf = AlignedBuf::for_file(&file, 1);
+        assert_eq!(buf.len(), align.offset);
+        buf.fill(b'd');
+        assert_eq!(block_on(file.write_at(&buf, 0)).unwrap(), buf.len());
+
+        // The misaligned read is turned down before the kernel sees it
+        let err = block_on(file.read_at(&mut buf[1..], 0)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        buf.fill(0);
+        assert_eq!(block_on(file.read_at(&mut buf, 0)).unwrap(), buf.len());
+        assert!(buf.iter().all(|b| *b == b'd'));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,137 @@
+//! aligned implements the buffers of the direct I/O, which bypasses the page
+//! cache and hence needs aligned memory, see
+//! [crate::io::OpenOptions::direct].
+//!
+//! ```ignore
+//! let file = File::options().read(true).direct(true).open(path).await?;
+//! let mut buf = AlignedBuf::for_file(&file, 64 * 1024);
+//! let n = file.read_at(&mut buf, 0).await?;
+//! ```
+
+use std::{
+    alloc::{self, Layout},
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use crate::io::File;
+
+/// AlignedBuf is a zeroed heap buffer whose memory is aligned.
+///
+/// Like a [crate::fixed::FixedBuf] it dereferences to its first `len` bytes,
+/// the length is the full buffer once allocated.
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+}
+
+impl AlignedBuf {
+    /// new allocates a buffer of `capacity` bytes aligned to `align`
+    ///
+    /// # Panics
+    /// Panics if the capacity is 0 or if the alignment is not a power of two.
+    pub fn new(capacity: usize, align: usize) -> Self {
+        assert!(capacity > 0, "buffer must not be empty");
+
+        let layout = Layout::from_size_align(capacity, align).expect("invalid alignment");
+
+        // # Safety
+        // The layout is non-zero sized.
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            alloc::handle_alloc_error(layout);
+        };
+
+        Self {
+            ptr,
+            len: capacity,
+            layout,
+        }
+    }
+
+    /// for_file allocates a buffer fit for the direct I/O of the file, the
+    /// capacity is rounded up to a multiple of the offset alignment. A file
+    /// not opened for direct I/O gets a page aligned buffer.
+    pub fn for_file(file: &File, capacity: usize) -> Self {
+        let align = file.alignment().unwrap_or_default();
+
+        Self::new(capacity.max(1).next_multiple_of(align.offset), align.mem)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn align(&self) -> usize {
+        self.layout.align()
+    }
+
+    /// set_len sets the number of bytes the buffer dereferences to
+    ///
+    /// # Panics
+    /// Panics if the length is larger than the capacity.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "length exceeds the capacity");
+        self.len = len;
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // # Safety
+        // The memory is allocated and zeroed up to the capacity.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // # Safety
+        // The memory is allocated and zeroed up to the capacity.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // # Safety
+        // The memory was allocated with the layout.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_is_aligned_and_zeroed() {
+        for align in [512, 4096, 1 << 16] {
+            let buf = AlignedBuf::new(100, align);
+            assert!((buf.as_ptr() as usize).is_multiple_of(align));
+            assert_eq!(buf.align(), align);
+            assert_eq!(buf.capacity(), 100);
+            assert_eq!(&*buf, &[0; 100]);
+        }
+    }
+
+    #[test]
+    fn set_len_bounds_the_data() {
+        let mut buf = AlignedBuf::new(8, 8);
+        buf.copy_from_slice(b"aligned!");
+
+        buf.set_len(7);
+        assert_eq!(&*buf, b"aligned");
+        buf.set_len(8);
+        assert_eq!(&*buf, b"aligned!");
+    }
+
+    #[test]
+    #[should_panic(expected = "length exceeds the capacity")]
+    fn set_len_past_the_capacity_panics() {
+        AlignedBuf::new(8, 8).set_len(9);
+    }
+}
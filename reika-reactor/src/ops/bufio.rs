@@ -0,0 +1,431 @@
+//! bufio implements buffering on top of the [AsyncReadRent] and
+//! [AsyncWriteRent] types, along with the helpers reading lines and exact
+//! amounts of data.
+//!
+//! ```ignore
+//! let mut reader = BufReader::new(file);
+//! let mut line = String::new();
+//! while reader.read_line(&mut line).await? > 0 {
+//!     // ...
+//!     line.clear();
+//! }
+//! ```
+
+use std::{future::Future, io as stdio};
+
+use crate::rent::{AsyncReadRent, AsyncWriteRent, BufResult, IoBuf, IoBufMut};
+
+/// DEFAULT_BUF_SIZE is the capacity of the buffers unless told otherwise
+pub const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// BufReader reads ahead from the inner reader in large chunks, rather than
+/// issuing an op for every small read.
+pub struct BufReader<R> {
+    inner: R,
+    /// buf holds the data read ahead, the part past `pos` is yet to be
+    /// consumed. It is None while it is rented to the inner reader.
+    buf: Option<Vec<u8>>,
+    pos: usize,
+    capacity: usize,
+}
+
+impl<R: AsyncReadRent> BufReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        Self {
+            inner,
+            buf: Some(Vec::with_capacity(capacity)),
+            pos: 0,
+            capacity,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// into_inner returns the inner reader, the buffered data is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// buffer returns the buffered data which is yet to be consumed
+    pub fn buffer(&self) -> &[u8] {
+        match &self.buf {
+            Some(buf) => &buf[self.pos..],
+            None => &[],
+        }
+    }
+
+    /// fill_buf returns the buffered data, reading more from the inner reader
+    /// if it has all been consumed. It is empty once the reader has reached
+    /// its end.
+    pub async fn fill_buf(&mut self) -> stdio::Result<&[u8]> {
+        if self.buffer().is_empty() {
+            let mut buf = self
+                .buf
+                .take()
+                .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+            buf.clear();
+            self.pos = 0;
+
+            let (res, buf) = self.inner.read(buf).await;
+            self.buf = Some(buf);
+            res?;
+        }
+
+        Ok(self.buffer())
+    }
+
+    /// consume marks `amt` bytes of the buffered data as consumed
+    pub fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.as_ref().map_or(0, Vec::len));
+    }
+
+    /// read_until reads into `out` till the delimiter (included) or the end
+    /// of the reader, it returns the number of bytes read.
+    pub async fn read_until(&mut self, delim: u8, out: &mut Vec<u8>) -> stdio::Result<usize> {
+        let mut read = 0;
+
+        loop {
+            let available = self.fill_buf().await?;
+            if available.is_empty() {
+                return Ok(read);
+            }
+
+            match available.iter().position(|b| *b == delim) {
+                Some(i) => {
+                    out.extend_from_slice(&available[..=i]);
+                    self.consume(i + 1);
+                    return Ok(read + i + 1);
+                }
+                None => {
+                    let n = available.len();
+                    out.extend_from_slice(available);
+                    self.consume(n);
+                    read += n;
+                }
+            }
+        }
+    }
+
+    /// read_line reads a line into `out`, the newline included. It returns
+    /// the number of bytes read, 0 once the reader has reached its end. Like
+    /// std, `out` is left as it was if the line is not valid UTF-8 or if the
+    /// read fails.
+    pub async fn read_line(&mut self, out: &mut String) -> stdio::Result<usize> {
+        // # Safety
+        // The guard truncates the bytes appended unless they are valid UTF-8.
+        let mut guard = Utf8Guard {
+            len: out.len(),
+            buf: unsafe { out.as_mut_vec() },
+        };
+
+        let n = self.read_until(b'\n', guard.buf).await?;
+        if std::str::from_utf8(&guard.buf[guard.len..]).is_err() {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            ));
+        }
+
+        guard.len = guard.buf.len();
+        Ok(n)
+    }
+
+    /// read_exact fills `out`, it fails with
+    /// [stdio::ErrorKind::UnexpectedEof] if the reader ends before.
+    pub async fn read_exact(&mut self, out: &mut [u8]) -> stdio::Result<()> {
+        let mut filled = 0;
+
+        while filled < out.len() {
+            let available = self.fill_buf().await?;
+            if available.is_empty() {
+                return Err(stdio::ErrorKind::UnexpectedEof.into());
+            }
+
+            let n = available.len().min(out.len() - filled);
+            out[filled..filled + n].copy_from_slice(&available[..n]);
+            self.consume(n);
+            filled += n;
+        }
+
+        Ok(())
+    }
+}
+
+/// Utf8Guard truncates the string bytes back to `len` once dropped, the
+/// bytes past it are yet to be validated.
+struct Utf8Guard<'a> {
+    len: usize,
+    buf: &'a mut Vec<u8>,
+}
+
+impl Drop for Utf8Guard<'_> {
+    fn drop(&mut self) {
+        self.buf.truncate(self.len);
+    }
+}
+
+impl<R: AsyncReadRent> AsyncReadRent for BufReader<R> {
+    async fn read<B: IoBufMut>(&mut self, mut buf: B) -> BufResult<usize, B> {
+        let available = match self.fill_buf().await {
+            Ok(available) => available,
+            Err(err) => return (Err(err), buf),
+        };
+
+        let n = available.len().min(buf.bytes_total());
+        // # Safety
+        // The buffer has room for `n` bytes, which are then initialized.
+        unsafe {
+            std::ptr::copy_nonoverlapping(available.as_ptr(), buf.write_ptr(), n);
+            buf.set_init(n);
+        }
+        self.consume(n);
+
+        (Ok(n), buf)
+    }
+}
+
+/// BufWriter gathers the small writes and writes them out to the inner writer
+/// in large chunks, once its buffer is full or once it is flushed.
+///
+/// NOTE: The buffered data is lost unless the writer is flushed, see
+/// [BufWriter::flush].
+pub struct BufWriter<W> {
+    inner: W,
+    /// buf holds the data yet to be written out, it is None while it is
+    /// rented to the inner writer.
+    buf: Option<Vec<u8>>,
+    capacity: usize,
+}
+
+impl<W: AsyncWriteRent> BufWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner,
+            buf: Some(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// into_inner returns the inner writer, the buffered data is lost
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// buffer returns the data yet to be written out
+    pub fn buffer(&self) -> &[u8] {
+        self.buf.as_deref().unwrap_or_default()
+    }
+
+    /// write_all buffers the data, the buffered data is written out first if
+    /// the data does not fit. Data larger than the buffer is written out
+    /// straight away.
+    pub async fn write_all(&mut self, data: &[u8]) -> stdio::Result<()> {
+        if self.buffer().len() + data.len() > self.capacity {
+            self.flush_buf().await?;
+        }
+
+        if data.len() >= self.capacity {
+            let (res, _) = self.inner.write_all(data.to_vec()).await;
+            return res;
+        }
+
+        self.buf
+            .get_or_insert_with(|| Vec::with_capacity(self.capacity))
+            .extend_from_slice(data);
+        Ok(())
+    }
+
+    /// flush writes out the buffered data and flushes the inner writer
+    pub async fn flush(&mut self) -> stdio::Result<()> {
+        self.flush_buf().await?;
+        self.inner.flush().await
+    }
+
+    /// flush_buf writes out the buffered data
+    async fn flush_buf(&mut self) -> stdio::Result<()> {
+        if self.buffer().is_empty() {
+            return Ok(());
+        }
+
+        let buf = self.buf.take().unwrap_or_default();
+        let (res, mut buf) = self.inner.write_all(buf).await;
+
+        // The data is dropped even if the write failed, like the kernel
+        // would drop it midway.
+        buf.clear();
+        self.buf = Some(buf);
+
+        res
+    }
+}
+
+impl<W: AsyncWriteRent> AsyncWriteRent for BufWriter<W> {
+    async fn write<B: IoBuf>(&mut self, buf: B) -> BufResult<usize, B> {
+        // # Safety
+        // The data of the buffer is initialized.
+        let data = unsafe { std::slice::from_raw_parts(buf.read_ptr(), buf.bytes_init()) };
+
+        let res = BufWriter::write_all(self, data).await.map(|_| data.len());
+        (res, buf)
+    }
+
+    fn flush(&mut self) -> impl Future<Output = stdio::Result<()>> {
+        BufWriter::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+
+    /// Script hands out its chunks one read at a time, a None chunk fails
+    /// the read. It keeps the writes made to it.
+    #[derive(Default)]
+    struct Script {
+        chunks: VecDeque<Option<Vec<u8>>>,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl Script {
+        fn new(chunks: &[Option<&[u8]>]) -> Self {
+            Self {
+                chunks: chunks
+                    .iter()
+                    .map(|chunk| chunk.map(<[u8]>::to_vec))
+                    .collect(),
+                writes: Vec::new(),
+            }
+        }
+    }
+
+    impl AsyncReadRent for Script {
+        fn read<B: IoBufMut>(&mut self, mut buf: B) -> impl Future<Output = BufResult<usize, B>> {
+            let res = match self.chunks.pop_front().unwrap_or(Some(Vec::new())) {
+                Some(chunk) => {
+                    let n = chunk.len().min(buf.bytes_total());
+                    if n < chunk.len() {
+                        self.chunks.push_front(Some(chunk[n..].to_vec()));
+                    }
+
+                    // # Safety
+                    // The buffer has room for `n` bytes.
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(chunk.as_ptr(), buf.write_ptr(), n);
+                        buf.set_init(n);
+                    }
+                    Ok(n)
+                }
+                None => Err(stdio::ErrorKind::ConnectionReset.into()),
+            };
+
+            std::future::ready((res, buf))
+        }
+    }
+
+    impl AsyncWriteRent for Script {
+        fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+            // # Safety
+            // The data of the buffer is initialized.
+            let data = unsafe { std::slice::from_raw_parts(buf.read_ptr(), buf.bytes_init()) };
+            self.writes.push(data.to_vec());
+
+            std::future::ready((Ok(data.len()), buf))
+        }
+    }
+
+    /// ready returns the output of a future which completes on its first poll
+    fn ready<F: Future>(fut: F) -> F::Output {
+        let mut ctx = Context::from_waker(Waker::noop());
+        match pin!(fut).poll(&mut ctx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future is not ready"),
+        }
+    }
+
+    #[test]
+    fn read_line_spans_reads() {
+        let script = Script::new(&[Some(b"fir"), Some(b"st\nsecond"), Some(b"\nlast")]);
+        let mut reader = BufReader::with_capacity(4, script);
+        let mut line = String::new();
+
+        assert_eq!(ready(reader.read_line(&mut line)).unwrap(), 6);
+        assert_eq!(ready(reader.read_line(&mut line)).unwrap(), 7);
+        assert_eq!(ready(reader.read_line(&mut line)).unwrap(), 4);
+        assert_eq!(ready(reader.read_line(&mut line)).unwrap(), 0);
+        assert_eq!(line, "first\nsecond\nlast");
+    }
+
+    #[test]
+    fn read_line_leaves_out_as_it_was() {
+        let script = Script::new(&[Some(b"ok\nbad\xff\n"), Some(b"cut"), None]);
+        let mut reader = BufReader::new(script);
+        let mut line = String::from("kept:");
+
+        assert_eq!(ready(reader.read_line(&mut line)).unwrap(), 3);
+        let err = ready(reader.read_line(&mut line)).unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidData);
+        let err = ready(reader.read_line(&mut line)).unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::ConnectionReset);
+        assert_eq!(line, "kept:ok\n");
+    }
+
+    #[test]
+    fn read_exact_fails_at_the_end() {
+        let script = Script::new(&[Some(b"ab"), Some(b"cd"), Some(b"e")]);
+        let mut reader = BufReader::new(script);
+
+        let mut out = [0; 4];
+        ready(reader.read_exact(&mut out)).unwrap();
+        assert_eq!(&out, b"abcd");
+        let err = ready(reader.read_exact(&mut out)).unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn writes_are_gathered_till_the_buffer_is_full() {
+        let mut writer = BufWriter::with_capacity(8, Script::default());
+
+        ready(writer.write_all(b"abc")).unwrap();
+        ready(writer.write_all(b"def")).unwrap();
+        assert!(writer.get_ref().writes.is_empty());
+
+        // The data no longer fits, the buffered data goes out first.
+        ready(writer.write_all(b"ghi")).unwrap();
+        // Data larger than the buffer goes straight out.
+        ready(writer.write_all(b"0123456789")).unwrap();
+        ready(writer.write_all(b"j")).unwrap();
+        assert_eq!(writer.buffer(), b"j");
+
+        ready(writer.flush()).unwrap();
+        let writes: Vec<&[u8]> = writer.get_ref().writes.iter().map(Vec::as_slice).collect();
+        assert_eq!(writes, [&b"abcdef"[..], b"ghi", b"0123456789", b"j"]);
+    }
+}
@@ -0,0 +1,206 @@
+//! bufring implements rings of buffers provided to the reactor. Rather than
+//! every pending receive holding on to a buffer of its own, the kernel picks
+//! a buffer of the ring once the data arrives, see
+//! [crate::net::TcpStream::recv_stream].
+
+use std::{
+    alloc::{self, Layout},
+    cell::{Cell, RefCell},
+    io as stdio,
+    ops::{Deref, DerefMut},
+    sync::atomic::Ordering,
+    task::Waker,
+};
+
+use crate::{backend::BufRingEntry, PerThreadReactor};
+
+/// RING_ALIGN is the alignment of the ring and of the buffers, the kernel
+/// wants the ring to be page aligned.
+const RING_ALIGN: usize = 4096;
+
+/// BufRing is a ring of buffers registered with the reactor of a thread as a
+/// buffer group. The buffers picked by the kernel are leased as [BufLease]
+/// and go back to the ring once dropped.
+pub struct BufRing {
+    ring: *mut BufRingEntry,
+    mem: *mut u8,
+    buf_size: usize,
+    count: u16,
+    group: u16,
+
+    /// tail is the local copy of the tail of the ring
+    tail: Cell<u16>,
+    /// leased is the number of buffers leased out
+    leased: Cell<u16>,
+    /// waiters are woken once a leased buffer is put back in the ring
+    waiters: RefCell<Vec<Waker>>,
+}
+
+impl BufRing {
+    /// register allocates `count` buffers of `size` bytes and registers them
+    /// with the reactor of the current thread as the buffer group `group`.
+    /// `count` must be a power of two, at most 32768.
+    ///
+    /// NOTE: Like the registration, the ring lives as long as the thread.
+    pub fn register(group: u16, count: u16, size: usize) -> stdio::Result<&'static BufRing> {
+        assert!(count.is_power_of_two(), "count must be a power of two");
+        assert!(size > 0 && size <= u32::MAX as usize, "invalid buffer size");
+
+        let ring_layout = Layout::array::<BufRingEntry>(count as usize)
+            .and_then(|layout| layout.align_to(RING_ALIGN))
+            .map_err(|err| stdio::Error::new(stdio::ErrorKind::InvalidInput, err))?;
+        let mem_layout = Layout::from_size_align(count as usize * size, RING_ALIGN)
+            .map_err(|err| stdio::Error::new(stdio::ErrorKind::InvalidInput, err))?;
+
+        // # Safety
+        // The layouts are non-zero sized.
+        let ring = unsafe { alloc::alloc_zeroed(ring_layout) } as *mut BufRingEntry;
+        if ring.is_null() {
+            alloc::handle_alloc_error(ring_layout);
+        }
+        let mem = unsafe { alloc::alloc_zeroed(mem_layout) };
+        if mem.is_null() {
+            alloc::handle_alloc_error(mem_layout);
+        }
+
+        let bufring = BufRing {
+            ring,
+            mem,
+            buf_size: size,
+            count,
+            group,
+            tail: Cell::new(0),
+            leased: Cell::new(0),
+            waiters: RefCell::new(Vec::new()),
+        };
+
+        (0..count).for_each(|bid| bufring.provide(bid));
+
+        // # Safety
+        // The memory is leaked along with the ring once registered.
+        let reactor = unsafe { PerThreadReactor::this() };
+        if let Err(err) = unsafe { reactor.register_buf_ring(ring, count, group) } {
+            unsafe {
+                alloc::dealloc(ring as *mut u8, ring_layout);
+                alloc::dealloc(mem, mem_layout);
+            }
+            return Err(err);
+        }
+
+        Ok(Box::leak(Box::new(bufring)))
+    }
+
+    /// group returns the buffer group the ring is registered as
+    pub fn group(&self) -> u16 {
+        self.group
+    }
+
+    /// buf_size returns the size of every buffer
+    pub fn buf_size(&self) -> usize {
+        self.buf_size
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    /// lease hands out the buffer picked by the kernel, `len` being the
+    /// number of bytes it received into it.
+    pub(crate) fn lease(&'static self, bid: u16, len: usize) -> BufLease {
+        assert!(bid < self.count && len <= self.buf_size, "invalid buffer");
+
+        self.leased.set(self.leased.get() + 1);
+        BufLease {
+            ring: self,
+            bid,
+            len,
+        }
+    }
+
+    /// exhausted returns true while every buffer of the ring is leased out,
+    /// the kernel has none to pick then. The buffers picked for the
+    /// completions which are yet to be polled are not leased out yet.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.leased.get() == self.count
+    }
+
+    /// wait wakes the waker once a leased buffer is put back in the ring
+    pub(crate) fn wait(&self, waker: &Waker) {
+        let mut waiters = self.waiters.borrow_mut();
+        if !waiters.iter().any(|waiter| waiter.will_wake(waker)) {
+            waiters.push(waker.clone());
+        }
+    }
+
+    /// provide adds the buffer at the tail of the ring
+    pub(crate) fn provide(&self, bid: u16) {
+        let tail = self.tail.get();
+
+        // # Safety
+        // The entry lies within the ring, the first entry is written field
+        // by field as its `resv` is the tail of the ring.
+        unsafe {
+            let entry = self.ring.add((tail & (self.count - 1)) as usize);
+            (*entry).addr = self.buf_ptr(bid) as u64;
+            (*entry).len = self.buf_size as u32;
+            (*entry).bid = bid;
+        }
+
+        let tail = tail.wrapping_add(1);
+        self.tail.set(tail);
+
+        // The kernel must see the entry before the tail moves past it
+        unsafe { BufRingEntry::tail(self.ring) }.store(tail, Ordering::Release);
+    }
+
+    fn buf_ptr(&self, bid: u16) -> *mut u8 {
+        // # Safety
+        // The buffer lies within the memory of the ring.
+        unsafe { self.mem.add(bid as usize * self.buf_size) }
+    }
+}
+
+/// BufLease is a buffer of a [BufRing] holding received data, it dereferences
+/// to the received bytes.
+pub struct BufLease {
+    ring: &'static BufRing,
+    bid: u16,
+    len: usize,
+}
+
+impl BufLease {
+    /// bid returns the id of the buffer within the ring
+    pub fn bid(&self) -> u16 {
+        self.bid
+    }
+}
+
+impl Deref for BufLease {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // # Safety
+        // The buffer is leased, the kernel does not pick it till it is back
+        // in the ring.
+        unsafe { std::slice::from_raw_parts(self.ring.buf_ptr(self.bid), self.len) }
+    }
+}
+
+impl DerefMut for BufLease {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // # Safety
+        // The buffer is leased, the kernel does not pick it till it is back
+        // in the ring.
+        unsafe { std::slice::from_raw_parts_mut(self.ring.buf_ptr(self.bid), self.len) }
+    }
+}
+
+impl Drop for BufLease {
+    fn drop(&mut self) {
+        self.ring.leased.set(self.ring.leased.get() - 1);
+        self.ring.provide(self.bid);
+
+        let waiters = self.ring.waiters.take();
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
@@ -0,0 +1,460 @@
+//! codec splits a stream of bytes into frames and back, see [Framed].
+//!
+//! ```ignore
+//! let mut framed = Framed::new(stream, LengthDelimitedCodec::new());
+//! while let Some(frame) = framed.next().await {
+//!     framed.send(frame?).await?;
+//! }
+//! ```
+
+use std::io as stdio;
+
+use crate::rent::{AsyncReadRent, AsyncWriteRent};
+
+/// READ_CHUNK is the amount of data [Framed] reads at once
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Decoder decodes the frames of a stream of bytes
+pub trait Decoder {
+    type Item;
+
+    /// decode decodes a frame from the start of `src`, it returns the frame
+    /// along with the number of bytes it spans or None if `src` does not
+    /// hold a whole frame yet.
+    fn decode(&mut self, src: &[u8]) -> stdio::Result<Option<(Self::Item, usize)>>;
+
+    /// decode_eof is [Decoder::decode] once the stream has reached its end.
+    /// By default the bytes which do not make a whole frame are an error.
+    fn decode_eof(&mut self, src: &[u8]) -> stdio::Result<Option<(Self::Item, usize)>> {
+        match self.decode(src)? {
+            None if !src.is_empty() => Err(stdio::Error::new(
+                stdio::ErrorKind::UnexpectedEof,
+                "stream ended within a frame",
+            )),
+            frame => Ok(frame),
+        }
+    }
+}
+
+/// Encoder encodes the frames into a stream of bytes
+pub trait Encoder<Item> {
+    /// encode appends the encoded frame to `dst`
+    fn encode(&mut self, item: Item, dst: &mut Vec<u8>) -> stdio::Result<()>;
+}
+
+/// Framed reads and writes the frames of the codec over a stream
+pub struct Framed<T, C> {
+    io: T,
+    codec: C,
+
+    /// read_buf holds the data read, the part past `read_pos` is yet to be
+    /// decoded.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    eof: bool,
+
+    /// write_buf holds the encoded frames yet to be written out
+    write_buf: Vec<u8>,
+}
+
+impl<T, C> Framed<T, C> {
+    pub fn new(io: T, codec: C) -> Self {
+        Self {
+            io,
+            codec,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            eof: false,
+            write_buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// into_inner returns the stream, the data which is yet to be decoded or
+    /// written out is lost.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: AsyncReadRent, C: Decoder> Framed<T, C> {
+    /// next reads the next frame, None once the stream has reached its end
+    pub async fn next(&mut self) -> Option<stdio::Result<C::Item>> {
+        loop {
+            let src = &self.read_buf[self.read_pos..];
+            let decoded = if self.eof {
+                self.codec.decode_eof(src)
+            } else {
+                self.codec.decode(src)
+            };
+
+            match decoded {
+                Ok(Some((frame, n))) => {
+                    self.read_pos += n;
+                    return Some(Ok(frame));
+                }
+                Ok(None) if self.eof => return None,
+                Ok(None) => {}
+                Err(err) => {
+                    // The rest of the stream cannot be decoded anymore
+                    self.eof = true;
+                    self.read_buf.clear();
+                    self.read_pos = 0;
+                    return Some(Err(err));
+                }
+            }
+
+            if let Err(err) = self.read_more().await {
+                return Some(Err(err));
+            }
+        }
+    }
+
+    /// read_more appends the next chunk of the stream to the read buffer
+    async fn read_more(&mut self) -> stdio::Result<()> {
+        self.read_buf.drain(..self.read_pos);
+        self.read_pos = 0;
+
+        let (res, chunk) = self.io.read(Vec::with_capacity(READ_CHUNK)).await;
+        match res? {
+            0 => self.eof = true,
+            _ => self.read_buf.extend_from_slice(&chunk),
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: AsyncWriteRent, C> Framed<T, C> {
+    /// send encodes the frame and writes it out along with the frames fed
+    /// before.
+    pub async fn send<I>(&mut self, item: I) -> stdio::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.feed(item)?;
+        self.flush().await
+    }
+
+    /// feed encodes the frame without writing it out, see [Framed::flush]
+    pub fn feed<I>(&mut self, item: I) -> stdio::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.codec.encode(item, &mut self.write_buf)
+    }
+
+    /// flush writes out the frames fed so far
+    pub async fn flush(&mut self) -> stdio::Result<()> {
+        if !self.write_buf.is_empty() {
+            let buf = std::mem::take(&mut self.write_buf);
+            let (res, mut buf) = self.io.write_all(buf).await;
+
+            buf.clear();
+            self.write_buf = buf;
+            res?;
+        }
+
+        self.io.flush().await
+    }
+}
+
+/// LinesCodec splits the stream into lines, without their trailing `\n` or
+/// `\r\n`. The last line of the stream may lack the newline.
+#[derive(Debug, Clone, Copy)]
+pub struct LinesCodec {
+    max_length: usize,
+}
+
+impl LinesCodec {
+    pub fn new() -> Self {
+        Self {
+            max_length: usize::MAX,
+        }
+    }
+
+    /// with_max_length bounds the length of the lines, a longer line fails
+    /// the decoding rather than being buffered without end.
+    pub fn with_max_length(max_length: usize) -> Self {
+        Self { max_length }
+    }
+
+    fn line(&self, line: &[u8]) -> stdio::Result<String> {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        String::from_utf8(line.to_vec())
+            .map_err(|err| stdio::Error::new(stdio::ErrorKind::InvalidData, err))
+    }
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+
+    fn decode(&mut self, src: &[u8]) -> stdio::Result<Option<(String, usize)>> {
+        match src.iter().position(|b| *b == b'\n') {
+            Some(i) if i <= self.max_length => Ok(Some((self.line(&src[..i])?, i + 1))),
+            None if src.len() <= self.max_length => Ok(None),
+            _ => Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidData,
+                "line exceeds the max length",
+            )),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &[u8]) -> stdio::Result<Option<(String, usize)>> {
+        match self.decode(src)? {
+            None if !src.is_empty() => Ok(Some((self.line(src)?, src.len()))),
+            line => Ok(line),
+        }
+    }
+}
+
+impl Encoder<&str> for LinesCodec {
+    fn encode(&mut self, line: &str, dst: &mut Vec<u8>) -> stdio::Result<()> {
+        dst.extend_from_slice(line.as_bytes());
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+impl Encoder<String> for LinesCodec {
+    fn encode(&mut self, line: String, dst: &mut Vec<u8>) -> stdio::Result<()> {
+        self.encode(line.as_str(), dst)
+    }
+}
+
+/// LENGTH_PREFIX is the size of the big-endian length prepended to the frames
+/// of [LengthDelimitedCodec].
+const LENGTH_PREFIX: usize = 4;
+
+/// LengthDelimitedCodec frames the data with its length, as a 4 bytes big
+/// endian prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthDelimitedCodec {
+    max_frame_length: usize,
+}
+
+impl LengthDelimitedCodec {
+    /// new returns the codec with frames of at most 8 MiB
+    pub fn new() -> Self {
+        Self::with_max_frame_length(8 * 1024 * 1024)
+    }
+
+    /// with_max_frame_length bounds the length of the frames, a longer frame
+    /// fails the decoding (or the encoding).
+    pub fn with_max_frame_length(max_frame_length: usize) -> Self {
+        assert!(
+            max_frame_length <= u32::MAX as usize,
+            "frames are at most u32::MAX long"
+        );
+        Self { max_frame_length }
+    }
+
+    fn check(&self, len: usize) -> stdio::Result<()> {
+        if len > self.max_frame_length {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidData,
+                "frame exceeds the max length",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, src: &[u8]) -> stdio::Result<Option<(Vec<u8>, usize)>> {
+        let Some(prefix) = src.first_chunk::<LENGTH_PREFIX>() else {
+            return Ok(None);
+        };
+
+        let len = u32::from_be_bytes(*prefix) as usize;
+        self.check(len)?;
+
+        match src[LENGTH_PREFIX..].get(..len) {
+            Some(frame) => Ok(Some((frame.to_vec(), LENGTH_PREFIX + len))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<&[u8]> for LengthDelimitedCodec {
+    fn encode(&mut self, frame: &[u8], dst: &mut Vec<u8>) -> stdio::Result<()> {
+        self.check(frame.len())?;
+
+        dst.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        dst.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+impl Encoder<Vec<u8>> for LengthDelimitedCodec {
+    fn encode(&mut self, frame: Vec<u8>, dst: &mut Vec<u8>) -> stdio::Result<()> {
+        self.encode(frame.as_slice(), dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+    use crate::rent::{BufResult, IoBuf, IoBufMut};
+
+    /// Chunks is a stream which hands out its chunks one read at a time and
+    /// keeps what is written to it.
+    #[derive(Default)]
+    struct Chunks {
+        chunks: VecDeque<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl AsyncReadRent for Chunks {
+        fn read<B: IoBufMut>(&mut self, mut buf: B) -> impl Future<Output = BufResult<usize, B>> {
+            let chunk = self.chunks.pop_front().unwrap_or_default();
+            let n = chunk.len().min(buf.bytes_total());
+            if n < chunk.len() {
+                self.chunks.push_front(chunk[n..].to_vec());
+            }
+
+            // # Safety
+            // The buffer has room for `n` bytes.
+            unsafe {
+                std::ptr::copy_nonoverlapping(chunk.as_ptr(), buf.write_ptr(), n);
+                buf.set_init(n);
+            }
+
+            std::future::ready((Ok(n), buf))
+        }
+    }
+
+    impl AsyncWriteRent for Chunks {
+        fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+            // # Safety
+            // The data of the buffer is initialized.
+            let data = unsafe { std::slice::from_raw_parts(buf.read_ptr(), buf.bytes_init()) };
+            self.written.extend_from_slice(data);
+
+            std::future::ready((Ok(data.len()), buf))
+        }
+    }
+
+    /// ready returns the output of a future which completes on its first poll
+    fn ready<F: Future>(fut: F) -> F::Output {
+        let mut ctx = Context::from_waker(Waker::noop());
+        match pin!(fut).poll(&mut ctx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future is not ready"),
+        }
+    }
+
+    #[test]
+    fn lines_span_reads() {
+        let stream = Chunks::new(&[b"fir", b"st\r\nsec", b"ond\n\nla", b"st"]);
+        let mut framed = Framed::new(stream, LinesCodec::new());
+
+        let mut lines = Vec::new();
+        while let Some(line) = ready(framed.next()) {
+            lines.push(line.unwrap());
+        }
+        assert_eq!(lines, ["first", "second", "", "last"]);
+    }
+
+    #[test]
+    fn long_line_ends_the_stream() {
+        let stream = Chunks::new(&[b"short\nmuch too long\nnext\n"]);
+        let mut framed = Framed::new(stream, LinesCodec::with_max_length(8));
+
+        assert_eq!(ready(framed.next()).unwrap().unwrap(), "short");
+        let err = ready(framed.next()).unwrap().unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidData);
+        assert!(ready(framed.next()).is_none());
+    }
+
+    #[test]
+    fn length_delimited_round_trip() {
+        let mut framed = Framed::new(Chunks::default(), LengthDelimitedCodec::new());
+        framed.feed(&b"one"[..]).unwrap();
+        framed.feed(Vec::new()).unwrap();
+        ready(framed.send(b"three".to_vec())).unwrap();
+
+        // The frames arrive a byte at a time
+        let written = framed.into_inner().written;
+        assert_eq!(&written[..7], b"\0\0\0\x03one");
+        let bytes: Vec<&[u8]> = written.chunks(1).collect();
+        let mut framed = Framed::new(Chunks::new(&bytes), LengthDelimitedCodec::new());
+
+        let mut frames = Vec::new();
+        while let Some(frame) = ready(framed.next()) {
+            frames.push(frame.unwrap());
+        }
+        assert_eq!(frames, [&b"one"[..], b"", b"three"]);
+    }
+
+    #[test]
+    fn truncated_frame_fails() {
+        let stream = Chunks::new(&[b"\0\0\0\x05abc"]);
+        let mut framed = Framed::new(stream, LengthDelimitedCodec::new());
+
+        let err = ready(framed.next()).unwrap().unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::UnexpectedEof);
+        assert!(ready(framed.next()).is_none());
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut codec = LengthDelimitedCodec::with_max_frame_length(4);
+
+        let mut dst = Vec::new();
+        let err = codec.encode(&b"12345"[..], &mut dst).unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidData);
+        assert!(dst.is_empty());
+
+        // The length is checked before the frame has arrived
+        let err = codec.decode(b"\0\0\0\x05").unwrap_err();
+        assert_eq!(err.kind(), stdio::ErrorKind::InvalidData);
+    }
+}
@@ -1,24 +1,183 @@
-use crate::{Reactor, ReactorRequest, PerThreadReactor};
+use std::{
+    future::Future,
+    io as stdio,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::{Backend, Op, Timespec},
+    PerThreadReactor, ReactorRequest,
+};
 
 #[derive(reika_macros::Future)]
 struct YieldMeta {
-    reactor: &'static Reactor,
+    reactor: &'static dyn Backend,
     req: ReactorRequest,
 }
 
 #[inline(always)]
 pub async fn yield_now() {
-	_yield_now().await.unwrap();
+    _yield_now().await.unwrap();
 }
 
 fn _yield_now() -> YieldMeta {
     let reactor = unsafe { PerThreadReactor::this() };
 
-    let nop = io_uring::opcode::Nop::new();
+    let req = ReactorRequest::new(Op::Nop);
+    YieldMeta { reactor, req }
+}
 
-    let req = ReactorRequest::new(nop.build());
-    YieldMeta {
+#[derive(reika_macros::Future)]
+struct TimeoutMeta {
+    reactor: &'static dyn Backend,
+    req: ReactorRequest,
+    /// _ts is pointed by the op, it is released once the backend is done
+    /// with the op.
+    #[keep]
+    _ts: Option<Box<Timespec>>,
+}
+
+fn _timeout(duration: Duration) -> TimeoutMeta {
+    let reactor = unsafe { PerThreadReactor::this() };
+
+    let ts = Box::new(Timespec::from(duration));
+    let req = ReactorRequest::new(Op::Timeout { ts: &*ts });
+
+    TimeoutMeta {
         reactor,
         req,
+        _ts: Some(ts),
+    }
+}
+
+/// now returns the current time of the reactor of the thread. It is the
+/// clock the timers run on, which is virtual under the simulation.
+pub fn now() -> Instant {
+    let reactor = unsafe { PerThreadReactor::this() };
+    reactor.clock()
+}
+
+/// sleep returns a future which completes once the duration has elapsed
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+/// sleep_until returns a future which completes once the deadline (see
+/// [now]) is reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        meta: None,
+    }
+}
+
+/// Sleep is the future returned by [sleep] and [sleep_until].
+///
+/// The timeout is submitted to the reactor when the future is first polled,
+/// with whatever is left till the deadline at that point.
+pub struct Sleep {
+    deadline: Instant,
+    meta: Option<TimeoutMeta>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<()> {
+        let deadline = self.deadline;
+        let meta = self
+            .meta
+            .get_or_insert_with(|| _timeout(deadline.saturating_duration_since(now())));
+
+        // The timeout completes with ETIME once it expires, there is nothing
+        // else it could fail with short of being cancelled.
+        Pin::new(meta).poll(ctx).map(|_| ())
+    }
+}
+
+/// timeout runs the future till it completes or the duration elapses, in
+/// which case the future is dropped and the error is of the kind
+/// [stdio::ErrorKind::TimedOut].
+pub fn timeout<F: Future>(duration: Duration, fut: F) -> Timeout<F> {
+    Timeout {
+        fut,
+        sleep: sleep(duration),
+    }
+}
+
+/// Timeout is the future returned by [timeout]
+pub struct Timeout<F> {
+    fut: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = stdio::Result<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        // # Safety
+        // The inner future is never moved out of the pinned timeout.
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+
+        if let Poll::Ready(output) = fut.poll(ctx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        Pin::new(&mut this.sleep)
+            .poll(ctx)
+            .map(|_| Err(stdio::ErrorKind::TimedOut.into()))
+    }
+}
+
+/// interval returns an [Interval] ticking every `period`, the first tick
+/// completes immediately.
+///
+/// # Panics
+/// Panics if the period is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "period must be non-zero");
+
+    Interval {
+        period,
+        next: now(),
+    }
+}
+
+/// Interval yields ticks at a fixed period. The ticks do not drift with the
+/// time spent between them, but the ticks missed by falling behind by more
+/// than a period are skipped.
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// tick waits for the next tick and returns the instant it was scheduled
+    /// for.
+    pub async fn tick(&mut self) -> Instant {
+        let scheduled = self.next;
+        sleep_until(scheduled).await;
+
+        self.next = scheduled + self.period;
+
+        let current = now();
+        if self.next < current {
+            self.next = current + self.period;
+        }
+
+        scheduled
     }
 }
@@ -0,0 +1,156 @@
+//! fixed implements buffers registered with the reactor. The kernel pins the
+//! pages of a registered buffer once rather than on every request, see
+//! [crate::io::File::read_fixed_at] and [crate::io::File::write_fixed_at].
+
+use std::{
+    alloc::{self, Layout},
+    cell::RefCell,
+    io as stdio,
+    ops::{Deref, DerefMut},
+};
+
+use crate::PerThreadReactor;
+
+/// BUF_ALIGN is the alignment of the buffers
+const BUF_ALIGN: usize = 4096;
+
+/// FixedBufferPool holds the buffers registered with the reactor of a thread,
+/// they are checked out as [FixedBuf] and go back to the pool once dropped.
+pub struct FixedBufferPool {
+    mem: *mut u8,
+    buf_size: usize,
+    count: u16,
+
+    free: RefCell<Vec<u16>>,
+}
+
+impl FixedBufferPool {
+    /// register allocates `count` buffers of `size` bytes and registers them
+    /// with the reactor of the current thread. The buffers are aligned to
+    /// 4096 bytes if the size is a multiple of it.
+    ///
+    /// NOTE: A reactor takes a single set of buffers, hence there is at most
+    /// one pool per thread. Like the registration, the pool lives as long as
+    /// the thread.
+    pub fn register(count: u16, size: usize) -> stdio::Result<&'static FixedBufferPool> {
+        assert!(count > 0 && size > 0, "pool must not be empty");
+
+        let layout = Layout::from_size_align(count as usize * size, BUF_ALIGN)
+            .map_err(|err| stdio::Error::new(stdio::ErrorKind::InvalidInput, err))?;
+
+        // # Safety
+        // The layout is non-zero sized.
+        let mem = unsafe { alloc::alloc_zeroed(layout) };
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        let iovecs: Vec<libc::iovec> = (0..count as usize)
+            .map(|idx| libc::iovec {
+                iov_base: unsafe { mem.add(idx * size) } as *mut libc::c_void,
+                iov_len: size,
+            })
+            .collect();
+
+        // # Safety
+        // The memory is leaked along with the pool once registered.
+        let reactor = unsafe { PerThreadReactor::this() };
+        if let Err(err) = unsafe { reactor.register_buffers(&iovecs) } {
+            unsafe { alloc::dealloc(mem, layout) };
+            return Err(err);
+        }
+
+        Ok(Box::leak(Box::new(FixedBufferPool {
+            mem,
+            buf_size: size,
+            count,
+            free: RefCell::new((0..count).rev().collect()),
+        })))
+    }
+
+    /// checkout takes a buffer out of the pool, None if all of them are in
+    /// use.
+    pub fn checkout(&'static self) -> Option<FixedBuf> {
+        let index = self.free.borrow_mut().pop()?;
+
+        Some(FixedBuf {
+            pool: self,
+            index,
+            len: self.buf_size,
+        })
+    }
+
+    /// available returns the number of buffers in the pool
+    pub fn available(&self) -> usize {
+        self.free.borrow().len()
+    }
+
+    /// buf_size returns the size of every buffer
+    pub fn buf_size(&self) -> usize {
+        self.buf_size
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+}
+
+/// FixedBuf is a buffer checked out of a [FixedBufferPool].
+///
+/// It dereferences to its first `len` bytes, which is what the fixed ops read
+/// into or write from. The length is the full buffer at checkout.
+pub struct FixedBuf {
+    pool: &'static FixedBufferPool,
+    index: u16,
+    len: usize,
+}
+
+impl FixedBuf {
+    /// buf_index returns the index of the buffer in the registration
+    pub fn buf_index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.pool.buf_size
+    }
+
+    /// set_len sets the number of bytes the buffer dereferences to
+    ///
+    /// # Panics
+    /// Panics if the length is larger than the capacity.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "length exceeds the capacity");
+        self.len = len;
+    }
+
+    fn ptr(&self) -> *mut u8 {
+        // # Safety
+        // The buffer lies within the memory of the pool.
+        unsafe { self.pool.mem.add(self.index as usize * self.pool.buf_size) }
+    }
+}
+
+impl Deref for FixedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // # Safety
+        // The buffer is checked out, nobody else refers to it.
+        unsafe { std::slice::from_raw_parts(self.ptr(), self.len) }
+    }
+}
+
+impl DerefMut for FixedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // # Safety
+        // The buffer is checked out, nobody else refers to it.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr(), self.len) }
+    }
+}
+
+impl Drop for FixedBuf {
+    fn drop(&mut self) {
+        self.pool.free.borrow_mut().push(self.index);
+    }
+}
@@ -1,7 +1,18 @@
-use std::{ io as stdio, os::fd::RawFd};
+use std::{
+    ffi::CString,
+    future::Future,
+    io::{self as stdio, IoSlice, IoSliceMut},
+    os::fd::{FromRawFd, RawFd},
+};
 
 use libc::mode_t;
 
+use crate::{
+    backend::{Fd, Op, CURRENT_POSITION},
+    fixed::FixedBuf,
+    rent::{AsyncReadRent, AsyncWriteRent, BufResult, IoBuf, IoBufMut, RentMeta},
+};
+
 #[derive(Clone, Copy)]
 pub struct OpenOptions {
     read: bool,
@@ -9,6 +20,8 @@ pub struct OpenOptions {
     append: bool,
     truncate: bool,
     create: bool,
+    fixed: bool,
+    direct: bool,
 
     custom_flags: i32,
     mode: mode_t,
@@ -22,6 +35,8 @@ impl OpenOptions {
             append: false,
             truncate: false,
             create: false,
+            fixed: false,
+            direct: false,
 
             custom_flags: 0,
             mode: 0o666,
@@ -53,6 +68,23 @@ impl OpenOptions {
         self
     }
 
+    /// fixed opens the file straight into the fixed file table of the reactor
+    /// rather than the file descriptor table of the process, see
+    /// [crate::PerThreadReactor::register_files].
+    pub fn fixed(&mut self, fixed: bool) -> &mut Self {
+        self.fixed = fixed;
+        self
+    }
+
+    /// direct opens the file for direct I/O (`O_DIRECT`), which bypasses the
+    /// page cache. The offsets, the lengths and the memory of the reads and
+    /// writes must then be aligned, see [File::alignment] and
+    /// [crate::aligned::AlignedBuf].
+    pub fn direct(&mut self, direct: bool) -> &mut Self {
+        self.direct = direct;
+        self
+    }
+
     pub fn mode(&mut self, mode: u32) -> &mut Self {
         self.mode = mode;
         self
@@ -74,6 +106,9 @@ impl OpenOptions {
         if self.create {
             flags |= libc::O_CREAT;
         }
+        if self.direct {
+            flags |= libc::O_DIRECT;
+        }
         if self.read && !self.write {
             flags |= libc::O_RDONLY;
         }
@@ -86,9 +121,16 @@ impl OpenOptions {
 
         flags |= self.custom_flags;
 
-        let fd = raw::open(pathname, flags, self.mode).await?;
+        let fd = if self.fixed {
+            let index = raw::open_fixed(pathname, flags, self.mode).await?;
+            Fd::Fixed(index as u32)
+        } else {
+            Fd::Raw(raw::open(pathname, flags, self.mode).await?)
+        };
 
-        Ok(File { fd })
+        let direct = self.direct.then(|| Alignment::discover(pathname, fd));
+
+        Ok(File { fd, direct })
     }
 }
 
@@ -98,8 +140,119 @@ impl Default for OpenOptions {
     }
 }
 
+/// Alignment is what the direct I/O of a file needs aligned, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    /// mem is the alignment of the memory of the buffers
+    pub mem: usize,
+    /// offset is the alignment of the offsets and of the lengths, the
+    /// logical block size of the device.
+    pub offset: usize,
+}
+
+/// DEFAULT_ALIGN is the alignment assumed when the kernel does not tell
+const DEFAULT_ALIGN: usize = 4096;
+
+/// STATX_DIOALIGN asks statx(2) for the alignment of the direct I/O
+const STATX_DIOALIGN: u32 = 0x2000;
+
+/// BLKSSZGET is the ioctl returning the logical block size of a block device
+const BLKSSZGET: u64 = 0x1268;
+
+impl Alignment {
+    /// discover asks the kernel about the alignment of the file. statx(2)
+    /// tells it for the files of the filesystems supporting it (Linux 6.1+),
+    /// otherwise the logical block size of a block device is asked via
+    /// ioctl. It falls back to the page size, which suits any device.
+    ///
+    /// NOTE: These are plain syscalls, the file is stated rather than read.
+    fn discover(pathname: &str, fd: Fd) -> Alignment {
+        let Ok(path) = CString::new(pathname) else {
+            return Alignment::default();
+        };
+
+        // # Safety
+        // The path is null terminated and statx is plain old data.
+        let mut stx: libc::statx = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::statx(
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                0,
+                libc::STATX_TYPE | STATX_DIOALIGN,
+                &mut stx,
+            )
+        };
+        if res < 0 {
+            return Alignment::default();
+        }
+
+        if stx.stx_mask & STATX_DIOALIGN != 0 && stx.stx_dio_offset_align != 0 {
+            return Alignment {
+                mem: stx.stx_dio_mem_align as usize,
+                offset: stx.stx_dio_offset_align as usize,
+            };
+        }
+
+        // A fixed file has no file descriptor to ask
+        if stx.stx_mode as u32 & libc::S_IFMT == libc::S_IFBLK {
+            if let Fd::Raw(fd) = fd {
+                let mut size: libc::c_int = 0;
+                // # Safety
+                // BLKSSZGET writes an int.
+                if unsafe { libc::ioctl(fd, BLKSSZGET as _, &mut size as *mut libc::c_int) } == 0
+                    && size > 0
+                {
+                    return Alignment {
+                        mem: size as usize,
+                        offset: size as usize,
+                    };
+                }
+            }
+        }
+
+        Alignment::default()
+    }
+
+    /// check checks that the I/O of `len` bytes at `offset` from the memory
+    /// at `ptr` is aligned, the kernel would fail it with a bare `EINVAL`
+    /// otherwise.
+    fn check(&self, ptr: *const u8, len: usize, offset: u64) -> stdio::Result<()> {
+        let misaligned = |what: &str, value: u64, align: usize| {
+            stdio::Error::new(
+                stdio::ErrorKind::InvalidInput,
+                format!("direct I/O needs the {what} aligned to {align} bytes, got {value}"),
+            )
+        };
+
+        if !(ptr as usize).is_multiple_of(self.mem) {
+            return Err(misaligned("buffer", ptr as u64, self.mem));
+        }
+        if !len.is_multiple_of(self.offset) {
+            return Err(misaligned("length", len as u64, self.offset));
+        }
+        if offset != CURRENT_POSITION && !offset.is_multiple_of(self.offset as u64) {
+            return Err(misaligned("offset", offset, self.offset));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Alignment {
+    /// default is the page size, which suits the direct I/O of any device
+    fn default() -> Self {
+        Alignment {
+            mem: DEFAULT_ALIGN,
+            offset: DEFAULT_ALIGN,
+        }
+    }
+}
+
 pub struct File {
-    fd: i32,
+    fd: Fd,
+    /// direct is the alignment of the file, if it is open for direct I/O
+    direct: Option<Alignment>,
 }
 
 impl File {
@@ -129,33 +282,117 @@ impl File {
         Ok(())
     }
 
+    /// as_raw_fd returns the file descriptor of the file
+    ///
+    /// # Panics
+    /// Panics if the file lives in the fixed file table, it has no file
+    /// descriptor then.
     pub fn as_raw_fd(&self) -> RawFd {
-        self.fd
+        match self.fd {
+            Fd::Raw(fd) => fd,
+            Fd::Fixed(_) => panic!("fixed files have no file descriptor"),
+        }
     }
 
-    pub async fn fallocate(&self, offset: u64, len: u64, mode: i32) -> stdio::Result<()> {
-        let _ = raw::fallocate(self.fd, offset, len, mode).await?;
-        Ok(())
+    /// fd returns what the ops of the file operate on
+    pub fn fd(&self) -> Fd {
+        self.fd
     }
 
-    pub async fn read(&self, buf: &'_ mut [u8]) -> stdio::Result<usize> {
-        let n = raw::read(self.fd, buf).await?;
-        Ok(n as usize)
+    /// alignment returns what the I/O of the file needs aligned, None unless
+    /// the file is open for direct I/O (see [OpenOptions::direct]).
+    pub fn alignment(&self) -> Option<Alignment> {
+        self.direct
     }
 
-    pub async fn read_at(&self, buf: &'_ mut [u8], offset: u64) -> stdio::Result<usize> {
-        let n = raw::read_at(self.fd, buf, offset as _).await?;
-        Ok(n as usize)
+    /// check_direct checks the alignment of the I/O, if the file is open for
+    /// direct I/O.
+    fn check_direct(&self, ptr: *const u8, len: usize, offset: u64) -> stdio::Result<()> {
+        match &self.direct {
+            Some(align) => align.check(ptr, len, offset),
+            None => Ok(()),
+        }
     }
 
-    pub async fn write(&self, buf: &'_ mut [u8]) -> stdio::Result<usize> {
-        let n = raw::write(self.fd, buf).await?;
-        Ok(n as usize)
+    pub async fn fallocate(&self, offset: u64, len: u64, mode: i32) -> stdio::Result<()> {
+        let _ = raw::fallocate(self.fd, offset, len, mode).await?;
+        Ok(())
     }
 
-    pub async fn write_at(&self, buf: &'_ mut [u8], offset: u64) -> stdio::Result<usize> {
-        let n = raw::write_at(self.fd, buf, offset as _).await?;
-        Ok(n as usize)
+    /// read reads at the current position of the file, it can be bounded
+    /// via [raw::ReadMeta::with_deadline].
+    pub fn read<'a>(&self, buf: &'a mut [u8]) -> raw::ReadMeta<'a> {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), CURRENT_POSITION);
+        raw::read(self.fd, buf).checked(res)
+    }
+
+    /// read_at reads at the offset. For a file open for direct I/O, the
+    /// misaligned reads fail with [stdio::ErrorKind::InvalidInput].
+    pub fn read_at<'a>(&self, buf: &'a mut [u8], offset: u64) -> raw::ReadMeta<'a> {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), offset);
+        raw::read_at(self.fd, buf, offset).checked(res)
+    }
+
+    /// write writes at the current position of the file, it can be bounded
+    /// via [raw::WriteMeta::with_deadline].
+    pub fn write<'a>(&self, buf: &'a [u8]) -> raw::WriteMeta<'a> {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), CURRENT_POSITION);
+        raw::write(self.fd, buf).checked(res)
+    }
+
+    /// write_at writes at the offset. For a file open for direct I/O, the
+    /// misaligned writes fail with [stdio::ErrorKind::InvalidInput].
+    pub fn write_at<'a>(&self, buf: &'a [u8], offset: u64) -> raw::WriteMeta<'a> {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), offset);
+        raw::write_at(self.fd, buf, offset).checked(res)
+    }
+
+    /// read_vectored_at reads into the buffers one after the other, as a
+    /// single op. `flags` are the flags of preadv2(2), like
+    /// `libc::RWF_NOWAIT` which fails with `EAGAIN` rather than waiting for
+    /// the data.
+    pub fn read_vectored_at<'a>(
+        &self,
+        bufs: &'a mut [IoSliceMut<'_>],
+        offset: u64,
+        flags: i32,
+    ) -> raw::ReadMeta<'a> {
+        let res = bufs
+            .iter()
+            .try_for_each(|buf| self.check_direct(buf.as_ptr(), buf.len(), offset));
+        raw::readv_at(self.fd, bufs, offset, flags).checked(res)
+    }
+
+    /// write_vectored_at writes the buffers one after the other, as a single
+    /// op. `flags` are the flags of pwritev2(2), like `libc::RWF_DSYNC`
+    /// which makes the data durable along with the write or
+    /// `libc::RWF_APPEND` which writes at the end of the file.
+    pub fn write_vectored_at<'a>(
+        &self,
+        bufs: &'a [IoSlice<'_>],
+        offset: u64,
+        flags: i32,
+    ) -> raw::WriteMeta<'a> {
+        let res = bufs
+            .iter()
+            .try_for_each(|buf| self.check_direct(buf.as_ptr(), buf.len(), offset));
+        raw::writev_at(self.fd, bufs, offset, flags).checked(res)
+    }
+
+    /// read_fixed_at reads into the registered buffer (up to its length), the
+    /// buffer is handed back along with the result.
+    /// Like [File::read_at], the misaligned direct I/O fails upfront.
+    pub fn read_fixed_at(&self, buf: FixedBuf, offset: u64) -> raw::FixedMeta {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), offset);
+        raw::read_fixed_at(self.fd, buf, offset as _).checked(res)
+    }
+
+    /// write_fixed_at writes the registered buffer (up to its length), the
+    /// buffer is handed back along with the result.
+    /// Like [File::write_at], the misaligned direct I/O fails upfront.
+    pub fn write_fixed_at(&self, buf: FixedBuf, offset: u64) -> raw::FixedMeta {
+        let res = self.check_direct(buf.as_ptr(), buf.len(), offset);
+        raw::write_fixed_at(self.fd, buf, offset as _).checked(res)
     }
 
     pub async fn close(&self) -> stdio::Result<()> {
@@ -164,182 +401,337 @@ impl File {
     }
 }
 
-pub mod raw {
-    use crate::{PerThreadReactor, Reactor, ReactorRequest};
-    use std::{ffi::CString, marker::PhantomData, os::fd::RawFd};
+impl AsyncReadRent for File {
+    /// read reads at the current position of the file
+    fn read<B: IoBufMut>(&mut self, mut buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        let res = self.check_direct(buf.write_ptr(), buf.bytes_total(), CURRENT_POSITION);
 
-    #[derive(reika_macros::Future)]
-    pub struct ReadMeta<'a> {
-        reactor: &'static Reactor,
-        req: ReactorRequest,
+        let read_op = Op::Read {
+            fd: self.fd,
+            buf: buf.write_ptr(),
+            len: buf.bytes_total() as u32,
+            offset: CURRENT_POSITION,
+        };
 
-        phantom: PhantomData<&'a ()>,
+        RentMeta::read(read_op, buf).checked(res)
     }
+}
 
-    pub fn read(fd: RawFd, buf: &'_ mut [u8]) -> ReadMeta<'_> {
-        let reactor = unsafe { PerThreadReactor::this() };
+impl AsyncWriteRent for File {
+    /// write writes at the current position of the file
+    fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        let res = self.check_direct(buf.read_ptr(), buf.bytes_init(), CURRENT_POSITION);
 
-        let read_op = io_uring::opcode::Read::new(
-            io_uring::types::Fd(fd),
-            buf.as_mut_ptr() as *mut _,
-            buf.len() as u32,
-        )
-        // Kernel will cast this to loff_t which is signed => -1
-        .offset(u64::MAX);
+        let write_op = Op::Write {
+            fd: self.fd,
+            buf: buf.read_ptr(),
+            len: buf.bytes_init() as u32,
+            offset: CURRENT_POSITION,
+        };
 
-        let req = ReactorRequest::new(read_op.build());
-        ReadMeta {
-            reactor,
-            req,
-            phantom: PhantomData {},
-        }
+        RentMeta::write(write_op, buf).checked(res)
     }
+}
 
-    pub fn read_at(fd: RawFd, buf: &'_ mut [u8], offset: i64) -> ReadMeta<'_> {
-        let reactor = unsafe { PerThreadReactor::this() };
-
-        let read_op = io_uring::opcode::Read::new(
-            io_uring::types::Fd(fd),
-            buf.as_mut_ptr() as *mut _,
-            buf.len() as u32,
-        )
-        .offset(offset.try_into().unwrap());
-
-        let req = ReactorRequest::new(read_op.build());
-        ReadMeta {
-            reactor,
-            req,
-            phantom: PhantomData {},
+impl FromRawFd for File {
+    /// from_raw_fd takes over an open file, like one received via
+    /// [crate::net::UnixStream::recv_with_fds].
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        File {
+            fd: Fd::Raw(fd),
+            direct: None,
         }
     }
+}
+
+pub mod raw {
+    use crate::{
+        backend::{Backend, Fd, Op, CURRENT_POSITION},
+        fixed::FixedBuf,
+        rent::BorrowMeta,
+        PerThreadReactor, ReactorRequest,
+    };
+    use std::{
+        ffi::CString,
+        future::Future,
+        io::{self as stdio, IoSlice, IoSliceMut},
+        pin::Pin,
+        task::{Context, Poll},
+        time::Instant,
+    };
+
+    /// ReadMeta is the future of a read into borrowed memory, see
+    /// [BorrowMeta]. It resolves to the number of bytes read.
+    pub type ReadMeta<'a> = BorrowMeta<'a>;
+
+    pub fn read(fd: Fd, buf: &'_ mut [u8]) -> ReadMeta<'_> {
+        // Kernel will cast this to loff_t which is signed => -1
+        read_at(fd, buf, CURRENT_POSITION)
+    }
+
+    /// read_at reads at the offset, the offset may be [CURRENT_POSITION]
+    pub fn read_at(fd: Fd, buf: &'_ mut [u8], offset: u64) -> ReadMeta<'_> {
+        BorrowMeta::new(Op::Read {
+            fd,
+            buf: buf.as_mut_ptr(),
+            len: buf.len() as u32,
+            offset,
+        })
+    }
+
+    /// readv_at reads into the buffers, the offset may be [CURRENT_POSITION]
+    pub fn readv_at<'a>(
+        fd: Fd,
+        bufs: &'a mut [IoSliceMut<'_>],
+        offset: u64,
+        flags: i32,
+    ) -> ReadMeta<'a> {
+        // IoSliceMut is ABI compatible with iovec, the kernel scatters the
+        // data straight into the buffers.
+        BorrowMeta::new(Op::Readv {
+            fd,
+            iovecs: bufs.as_ptr() as *const libc::iovec,
+            len: bufs.len() as u32,
+            offset,
+            flags,
+        })
+    }
 
     #[derive(reika_macros::Future)]
     pub struct OpenMeta {
-        reactor: &'static Reactor,
+        reactor: &'static dyn Backend,
         req: ReactorRequest,
-        path: CString,
+        #[keep]
+        path: Option<CString>,
     }
 
     pub fn open(pathname: &str, flags: i32, mode: u32) -> OpenMeta {
+        _open(pathname, flags, mode, false)
+    }
+
+    /// open_fixed is like [open] but the file is installed into the fixed
+    /// file table, the result is the index of its slot.
+    pub fn open_fixed(pathname: &str, flags: i32, mode: u32) -> OpenMeta {
+        _open(pathname, flags, mode, true)
+    }
+
+    fn _open(pathname: &str, flags: i32, mode: u32, fixed: bool) -> OpenMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
         let path = CString::new(pathname).expect("pathname should not contain null bytes");
 
-        let open_op =
-            io_uring::opcode::OpenAt::new(io_uring::types::Fd(libc::AT_FDCWD), path.as_ptr())
-                .flags(flags)
-                .mode(mode);
+        let open_op = Op::OpenAt {
+            dirfd: libc::AT_FDCWD,
+            path: path.as_ptr(),
+            flags,
+            mode,
+            fixed,
+        };
 
-        let req = ReactorRequest::new(open_op.build());
+        let req = ReactorRequest::new(open_op);
 
-        OpenMeta { reactor, req, path }
+        OpenMeta {
+            reactor,
+            req,
+            path: Some(path),
+        }
     }
 
     #[derive(reika_macros::Future)]
     pub struct CloseMeta {
-        reactor: &'static Reactor,
+        reactor: &'static dyn Backend,
         req: ReactorRequest,
     }
 
-    pub fn close(fd: RawFd) -> CloseMeta {
+    pub fn close(fd: Fd) -> CloseMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let close_op = io_uring::opcode::Close::new(io_uring::types::Fd(fd));
-        let req = ReactorRequest::new(close_op.build());
+        let req = ReactorRequest::new(Op::Close { fd });
 
         CloseMeta { reactor, req }
     }
 
+    /// WriteMeta is the future of a write from borrowed memory, see
+    /// [BorrowMeta]. It resolves to the number of bytes written.
+    pub type WriteMeta<'a> = BorrowMeta<'a>;
+
+    pub fn write(fd: Fd, buf: &'_ [u8]) -> WriteMeta<'_> {
+        // Kernel will cast this to loff_t which is signed => -1
+        write_at(fd, buf, CURRENT_POSITION)
+    }
+
+    /// write_at writes at the offset, the offset may be [CURRENT_POSITION]
+    pub fn write_at(fd: Fd, buf: &'_ [u8], offset: u64) -> WriteMeta<'_> {
+        BorrowMeta::new(Op::Write {
+            fd,
+            buf: buf.as_ptr(),
+            len: buf.len() as u32,
+            offset,
+        })
+    }
+
+    /// writev_at writes the buffers, the offset may be [CURRENT_POSITION]
+    pub fn writev_at<'a>(
+        fd: Fd,
+        bufs: &'a [IoSlice<'_>],
+        offset: u64,
+        flags: i32,
+    ) -> WriteMeta<'a> {
+        // IoSlice is ABI compatible with iovec, the kernel gathers the data
+        // straight from the buffers.
+        BorrowMeta::new(Op::Writev {
+            fd,
+            iovecs: bufs.as_ptr() as *const libc::iovec,
+            len: bufs.len() as u32,
+            offset,
+            flags,
+        })
+    }
+
     #[derive(reika_macros::Future)]
-    pub struct WriteMeta<'a> {
-        reactor: &'static Reactor,
+    pub struct FsyncMeta {
+        reactor: &'static dyn Backend,
         req: ReactorRequest,
-
-        phantom: PhantomData<&'a ()>,
     }
 
-    pub fn write(fd: RawFd, buf: &'_ mut [u8]) -> WriteMeta<'_> {
+    pub fn fsync(fd: Fd) -> FsyncMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let write_op = io_uring::opcode::Write::new(
-            io_uring::types::Fd(fd),
-            buf.as_mut_ptr() as *mut _,
-            buf.len() as u32,
-        )
-        // Kernel will cast this to loff_t which is signed => -1
-        .offset(u64::MAX);
+        let req = ReactorRequest::new(Op::Fsync {
+            fd,
+            datasync: false,
+        });
+        FsyncMeta { reactor, req }
+    }
 
-        let req = ReactorRequest::new(write_op.build());
-        WriteMeta {
-            reactor,
-            req,
-            phantom: PhantomData {},
-        }
+    #[derive(reika_macros::Future)]
+    pub struct FDatasyncMeta {
+        reactor: &'static dyn Backend,
+        req: ReactorRequest,
     }
 
-    pub fn write_at(fd: RawFd, buf: &'_ mut [u8], offset: i64) -> WriteMeta<'_> {
+    pub fn fdatasync(fd: Fd) -> FDatasyncMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let write_op = io_uring::opcode::Write::new(
-            io_uring::types::Fd(fd),
-            buf.as_mut_ptr() as *mut _,
-            buf.len() as u32,
-        )
-        .offset(offset.try_into().unwrap());
-
-        let req = ReactorRequest::new(write_op.build());
-        WriteMeta {
-            reactor,
-            req,
-            phantom: PhantomData {},
-        }
+        let req = ReactorRequest::new(Op::Fsync { fd, datasync: true });
+        FDatasyncMeta { reactor, req }
     }
 
     #[derive(reika_macros::Future)]
-    pub struct FsyncMeta {
-        reactor: &'static Reactor,
+    pub struct FallocateMeta {
+        reactor: &'static dyn Backend,
         req: ReactorRequest,
     }
 
-    pub fn fsync(fd: RawFd) -> FsyncMeta {
+    pub fn fallocate(fd: Fd, offset: u64, len: u64, mode: i32) -> FallocateMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let fsync_op = io_uring::opcode::Fsync::new(io_uring::types::Fd(fd));
+        let fallocate_op = Op::Fallocate {
+            fd,
+            offset,
+            len,
+            mode,
+        };
 
-        let req = ReactorRequest::new(fsync_op.build());
-        FsyncMeta { reactor, req }
+        let req = ReactorRequest::new(fallocate_op);
+        FallocateMeta { reactor, req }
     }
 
-    #[derive(reika_macros::Future)]
-    pub struct FDatasyncMeta {
-        reactor: &'static Reactor,
+    /// FixedMeta is the future of a fixed op, it owns the buffer till the op
+    /// completes.
+    pub struct FixedMeta {
+        reactor: &'static dyn Backend,
         req: ReactorRequest,
+        buf: Option<FixedBuf>,
+        /// failed is the error the future resolves to without issuing the
+        /// op, see [FixedMeta::checked].
+        failed: Option<stdio::Error>,
+    }
+
+    impl FixedMeta {
+        /// checked fails the future with the error of `res`, if any, rather
+        /// than issuing the op. It is for the checks of the op made upfront.
+        pub(crate) fn checked(mut self, res: stdio::Result<()>) -> Self {
+            self.failed = res.err();
+            self
+        }
+
+        /// with_deadline bounds the op, see [ReactorRequest::set_deadline]
+        pub fn with_deadline(mut self, deadline: Instant) -> Self {
+            self.req.set_deadline(deadline);
+            self
+        }
     }
 
-    pub fn fdatasync(fd: RawFd) -> FDatasyncMeta {
-        let reactor = unsafe { PerThreadReactor::this() };
+    impl Future for FixedMeta {
+        type Output = (stdio::Result<usize>, FixedBuf);
 
-        let fdatasync_op = io_uring::opcode::Fsync::new(io_uring::types::Fd(fd))
-            .flags(io_uring::types::FsyncFlags::DATASYNC);
+        fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = &mut *self;
 
-        let req = ReactorRequest::new(fdatasync_op.build());
-        FDatasyncMeta { reactor, req }
+            if let Some(err) = this.failed.take() {
+                let buf = this.buf.take().expect("polled after completion");
+                return Poll::Ready((Err(err), buf));
+            }
+
+            match unsafe { this.req.poll(this.reactor, ctx) } {
+                Poll::Ready(res) => {
+                    let buf = this.buf.take().expect("polled after completion");
+                    Poll::Ready((res.map(|n| n as usize), buf))
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        }
     }
 
-    #[derive(reika_macros::Future)]
-    pub struct FallocateMeta {
-        reactor: &'static Reactor,
-        req: ReactorRequest,
+    impl Drop for FixedMeta {
+        fn drop(&mut self) {
+            // The buffer goes back to the pool only once the backend is done
+            // with it.
+            if let Some(buf) = self.buf.take() {
+                self.req
+                    .cancel_then(self.reactor, Box::new(move || drop(buf)));
+            }
+        }
     }
 
-    pub fn fallocate(fd: RawFd, offset: u64, len: u64, mode: i32) -> FallocateMeta {
+    pub fn read_fixed_at(fd: Fd, mut buf: FixedBuf, offset: i64) -> FixedMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let fallocate_op = io_uring::opcode::Fallocate::new(io_uring::types::Fd(fd), len)
-            .offset(offset)
-            .mode(mode);
+        let read_op = Op::ReadFixed {
+            fd,
+            buf: buf.as_mut_ptr(),
+            len: buf.len() as u32,
+            offset: offset.try_into().unwrap(),
+            buf_index: buf.buf_index(),
+        };
 
-        let req = ReactorRequest::new(fallocate_op.build());
-        FallocateMeta { reactor, req }
+        let req = ReactorRequest::new(read_op);
+        FixedMeta {
+            reactor,
+            req,
+            buf: Some(buf),
+            failed: None,
+        }
+    }
+
+    pub fn write_fixed_at(fd: Fd, buf: FixedBuf, offset: i64) -> FixedMeta {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let write_op = Op::WriteFixed {
+            fd,
+            buf: buf.as_ptr(),
+            len: buf.len() as u32,
+            offset: offset.try_into().unwrap(),
+            buf_index: buf.buf_index(),
+        };
+
+        let req = ReactorRequest::new(write_op);
+        FixedMeta {
+            reactor,
+            req,
+            buf: Some(buf),
+            failed: None,
+        }
     }
 }
@@ -1,3 +1,9 @@
+pub mod aligned;
+pub mod bufio;
+pub mod bufring;
+pub mod codec;
+pub mod core;
+pub mod fixed;
 pub mod io;
 pub mod net;
-pub mod core;
\ No newline at end of file
+pub mod rent;
@@ -1,10 +1,22 @@
-use std::io::{Error, Result};
+use std::ffi::OsString;
+use std::future::Future;
+use std::io::{Error, IoSlice, IoSliceMut, Result};
 use std::marker::PhantomData;
 use std::mem::size_of;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::os::fd::RawFd;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
 
-use crate::{io, PerThreadReactor, Reactor, ReactorRequest};
+use crate::backend::{Backend, Fd, Op};
+use crate::bufring::{BufLease, BufRing};
+use crate::rent::{
+    AsyncReadRent, AsyncWriteRent, BorrowMeta, BufResult, IoBuf, IoBufMut, RentMeta,
+};
+use crate::{io, MultishotRequest, PerThreadReactor, ReactorRequest};
 
 pub const SOMAXCONN: i32 = libc::SOMAXCONN;
 
@@ -15,35 +27,43 @@ pub struct TcpListner {
 
 #[derive(Clone, Copy)]
 pub struct TcpStream {
-    connfd: RawFd,
+    connfd: Fd,
 }
 
+/// TcpReadMeta is the future of a receive into borrowed memory, see
+/// [BorrowMeta].
+pub type TcpReadMeta<'a> = BorrowMeta<'a>;
+
+/// TcpWriteMeta is the future of a send of borrowed memory, see [BorrowMeta].
+pub type TcpWriteMeta<'a> = BorrowMeta<'a>;
+
 #[derive(reika_macros::Future)]
-pub struct TcpReadMeta<'a> {
-    reactor: &'static Reactor,
+struct SocketMeta {
+    reactor: &'static dyn Backend,
     req: ReactorRequest,
-
-    phantom: PhantomData<&'a ()>,
 }
 
 #[derive(reika_macros::Future)]
-pub struct TcpWriteMeta<'a> {
-    reactor: &'static Reactor,
+struct AcceptMeta {
+    reactor: &'static dyn Backend,
     req: ReactorRequest,
-
-    phantom: PhantomData<&'a ()>,
 }
 
 #[derive(reika_macros::Future)]
-struct SocketMeta {
-    reactor: &'static Reactor,
+struct ConnectMeta {
+    reactor: &'static dyn Backend,
     req: ReactorRequest,
+    /// _addr is pointed by the op, it lives as long as the request
+    #[keep]
+    _addr: Option<Box<libc::sockaddr_storage>>,
 }
 
 #[derive(reika_macros::Future)]
-struct AcceptMeta {
-    reactor: &'static Reactor,
+struct SockCtlMeta<'a> {
+    reactor: &'static dyn Backend,
     req: ReactorRequest,
+
+    phantom: PhantomData<&'a ()>,
 }
 
 impl TcpListner {
@@ -56,125 +76,471 @@ impl TcpListner {
             SocketAddr::V4(ref a) => {
                 let socket =
                     Self::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).await?;
-                unsafe {
-                    Self::defaultsockopt(socket)?;
-                    Self::_bind4(socket, a.ip(), a.port())?;
-                }
+                Self::defaultsockopt(socket).await?;
+                Self::_bind4(socket, a.ip(), a.port()).await?;
                 socket
             }
             SocketAddr::V6(ref a) => {
                 let socket =
                     Self::socket(libc::AF_INET6, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).await?;
-                unsafe {
-                    Self::defaultsockopt(socket)?;
-                    Self::_bind6(socket, a.ip(), a.port())?;
-                }
+                Self::defaultsockopt(socket).await?;
+                Self::_bind6(socket, a).await?;
                 socket
             }
         };
 
         if sock_fd == 0 {
-            return Err(Error::new(std::io::ErrorKind::Other, "failed to bind"));
+            return Err(Error::other("failed to bind"));
         }
 
-        unsafe {
-            Self::listen(sock_fd, backlog)?;
-        }
+        Self::listen(sock_fd, backlog).await?;
 
         Ok(TcpListner { sock_fd })
     }
 
     #[inline(always)]
     pub async fn accept(&self) -> Result<TcpStream> {
-        let fd = Self::_accept(self.sock_fd).await?;
-        Ok(TcpStream { connfd: fd })
+        let fd = Self::_accept(self.sock_fd, false).await?;
+        Ok(TcpStream {
+            connfd: Fd::Raw(fd),
+        })
     }
 
-    fn _accept(socket: RawFd) -> AcceptMeta {
+    /// accept_fixed is like [TcpListner::accept] but the connection is
+    /// installed into the fixed file table of the reactor, see
+    /// [crate::PerThreadReactor::register_files].
+    #[inline(always)]
+    pub async fn accept_fixed(&self) -> Result<TcpStream> {
+        let index = Self::_accept(self.sock_fd, true).await?;
+        Ok(TcpStream {
+            connfd: Fd::Fixed(index as u32),
+        })
+    }
+
+    /// incoming returns the stream of the connections accepted by the
+    /// listener, a single multishot accept keeps delivering them.
+    pub fn incoming(&self) -> Incoming {
+        Self::_incoming(self.sock_fd, false)
+    }
+
+    /// incoming_fixed is like [TcpListner::incoming] but the connections are
+    /// installed into the fixed file table, see [TcpListner::accept_fixed].
+    pub fn incoming_fixed(&self) -> Incoming {
+        Self::_incoming(self.sock_fd, true)
+    }
+
+    fn _incoming(socket: RawFd, fixed: bool) -> Incoming {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let accept_op = io_uring::opcode::Accept::new(
-            io_uring::types::Fd(socket),
-            std::ptr::null_mut(),
-            std::ptr::null_mut(),
-        );
-        let req = ReactorRequest::new(accept_op.build());
+        let req = MultishotRequest::new(Op::AcceptMulti {
+            fd: Fd::Raw(socket),
+            fixed,
+        });
+        Incoming {
+            reactor,
+            req,
+            fixed,
+        }
+    }
+
+    fn _accept(socket: RawFd, fixed: bool) -> AcceptMeta {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let req = ReactorRequest::new(Op::Accept {
+            fd: Fd::Raw(socket),
+            fixed,
+        });
         AcceptMeta { reactor, req }
     }
 
     fn socket(domain: i32, socket_type: i32, protocol: i32) -> SocketMeta {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let socket_op = io_uring::opcode::Socket::new(domain, socket_type, protocol);
-        let req = ReactorRequest::new(socket_op.build());
+        let socket_op = Op::Socket {
+            domain,
+            socket_type,
+            protocol,
+        };
+        let req = ReactorRequest::new(socket_op);
         SocketMeta { reactor, req }
     }
 
-    unsafe fn listen(socket: RawFd, backlog: i32) -> Result<()> {
-        let res = libc::listen(socket, backlog);
-        if res == 0 {
-            Ok(())
-        } else {
-            Err(Error::from_raw_os_error(-res))
-        }
+    async fn listen(socket: RawFd, backlog: i32) -> Result<()> {
+        let _ = Self::sockctl(Op::Listen {
+            fd: socket,
+            backlog,
+        })
+        .await?;
+        Ok(())
     }
 
-    unsafe fn defaultsockopt(socket: RawFd) -> Result<()> {
-        let yes = 1i32;
-        let res = libc::setsockopt(
-            socket,
-            libc::SOL_SOCKET,
-            libc::SO_REUSEPORT,
-            &yes as *const _ as *const libc::c_void,
-            size_of::<i32>() as _,
-        );
+    async fn defaultsockopt(socket: RawFd) -> Result<()> {
+        let _ = Self::sockctl(Op::SetSockOpt {
+            fd: socket,
+            level: libc::SOL_SOCKET,
+            name: libc::SO_REUSEPORT,
+            value: 1,
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn _bind4(socket: libc::c_int, addr: &Ipv4Addr, port: u16) -> Result<()> {
+        let sockaddr = sockaddr_in(addr, port);
+
+        let _ = Self::sockctl(Op::Bind {
+            fd: socket,
+            addr: &sockaddr as *const _ as *const libc::sockaddr,
+            addrlen: size_of::<libc::sockaddr_in>() as _,
+        })
+        .await?;
+        Ok(())
+    }
 
-        if res == 0 {
-            Ok(())
-        } else {
-            Err(Error::from_raw_os_error(-res))
+    async fn _bind6(socket: libc::c_int, addr: &SocketAddrV6) -> Result<()> {
+        let sockaddr = sockaddr_in6(addr);
+
+        let _ = Self::sockctl(Op::Bind {
+            fd: socket,
+            addr: &sockaddr as *const _ as *const libc::sockaddr,
+            addrlen: size_of::<libc::sockaddr_in6>() as _,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// sockctl issues one of the socket control ops, the memory referred by
+    /// the op must outlive the returned future.
+    fn sockctl<'a>(op: Op) -> SockCtlMeta<'a> {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let req = ReactorRequest::new(op);
+        SockCtlMeta {
+            reactor,
+            req,
+            phantom: PhantomData {},
         }
     }
+}
 
-    unsafe fn _bind4(socket: libc::c_int, addr: &Ipv4Addr, port: u16) -> Result<()> {
-        let sockaddr = libc::sockaddr_in {
-            sin_family: libc::AF_INET as _,
-            sin_port: port.to_be(),
-            sin_addr: libc::in_addr {
-                s_addr: u32::from_be_bytes(addr.octets()).to_be(),
+impl FromRawFd for TcpStream {
+    /// from_raw_fd takes over a connected socket, like one received via
+    /// [UnixStream::recv_with_fds].
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        TcpStream {
+            connfd: Fd::Raw(fd),
+        }
+    }
+}
+
+impl AsyncReadRent for TcpStream {
+    fn read<B: IoBufMut>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        recv_rent(self.connfd, buf)
+    }
+}
+
+impl AsyncWriteRent for TcpStream {
+    fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        send_rent(self.connfd, buf)
+    }
+}
+
+/// Incoming is the stream of connections returned by [TcpListner::incoming],
+/// it never ends. The accept is cancelled once the stream is dropped.
+pub struct Incoming {
+    reactor: &'static dyn Backend,
+    req: MultishotRequest,
+    fixed: bool,
+}
+
+impl Incoming {
+    /// next waits for the next connection
+    pub async fn next(&mut self) -> Result<TcpStream> {
+        std::future::poll_fn(|ctx| self.poll_next(ctx)).await
+    }
+
+    pub fn poll_next(&mut self, ctx: &mut Context<'_>) -> Poll<Result<TcpStream>> {
+        let fixed = self.fixed;
+
+        unsafe { self.req.poll_next(self.reactor, ctx) }.map(|res| {
+            res.map(|completion| {
+                let connfd = if fixed {
+                    Fd::Fixed(completion.result as u32)
+                } else {
+                    Fd::Raw(completion.result)
+                };
+
+                TcpStream { connfd }
+            })
+        })
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.req.cancel(self.reactor);
+    }
+}
+
+/// SendZcMeta is the future of a zero-copy send, it owns the buffer till the
+/// kernel releases it, which may be well after the data has been sent. The
+/// buffer is handed back along with the result.
+pub struct SendZcMeta {
+    reactor: &'static dyn Backend,
+    op: Op,
+    token: Option<usize>,
+    /// sent is the result of the send, it arrives before the buffer is
+    /// released.
+    sent: Option<i32>,
+    buf: Option<Vec<u8>>,
+}
+
+impl Future for SendZcMeta {
+    type Output = (Result<usize>, Vec<u8>);
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        assert!(this.buf.is_some(), "polled after completion");
+
+        let token = match this.token {
+            Some(token) => token,
+            None => match unsafe { this.reactor.submit_multishot(&this.op) } {
+                Ok(token) => {
+                    this.token = Some(token);
+                    token
+                }
+                Err(_) => {
+                    // enqueue immediately
+                    ctx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
             },
-            sin_zero: [0; 8],
         };
 
-        let bindres = libc::bind(
-            socket,
-            &sockaddr as *const _ as *const libc::sockaddr,
-            size_of::<libc::sockaddr_in>() as _,
-        );
+        loop {
+            match this.reactor.poll_next(token, ctx) {
+                Poll::Ready(Some(completion)) => {
+                    this.sent.get_or_insert(completion.result);
+                }
+                // The send has finished once the buffer is released
+                Poll::Ready(None) => {
+                    this.token = None;
+
+                    let sent = this.sent.take().expect("send finished without a result");
+                    let res = if sent < 0 {
+                        Err(Error::from_raw_os_error(-sent))
+                    } else {
+                        Ok(sent as usize)
+                    };
+
+                    return Poll::Ready((res, this.buf.take().unwrap()));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Drop for SendZcMeta {
+    fn drop(&mut self) {
+        // The buffer is dropped only once the kernel has released it
+        if let Some(buf) = self.buf.take() {
+            match self.token.take() {
+                Some(token) => self
+                    .reactor
+                    .cancel_op_then(token, Box::new(move || drop(buf))),
+                None => drop(buf),
+            }
+        }
+    }
+}
+
+/// RecvStream is the stream of the data received on a connection, returned by
+/// [TcpStream::recv_stream]. It ends once the peer closes the connection.
+///
+/// If every buffer of the ring is leased then the stream yields an error
+/// (`ENOBUFS`), it picks up again once some leases are dropped. The receive
+/// is armed again only then, rather than failing over and over.
+pub struct RecvStream {
+    reactor: &'static dyn Backend,
+    req: MultishotRequest,
+    ring: &'static BufRing,
+    eof: bool,
+    /// starved is set once the ring has run dry, the stream waits for a
+    /// lease to be dropped then.
+    starved: bool,
+}
+
+impl RecvStream {
+    /// next waits for the next chunk of data, None once the connection is
+    /// closed.
+    pub async fn next(&mut self) -> Option<Result<BufLease>> {
+        std::future::poll_fn(|ctx| self.poll_next(ctx)).await
+    }
+
+    pub fn poll_next(&mut self, ctx: &mut Context<'_>) -> Poll<Option<Result<BufLease>>> {
+        if self.eof {
+            return Poll::Ready(None);
+        }
+
+        if self.starved {
+            if self.ring.exhausted() {
+                self.ring.wait(ctx.waker());
+                return Poll::Pending;
+            }
+            self.starved = false;
+        }
+
+        let completion = match std::task::ready!(unsafe { self.req.poll_next(self.reactor, ctx) }) {
+            Ok(completion) => completion,
+            Err(err) => {
+                self.starved = err.raw_os_error() == Some(libc::ENOBUFS);
+                return Poll::Ready(Some(Err(err)));
+            }
+        };
 
-        if bindres == 0 {
-            Ok(())
-        } else {
-            Err(Error::from_raw_os_error(-bindres))
+        // Only the end of the stream completes without a buffer
+        match completion.buf_id {
+            Some(bid) => {
+                let lease = self.ring.lease(bid, completion.result as usize);
+                Poll::Ready(Some(Ok(lease)))
+            }
+            None => {
+                self.eof = true;
+                Poll::Ready(None)
+            }
         }
     }
+}
 
-    unsafe fn _bind6(_socket: libc::c_int, _addr: &Ipv6Addr, _port: u16) -> Result<()> {
-        unimplemented!()
+impl Drop for RecvStream {
+    fn drop(&mut self) {
+        // The buffers picked after the stream is gone go back to the ring
+        let ring = self.ring;
+        self.req.cancel_with(
+            self.reactor,
+            Box::new(move |completion| {
+                if let Some(bid) = completion.buf_id {
+                    ring.provide(bid);
+                }
+            }),
+        );
     }
 }
 
 impl TcpStream {
+    /// connect opens a connection to the address, either IPv4 or IPv6.
+    pub async fn connect(addr: &str) -> Result<TcpStream> {
+        let parsed_addr: SocketAddr = addr
+            .parse()
+            .map_err(|err| Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let domain = match parsed_addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+
+        let socket = TcpListner::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).await?;
+
+        if let Err(err) = Self::_connect(socket, sockaddr_storage(&parsed_addr)).await {
+            let _ = io::raw::close(Fd::Raw(socket)).await;
+            return Err(err);
+        }
+
+        Ok(TcpStream {
+            connfd: Fd::Raw(socket),
+        })
+    }
+
+    /// _connect connects the socket to the encoded address, either an IP
+    /// address or the path of a unix socket.
+    fn _connect(
+        socket: RawFd,
+        (storage, addrlen): (libc::sockaddr_storage, libc::socklen_t),
+    ) -> ConnectMeta {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let storage = Box::new(storage);
+
+        let connect_op = Op::Connect {
+            fd: Fd::Raw(socket),
+            addr: &*storage as *const _ as *const libc::sockaddr,
+            addrlen,
+        };
+
+        let req = ReactorRequest::new(connect_op);
+        ConnectMeta {
+            reactor,
+            req,
+            _addr: Some(storage),
+        }
+    }
+
+    /// read returns the future of the read, it resolves to the number of bytes
+    /// read and can be bounded via [TcpReadMeta::with_deadline].
     #[inline(always)]
-    pub async fn read(&self, buf: &'_ mut [u8]) -> Result<usize> {
-        let readbytes = Self::_read(self.connfd, buf).await?;
-        Ok(readbytes as usize)
+    pub fn read<'a>(&self, buf: &'a mut [u8]) -> TcpReadMeta<'a> {
+        Self::_read(self.connfd, buf)
     }
 
+    /// send returns the future of the send, it resolves to the number of bytes
+    /// sent and can be bounded via [TcpWriteMeta::with_deadline].
     #[inline(always)]
-    pub async fn send(&mut self, buf: &'_ [u8]) -> Result<usize> {
-        let sendbytes = Self::_write(self.connfd, buf).await?;
-        Ok(sendbytes as usize)
+    pub fn send<'a>(&mut self, buf: &'a [u8]) -> TcpWriteMeta<'a> {
+        Self::_write(self.connfd, buf)
+    }
+
+    /// send_vectored returns the future of the send of the buffers one after
+    /// the other, as a single op. It resolves to the number of bytes sent.
+    pub fn send_vectored<'a>(&mut self, bufs: &'a [IoSlice<'_>]) -> SendMsgMeta<'a> {
+        send_msg(self.connfd, MsgHdr::gather(bufs))
+    }
+
+    /// recv_vectored returns the future of the receive into the buffers one
+    /// after the other, as a single op. It resolves to the number of bytes
+    /// received, 0 once the peer has closed the connection.
+    pub fn recv_vectored<'a>(&self, bufs: &'a mut [IoSliceMut<'_>]) -> RecvMsgMeta<'a, ()> {
+        RecvMsgMeta::new(self.connfd, MsgHdr::scatter(bufs), 0, |_| Ok(()))
+    }
+
+    /// recv_stream returns the stream of the data received on the
+    /// connection. The data is received into the buffers of the ring, which
+    /// the kernel picks only once the data arrives.
+    pub fn recv_stream(&self, ring: &'static BufRing) -> RecvStream {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let req = MultishotRequest::new(Op::RecvMulti {
+            fd: self.connfd,
+            buf_group: ring.group(),
+        });
+        RecvStream {
+            reactor,
+            req,
+            ring,
+            eof: false,
+            starved: false,
+        }
+    }
+
+    /// send_zc sends the buffer without copying it into the kernel, the
+    /// future resolves once the kernel no longer refers to the buffer. It
+    /// pays off for large buffers.
+    pub fn send_zc(&mut self, buf: Vec<u8>) -> SendZcMeta {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        let send_op = Op::SendZc {
+            fd: self.connfd,
+            buf: buf.as_ptr(),
+            len: buf.len() as u32,
+        };
+
+        SendZcMeta {
+            reactor,
+            op: send_op,
+            token: None,
+            sent: None,
+            buf: Some(buf),
+        }
     }
 
     #[inline(always)]
@@ -183,37 +549,777 @@ impl TcpStream {
         Ok(())
     }
 
-    fn _write(fd: RawFd, buf: &'_ [u8]) -> TcpWriteMeta<'_> {
+    /// as_raw_fd returns the file descriptor of the connection, it can be
+    /// passed to another process via [UnixStream::send_with_fds].
+    ///
+    /// # Panics
+    /// Panics if the connection lives in the fixed file table, it has no file
+    /// descriptor then.
+    pub fn as_raw_fd(&self) -> RawFd {
+        match self.connfd {
+            Fd::Raw(fd) => fd,
+            Fd::Fixed(_) => panic!("fixed connections have no file descriptor"),
+        }
+    }
+
+    fn _write(fd: Fd, buf: &'_ [u8]) -> TcpWriteMeta<'_> {
+        BorrowMeta::new(Op::Send {
+            fd,
+            buf: buf.as_ptr(),
+            len: buf.len() as u32,
+        })
+    }
+
+    fn _read(fd: Fd, buf: &'_ mut [u8]) -> TcpReadMeta<'_> {
+        BorrowMeta::new(Op::Recv {
+            fd,
+            buf: buf.as_mut_ptr(),
+            len: buf.len() as u32,
+        })
+    }
+}
+
+/// UdpSocket is a datagram socket. It exchanges datagrams with any peer via
+/// [UdpSocket::send_to] and [UdpSocket::recv_from], or with the peer it is
+/// connected to via [UdpSocket::send] and [UdpSocket::recv].
+#[derive(Clone, Copy)]
+pub struct UdpSocket {
+    sock_fd: RawFd,
+}
+
+/// SendMsgMeta is the future of the sends of a message, like
+/// [UdpSocket::send_to], it resolves to the number of bytes sent. The message
+/// points to the borrowed data, hence like a [BorrowMeta] dropping the future
+/// while the send is in-flight waits for its cancellation.
+pub struct SendMsgMeta<'a> {
+    reactor: &'static dyn Backend,
+    req: ReactorRequest,
+    /// _msg is pointed by the op, it lives as long as the request
+    _msg: Box<MsgHdr>,
+
+    phantom: PhantomData<&'a [u8]>,
+}
+
+impl SendMsgMeta<'_> {
+    /// with_deadline bounds the send, see [ReactorRequest::set_deadline]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.req.set_deadline(deadline);
+        self
+    }
+}
+
+impl Future for SendMsgMeta<'_> {
+    type Output = Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        unsafe { this.req.poll(this.reactor, ctx) }.map(|res| res.map(|n| n as usize))
+    }
+}
+
+impl Drop for SendMsgMeta<'_> {
+    fn drop(&mut self) {
+        self.req.cancel_and_wait(self.reactor);
+    }
+}
+
+/// RecvMsgMeta is the future of the receives of a message, like
+/// [UdpSocket::recv_from]. It resolves to the number of bytes received along
+/// with what else the message carried, like the address of the sender. The
+/// data is received straight into the borrowed buffers, see [SendMsgMeta].
+pub struct RecvMsgMeta<'a, T> {
+    reactor: &'static dyn Backend,
+    req: ReactorRequest,
+    msg: Box<MsgHdr>,
+    /// parse extracts the rest of the output from the received message
+    parse: fn(&MsgHdr) -> Result<T>,
+
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+impl<T> RecvMsgMeta<'_, T> {
+    /// new returns the future of the receive of the message
+    fn new(fd: Fd, mut msg: Box<MsgHdr>, flags: u32, parse: fn(&MsgHdr) -> Result<T>) -> Self {
         let reactor = unsafe { PerThreadReactor::this() };
 
-        let send_op = io_uring::opcode::Send::new(
-            io_uring::types::Fd(fd),
-            buf.as_ptr() as *const _,
-            buf.len() as u32,
-        );
+        let recvmsg_op = Op::RecvMsg {
+            fd,
+            msg: &mut msg.hdr,
+            flags,
+        };
 
-        let req = ReactorRequest::new(send_op.build());
-        TcpWriteMeta {
+        let req = ReactorRequest::new(recvmsg_op);
+        RecvMsgMeta {
             reactor,
             req,
-            phantom: PhantomData {},
+            msg,
+            parse,
+            phantom: PhantomData,
         }
     }
 
-    fn _read(fd: RawFd, buf: &'_ mut [u8]) -> TcpReadMeta<'_> {
-        let reactor = unsafe { PerThreadReactor::this() };
+    /// with_deadline bounds the receive, see [ReactorRequest::set_deadline]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.req.set_deadline(deadline);
+        self
+    }
+}
 
-        let recv_op = io_uring::opcode::Recv::new(
-            io_uring::types::Fd(fd),
-            buf.as_mut_ptr() as *mut _,
-            buf.len() as u32,
-        );
+impl<T> Future for RecvMsgMeta<'_, T> {
+    type Output = Result<(usize, T)>;
 
-        let req = ReactorRequest::new(recv_op.build());
-        TcpReadMeta {
-            reactor,
-            req,
-            phantom: PhantomData {},
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        let n = std::task::ready!(unsafe { this.req.poll(this.reactor, ctx) })? as usize;
+
+        Poll::Ready((this.parse)(&this.msg).map(|rest| (n, rest)))
+    }
+}
+
+impl<T> Drop for RecvMsgMeta<'_, T> {
+    fn drop(&mut self) {
+        self.req.cancel_and_wait(self.reactor);
+    }
+}
+
+/// send_msg sends the message on the socket
+fn send_msg<'a>(fd: Fd, msg: Box<MsgHdr>) -> SendMsgMeta<'a> {
+    let reactor = unsafe { PerThreadReactor::this() };
+
+    let sendmsg_op = Op::SendMsg { fd, msg: &msg.hdr };
+
+    let req = ReactorRequest::new(sendmsg_op);
+    SendMsgMeta {
+        reactor,
+        req,
+        _msg: msg,
+        phantom: PhantomData {},
+    }
+}
+
+/// MsgHdr is the message of [Op::SendMsg] and [Op::RecvMsg]. The header
+/// points to the iovecs, the address and the control data next to it, hence
+/// it is always boxed.
+///
+/// The iovecs point to the borrowed memory of the caller, the futures of the
+/// messages keep the borrow till the backend is done with it.
+struct MsgHdr {
+    hdr: libc::msghdr,
+    iovecs: Vec<libc::iovec>,
+    addr: libc::sockaddr_storage,
+    /// control holds the ancillary data, it is made of words to keep the
+    /// headers within aligned.
+    control: Vec<u64>,
+}
+
+impl MsgHdr {
+    /// new returns the message of the data the iovecs point to, with neither
+    /// an address nor control data.
+    fn new(iovecs: Vec<libc::iovec>) -> Box<MsgHdr> {
+        // # Safety
+        // The header and the address are plain old data.
+        let mut msg = Box::new(MsgHdr {
+            hdr: unsafe { std::mem::zeroed() },
+            iovecs,
+            addr: unsafe { std::mem::zeroed() },
+            control: Vec::new(),
+        });
+
+        msg.hdr.msg_iov = msg.iovecs.as_mut_ptr();
+        msg.hdr.msg_iovlen = msg.iovecs.len() as _;
+
+        msg
+    }
+
+    /// gather returns the message of the buffers one after the other
+    fn gather(bufs: &[IoSlice<'_>]) -> Box<MsgHdr> {
+        MsgHdr::new(
+            bufs.iter()
+                .map(|buf| libc::iovec {
+                    iov_base: buf.as_ptr() as *mut libc::c_void,
+                    iov_len: buf.len(),
+                })
+                .collect(),
+        )
+    }
+
+    /// scatter returns the message to be received into the buffers one after
+    /// the other
+    fn scatter(bufs: &mut [IoSliceMut<'_>]) -> Box<MsgHdr> {
+        MsgHdr::new(
+            bufs.iter_mut()
+                .map(|buf| libc::iovec {
+                    iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: buf.len(),
+                })
+                .collect(),
+        )
+    }
+
+    /// send_to sets the address the message is sent to
+    fn send_to(&mut self, (storage, addrlen): (libc::sockaddr_storage, libc::socklen_t)) {
+        self.addr = storage;
+        self.hdr.msg_name = &mut self.addr as *mut _ as *mut libc::c_void;
+        self.hdr.msg_namelen = addrlen;
+    }
+
+    /// recv_from makes room for the address of the sender
+    fn recv_from(&mut self) {
+        self.hdr.msg_name = &mut self.addr as *mut _ as *mut libc::c_void;
+        self.hdr.msg_namelen = size_of::<libc::sockaddr_storage>() as _;
+    }
+
+    /// send_fds passes the file descriptors along the message (SCM_RIGHTS)
+    fn send_fds(&mut self, fds: &[RawFd]) {
+        if fds.is_empty() {
+            return;
+        }
+
+        let data_len = std::mem::size_of_val(fds) as u32;
+        self.set_control(data_len);
+
+        // # Safety
+        // The control data has room for a header and the file descriptors.
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&self.hdr);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+            std::ptr::copy_nonoverlapping(
+                fds.as_ptr(),
+                libc::CMSG_DATA(cmsg) as *mut RawFd,
+                fds.len(),
+            );
+        }
+    }
+
+    /// recv_fds makes room for up to `max` file descriptors passed along the
+    /// message.
+    fn recv_fds(&mut self, max: usize) {
+        self.set_control((max * size_of::<RawFd>()) as u32);
+    }
+
+    /// set_control allocates zeroed control data for a single header with
+    /// `data_len` bytes of data.
+    fn set_control(&mut self, data_len: u32) {
+        // # Safety
+        // CMSG_SPACE merely computes the size.
+        let space = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+
+        self.control = vec![0; space.div_ceil(size_of::<u64>())];
+        self.hdr.msg_control = self.control.as_mut_ptr() as *mut libc::c_void;
+        self.hdr.msg_controllen = space as _;
+    }
+
+    /// inet_addr returns the IPv4 or IPv6 address of the sender
+    fn inet_addr(&self) -> Result<SocketAddr> {
+        // # Safety
+        // The address lies within the message.
+        let addr = unsafe {
+            sockaddr_to_addr(
+                &self.addr as *const _ as *const libc::sockaddr,
+                self.hdr.msg_namelen,
+            )
+        };
+
+        addr.ok_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unsupported address family",
+            )
+        })
+    }
+
+    /// unix_path returns the path of the sender, None if its socket is not
+    /// bound to one.
+    fn unix_path(&self) -> Result<Option<PathBuf>> {
+        // # Safety
+        // sockaddr_storage is large enough for any address.
+        let addr = unsafe { &*(&self.addr as *const _ as *const libc::sockaddr_un) };
+
+        let len = (self.hdr.msg_namelen as usize)
+            .saturating_sub(size_of::<libc::sa_family_t>())
+            .min(addr.sun_path.len());
+        let path: Vec<u8> = addr.sun_path[..len]
+            .iter()
+            .map(|c| *c as u8)
+            .take_while(|c| *c != 0)
+            .collect();
+
+        if path.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(PathBuf::from(OsString::from_vec(path))))
+    }
+
+    /// fds returns the file descriptors passed along the received message
+    fn fds(&self) -> Result<Vec<RawFd>> {
+        let mut fds = Vec::new();
+
+        // # Safety
+        // The headers lie within the control data, which is zeroed past the
+        // ones the kernel wrote.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&self.hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+
+                    for i in 0..data_len / size_of::<RawFd>() {
+                        fds.push(data.add(i).read_unaligned());
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&self.hdr, cmsg);
+            }
+        }
+
+        Ok(fds)
+    }
+}
+
+impl UdpSocket {
+    /// bind creates a socket bound to the address, either IPv4 or IPv6. A
+    /// port of 0 binds to an ephemeral port.
+    pub async fn bind(addr: &str) -> Result<UdpSocket> {
+        let parsed_addr: SocketAddr = addr
+            .parse()
+            .map_err(|err| Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let domain = match parsed_addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+
+        let socket = TcpListner::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0).await?;
+
+        let bound = match parsed_addr {
+            SocketAddr::V4(ref a) => TcpListner::_bind4(socket, a.ip(), a.port()).await,
+            SocketAddr::V6(ref a) => TcpListner::_bind6(socket, a).await,
+        };
+
+        if let Err(err) = bound {
+            let _ = io::raw::close(Fd::Raw(socket)).await;
+            return Err(err);
+        }
+
+        Ok(UdpSocket { sock_fd: socket })
+    }
+
+    /// connect sets the peer of the socket, [UdpSocket::send] sends to it and
+    /// [UdpSocket::recv] receives only from it.
+    pub async fn connect(&self, addr: &str) -> Result<()> {
+        let parsed_addr: SocketAddr = addr
+            .parse()
+            .map_err(|err| Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let _ = TcpStream::_connect(self.sock_fd, sockaddr_storage(&parsed_addr)).await?;
+        Ok(())
+    }
+
+    /// send sends the datagram to the peer of the socket, see
+    /// [UdpSocket::connect].
+    #[inline(always)]
+    pub fn send<'a>(&self, buf: &'a [u8]) -> TcpWriteMeta<'a> {
+        TcpStream::_write(Fd::Raw(self.sock_fd), buf)
+    }
+
+    /// recv receives a datagram from the peer of the socket, the part of the
+    /// datagram which does not fit in the buffer is discarded.
+    #[inline(always)]
+    pub fn recv<'a>(&self, buf: &'a mut [u8]) -> TcpReadMeta<'a> {
+        TcpStream::_read(Fd::Raw(self.sock_fd), buf)
+    }
+
+    /// send_to sends the datagram to the address, it resolves to the number
+    /// of bytes sent.
+    pub fn send_to<'a>(&self, buf: &'a [u8], addr: &SocketAddr) -> SendMsgMeta<'a> {
+        let mut msg = MsgHdr::gather(&[IoSlice::new(buf)]);
+        msg.send_to(sockaddr_storage(addr));
+
+        send_msg(Fd::Raw(self.sock_fd), msg)
+    }
+
+    /// recv_from receives a datagram from any peer, it resolves to the size
+    /// of the datagram and the address of its sender. Like [UdpSocket::recv]
+    /// the part which does not fit in the buffer is discarded.
+    pub fn recv_from<'a>(&self, buf: &'a mut [u8]) -> RecvMsgMeta<'a, SocketAddr> {
+        let mut msg = MsgHdr::scatter(&mut [IoSliceMut::new(buf)]);
+        msg.recv_from();
+
+        RecvMsgMeta::new(Fd::Raw(self.sock_fd), msg, 0, MsgHdr::inet_addr)
+    }
+
+    #[inline(always)]
+    pub async fn close(&mut self) -> Result<()> {
+        let _ = io::raw::close(Fd::Raw(self.sock_fd)).await?;
+        Ok(())
+    }
+}
+
+/// UNIX_MAX_FDS is the most file descriptors received along a message of a
+/// unix socket, the kernel closes the ones beyond.
+pub const UNIX_MAX_FDS: usize = 16;
+
+/// UnixListener is a unix stream socket listening at a path
+#[derive(Clone, Copy)]
+pub struct UnixListener {
+    sock_fd: RawFd,
+}
+
+/// UnixStream is a connected unix stream socket. Besides data it carries
+/// file descriptors between processes, see [UnixStream::send_with_fds].
+#[derive(Clone, Copy)]
+pub struct UnixStream {
+    fd: RawFd,
+}
+
+/// UnixDatagram is a unix datagram socket, like [UdpSocket] it exchanges
+/// datagrams with any socket via [UnixDatagram::send_to] and
+/// [UnixDatagram::recv_from], or with the socket it is connected to.
+#[derive(Clone, Copy)]
+pub struct UnixDatagram {
+    sock_fd: RawFd,
+}
+
+impl UnixListener {
+    /// bind creates a socket listening at the path, which must not exist
+    /// yet.
+    pub async fn bind(path: &str, backlog: i32) -> Result<UnixListener> {
+        let socket = bind_unix(path, libc::SOCK_STREAM).await?;
+
+        if let Err(err) = TcpListner::listen(socket, backlog).await {
+            let _ = io::raw::close(Fd::Raw(socket)).await;
+            return Err(err);
+        }
+
+        Ok(UnixListener { sock_fd: socket })
+    }
+
+    #[inline(always)]
+    pub async fn accept(&self) -> Result<UnixStream> {
+        let fd = TcpListner::_accept(self.sock_fd, false).await?;
+        Ok(UnixStream { fd })
+    }
+
+    #[inline(always)]
+    pub async fn close(&mut self) -> Result<()> {
+        let _ = io::raw::close(Fd::Raw(self.sock_fd)).await?;
+        Ok(())
+    }
+}
+
+impl UnixStream {
+    /// connect opens a connection to the socket listening at the path
+    pub async fn connect(path: &str) -> Result<UnixStream> {
+        let addr = sockaddr_un(path)?;
+        let socket =
+            TcpListner::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0).await?;
+
+        if let Err(err) = TcpStream::_connect(socket, addr).await {
+            let _ = io::raw::close(Fd::Raw(socket)).await;
+            return Err(err);
         }
+
+        Ok(UnixStream { fd: socket })
+    }
+
+    /// read returns the future of the read, it resolves to the number of bytes
+    /// read and can be bounded via [TcpReadMeta::with_deadline].
+    #[inline(always)]
+    pub fn read<'a>(&self, buf: &'a mut [u8]) -> TcpReadMeta<'a> {
+        TcpStream::_read(Fd::Raw(self.fd), buf)
+    }
+
+    /// send returns the future of the send, it resolves to the number of bytes
+    /// sent and can be bounded via [TcpWriteMeta::with_deadline].
+    #[inline(always)]
+    pub fn send<'a>(&mut self, buf: &'a [u8]) -> TcpWriteMeta<'a> {
+        TcpStream::_write(Fd::Raw(self.fd), buf)
+    }
+
+    /// send_with_fds sends the data along with the file descriptors, the
+    /// peer receives descriptors of its own referring to the same files (see
+    /// [UnixStream::recv_with_fds]). The descriptors stay open here, and
+    /// `buf` must not be empty for them to be sent.
+    pub fn send_with_fds<'a>(&mut self, buf: &'a [u8], fds: &[RawFd]) -> SendMsgMeta<'a> {
+        let mut msg = MsgHdr::gather(&[IoSlice::new(buf)]);
+        msg.send_fds(fds);
+
+        send_msg(Fd::Raw(self.fd), msg)
+    }
+
+    /// recv_with_fds receives data along with the file descriptors passed
+    /// with it, up to [UNIX_MAX_FDS]. The descriptors are close-on-exec and
+    /// are taken over via [FromRawFd], like [TcpStream::from_raw_fd].
+    ///
+    /// NOTE: The descriptors are leaked if the future is dropped once the
+    /// receive has completed but before it has been polled.
+    pub fn recv_with_fds<'a>(&self, buf: &'a mut [u8]) -> RecvMsgMeta<'a, Vec<RawFd>> {
+        let mut msg = MsgHdr::scatter(&mut [IoSliceMut::new(buf)]);
+        msg.recv_fds(UNIX_MAX_FDS);
+
+        RecvMsgMeta::new(
+            Fd::Raw(self.fd),
+            msg,
+            libc::MSG_CMSG_CLOEXEC as u32,
+            MsgHdr::fds,
+        )
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    #[inline(always)]
+    pub async fn close(&mut self) -> Result<()> {
+        let _ = io::raw::close(Fd::Raw(self.fd)).await?;
+        Ok(())
+    }
+}
+
+impl FromRawFd for UnixStream {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        UnixStream { fd }
+    }
+}
+
+impl AsyncReadRent for UnixStream {
+    fn read<B: IoBufMut>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        recv_rent(Fd::Raw(self.fd), buf)
+    }
+}
+
+impl AsyncWriteRent for UnixStream {
+    fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+        send_rent(Fd::Raw(self.fd), buf)
+    }
+}
+
+impl UnixDatagram {
+    /// bind creates a socket bound to the path, which must not exist yet.
+    pub async fn bind(path: &str) -> Result<UnixDatagram> {
+        let socket = bind_unix(path, libc::SOCK_DGRAM).await?;
+        Ok(UnixDatagram { sock_fd: socket })
+    }
+
+    /// unbound creates a socket bound to no path, it sends datagrams but no
+    /// reply can be sent back to it unless it is connected.
+    pub async fn unbound() -> Result<UnixDatagram> {
+        let socket =
+            TcpListner::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0).await?;
+        Ok(UnixDatagram { sock_fd: socket })
+    }
+
+    /// connect sets the peer of the socket, [UnixDatagram::send] sends to it
+    /// and [UnixDatagram::recv] receives only from it.
+    pub async fn connect(&self, path: &str) -> Result<()> {
+        let _ = TcpStream::_connect(self.sock_fd, sockaddr_un(path)?).await?;
+        Ok(())
+    }
+
+    /// send sends the datagram to the peer of the socket, see
+    /// [UnixDatagram::connect].
+    #[inline(always)]
+    pub fn send<'a>(&self, buf: &'a [u8]) -> TcpWriteMeta<'a> {
+        TcpStream::_write(Fd::Raw(self.sock_fd), buf)
+    }
+
+    /// recv receives a datagram, the part of the datagram which does not fit
+    /// in the buffer is discarded.
+    #[inline(always)]
+    pub fn recv<'a>(&self, buf: &'a mut [u8]) -> TcpReadMeta<'a> {
+        TcpStream::_read(Fd::Raw(self.sock_fd), buf)
+    }
+
+    /// send_to sends the datagram to the socket bound to the path
+    pub async fn send_to(&self, buf: &[u8], path: &str) -> Result<usize> {
+        let mut msg = MsgHdr::gather(&[IoSlice::new(buf)]);
+        msg.send_to(sockaddr_un(path)?);
+
+        send_msg(Fd::Raw(self.sock_fd), msg).await
     }
+
+    /// recv_from receives a datagram from any socket, it resolves to the size
+    /// of the datagram and the path of the sender, None if it is unbound.
+    pub fn recv_from<'a>(&self, buf: &'a mut [u8]) -> RecvMsgMeta<'a, Option<PathBuf>> {
+        let mut msg = MsgHdr::scatter(&mut [IoSliceMut::new(buf)]);
+        msg.recv_from();
+
+        RecvMsgMeta::new(Fd::Raw(self.sock_fd), msg, 0, MsgHdr::unix_path)
+    }
+
+    /// send_with_fds sends the datagram along with the file descriptors to
+    /// the peer of the socket, see [UnixStream::send_with_fds].
+    pub fn send_with_fds<'a>(&self, buf: &'a [u8], fds: &[RawFd]) -> SendMsgMeta<'a> {
+        let mut msg = MsgHdr::gather(&[IoSlice::new(buf)]);
+        msg.send_fds(fds);
+
+        send_msg(Fd::Raw(self.sock_fd), msg)
+    }
+
+    /// recv_with_fds receives a datagram along with the file descriptors
+    /// passed with it, see [UnixStream::recv_with_fds].
+    pub fn recv_with_fds<'a>(&self, buf: &'a mut [u8]) -> RecvMsgMeta<'a, Vec<RawFd>> {
+        let mut msg = MsgHdr::scatter(&mut [IoSliceMut::new(buf)]);
+        msg.recv_fds(UNIX_MAX_FDS);
+
+        RecvMsgMeta::new(
+            Fd::Raw(self.sock_fd),
+            msg,
+            libc::MSG_CMSG_CLOEXEC as u32,
+            MsgHdr::fds,
+        )
+    }
+
+    #[inline(always)]
+    pub async fn close(&mut self) -> Result<()> {
+        let _ = io::raw::close(Fd::Raw(self.sock_fd)).await?;
+        Ok(())
+    }
+}
+
+/// recv_rent receives into the rented buffer, see [AsyncReadRent]
+fn recv_rent<B: IoBufMut>(fd: Fd, mut buf: B) -> RentMeta<B> {
+    let recv_op = Op::Recv {
+        fd,
+        buf: buf.write_ptr(),
+        len: buf.bytes_total() as u32,
+    };
+
+    RentMeta::read(recv_op, buf)
+}
+
+/// send_rent sends from the rented buffer, see [AsyncWriteRent]
+fn send_rent<B: IoBuf>(fd: Fd, buf: B) -> RentMeta<B> {
+    let send_op = Op::Send {
+        fd,
+        buf: buf.read_ptr(),
+        len: buf.bytes_init() as u32,
+    };
+
+    RentMeta::write(send_op, buf)
+}
+
+/// bind_unix creates a unix socket of the type, bound to the path
+async fn bind_unix(path: &str, socket_type: i32) -> Result<RawFd> {
+    let (storage, addrlen) = sockaddr_un(path)?;
+    let socket = TcpListner::socket(libc::AF_UNIX, socket_type | libc::SOCK_CLOEXEC, 0).await?;
+
+    let bound = TcpListner::sockctl(Op::Bind {
+        fd: socket,
+        addr: &storage as *const _ as *const libc::sockaddr,
+        addrlen,
+    })
+    .await;
+
+    if let Err(err) = bound {
+        let _ = io::raw::close(Fd::Raw(socket)).await;
+        return Err(err);
+    }
+
+    Ok(socket)
+}
+
+fn sockaddr_in(addr: &Ipv4Addr, port: u16) -> libc::sockaddr_in {
+    libc::sockaddr_in {
+        sin_family: libc::AF_INET as _,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from_be_bytes(addr.octets()).to_be(),
+        },
+        sin_zero: [0; 8],
+    }
+}
+
+fn sockaddr_in6(addr: &SocketAddrV6) -> libc::sockaddr_in6 {
+    libc::sockaddr_in6 {
+        sin6_family: libc::AF_INET6 as _,
+        sin6_port: addr.port().to_be(),
+        sin6_flowinfo: addr.flowinfo(),
+        sin6_addr: libc::in6_addr {
+            s6_addr: addr.ip().octets(),
+        },
+        sin6_scope_id: addr.scope_id(),
+    }
+}
+
+/// sockaddr_storage encodes the address, along with its length
+pub(crate) fn sockaddr_storage(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // # Safety
+    // sockaddr_storage is plain old data, and large enough for any address.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let storage_ptr = &mut storage as *mut libc::sockaddr_storage;
+
+    let addrlen = match addr {
+        SocketAddr::V4(a) => {
+            unsafe { *(storage_ptr as *mut libc::sockaddr_in) = sockaddr_in(a.ip(), a.port()) };
+            size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            unsafe { *(storage_ptr as *mut libc::sockaddr_in6) = sockaddr_in6(a) };
+            size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, addrlen as _)
+}
+
+/// sockaddr_to_addr decodes an `AF_INET` or `AF_INET6` socket address
+///
+/// # Safety
+/// `addr` must point to at least `addrlen` valid bytes.
+pub(crate) unsafe fn sockaddr_to_addr(
+    addr: *const libc::sockaddr,
+    addrlen: libc::socklen_t,
+) -> Option<SocketAddr> {
+    let addrlen = addrlen as usize;
+
+    match (*addr).sa_family as i32 {
+        libc::AF_INET if addrlen >= size_of::<libc::sockaddr_in>() => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 if addrlen >= size_of::<libc::sockaddr_in6>() => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// sockaddr_un encodes the path of a unix socket, along with its length
+fn sockaddr_un(path: &str) -> Result<(libc::sockaddr_storage, libc::socklen_t)> {
+    // # Safety
+    // sockaddr_storage is plain old data, and large enough for any address.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let addr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_un) };
+
+    // The path is nul terminated within sun_path
+    let path = path.as_bytes();
+    if path.is_empty() || path.contains(&0) || path.len() >= addr.sun_path.len() {
+        return Err(Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid unix socket path",
+        ));
+    }
+
+    addr.sun_family = libc::AF_UNIX as _;
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+
+    let addrlen = size_of::<libc::sa_family_t>() + path.len() + 1;
+    Ok((storage, addrlen as _))
 }
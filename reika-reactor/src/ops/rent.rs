@@ -0,0 +1,505 @@
+//! rent implements the I/O traits of reika. The buffers are owned and rented
+//! to the backend for the duration of an op, they are handed back along with
+//! the result. Unlike a borrowed buffer, a rented one stays alive till the
+//! backend is done with it even if the future of the op is dropped.
+//!
+//! ```ignore
+//! let (res, buf) = stream.read(Vec::with_capacity(4096)).await;
+//! let n = res?;
+//! ```
+//!
+//! NOTE: [crate::io::File] also has inherent `read`/`write` methods taking
+//! borrowed buffers, those take precedence over the methods of the traits.
+
+use std::{
+    future::Future,
+    io as stdio,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use crate::{
+    aligned::AlignedBuf,
+    backend::{Backend, Op},
+    fixed::FixedBuf,
+    PerThreadReactor, ReactorRequest,
+};
+
+/// BufResult is the output of an op on a rented buffer, the buffer is handed
+/// back whether the op succeeded or not.
+pub type BufResult<T, B> = (stdio::Result<T>, B);
+
+/// IoBuf is an owned buffer the backend reads from, like the data to write.
+///
+/// # Safety
+/// The memory at [IoBuf::read_ptr] must be valid for [IoBuf::bytes_init]
+/// bytes and must stay put when the buffer is moved, for as long as the
+/// buffer lives.
+pub unsafe trait IoBuf: 'static {
+    fn read_ptr(&self) -> *const u8;
+
+    /// bytes_init returns the number of bytes holding data
+    fn bytes_init(&self) -> usize;
+}
+
+/// IoBufMut is an owned buffer the backend writes into, like the buffer of a
+/// read. The data is written from the start of the buffer.
+///
+/// # Safety
+/// The memory at [IoBufMut::write_ptr] must be valid for
+/// [IoBufMut::bytes_total] bytes and must stay put when the buffer is moved,
+/// for as long as the buffer lives.
+pub unsafe trait IoBufMut: 'static {
+    fn write_ptr(&mut self) -> *mut u8;
+
+    /// bytes_total returns the number of bytes the buffer has room for
+    fn bytes_total(&mut self) -> usize;
+
+    /// set_init marks the first `pos` bytes as holding data
+    ///
+    /// # Safety
+    /// The first `pos` bytes must have been initialized.
+    unsafe fn set_init(&mut self, pos: usize);
+}
+
+unsafe impl IoBuf for Vec<u8> {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for Vec<u8> {
+    fn write_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn bytes_total(&mut self) -> usize {
+        self.capacity()
+    }
+
+    unsafe fn set_init(&mut self, pos: usize) {
+        if self.len() < pos {
+            self.set_len(pos);
+        }
+    }
+}
+
+unsafe impl IoBuf for Box<[u8]> {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for Box<[u8]> {
+    fn write_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn bytes_total(&mut self) -> usize {
+        self.len()
+    }
+
+    unsafe fn set_init(&mut self, _pos: usize) {}
+}
+
+unsafe impl IoBuf for &'static [u8] {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for &'static str {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for FixedBuf {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for FixedBuf {
+    fn write_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn bytes_total(&mut self) -> usize {
+        self.capacity()
+    }
+
+    unsafe fn set_init(&mut self, pos: usize) {
+        if self.len() < pos {
+            self.set_len(pos);
+        }
+    }
+}
+
+unsafe impl IoBuf for AlignedBuf {
+    fn read_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for AlignedBuf {
+    fn write_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    fn bytes_total(&mut self) -> usize {
+        self.len()
+    }
+
+    unsafe fn set_init(&mut self, _pos: usize) {}
+}
+
+/// Slice is the data of a rented buffer past `begin`, it lets a write carry
+/// on from where a short one stopped.
+pub struct Slice<B> {
+    buf: B,
+    begin: usize,
+}
+
+impl<B: IoBuf> Slice<B> {
+    /// new returns the data of the buffer past `begin`
+    ///
+    /// # Panics
+    /// Panics if `begin` is past the data of the buffer.
+    pub fn new(buf: B, begin: usize) -> Self {
+        assert!(begin <= buf.bytes_init(), "begin is past the data");
+        Self { buf, begin }
+    }
+
+    pub fn begin(&self) -> usize {
+        self.begin
+    }
+
+    /// advance moves the beginning of the slice `n` bytes further
+    ///
+    /// # Panics
+    /// Panics if that is past the data of the buffer.
+    pub fn advance(&mut self, n: usize) {
+        assert!(n <= self.bytes_init(), "advanced past the data");
+        self.begin += n;
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+}
+
+unsafe impl<B: IoBuf> IoBuf for Slice<B> {
+    fn read_ptr(&self) -> *const u8 {
+        // # Safety
+        // The beginning lies within the data of the buffer.
+        unsafe { self.buf.read_ptr().add(self.begin) }
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.buf.bytes_init() - self.begin
+    }
+}
+
+/// AsyncReadRent reads into rented buffers, it is implemented by the files
+/// and the stream sockets.
+pub trait AsyncReadRent {
+    /// read reads into the buffer, up to [IoBufMut::bytes_total] bytes. It
+    /// resolves to the number of bytes read, 0 once the end is reached.
+    fn read<B: IoBufMut>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>>;
+}
+
+/// AsyncWriteRent writes from rented buffers, it is implemented by the files
+/// and the stream sockets.
+pub trait AsyncWriteRent {
+    /// write writes the data of the buffer, up to [IoBuf::bytes_init] bytes.
+    /// It resolves to the number of bytes written, which may be less.
+    fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>>;
+
+    /// write_all writes all the data of the buffer, carrying on after short
+    /// writes. It fails with [stdio::ErrorKind::WriteZero] if nothing can be
+    /// written anymore.
+    fn write_all<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<(), B>> {
+        async move {
+            let mut slice = Slice::new(buf, 0);
+
+            while slice.bytes_init() > 0 {
+                let (res, written) = self.write(slice).await;
+                slice = written;
+
+                match res {
+                    Ok(0) => return (Err(stdio::ErrorKind::WriteZero.into()), slice.into_inner()),
+                    Ok(n) => slice.advance(n),
+                    Err(err) => return (Err(err), slice.into_inner()),
+                }
+            }
+
+            (Ok(()), slice.into_inner())
+        }
+    }
+
+    /// flush writes out whatever the writer holds on to, it is a no-op for
+    /// the writers which hold on to nothing.
+    fn flush(&mut self) -> impl Future<Output = stdio::Result<()>> {
+        std::future::ready(Ok(()))
+    }
+}
+
+/// RentMeta is the future of an op on a rented buffer, it owns the buffer
+/// till the op completes and hands it back along with the result.
+pub struct RentMeta<B: 'static> {
+    reactor: &'static dyn Backend,
+    req: ReactorRequest,
+    buf: Option<B>,
+
+    /// filled is called with the result of the op, a read marks the bytes it
+    /// read as initialized.
+    filled: fn(&mut B, usize),
+    /// failed is the error the future resolves to without issuing the op,
+    /// see [RentMeta::checked].
+    failed: Option<stdio::Error>,
+}
+
+impl<B: IoBufMut> RentMeta<B> {
+    /// read returns the future of `op`, an op reading into the buffer
+    pub(crate) fn read(op: Op, buf: B) -> Self {
+        // # Safety
+        // The backend has written the bytes it has read.
+        Self::new(op, buf, |buf, n| unsafe { buf.set_init(n) })
+    }
+}
+
+impl<B: IoBuf> RentMeta<B> {
+    /// write returns the future of `op`, an op writing from the buffer
+    pub(crate) fn write(op: Op, buf: B) -> Self {
+        Self::new(op, buf, |_, _| {})
+    }
+}
+
+impl<B: 'static> RentMeta<B> {
+    pub(crate) fn new(op: Op, buf: B, filled: fn(&mut B, usize)) -> Self {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        RentMeta {
+            reactor,
+            req: ReactorRequest::new(op),
+            buf: Some(buf),
+            filled,
+            failed: None,
+        }
+    }
+
+    /// checked fails the future with the error of `res`, if any, rather than
+    /// issuing the op. It is for the checks of the op made upfront.
+    pub(crate) fn checked(mut self, res: stdio::Result<()>) -> Self {
+        self.failed = res.err();
+        self
+    }
+
+    /// with_deadline bounds the op, see [ReactorRequest::set_deadline]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.req.set_deadline(deadline);
+        self
+    }
+}
+
+// The buffer is never pinned, it is merely handed back
+impl<B: 'static> Unpin for RentMeta<B> {}
+
+impl<B: 'static> Future for RentMeta<B> {
+    type Output = BufResult<usize, B>;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        if let Some(err) = this.failed.take() {
+            let buf = this.buf.take().expect("polled after completion");
+            return Poll::Ready((Err(err), buf));
+        }
+
+        match unsafe { this.req.poll(this.reactor, ctx) } {
+            Poll::Ready(res) => {
+                let mut buf = this.buf.take().expect("polled after completion");
+                let res = res.map(|n| n as usize);
+                if let Ok(n) = res {
+                    (this.filled)(&mut buf, n);
+                }
+
+                Poll::Ready((res, buf))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<B: 'static> Drop for RentMeta<B> {
+    fn drop(&mut self) {
+        // The buffer is dropped only once the backend is done with it
+        if let Some(buf) = self.buf.take() {
+            self.req
+                .cancel_then(self.reactor, Box::new(move || drop(buf)));
+        }
+    }
+}
+
+/// BorrowMeta is the future of an op on borrowed memory, the backend reads
+/// from (or writes into) the memory of the caller without any copy. The
+/// memory may be reused as soon as the borrow ends, hence dropping the future
+/// while the op is in-flight blocks till the backend has processed its
+/// cancellation, see [ReactorRequest::cancel_and_wait].
+///
+/// It resolves to the number of bytes read or written.
+pub struct BorrowMeta<'a> {
+    reactor: &'static dyn Backend,
+    req: ReactorRequest,
+    /// failed is the error the future resolves to without issuing the op,
+    /// see [BorrowMeta::checked].
+    failed: Option<stdio::Error>,
+
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+impl BorrowMeta<'_> {
+    /// new returns the future of `op`, an op on the borrowed memory
+    pub(crate) fn new(op: Op) -> Self {
+        let reactor = unsafe { PerThreadReactor::this() };
+
+        BorrowMeta {
+            reactor,
+            req: ReactorRequest::new(op),
+            failed: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// checked fails the future with the error of `res`, if any, rather than
+    /// issuing the op, see [RentMeta::checked].
+    pub(crate) fn checked(mut self, res: stdio::Result<()>) -> Self {
+        self.failed = res.err();
+        self
+    }
+
+    /// with_deadline bounds the op, see [ReactorRequest::set_deadline]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.req.set_deadline(deadline);
+        self
+    }
+}
+
+impl Future for BorrowMeta<'_> {
+    type Output = stdio::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        if let Some(err) = this.failed.take() {
+            return Poll::Ready(Err(err));
+        }
+
+        unsafe { this.req.poll(this.reactor, ctx) }.map(|res| res.map(|n| n as usize))
+    }
+}
+
+impl Drop for BorrowMeta<'_> {
+    fn drop(&mut self) {
+        self.req.cancel_and_wait(self.reactor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, task::Waker};
+
+    use super::*;
+
+    /// Short writes at most `max` bytes at a time into `data`
+    struct Short {
+        data: Vec<u8>,
+        max: usize,
+    }
+
+    impl AsyncWriteRent for Short {
+        fn write<B: IoBuf>(&mut self, buf: B) -> impl Future<Output = BufResult<usize, B>> {
+            let n = buf.bytes_init().min(self.max);
+            // # Safety
+            // The data of the buffer is initialized.
+            let data = unsafe { std::slice::from_raw_parts(buf.read_ptr(), n) };
+            self.data.extend_from_slice(data);
+
+            std::future::ready((Ok(n), buf))
+        }
+    }
+
+    /// ready returns the output of a future which completes on its first poll
+    fn ready<F: Future>(fut: F) -> F::Output {
+        let mut ctx = Context::from_waker(Waker::noop());
+        match pin!(fut).poll(&mut ctx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future is not ready"),
+        }
+    }
+
+    #[test]
+    fn write_all_carries_on_after_short_writes() {
+        let mut writer = Short {
+            data: Vec::new(),
+            max: 3,
+        };
+
+        let (res, buf) = ready(writer.write_all(b"hello world".to_vec()));
+        res.unwrap();
+        assert_eq!(buf, b"hello world");
+        assert_eq!(writer.data, b"hello world");
+    }
+
+    #[test]
+    fn write_all_fails_once_nothing_is_written() {
+        let mut writer = Short {
+            data: Vec::new(),
+            max: 0,
+        };
+
+        let (res, buf) = ready(writer.write_all("stuck"));
+        assert_eq!(res.unwrap_err().kind(), stdio::ErrorKind::WriteZero);
+        assert_eq!(buf, "stuck");
+    }
+
+    #[test]
+    fn slice_skips_the_written_data() {
+        let mut slice = Slice::new(b"abcdef".to_vec(), 2);
+        slice.advance(1);
+
+        assert_eq!(slice.begin(), 3);
+        assert_eq!(slice.bytes_init(), 3);
+        // # Safety
+        // The slice points within the data of the buffer.
+        let data = unsafe { std::slice::from_raw_parts(slice.read_ptr(), slice.bytes_init()) };
+        assert_eq!(data, b"def");
+    }
+}
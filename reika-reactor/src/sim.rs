@@ -0,0 +1,2589 @@
+//! sim is a deterministic, in-memory [Backend] meant for testing.
+//!
+//! The backend executes the file ops against an in-memory filesystem and the
+//! socket ops (TCP and UDP) against a simulated network. Everything that
+//! could differ between two runs (latency of the ops, the order in which they
+//! complete and the injected errors) is derived from a single seed, hence a
+//! failing run can be reproduced by running it again with the same seed.
+//! Unix sockets are not simulated, creating one fails with `EAFNOSUPPORT`.
+//!
+//! Time is virtual as well, whenever no op is ready the clock jumps straight
+//! to the next op that will become ready. The timers follow the virtual clock,
+//! the deadlines are to be derived from [crate::core::now].
+//!
+//! ```ignore
+//! let sim = SimOptions::new().seed(42).io_error_rate(0.01).build();
+//! PerThreadReactor::install(Box::new(sim.clone())).ok().unwrap();
+//!
+//! // ... spawn the tasks, play the clients via `sim.connect(..)`
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    ffi::CStr,
+    io as stdio,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::RawFd,
+    rc::Rc,
+    sync::atomic::Ordering,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::backend::{
+    Backend, BufRingEntry, Completion, Fd, Inflight, Op, Timespec, CURRENT_POSITION,
+};
+use crate::net::{sockaddr_storage, sockaddr_to_addr};
+
+/// FIRST_FD is the first file descriptor handed out by the simulator, it is
+/// kept far from the real ones to make mixups obvious.
+const FIRST_FD: RawFd = 1000;
+
+/// FIRST_EPHEMERAL_PORT is the first port assigned to sockets bound to port 0
+const FIRST_EPHEMERAL_PORT: u16 = 32768;
+
+/// SimOptions configures a [SimBackend].
+#[derive(Clone, Copy)]
+pub struct SimOptions {
+    seed: u64,
+    min_latency: Duration,
+    max_latency: Duration,
+    io_error_rate: f64,
+}
+
+impl SimOptions {
+    pub fn new() -> Self {
+        Self {
+            seed: 0,
+            min_latency: Duration::from_micros(1),
+            max_latency: Duration::from_micros(100),
+            io_error_rate: 0.0,
+        }
+    }
+
+    /// seed sets the seed of the RNG driving the simulation
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// latency sets the range from which the (virtual) latency of every op is
+    /// picked.
+    pub fn latency(&mut self, min: Duration, max: Duration) -> &mut Self {
+        assert!(min <= max, "min latency must not exceed max latency");
+
+        self.min_latency = min;
+        self.max_latency = max;
+        self
+    }
+
+    /// io_error_rate sets the probability (`0.0..=1.0`) of a data op (read,
+    /// write, sync, send, recv, ...) failing with an injected error.
+    pub fn io_error_rate(&mut self, rate: f64) -> &mut Self {
+        self.io_error_rate = rate;
+        self
+    }
+
+    pub fn build(&self) -> SimBackend {
+        SimBackend {
+            state: Rc::new(RefCell::new(State::new(*self))),
+        }
+    }
+}
+
+impl Default for SimOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SimBackend is the simulation [Backend].
+///
+/// It is a handle to the shared state of the simulation, a clone is installed
+/// as the backend of the thread while the others can be used to drive the
+/// simulation from the outside (inspecting files, connecting peers, ...).
+#[derive(Clone)]
+pub struct SimBackend {
+    state: Rc<RefCell<State>>,
+}
+
+impl SimBackend {
+    pub fn new(seed: u64) -> Self {
+        SimOptions::new().seed(seed).build()
+    }
+
+    /// now returns the virtual time elapsed since the simulation started
+    pub fn now(&self) -> Duration {
+        self.state.borrow().now
+    }
+
+    /// write_file creates (or replaces) a file in the simulated filesystem,
+    /// the content is considered durable.
+    pub fn write_file(&self, path: &str, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let inode = state.fs.lookup_or_create(path);
+
+        let inode = &mut state.fs.inodes[inode];
+        inode.data = data.to_vec();
+        inode.durable = data.to_vec();
+    }
+
+    /// read_file returns the current content of a file in the simulated
+    /// filesystem.
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.borrow();
+        state
+            .fs
+            .paths
+            .get(path)
+            .map(|inode| state.fs.inodes[*inode].data.clone())
+    }
+
+    /// crash simulates a power loss, every file loses the data that was not
+    /// synced (via fsync or fdatasync) before the crash and every file
+    /// descriptor is closed.
+    ///
+    /// It is meant to be called once the tasks using the files are gone, the
+    /// ops of the tasks which are left behind fail with `EBADF`.
+    pub fn crash(&self) {
+        let mut state = self.state.borrow_mut();
+        for inode in state.fs.inodes.iter_mut() {
+            inode.data = inode.durable.clone();
+        }
+
+        for (_, fd) in std::mem::take(&mut state.fds) {
+            if let Desc::Socket(socket) = fd {
+                state.net.close(socket);
+            }
+        }
+
+        state.fail_at = None;
+        state.failed = false;
+    }
+
+    /// fail_at cuts the power once a write reaches `offset` of the file. The
+    /// data written to the file before `offset` reaches the disk, the rest of
+    /// the write and every op after it fails with `EIO` till
+    /// [SimBackend::crash] is called.
+    ///
+    /// It simulates a crash at an arbitrary point of a file that is written
+    /// sequentially, like a log.
+    pub fn fail_at(&self, path: &str, offset: u64) {
+        self.state.borrow_mut().fail_at = Some((path.to_string(), offset));
+    }
+
+    /// connect opens a connection to a simulated listener from outside of the
+    /// simulation, the returned peer plays the remote end of the connection.
+    pub fn connect(&self, addr: SocketAddr) -> stdio::Result<SimPeer> {
+        let mut state = self.state.borrow_mut();
+
+        let (rx, tx) = state
+            .connect(addr)
+            .map_err(stdio::Error::from_raw_os_error)?;
+
+        Ok(SimPeer {
+            state: self.state.clone(),
+            rx,
+            tx,
+        })
+    }
+}
+
+/// SimPeer is the remote end of a simulated TCP connection, it is driven
+/// synchronously from outside of the simulation.
+pub struct SimPeer {
+    state: Rc<RefCell<State>>,
+    rx: usize,
+    tx: usize,
+}
+
+impl SimPeer {
+    /// send queues the bytes for the simulated socket to receive
+    pub fn send(&self, buf: &[u8]) -> stdio::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        let pipe = &mut state.net.pipes[self.tx];
+
+        if pipe.closed {
+            return Err(stdio::Error::from_raw_os_error(libc::EPIPE));
+        }
+
+        pipe.data.extend(buf);
+        Ok(buf.len())
+    }
+
+    /// recv takes the bytes sent by the simulated socket so far, it returns 0
+    /// once the socket is closed and `WouldBlock` if there is nothing to read.
+    pub fn recv(&self, buf: &mut [u8]) -> stdio::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        let pipe = &mut state.net.pipes[self.rx];
+
+        if pipe.data.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+
+            return Err(stdio::ErrorKind::WouldBlock.into());
+        }
+
+        Ok(pipe.read(buf))
+    }
+
+    /// close closes the connection, the simulated socket reads EOF
+    pub fn close(&self) {
+        let mut state = self.state.borrow_mut();
+        state.net.pipes[self.tx].closed = true;
+        state.net.pipes[self.rx].closed = true;
+    }
+}
+
+impl Drop for SimPeer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Backend for SimBackend {
+    unsafe fn submit(&self, op: &Op) -> stdio::Result<usize> {
+        Ok(self.state.borrow_mut().submit(op, None))
+    }
+
+    unsafe fn submit_multishot(&self, op: &Op) -> stdio::Result<usize> {
+        Ok(self.state.borrow_mut().submit_multishot(op))
+    }
+
+    unsafe fn submit_with_timeout(&self, op: &Op, ts: *const Timespec) -> stdio::Result<usize> {
+        let mut state = self.state.borrow_mut();
+
+        let deadline = state.now + Duration::from(*ts);
+        Ok(state.submit(op, Some(deadline)))
+    }
+
+    fn poll_op(&self, token: usize, ctx: &mut Context<'_>) -> Poll<i32> {
+        self.state.borrow_mut().inflight.poll(token, ctx)
+    }
+
+    fn poll_next(&self, token: usize, ctx: &mut Context<'_>) -> Poll<Option<Completion>> {
+        self.state.borrow_mut().inflight.poll_next(token, ctx)
+    }
+
+    fn cancel_op(&self, token: usize) {
+        let mut state = self.state.borrow_mut();
+        if state.inflight.cancel(token) {
+            state.cancel(token);
+        }
+    }
+
+    fn cancel_multishot(&self, token: usize, discard: Box<dyn FnMut(Completion)>) {
+        let mut state = self.state.borrow_mut();
+        if state.inflight.cancel_multishot(token, discard) {
+            state.cancel(token);
+        }
+    }
+
+    fn cancel_op_then(&self, token: usize, release: Box<dyn FnOnce()>) {
+        let mut state = self.state.borrow_mut();
+        if state.inflight.cancel_then(token, release) {
+            state.cancel(token);
+        }
+    }
+
+    unsafe fn register_buffers(&self, bufs: &[libc::iovec]) -> stdio::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.buffers.is_empty() {
+            return Err(stdio::Error::from_raw_os_error(libc::EBUSY));
+        }
+
+        state.buffers = bufs
+            .iter()
+            .map(|iov| (iov.iov_base as usize, iov.iov_len))
+            .collect();
+        Ok(())
+    }
+
+    fn register_files(&self, count: u32) -> stdio::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.fixed_files > 0 {
+            return Err(stdio::Error::from_raw_os_error(libc::EBUSY));
+        }
+        if count == 0 {
+            return Err(stdio::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        state.fixed_files = count;
+        Ok(())
+    }
+
+    unsafe fn register_buf_ring(
+        &self,
+        ring: *mut BufRingEntry,
+        entries: u16,
+        group: u16,
+    ) -> stdio::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.buf_rings.contains_key(&group) {
+            return Err(stdio::Error::from_raw_os_error(libc::EEXIST));
+        }
+        if !entries.is_power_of_two() {
+            return Err(stdio::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        state.buf_rings.insert(
+            group,
+            BufRing {
+                ring,
+                entries,
+                head: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn poll_completions(&self) -> stdio::Result<()> {
+        self.run(None);
+        Ok(())
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> stdio::Result<()> {
+        self.run(Some(timeout.unwrap_or(Duration::MAX)));
+        Ok(())
+    }
+
+    fn wake(&self) {
+        self.state.borrow_mut().woken = true;
+    }
+
+    fn inflight(&self) -> usize {
+        self.state.borrow().inflight.pending()
+    }
+
+    fn clock(&self) -> Instant {
+        let state = self.state.borrow();
+        state.epoch + state.now
+    }
+}
+
+impl SimBackend {
+    /// run completes the ops that are ready, if there are none then the clock
+    /// is advanced to the next op becoming ready (but at most by `timeout` if
+    /// it is given).
+    ///
+    /// Waking a task only queues it on the executor, hence the wakers are
+    /// woken while the state is borrowed.
+    fn run(&self, timeout: Option<Duration>) {
+        let mut state = self.state.borrow_mut();
+        let woken = std::mem::replace(&mut state.woken, false);
+
+        if state.complete_ready() > 0 || woken {
+            return;
+        }
+
+        // Ops blocked on the network become ready only once a peer acts (or
+        // their deadline passes)
+        let next = state
+            .pending
+            .iter()
+            .flat_map(|p| [Some(p.ready_at), p.deadline])
+            .flatten()
+            .filter(|at| *at > state.now)
+            .min();
+        let deadline = timeout.map(|timeout| state.now.saturating_add(timeout));
+
+        let advance_to = match (next, deadline) {
+            (Some(next), Some(deadline)) => next.min(deadline),
+            (Some(next), None) => next,
+            (None, Some(deadline)) if deadline != Duration::MAX => deadline,
+            _ => return,
+        };
+
+        state.now = advance_to;
+        state.complete_ready();
+    }
+}
+
+struct Pending {
+    token: usize,
+    op: Op,
+    path: Option<String>,
+    ready_at: Duration,
+    /// deadline is the time at which the op is cancelled, if it has one
+    deadline: Option<Duration>,
+    /// multishot is set for the ops which stay pending once they complete
+    multishot: bool,
+}
+
+/// Outcome is the result of trying to execute an op
+enum Outcome {
+    Done(i32),
+    /// Done with the data in the provided buffer of the given id
+    Selected(i32, u16),
+    /// The op cannot make progress yet (eg. reading an empty socket)
+    Blocked,
+}
+
+struct State {
+    options: SimOptions,
+    rng: Rng,
+    now: Duration,
+    /// epoch is the instant at which the virtual clock started
+    epoch: Instant,
+    woken: bool,
+
+    inflight: Inflight,
+    pending: Vec<Pending>,
+
+    /// fds holds the open files, both the file descriptors and the slots of
+    /// the fixed file table.
+    fds: BTreeMap<Fd, Desc>,
+    next_fd: RawFd,
+    /// fixed_files is the size of the fixed file table, 0 till it is
+    /// registered.
+    fixed_files: u32,
+
+    fs: Fs,
+    net: Net,
+
+    /// buffers are the registered buffers (address and length)
+    buffers: Vec<(usize, usize)>,
+    /// buf_rings are the provided buffer rings by their group
+    buf_rings: BTreeMap<u16, BufRing>,
+
+    /// fail_at is the file and the offset at which the power is cut
+    fail_at: Option<(String, u64)>,
+    /// failed is set once the power is cut
+    failed: bool,
+}
+
+impl State {
+    fn new(options: SimOptions) -> Self {
+        Self {
+            options,
+            rng: Rng::new(options.seed),
+            now: Duration::ZERO,
+            epoch: Instant::now(),
+            woken: false,
+            inflight: Inflight::new(),
+            pending: Vec::new(),
+            fds: BTreeMap::new(),
+            next_fd: FIRST_FD,
+            fixed_files: 0,
+            fs: Fs::default(),
+            net: Net::new(),
+            buffers: Vec::new(),
+            buf_rings: BTreeMap::new(),
+            fail_at: None,
+            failed: false,
+        }
+    }
+
+    /// submit queues the op, it is cancelled at the deadline if it has not
+    /// completed by then.
+    ///
+    /// # Safety
+    /// Same as [Backend::submit].
+    unsafe fn submit(&mut self, op: &Op, deadline: Option<Duration>) -> usize {
+        // The path is copied right away, the kernel does the same
+        let path = match *op {
+            Op::OpenAt { path, .. } => Some(CStr::from_ptr(path).to_string_lossy().into_owned()),
+            _ => None,
+        };
+
+        let token = self.inflight.insert();
+        let ready_at = match *op {
+            // Timeouts fire on time, the latency is only for the IO
+            Op::Timeout { ts } => self.now + Duration::from(*ts),
+            _ => self.now + self.latency(),
+        };
+
+        self.pending.push(Pending {
+            token,
+            op: *op,
+            path,
+            ready_at,
+            deadline,
+            multishot: false,
+        });
+
+        token
+    }
+
+    /// submit_multishot queues the multishot op
+    fn submit_multishot(&mut self, op: &Op) -> usize {
+        let token = self.inflight.insert_multishot();
+        let ready_at = self.now + self.latency();
+
+        self.pending.push(Pending {
+            token,
+            op: *op,
+            path: None,
+            ready_at,
+            deadline: None,
+            multishot: true,
+        });
+
+        token
+    }
+
+    /// cancel cancels the pending op, cancellation always succeeds in the
+    /// simulation.
+    fn cancel(&mut self, token: usize) {
+        if let Some(idx) = self.pending.iter().position(|p| p.token == token) {
+            self.pending.remove(idx);
+            self.inflight.complete(token, -libc::ECANCELED);
+        }
+    }
+
+    fn latency(&mut self) -> Duration {
+        let min = self.options.min_latency.as_nanos() as u64;
+        let max = self.options.max_latency.as_nanos() as u64;
+
+        Duration::from_nanos(min + self.rng.below(max - min + 1))
+    }
+
+    /// complete_ready executes the ops which are ready in a random order and
+    /// returns the number of ops that completed.
+    fn complete_ready(&mut self) -> usize {
+        let mut ready: Vec<usize> = (0..self.pending.len())
+            .filter(|idx| self.pending[*idx].ready_at <= self.now)
+            .collect();
+        self.rng.shuffle(&mut ready);
+
+        let mut done = vec![false; self.pending.len()];
+        let mut completed = 0;
+
+        // The results are handed out in the (random) execution order as that
+        // is the order in which the tasks are woken.
+        for idx in ready {
+            let pending = &self.pending[idx];
+            let (token, op, path) = (pending.token, pending.op, pending.path.clone());
+
+            if pending.multishot {
+                // A multishot op keeps completing till it blocks, it finishes
+                // only once it fails or the connection it receives from is
+                // closed
+                loop {
+                    let completion = match self.execute(&op, path.as_deref()) {
+                        Outcome::Done(result) => Completion::new(result),
+                        Outcome::Selected(result, bid) => Completion {
+                            result,
+                            buf_id: Some(bid),
+                        },
+                        Outcome::Blocked => break,
+                    };
+                    completed += 1;
+
+                    let eof = completion.result == 0 && matches!(op, Op::RecvMulti { .. });
+                    if completion.result < 0 || eof {
+                        self.inflight.complete_with(token, completion);
+                        done[idx] = true;
+                        break;
+                    }
+
+                    self.inflight.push(token, completion);
+
+                    // The data of a zero-copy send is copied right away, the
+                    // notification releasing the buffer follows the result
+                    if let Op::SendZc { .. } = op {
+                        self.inflight.complete(token, 0);
+                        done[idx] = true;
+                        break;
+                    }
+                }
+
+                continue;
+            }
+
+            if let Outcome::Done(result) = self.execute(&op, path.as_deref()) {
+                self.inflight.complete(token, result);
+                done[idx] = true;
+                completed += 1;
+            }
+        }
+
+        // The ops which are late are cancelled, like the kernel does once
+        // their linked timeout fires
+        for (idx, pending) in self.pending.iter().enumerate() {
+            let late = pending
+                .deadline
+                .is_some_and(|deadline| deadline <= self.now);
+            if !done[idx] && late {
+                self.inflight.complete(pending.token, -libc::ECANCELED);
+                done[idx] = true;
+                completed += 1;
+            }
+        }
+
+        let mut done = done.into_iter();
+        self.pending.retain(|_| !done.next().unwrap());
+
+        completed
+    }
+
+    fn inject_error(&mut self) -> bool {
+        self.options.io_error_rate > 0.0 && self.rng.chance(self.options.io_error_rate)
+    }
+
+    fn alloc_fd(&mut self, desc: Desc) -> RawFd {
+        let raw = self.next_fd;
+        self.next_fd += 1;
+        self.fds.insert(Fd::Raw(raw), desc);
+        raw
+    }
+
+    /// alloc_fixed installs the file into the lowest free slot of the fixed
+    /// file table and returns its index.
+    fn alloc_fixed(&mut self, desc: Desc) -> Result<i32, i32> {
+        if self.fixed_files == 0 {
+            return Err(libc::ENXIO);
+        }
+
+        let index = (0..self.fixed_files)
+            .find(|index| !self.fds.contains_key(&Fd::Fixed(*index)))
+            .ok_or(libc::ENFILE)?;
+
+        self.fds.insert(Fd::Fixed(index), desc);
+        Ok(index as i32)
+    }
+
+    /// install installs a newly opened file either as a file descriptor or
+    /// into the fixed file table.
+    fn install(&mut self, desc: Desc, fixed: bool) -> Result<i32, i32> {
+        if fixed {
+            self.alloc_fixed(desc)
+        } else {
+            Ok(self.alloc_fd(desc))
+        }
+    }
+
+    fn execute(&mut self, op: &Op, path: Option<&str>) -> Outcome {
+        if self.failed && !matches!(op, Op::Nop | Op::Timeout { .. }) {
+            return Outcome::Done(-libc::EIO);
+        }
+
+        let result = match *op {
+            Op::Nop => Ok(0),
+            Op::OpenAt { flags, fixed, .. } => self.open(path.unwrap_or_default(), flags, fixed),
+            Op::Close { fd } => self.close(fd),
+            Op::Read {
+                fd,
+                buf,
+                len,
+                offset,
+            } => {
+                if self.inject_error() {
+                    Err(libc::EIO)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the buffer is valid
+                    let buf = unsafe { std::slice::from_raw_parts_mut(buf, len as usize) };
+                    self.read(fd, buf, offset)
+                }
+            }
+            Op::Write {
+                fd,
+                buf,
+                len,
+                offset,
+            } => {
+                if self.inject_error() {
+                    Err(libc::EIO)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the buffer is valid
+                    let buf = unsafe { std::slice::from_raw_parts(buf, len as usize) };
+                    self.write(fd, buf, offset)
+                }
+            }
+            Op::Readv {
+                fd,
+                iovecs,
+                len,
+                offset,
+                ..
+            } => {
+                if self.inject_error() {
+                    Err(libc::EIO)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the iovecs are valid
+                    unsafe {
+                        let iovecs = std::slice::from_raw_parts(iovecs, len as usize);
+                        self.readv(fd, iovecs, offset)
+                    }
+                }
+            }
+            Op::Writev {
+                fd,
+                iovecs,
+                len,
+                offset,
+                flags,
+            } => {
+                if self.inject_error() {
+                    Err(libc::EIO)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the iovecs are valid
+                    let buf = unsafe { gather(std::slice::from_raw_parts(iovecs, len as usize)) };
+                    self.writev(fd, &buf, offset, flags)
+                }
+            }
+            Op::ReadFixed {
+                fd,
+                buf,
+                len,
+                offset,
+                buf_index,
+            } => match self.check_fixed(buf as usize, len, buf_index) {
+                Ok(()) => {
+                    let op = Op::Read {
+                        fd,
+                        buf,
+                        len,
+                        offset,
+                    };
+                    return self.execute(&op, path);
+                }
+                Err(errno) => Err(errno),
+            },
+            Op::WriteFixed {
+                fd,
+                buf,
+                len,
+                offset,
+                buf_index,
+            } => match self.check_fixed(buf as usize, len, buf_index) {
+                Ok(()) => {
+                    let op = Op::Write {
+                        fd,
+                        buf,
+                        len,
+                        offset,
+                    };
+                    return self.execute(&op, path);
+                }
+                Err(errno) => Err(errno),
+            },
+            Op::Fsync { fd, .. } => {
+                if self.inject_error() {
+                    Err(libc::EIO)
+                } else {
+                    self.fsync(fd)
+                }
+            }
+            Op::Fallocate {
+                fd,
+                offset,
+                len,
+                mode,
+            } => self.fallocate(fd, offset, len, mode),
+            Op::Socket {
+                domain,
+                socket_type,
+                ..
+            } => self.socket(domain, socket_type),
+            Op::SetSockOpt { fd, .. } => self.socket_mut(Fd::Raw(fd)).map(|_| 0),
+            Op::Bind { fd, addr, addrlen } => {
+                // # Safety
+                // The submitter guarantees that the address is valid
+                match unsafe { sockaddr_to_addr(addr, addrlen) } {
+                    Some(addr) => self.bind(Fd::Raw(fd), addr),
+                    None => Err(libc::EAFNOSUPPORT),
+                }
+            }
+            Op::Listen { fd, .. } => self.listen(Fd::Raw(fd)),
+            Op::Accept { fd, fixed } | Op::AcceptMulti { fd, fixed } => {
+                return self.accept(fd, fixed)
+            }
+            Op::Connect { fd, addr, addrlen } => {
+                // # Safety
+                // The submitter guarantees that the address is valid
+                match unsafe { sockaddr_to_addr(addr, addrlen) } {
+                    Some(addr) => self.connect_socket(fd, addr),
+                    None => Err(libc::EAFNOSUPPORT),
+                }
+            }
+            Op::Recv { fd, buf, len } => {
+                if self.inject_error() {
+                    Err(libc::ECONNRESET)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the buffer is valid
+                    let buf = unsafe { std::slice::from_raw_parts_mut(buf, len as usize) };
+                    return self.recv(fd, buf);
+                }
+            }
+            Op::RecvMulti { fd, buf_group } => {
+                if self.inject_error() {
+                    Err(libc::ECONNRESET)
+                } else {
+                    return self.recv_select(fd, buf_group);
+                }
+            }
+            Op::Send { fd, buf, len } | Op::SendZc { fd, buf, len } => {
+                if self.inject_error() {
+                    Err(libc::ECONNRESET)
+                } else {
+                    // # Safety
+                    // The submitter guarantees that the buffer is valid
+                    let buf = unsafe { std::slice::from_raw_parts(buf, len as usize) };
+                    self.send(fd, buf)
+                }
+            }
+            Op::SendMsg { fd, msg } => {
+                // # Safety
+                // The submitter guarantees that the message is valid
+                match unsafe { read_msg(msg) } {
+                    // An injected failure loses a datagram on its way, but
+                    // resets a connection like it does for [Op::Send]
+                    Ok((_, buf)) if self.inject_error() => match self.socket_mut(fd) {
+                        Ok(Socket::Connected { .. }) => Err(libc::ECONNRESET),
+                        _ => Ok(buf.len() as i32),
+                    },
+                    Ok((to, buf)) => self.send_msg(fd, to, &buf),
+                    Err(errno) => Err(errno),
+                }
+            }
+            Op::RecvMsg { fd, msg, .. } => {
+                // # Safety
+                // The submitter guarantees that the message is valid
+                return unsafe { self.recv_msg(fd, msg) };
+            }
+            Op::Timeout { .. } => Err(libc::ETIME),
+        };
+
+        Outcome::Done(result.unwrap_or_else(|errno| -errno))
+    }
+
+    /// check_fixed checks that the memory of a fixed op lies within the
+    /// registered buffer, like the kernel does.
+    fn check_fixed(&self, buf: usize, len: u32, buf_index: u16) -> Result<(), i32> {
+        let Some((start, size)) = self.buffers.get(buf_index as usize).copied() else {
+            return Err(libc::EFAULT);
+        };
+
+        if buf < start || buf + len as usize > start + size {
+            return Err(libc::EFAULT);
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: i32, fixed: bool) -> Result<i32, i32> {
+        let inode = match self.fs.paths.get(path) {
+            Some(_) if flags & libc::O_CREAT != 0 && flags & libc::O_EXCL != 0 => {
+                return Err(libc::EEXIST)
+            }
+            Some(inode) => *inode,
+            None if flags & libc::O_CREAT != 0 => self.fs.lookup_or_create(path),
+            None => return Err(libc::ENOENT),
+        };
+
+        let access = flags & libc::O_ACCMODE;
+        if flags & libc::O_TRUNC != 0 && access != libc::O_RDONLY {
+            self.fs.inodes[inode].data.clear();
+        }
+
+        let file = File {
+            inode,
+            pos: 0,
+            readable: access != libc::O_WRONLY,
+            writable: access != libc::O_RDONLY,
+            append: flags & libc::O_APPEND != 0,
+        };
+        self.install(Desc::File(file), fixed)
+    }
+
+    fn close(&mut self, fd: Fd) -> Result<i32, i32> {
+        match self.fds.remove(&fd) {
+            Some(Desc::Socket(socket)) => {
+                self.net.close(socket);
+                Ok(0)
+            }
+            Some(Desc::File(_)) => Ok(0),
+            None => Err(libc::EBADF),
+        }
+    }
+
+    fn file_mut(&mut self, fd: Fd) -> Result<&mut File, i32> {
+        match self.fds.get_mut(&fd) {
+            Some(Desc::File(file)) => Ok(file),
+            Some(Desc::Socket(_)) => Err(libc::ESPIPE),
+            None => Err(libc::EBADF),
+        }
+    }
+
+    fn read(&mut self, fd: Fd, buf: &mut [u8], offset: u64) -> Result<i32, i32> {
+        let file = self.file_mut(fd)?;
+        if !file.readable {
+            return Err(libc::EBADF);
+        }
+
+        let (inode, pos) = (file.inode, file.pos);
+        let start = if offset == CURRENT_POSITION {
+            pos
+        } else {
+            offset
+        };
+
+        let data = &self.fs.inodes[inode].data;
+        let start = (start as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+
+        if offset == CURRENT_POSITION {
+            self.file_mut(fd)?.pos += n as u64;
+        }
+
+        Ok(n as i32)
+    }
+
+    fn write(&mut self, fd: Fd, buf: &[u8], offset: u64) -> Result<i32, i32> {
+        let file = self.file_mut(fd)?;
+        if !file.writable {
+            return Err(libc::EBADF);
+        }
+
+        let (inode, pos, append) = (file.inode, file.pos, file.append);
+
+        let data = &mut self.fs.inodes[inode].data;
+        let start = if append {
+            data.len()
+        } else if offset == CURRENT_POSITION {
+            pos as usize
+        } else {
+            offset as usize
+        };
+
+        // The power is cut once the write reaches the failure point
+        let cut = self.fail_at.as_ref().and_then(|(path, offset)| {
+            let offset = *offset as usize;
+            (self.fs.paths.get(path) == Some(&inode) && start + buf.len() > offset)
+                .then(|| offset.saturating_sub(start))
+        });
+        let buf = &buf[..cut.unwrap_or(buf.len())];
+
+        let data = &mut self.fs.inodes[inode].data;
+        if data.len() < start + buf.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+
+        if cut.is_some() {
+            let inode = &mut self.fs.inodes[inode];
+            inode.durable = inode.data.clone();
+            self.failed = true;
+
+            return Err(libc::EIO);
+        }
+
+        if offset == CURRENT_POSITION || append {
+            self.file_mut(fd)?.pos = (start + buf.len()) as u64;
+        }
+
+        Ok(buf.len() as i32)
+    }
+
+    /// readv is [State::read] scattering the data into the iovecs
+    ///
+    /// # Safety
+    /// The iovecs must be valid.
+    unsafe fn readv(&mut self, fd: Fd, iovecs: &[libc::iovec], offset: u64) -> Result<i32, i32> {
+        let mut buf = vec![0; iovecs.iter().map(|iov| iov.iov_len).sum()];
+
+        let n = self.read(fd, &mut buf, offset)?;
+        scatter(iovecs, &buf[..n as usize]);
+
+        Ok(n)
+    }
+
+    /// writev is [State::write] along with the flags of pwritev2(2), the data
+    /// is already gathered from the iovecs.
+    fn writev(&mut self, fd: Fd, buf: &[u8], offset: u64, flags: i32) -> Result<i32, i32> {
+        let n = if flags & libc::RWF_APPEND != 0 {
+            let inode = self.file_mut(fd)?.inode;
+            let end = self.fs.inodes[inode].data.len();
+
+            let n = self.write(fd, buf, end as u64)?;
+            // Like pwritev2, the position moves only when it is the one used
+            if offset == CURRENT_POSITION {
+                self.file_mut(fd)?.pos = (end + n as usize) as u64;
+            }
+            n
+        } else {
+            self.write(fd, buf, offset)?
+        };
+
+        if flags & (libc::RWF_DSYNC | libc::RWF_SYNC) != 0 {
+            self.fsync(fd)?;
+        }
+
+        Ok(n)
+    }
+
+    fn fsync(&mut self, fd: Fd) -> Result<i32, i32> {
+        let inode = self.file_mut(fd)?.inode;
+
+        let inode = &mut self.fs.inodes[inode];
+        inode.durable = inode.data.clone();
+
+        Ok(0)
+    }
+
+    fn fallocate(&mut self, fd: Fd, offset: u64, len: u64, mode: i32) -> Result<i32, i32> {
+        let inode = self.file_mut(fd)?.inode;
+        let data = &mut self.fs.inodes[inode].data;
+
+        match mode {
+            0 => {
+                let end = (offset + len) as usize;
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                Ok(0)
+            }
+            libc::FALLOC_FL_KEEP_SIZE => Ok(0),
+            _ => Err(libc::EOPNOTSUPP),
+        }
+    }
+
+    fn socket(&mut self, domain: i32, socket_type: i32) -> Result<i32, i32> {
+        if domain != libc::AF_INET && domain != libc::AF_INET6 {
+            return Err(libc::EAFNOSUPPORT);
+        }
+
+        let socket = match socket_type & !(libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK) {
+            libc::SOCK_STREAM => Socket::Unbound,
+            libc::SOCK_DGRAM => Socket::Datagram {
+                local: None,
+                peer: None,
+            },
+            _ => return Err(libc::EPROTONOSUPPORT),
+        };
+
+        Ok(self.alloc_fd(Desc::Socket(socket)))
+    }
+
+    fn socket_mut(&mut self, fd: Fd) -> Result<&mut Socket, i32> {
+        match self.fds.get_mut(&fd) {
+            Some(Desc::Socket(socket)) => Ok(socket),
+            Some(Desc::File(_)) => Err(libc::ENOTSOCK),
+            None => Err(libc::EBADF),
+        }
+    }
+
+    fn bind(&mut self, fd: Fd, mut addr: SocketAddr) -> Result<i32, i32> {
+        if addr.port() == 0 {
+            addr.set_port(self.net.ephemeral_port());
+        }
+
+        let socket = self.socket_mut(fd)?;
+        match socket {
+            Socket::Unbound => {
+                *socket = Socket::Bound(addr);
+                Ok(0)
+            }
+            Socket::Datagram { local: None, .. } => self.bind_datagram(fd, addr).map(|_| 0),
+            _ => Err(libc::EINVAL),
+        }
+    }
+
+    /// bind_datagram binds the datagram socket, its datagrams are queued at
+    /// the address from then on.
+    fn bind_datagram(&mut self, fd: Fd, addr: SocketAddr) -> Result<SocketAddr, i32> {
+        if self.net.inboxes.contains_key(&addr) {
+            return Err(libc::EADDRINUSE);
+        }
+
+        if let Socket::Datagram { local, .. } = self.socket_mut(fd)? {
+            *local = Some(addr);
+        }
+        self.net.inboxes.insert(addr, VecDeque::new());
+
+        Ok(addr)
+    }
+
+    /// datagram_local returns the address of the datagram socket, like the
+    /// kernel it binds the socket to an ephemeral port if it is not bound
+    /// yet.
+    fn datagram_local(&mut self, fd: Fd, peer: &SocketAddr) -> Result<SocketAddr, i32> {
+        match self.socket_mut(fd)? {
+            Socket::Datagram {
+                local: Some(local), ..
+            } => Ok(*local),
+            Socket::Datagram { local: None, .. } => {
+                let ip: IpAddr = match peer {
+                    SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+                };
+                let port = self.net.ephemeral_port();
+                self.bind_datagram(fd, SocketAddr::new(ip, port))
+            }
+            _ => Err(libc::EINVAL),
+        }
+    }
+
+    fn listen(&mut self, fd: Fd) -> Result<i32, i32> {
+        let socket = self.socket_mut(fd)?;
+        let Socket::Bound(addr) = *socket else {
+            return Err(libc::EINVAL);
+        };
+
+        if self.net.listeners.contains_key(&addr) {
+            return Err(libc::EADDRINUSE);
+        }
+
+        *self.socket_mut(fd)? = Socket::Listening(addr);
+        self.net.listeners.insert(addr, VecDeque::new());
+
+        Ok(0)
+    }
+
+    fn accept(&mut self, fd: Fd, fixed: bool) -> Outcome {
+        let addr = match self.socket_mut(fd) {
+            Ok(Socket::Listening(addr)) => *addr,
+            Ok(_) => return Outcome::Done(-libc::EINVAL),
+            Err(errno) => return Outcome::Done(-errno),
+        };
+
+        let backlog = self.net.listeners.get_mut(&addr).unwrap();
+        match backlog.pop_front() {
+            Some((rx, tx)) => {
+                let socket = Desc::Socket(Socket::Connected { rx, tx });
+                Outcome::Done(self.install(socket, fixed).unwrap_or_else(|errno| -errno))
+            }
+            None => Outcome::Blocked,
+        }
+    }
+
+    fn recv(&mut self, fd: Fd, buf: &mut [u8]) -> Outcome {
+        let rx = match self.socket_mut(fd) {
+            Ok(Socket::Connected { rx, .. }) => *rx,
+            Ok(Socket::Datagram { .. }) => {
+                return match self.recv_datagram(fd) {
+                    Ok(Some((_, datagram))) => {
+                        let n = buf.len().min(datagram.len());
+                        buf[..n].copy_from_slice(&datagram[..n]);
+                        Outcome::Done(n as i32)
+                    }
+                    Ok(None) => Outcome::Blocked,
+                    Err(errno) => Outcome::Done(-errno),
+                };
+            }
+            Ok(_) => return Outcome::Done(-libc::ENOTCONN),
+            Err(errno) => return Outcome::Done(-errno),
+        };
+
+        let pipe = &mut self.net.pipes[rx];
+        if pipe.data.is_empty() {
+            if pipe.closed {
+                return Outcome::Done(0);
+            }
+
+            return Outcome::Blocked;
+        }
+
+        Outcome::Done(pipe.read(buf) as i32)
+    }
+
+    /// recv_select is [State::recv] into a buffer picked from the buffer
+    /// group, the buffer is picked only once there is data to receive.
+    fn recv_select(&mut self, fd: Fd, group: u16) -> Outcome {
+        let rx = match self.socket_mut(fd) {
+            Ok(Socket::Connected { rx, .. }) => *rx,
+            Ok(_) => return Outcome::Done(-libc::ENOTCONN),
+            Err(errno) => return Outcome::Done(-errno),
+        };
+
+        let pipe = &self.net.pipes[rx];
+        if pipe.data.is_empty() {
+            if pipe.closed {
+                return Outcome::Done(0);
+            }
+
+            return Outcome::Blocked;
+        }
+
+        let Some(ring) = self.buf_rings.get_mut(&group) else {
+            return Outcome::Done(-libc::ENOBUFS);
+        };
+
+        // # Safety
+        // The ring is valid for as long as the backend
+        let Some((buf, bid)) = (unsafe { ring.pick() }) else {
+            return Outcome::Done(-libc::ENOBUFS);
+        };
+
+        Outcome::Selected(self.net.pipes[rx].read(buf) as i32, bid)
+    }
+
+    fn send(&mut self, fd: Fd, buf: &[u8]) -> Result<i32, i32> {
+        let tx = match self.socket_mut(fd)? {
+            Socket::Connected { tx, .. } => *tx,
+            Socket::Datagram { peer, .. } => {
+                let peer = *peer;
+                return self.send_msg(fd, peer, buf);
+            }
+            _ => return Err(libc::ENOTCONN),
+        };
+
+        let pipe = &mut self.net.pipes[tx];
+        if pipe.closed {
+            return Err(libc::EPIPE);
+        }
+
+        pipe.data.extend(buf);
+        Ok(buf.len() as i32)
+    }
+
+    /// send_msg queues the datagram at the socket bound to `to`, or at the
+    /// peer of the socket if there is no `to`. Like UDP the datagram is lost
+    /// if there is no such socket.
+    fn send_msg(&mut self, fd: Fd, to: Option<SocketAddr>, buf: &[u8]) -> Result<i32, i32> {
+        let to = match (to, self.socket_mut(fd)?) {
+            (Some(to), Socket::Datagram { .. }) => to,
+            (
+                None,
+                Socket::Datagram {
+                    peer: Some(peer), ..
+                },
+            ) => *peer,
+            (None, Socket::Datagram { peer: None, .. }) => return Err(libc::EDESTADDRREQ),
+            (None, Socket::Connected { .. }) => return self.send(fd, buf),
+            (Some(_), Socket::Connected { .. }) => return Err(libc::EISCONN),
+            _ => return Err(libc::ENOTCONN),
+        };
+
+        let from = self.datagram_local(fd, &to)?;
+        if let Some(inbox) = self.net.inbox_mut(&to) {
+            inbox.push_back((from, buf.to_vec()));
+        }
+
+        Ok(buf.len() as i32)
+    }
+
+    /// recv_datagram takes the next datagram queued for the socket along
+    /// with its sender, None if there is none yet. A connected socket only
+    /// takes the datagrams of its peer, the others are dropped.
+    fn recv_datagram(&mut self, fd: Fd) -> Result<Option<(SocketAddr, Vec<u8>)>, i32> {
+        let (local, peer) = match self.socket_mut(fd)? {
+            Socket::Datagram { local, peer } => (*local, *peer),
+            _ => return Err(libc::ENOTCONN),
+        };
+
+        // An unbound socket has nothing to receive
+        let Some(inbox) = local.and_then(|local| self.net.inboxes.get_mut(&local)) else {
+            return Ok(None);
+        };
+
+        while let Some((from, datagram)) = inbox.pop_front() {
+            if peer.is_none_or(|peer| peer == from) {
+                return Ok(Some((from, datagram)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// recv_msg receives the next datagram into the iovecs of the message,
+    /// and writes its sender to the name of the message.
+    ///
+    /// # Safety
+    /// The message, its iovecs and its name must be valid.
+    unsafe fn recv_msg(&mut self, fd: Fd, msg: *mut libc::msghdr) -> Outcome {
+        let (from, datagram) = match self.socket_mut(fd) {
+            Ok(Socket::Datagram { .. }) => match self.recv_datagram(fd) {
+                Ok(Some(received)) => received,
+                Ok(None) => return Outcome::Blocked,
+                Err(errno) => return Outcome::Done(-errno),
+            },
+            Ok(Socket::Connected { .. }) => return self.recv_stream_msg(fd, msg),
+            Ok(_) => return Outcome::Done(-libc::ENOTCONN),
+            Err(errno) => return Outcome::Done(-errno),
+        };
+
+        let msg = &mut *msg;
+        let iovs = std::slice::from_raw_parts(msg.msg_iov, msg.msg_iovlen);
+        let n = scatter(iovs, &datagram);
+
+        // The rest of the datagram is discarded
+        msg.msg_flags = if n < datagram.len() {
+            libc::MSG_TRUNC
+        } else {
+            0
+        };
+
+        if !msg.msg_name.is_null() {
+            let (storage, addrlen) = sockaddr_storage(&from);
+            let len = (addrlen as usize).min(msg.msg_namelen as usize);
+            std::ptr::copy_nonoverlapping(
+                &storage as *const _ as *const u8,
+                msg.msg_name as *mut u8,
+                len,
+            );
+            msg.msg_namelen = addrlen;
+        }
+
+        Outcome::Done(n as i32)
+    }
+
+    /// recv_stream_msg is [State::recv] scattering the data into the iovecs
+    /// of the message, for the connected sockets.
+    ///
+    /// # Safety
+    /// The message and its iovecs must be valid.
+    unsafe fn recv_stream_msg(&mut self, fd: Fd, msg: *mut libc::msghdr) -> Outcome {
+        let msg = &mut *msg;
+        let iovs = std::slice::from_raw_parts(msg.msg_iov, msg.msg_iovlen);
+
+        let mut buf = vec![0; iovs.iter().map(|iov| iov.iov_len).sum()];
+        let outcome = self.recv(fd, &mut buf);
+        if let Outcome::Done(n) = outcome {
+            if n > 0 {
+                scatter(iovs, &buf[..n as usize]);
+            }
+        }
+
+        msg.msg_flags = 0;
+        msg.msg_namelen = 0;
+        outcome
+    }
+
+    /// connect_socket connects the simulated socket to the listener at `addr`,
+    /// a datagram socket merely takes `addr` as its peer.
+    fn connect_socket(&mut self, fd: Fd, addr: SocketAddr) -> Result<i32, i32> {
+        match self.socket_mut(fd)? {
+            Socket::Datagram { .. } => {
+                self.datagram_local(fd, &addr)?;
+                if let Socket::Datagram { peer, .. } = self.socket_mut(fd)? {
+                    *peer = Some(addr);
+                }
+                return Ok(0);
+            }
+            Socket::Unbound | Socket::Bound(_) => {}
+            Socket::Connected { .. } => return Err(libc::EISCONN),
+            Socket::Listening(_) => return Err(libc::EINVAL),
+        }
+
+        let (rx, tx) = self.connect(addr)?;
+        *self.socket_mut(fd)? = Socket::Connected { rx, tx };
+
+        Ok(0)
+    }
+
+    /// connect queues a new connection on the listener at `addr` and returns
+    /// the pipes of the remote end (rx, tx).
+    fn connect(&mut self, addr: SocketAddr) -> Result<(usize, usize), i32> {
+        if !self.net.listeners.contains_key(&addr) {
+            return Err(libc::ECONNREFUSED);
+        }
+
+        let to_listener = self.net.pipe();
+        let from_listener = self.net.pipe();
+
+        let backlog = self.net.listeners.get_mut(&addr).unwrap();
+        backlog.push_back((to_listener, from_listener));
+
+        Ok((from_listener, to_listener))
+    }
+}
+
+/// BufRing is a provided buffer ring registered with the simulation, the
+/// buffers are picked from its head.
+struct BufRing {
+    ring: *mut BufRingEntry,
+    entries: u16,
+    head: u16,
+}
+
+impl BufRing {
+    /// pick takes the buffer at the head of the ring, None if the ring is
+    /// empty.
+    ///
+    /// # Safety
+    /// The ring and its buffers must be valid.
+    unsafe fn pick<'a>(&mut self) -> Option<(&'a mut [u8], u16)> {
+        let tail = BufRingEntry::tail(self.ring).load(Ordering::Acquire);
+        if tail == self.head {
+            return None;
+        }
+
+        let entry = *self.ring.add((self.head & (self.entries - 1)) as usize);
+        self.head = self.head.wrapping_add(1);
+
+        let buf = std::slice::from_raw_parts_mut(entry.addr as *mut u8, entry.len as usize);
+        Some((buf, entry.bid))
+    }
+}
+
+/// Desc is an open file description, what a file descriptor refers to
+enum Desc {
+    File(File),
+    Socket(Socket),
+}
+
+struct File {
+    inode: usize,
+    pos: u64,
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+#[derive(Default)]
+struct Fs {
+    paths: BTreeMap<String, usize>,
+    inodes: Vec<Inode>,
+}
+
+#[derive(Default)]
+struct Inode {
+    /// data is the current content of the file
+    data: Vec<u8>,
+    /// durable is the content of the file as of the last sync
+    durable: Vec<u8>,
+}
+
+impl Fs {
+    fn lookup_or_create(&mut self, path: &str) -> usize {
+        if let Some(inode) = self.paths.get(path) {
+            return *inode;
+        }
+
+        self.inodes.push(Inode::default());
+        self.paths.insert(path.to_string(), self.inodes.len() - 1);
+        self.inodes.len() - 1
+    }
+}
+
+enum Socket {
+    Unbound,
+    Bound(SocketAddr),
+    Listening(SocketAddr),
+    /// Connected holds the indices of the receiving and sending pipes
+    Connected {
+        rx: usize,
+        tx: usize,
+    },
+    /// Datagram is a UDP socket, its datagrams are queued at `local` once it
+    /// is bound and `peer` is the address it is connected to.
+    Datagram {
+        local: Option<SocketAddr>,
+        peer: Option<SocketAddr>,
+    },
+}
+
+struct Net {
+    /// listeners maps the listening addresses to their backlogs of (rx, tx)
+    /// pipes of the connections waiting to be accepted.
+    listeners: BTreeMap<SocketAddr, VecDeque<(usize, usize)>>,
+    /// inboxes maps the addresses of the bound datagram sockets to the
+    /// datagrams queued for them, along with their senders.
+    inboxes: BTreeMap<SocketAddr, VecDeque<(SocketAddr, Vec<u8>)>>,
+    pipes: Vec<Pipe>,
+    next_port: u16,
+}
+
+impl Net {
+    fn new() -> Self {
+        Self {
+            listeners: BTreeMap::new(),
+            inboxes: BTreeMap::new(),
+            pipes: Vec::new(),
+            next_port: FIRST_EPHEMERAL_PORT,
+        }
+    }
+
+    fn pipe(&mut self) -> usize {
+        self.pipes.push(Pipe::default());
+        self.pipes.len() - 1
+    }
+
+    /// inbox_mut returns the inbox of the datagram socket bound to `addr`,
+    /// either to the address itself or to the unspecified address.
+    fn inbox_mut(&mut self, addr: &SocketAddr) -> Option<&mut VecDeque<(SocketAddr, Vec<u8>)>> {
+        let any: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+
+        let addr = if self.inboxes.contains_key(addr) {
+            *addr
+        } else {
+            SocketAddr::new(any, addr.port())
+        };
+        self.inboxes.get_mut(&addr)
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = self
+            .next_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+        port
+    }
+
+    fn close(&mut self, socket: Socket) {
+        match socket {
+            Socket::Listening(addr) => {
+                // Connections which were never accepted are reset
+                for (rx, tx) in self.listeners.remove(&addr).unwrap_or_default() {
+                    self.pipes[rx].closed = true;
+                    self.pipes[tx].closed = true;
+                }
+            }
+            Socket::Connected { rx, tx } => {
+                self.pipes[rx].closed = true;
+                self.pipes[tx].closed = true;
+            }
+            Socket::Datagram {
+                local: Some(local), ..
+            } => {
+                self.inboxes.remove(&local);
+            }
+            Socket::Unbound | Socket::Bound(_) | Socket::Datagram { .. } => {}
+        }
+    }
+}
+
+/// Pipe is one direction of a simulated connection
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.data.len());
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+}
+
+/// read_msg reads the destination and gathers the data of a message to send
+///
+/// # Safety
+/// The message, its iovecs and its name must be valid.
+unsafe fn read_msg(msg: *const libc::msghdr) -> Result<(Option<SocketAddr>, Vec<u8>), i32> {
+    let msg = &*msg;
+
+    let to = if msg.msg_name.is_null() {
+        None
+    } else {
+        let addr = sockaddr_to_addr(msg.msg_name as *const libc::sockaddr, msg.msg_namelen);
+        Some(addr.ok_or(libc::EAFNOSUPPORT)?)
+    };
+
+    let iovs = std::slice::from_raw_parts(msg.msg_iov, msg.msg_iovlen);
+    Ok((to, gather(iovs)))
+}
+
+/// gather gathers the data of the iovecs
+///
+/// # Safety
+/// The iovecs must be valid.
+unsafe fn gather(iovs: &[libc::iovec]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for iov in iovs {
+        buf.extend_from_slice(std::slice::from_raw_parts(
+            iov.iov_base as *const u8,
+            iov.iov_len,
+        ));
+    }
+
+    buf
+}
+
+/// scatter scatters the data into the iovecs, it returns the number of bytes
+/// that fit.
+///
+/// # Safety
+/// The iovecs must be valid.
+unsafe fn scatter(iovs: &[libc::iovec], data: &[u8]) -> usize {
+    let mut n = 0;
+    for iov in iovs {
+        let len = iov.iov_len.min(data.len() - n);
+        std::ptr::copy_nonoverlapping(data[n..].as_ptr(), iov.iov_base as *mut u8, len);
+        n += len;
+    }
+
+    n
+}
+
+/// Rng is a SplitMix64 generator, it is tiny and its output for a seed is
+/// stable forever which is exactly what reproducing a simulation needs.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// below returns a number in `0..n` (`n` must not be 0)
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// chance returns true with the probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        io::{IoSlice, IoSliceMut},
+        pin::{pin, Pin},
+        rc::Rc,
+        task::Waker,
+    };
+
+    use super::*;
+    use crate::{
+        aligned::AlignedBuf,
+        bufring::BufRing,
+        core,
+        fixed::FixedBufferPool,
+        io::File,
+        net::{TcpListner, TcpStream, UdpSocket},
+        rent::{AsyncReadRent, AsyncWriteRent},
+        PerThreadReactor,
+    };
+
+    type Task<T> = Pin<Box<dyn Future<Output = T>>>;
+
+    /// simulate runs `f` on a thread of its own with the simulation built
+    /// from the options as its backend, the backend of a thread is set once.
+    fn simulate<T: Send + 'static>(
+        options: SimOptions,
+        f: impl FnOnce(SimBackend) -> T + Send + 'static,
+    ) -> T {
+        std::thread::spawn(move || {
+            let sim = options.build();
+            PerThreadReactor::install(Box::new(sim.clone()))
+                .ok()
+                .unwrap();
+            f(sim)
+        })
+        .join()
+        .unwrap()
+    }
+
+    /// join runs the tasks concurrently, it returns their outputs (along with
+    /// their index) in the order in which they completed.
+    fn join<T>(sim: &SimBackend, tasks: Vec<Task<T>>) -> Vec<(usize, T)> {
+        let mut ctx = Context::from_waker(Waker::noop());
+        let mut tasks: Vec<Option<Task<T>>> = tasks.into_iter().map(Some).collect();
+        let mut done = Vec::new();
+
+        while done.len() < tasks.len() {
+            for (idx, slot) in tasks.iter_mut().enumerate() {
+                let Some(task) = slot else { continue };
+                if let Poll::Ready(output) = task.as_mut().poll(&mut ctx) {
+                    done.push((idx, output));
+                    *slot = None;
+                }
+            }
+
+            if done.len() < tasks.len() {
+                assert!(sim.inflight() > 0, "tasks wait for nothing");
+                sim.run(Some(Duration::MAX));
+            }
+        }
+
+        done
+    }
+
+    fn block_on<T: 'static>(sim: &SimBackend, fut: impl Future<Output = T> + 'static) -> T {
+        join(sim, vec![Box::pin(fut)]).pop().unwrap().1
+    }
+
+    /// Reads are the reads in the order in which they completed, along with
+    /// their results (or errno).
+    type Reads = Vec<(usize, Result<usize, i32>)>;
+
+    /// reads issues concurrent reads of a file and returns the order in which
+    /// they completed along with their results and the virtual time.
+    fn reads(options: SimOptions) -> (Reads, Duration) {
+        simulate(options, |sim| {
+            sim.write_file("data", &[7; 4096]);
+            let file = Rc::new(block_on(&sim, File::open("data")).unwrap());
+
+            let tasks: Vec<Task<Result<usize, i32>>> = (0..16)
+                .map(|idx| {
+                    let file = file.clone();
+                    Box::pin(async move {
+                        let mut buf = [0; 256];
+                        file.read_at(&mut buf, idx * 256)
+                            .await
+                            .map_err(|err| err.raw_os_error().unwrap())
+                    }) as Task<_>
+                })
+                .collect();
+
+            (join(&sim, tasks), sim.now())
+        })
+    }
+
+    #[test]
+    fn same_seed_replays() {
+        let mut options = SimOptions::new();
+        options.seed(7).io_error_rate(0.3);
+
+        let first = reads(options);
+        assert_eq!(first, reads(options));
+
+        // The injected errors are part of the replay
+        assert!(first.0.iter().any(|(_, res)| res.is_err()));
+        assert!(first.0.iter().any(|(_, res)| res.is_ok()));
+    }
+
+    #[test]
+    fn seeds_reorder_completions() {
+        let orders: Vec<Vec<usize>> = (0..8)
+            .map(|seed| {
+                let (done, _) = reads(*SimOptions::new().seed(seed));
+                done.into_iter().map(|(idx, _)| idx).collect()
+            })
+            .collect();
+
+        assert!(orders.iter().any(|order| *order != orders[0]));
+    }
+
+    #[test]
+    fn injected_errors() {
+        let (done, _) = reads(*SimOptions::new().io_error_rate(1.0));
+        assert!(done.iter().all(|(_, res)| *res == Err(libc::EIO)));
+
+        let (done, _) = reads(*SimOptions::new().io_error_rate(0.0));
+        assert!(done.iter().all(|(_, res)| *res == Ok(256)));
+    }
+
+    #[test]
+    fn latency_is_bounded() {
+        let min = Duration::from_millis(2);
+        let max = Duration::from_millis(3);
+
+        let (_, now) = reads(*SimOptions::new().latency(min, max));
+        // The open and the (concurrent) reads
+        assert!(now >= 2 * min && now <= 2 * max);
+    }
+
+    #[test]
+    fn clock_is_virtual() {
+        let started = Instant::now();
+
+        simulate(SimOptions::new(), |sim| {
+            let start = core::now();
+            block_on(&sim, core::sleep(Duration::from_secs(3600)));
+
+            assert_eq!(core::now() - start, Duration::from_secs(3600));
+            assert_eq!(sim.now(), Duration::from_secs(3600));
+        });
+
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut options = SimOptions::new();
+        options.latency(Duration::from_millis(5), Duration::from_millis(5));
+
+        simulate(options, |sim| {
+            let res = block_on(
+                &sim,
+                core::timeout(
+                    Duration::from_millis(10),
+                    core::sleep(Duration::from_secs(1)),
+                ),
+            );
+            assert_eq!(res.unwrap_err().kind(), stdio::ErrorKind::TimedOut);
+            assert_eq!(sim.now(), Duration::from_millis(10));
+
+            sim.write_file("data", b"data");
+            let file = Rc::new(block_on(&sim, File::open("data")).unwrap());
+
+            // The read takes 5ms, the deadline cancels it midway
+            let late = file.clone();
+            let res = block_on(&sim, async move {
+                let mut buf = [0; 4];
+                let deadline = core::now() + Duration::from_millis(1);
+                late.read_at(&mut buf, 0).with_deadline(deadline).await
+            });
+            assert_eq!(res.unwrap_err().kind(), stdio::ErrorKind::TimedOut);
+
+            let res = block_on(&sim, async move {
+                let mut buf = [0; 4];
+                let deadline = core::now() + Duration::from_millis(10);
+                let n = file.read_at(&mut buf, 0).with_deadline(deadline).await;
+                n.map(|n| buf[..n].to_vec())
+            });
+            assert_eq!(res.unwrap(), b"data");
+        });
+    }
+
+    #[test]
+    fn interval_does_not_drift() {
+        simulate(SimOptions::new(), |sim| {
+            let ticks = block_on(&sim, async {
+                let start = core::now();
+                let mut interval = core::interval(Duration::from_millis(10));
+
+                let mut ticks = Vec::new();
+                for _ in 0..4 {
+                    let scheduled = interval.tick().await;
+                    ticks.push((scheduled - start, core::now() - start));
+
+                    // The work between the ticks does not delay them
+                    core::sleep(Duration::from_millis(3)).await;
+                }
+
+                // Falling behind by more than a period skips the missed ticks
+                core::sleep(Duration::from_millis(25)).await;
+                interval.tick().await;
+                ticks.push((interval.tick().await - start, core::now() - start));
+
+                ticks
+            });
+
+            let ms = Duration::from_millis;
+            assert_eq!(
+                ticks,
+                [
+                    (ms(0), ms(0)),
+                    (ms(10), ms(10)),
+                    (ms(20), ms(20)),
+                    (ms(30), ms(30)),
+                    (ms(68), ms(68)),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn timeout_drops_the_inner_future() {
+        let mut options = SimOptions::new();
+        options.latency(Duration::from_millis(20), Duration::from_millis(20));
+
+        simulate(options, |sim| {
+            sim.write_file("data", b"data");
+            let file = block_on(&sim, File::open("data")).unwrap();
+            let start = sim.now();
+
+            let res = block_on(&sim, async move {
+                let mut buf = [0; 4];
+                core::timeout(Duration::from_millis(5), file.read_at(&mut buf, 0)).await
+            });
+
+            // The timeout fired first and the read was cancelled
+            assert_eq!(res.unwrap_err().kind(), stdio::ErrorKind::TimedOut);
+            assert_eq!(sim.now() - start, Duration::from_millis(5));
+            assert_eq!(sim.inflight(), 0);
+        });
+    }
+
+    #[test]
+    fn dropped_read_is_cancelled() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            let mut buf = [0; 4];
+            let mut ctx = Context::from_waker(Waker::noop());
+            let mut read = stream.read(&mut buf);
+            assert!(Pin::new(&mut read).poll(&mut ctx).is_pending());
+            assert_eq!(sim.inflight(), 1);
+
+            // The read no longer refers to the buffer once it is dropped
+            drop(read);
+            assert_eq!(sim.inflight(), 0);
+
+            peer.send(b"data").unwrap();
+            sim.run(Some(Duration::MAX));
+            assert_eq!(buf, [0; 4]);
+
+            let data = block_on(&sim, async move {
+                let mut buf = [0; 4];
+                let n = stream.read(&mut buf).await.unwrap();
+                buf[..n].to_vec()
+            });
+            assert_eq!(data, b"data");
+        });
+    }
+
+    #[test]
+    fn network_round_trip() {
+        simulate(SimOptions::new(), |sim| {
+            let addr = "127.0.0.1:4000".parse().unwrap();
+            assert_eq!(
+                sim.connect(addr).err().unwrap().raw_os_error(),
+                Some(libc::ECONNREFUSED)
+            );
+
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect(addr).unwrap();
+            let mut stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            peer.send(b"ping").unwrap();
+            let (stream, data) = block_on(&sim, async move {
+                let mut buf = [0; 8];
+                let n = stream.read(&mut buf).await.unwrap();
+                stream.send(b"pong").await.unwrap();
+                (stream, buf[..n].to_vec())
+            });
+            assert_eq!(data, b"ping");
+
+            let mut buf = [0; 8];
+            assert_eq!(peer.recv(&mut buf).unwrap(), 4);
+            assert_eq!(&buf[..4], b"pong");
+
+            // The stream reads EOF once the peer is gone
+            drop(peer);
+            let n = block_on(&sim, async move {
+                let mut buf = [0; 8];
+                stream.read(&mut buf).await.unwrap()
+            });
+            assert_eq!(n, 0);
+        });
+    }
+
+    #[test]
+    fn connect_over_ipv4_and_ipv6() {
+        simulate(SimOptions::new(), |sim| {
+            let res = block_on(&sim, TcpStream::connect("127.0.0.1:4000"));
+            assert_eq!(res.err().unwrap().raw_os_error(), Some(libc::ECONNREFUSED));
+
+            for addr in ["127.0.0.1:4000", "[::1]:4000"] {
+                let listener = block_on(&sim, TcpListner::bind(addr, 16)).unwrap();
+
+                let data = block_on(&sim, async move {
+                    let mut client = TcpStream::connect(addr).await.unwrap();
+                    let server = listener.accept().await.unwrap();
+
+                    client.send(b"hello").await.unwrap();
+                    let mut buf = [0; 8];
+                    let n = server.read(&mut buf).await.unwrap();
+                    buf[..n].to_vec()
+                });
+                assert_eq!(data, b"hello");
+            }
+        });
+    }
+
+    #[test]
+    fn udp_send_to_and_recv_from() {
+        simulate(SimOptions::new(), |sim| {
+            let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+            let (sent, received) = block_on(&sim, async move {
+                let server = UdpSocket::bind("127.0.0.1:5000").await.unwrap();
+                let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+                let sent = client.send_to(b"ping", &server_addr).await.unwrap();
+                let mut buf = [0; 16];
+                let (n, from) = server.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"ping");
+
+                // The reply goes back to the ephemeral port of the client
+                server.send_to(b"pong", &from).await.unwrap();
+                let (n, reply_from) = client.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"pong");
+
+                (sent, reply_from)
+            });
+            assert_eq!(sent, 4);
+            assert_eq!(received, server_addr);
+        });
+    }
+
+    #[test]
+    fn udp_connect_and_truncation() {
+        simulate(SimOptions::new(), |sim| {
+            block_on(&sim, async move {
+                let server = UdpSocket::bind("127.0.0.1:5000").await.unwrap();
+                let client = UdpSocket::bind("127.0.0.1:5001").await.unwrap();
+                client.connect("127.0.0.1:5000").await.unwrap();
+
+                client.send(b"abcdef").await.unwrap();
+                client.send(b"gh").await.unwrap();
+
+                // The part of a datagram which does not fit is discarded
+                let mut buf = [0; 4];
+                let (n, from) = server.recv_from(&mut buf).await.unwrap();
+                assert_eq!(
+                    (&buf[..n], from),
+                    (&b"abcd"[..], "127.0.0.1:5001".parse().unwrap())
+                );
+
+                let (n, _) = server.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"gh");
+            });
+        });
+    }
+
+    #[test]
+    fn dropped_recv_from_is_cancelled() {
+        simulate(SimOptions::new(), |sim| {
+            let server = block_on(&sim, UdpSocket::bind("127.0.0.1:5000")).unwrap();
+            let client = block_on(&sim, UdpSocket::bind("127.0.0.1:0")).unwrap();
+            let server_addr = "127.0.0.1:5000".parse().unwrap();
+
+            let mut buf = [0; 4];
+            let mut ctx = Context::from_waker(Waker::noop());
+            let mut recv = server.recv_from(&mut buf);
+            assert!(Pin::new(&mut recv).poll(&mut ctx).is_pending());
+
+            // The message no longer points to the buffer once it is dropped
+            drop(recv);
+            assert_eq!(sim.inflight(), 0);
+
+            let n = block_on(&sim, async move {
+                client.send_to(b"late", &server_addr).await.unwrap()
+            });
+            assert_eq!(n, 4);
+            assert_eq!(buf, [0; 4]);
+
+            let data = block_on(&sim, async move {
+                let mut buf = [0; 4];
+                let (n, _) = server.recv_from(&mut buf).await.unwrap();
+                buf[..n].to_vec()
+            });
+            assert_eq!(data, b"late");
+        });
+    }
+
+    #[test]
+    fn rented_buffers_are_handed_back() {
+        simulate(SimOptions::new(), |sim| {
+            block_on(&sim, async {
+                let mut file = File::create("data").await.unwrap();
+                let (res, buf) = AsyncWriteRent::write_all(&mut file, b"rented".to_vec()).await;
+                res.unwrap();
+                assert_eq!(buf, b"rented");
+
+                // The read fills the spare capacity of the vector
+                let mut file = File::open("data").await.unwrap();
+                let (res, buf) = AsyncReadRent::read(&mut file, Vec::with_capacity(4)).await;
+                assert_eq!(res.unwrap(), 4);
+                assert_eq!(buf, b"rent");
+
+                let (res, buf) =
+                    AsyncReadRent::read(&mut file, vec![0; 8].into_boxed_slice()).await;
+                assert_eq!(res.unwrap(), 2);
+                assert_eq!(&buf[..2], b"ed");
+
+                // The buffer comes back along with the error as well
+                let (res, buf) = AsyncWriteRent::write(&mut file, "read-only").await;
+                assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EBADF));
+                assert_eq!(buf, "read-only");
+            });
+            assert_eq!(sim.read_file("data").unwrap(), b"rented");
+        });
+    }
+
+    #[test]
+    fn dropped_rented_read_keeps_the_buffer() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let mut stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            let mut ctx = Context::from_waker(Waker::noop());
+            {
+                let mut read = pin!(AsyncReadRent::read(&mut stream, Vec::with_capacity(8)));
+                assert!(read.as_mut().poll(&mut ctx).is_pending());
+            }
+            // The buffer is released along with the cancelled read
+            assert_eq!(sim.inflight(), 0);
+
+            peer.send(b"stream").unwrap();
+            let (res, buf) = block_on(&sim, async move {
+                AsyncReadRent::read(&mut stream, Vec::with_capacity(8)).await
+            });
+            assert_eq!(res.unwrap(), 6);
+            assert_eq!(buf, b"stream");
+        });
+    }
+
+    #[test]
+    fn crash_loses_unsynced_data() {
+        simulate(SimOptions::new(), |sim| {
+            block_on(&sim, async {
+                let file = File::create("log").await.unwrap();
+                file.write_at(b"durable", 0).await.unwrap();
+                file.sync_data().await.unwrap();
+                file.write_at(b"lost", 7).await.unwrap();
+            });
+            assert_eq!(sim.read_file("log").unwrap(), b"durablelost");
+
+            sim.crash();
+            assert_eq!(sim.read_file("log").unwrap(), b"durable");
+        });
+    }
+
+    #[test]
+    fn fail_at_cuts_the_write() {
+        simulate(SimOptions::new(), |sim| {
+            sim.fail_at("log", 4);
+
+            let res = block_on(&sim, async {
+                let file = File::create("log").await.unwrap();
+                let res = file.write_at(b"abcdefgh", 0).await;
+
+                // Every op fails till the crash
+                assert_eq!(
+                    file.sync_data().await.unwrap_err().raw_os_error(),
+                    Some(libc::EIO)
+                );
+                res
+            });
+            assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EIO));
+
+            sim.crash();
+            assert_eq!(sim.read_file("log").unwrap(), b"abcd");
+        });
+    }
+
+    #[test]
+    fn fixed_file_and_buffers() {
+        simulate(SimOptions::new(), |sim| {
+            PerThreadReactor::register_files(2).unwrap();
+            let pool = FixedBufferPool::register(2, 4096).unwrap();
+
+            // A reactor takes a single set of buffers
+            assert_eq!(
+                FixedBufferPool::register(1, 4096)
+                    .err()
+                    .unwrap()
+                    .raw_os_error(),
+                Some(libc::EBUSY)
+            );
+
+            let data = block_on(&sim, async move {
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .fixed(true)
+                    .open("data")
+                    .await
+                    .unwrap();
+
+                let mut buf = pool.checkout().unwrap();
+                buf.set_len(5);
+                buf.copy_from_slice(b"fixed");
+                let (res, buf) = file.write_fixed_at(buf, 0).await;
+                assert_eq!(res.unwrap(), 5);
+                drop(buf);
+
+                let mut buf = pool.checkout().unwrap();
+                let _other = pool.checkout().unwrap();
+                assert!(pool.checkout().is_none());
+
+                buf.set_len(8);
+                let (res, buf) = file.read_fixed_at(buf, 0).await;
+                let n = res.unwrap();
+                buf[..n].to_vec()
+            });
+            assert_eq!(data, b"fixed");
+            assert_eq!(pool.available(), 2);
+            assert_eq!(sim.read_file("data").unwrap(), b"fixed");
+        });
+    }
+
+    #[test]
+    fn fixed_file_table() {
+        simulate(SimOptions::new(), |sim| {
+            let open = || async {
+                File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .fixed(true)
+                    .open("data")
+                    .await
+            };
+
+            // There is no table till it is registered
+            let res = block_on(&sim, open());
+            assert_eq!(res.err().unwrap().raw_os_error(), Some(libc::ENXIO));
+
+            PerThreadReactor::register_files(2).unwrap();
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+
+            let (file, mut stream) = block_on(&sim, async move {
+                let file = open().await.unwrap();
+                let stream = listener.accept_fixed().await.unwrap();
+                (file, stream)
+            });
+            assert_eq!(file.fd(), Fd::Fixed(0));
+
+            // The table is full, a slot is freed once its file is closed
+            let file = block_on(&sim, async move {
+                let res = open().await;
+                assert_eq!(res.err().unwrap().raw_os_error(), Some(libc::ENFILE));
+
+                file.write_at(b"table", 0).await.unwrap();
+                file.close().await.unwrap();
+                open().await.unwrap()
+            });
+            assert_eq!(file.fd(), Fd::Fixed(0));
+            assert_eq!(sim.read_file("data").unwrap(), b"table");
+
+            // The accepted connection is used through its slot
+            peer.send(b"ping").unwrap();
+            let data = block_on(&sim, async move {
+                let mut buf = [0; 8];
+                let n = stream.read(&mut buf).await.unwrap();
+                stream.send(b"pong").await.unwrap();
+                buf[..n].to_vec()
+            });
+            assert_eq!(data, b"ping");
+
+            let mut buf = [0; 8];
+            assert_eq!(peer.recv(&mut buf).unwrap(), 4);
+            assert_eq!(&buf[..4], b"pong");
+        });
+    }
+
+    #[test]
+    fn incoming_accepts_with_a_single_op() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let mut incoming = listener.incoming();
+            let mut ctx = Context::from_waker(Waker::noop());
+            assert!(incoming.poll_next(&mut ctx).is_pending());
+
+            let peers: Vec<_> = (0..3)
+                .map(|_| sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap())
+                .collect();
+            sim.run(Some(Duration::MAX));
+
+            // The connections are all delivered by the op armed at first
+            let mut streams = Vec::new();
+            while let Poll::Ready(stream) = incoming.poll_next(&mut ctx) {
+                streams.push(stream.unwrap());
+                assert_eq!(sim.inflight(), 1);
+            }
+            assert_eq!(streams.len(), peers.len());
+
+            for (peer, stream) in peers.iter().zip(streams) {
+                peer.send(b"ping").unwrap();
+                let n = block_on(&sim, async move {
+                    let mut buf = [0; 8];
+                    stream.read(&mut buf).await.unwrap()
+                });
+                assert_eq!(n, 4);
+            }
+
+            drop(incoming);
+            assert_eq!(sim.inflight(), 0);
+        });
+    }
+
+    #[test]
+    fn incoming_is_armed_again_after_a_failure() {
+        simulate(SimOptions::new(), |sim| {
+            PerThreadReactor::register_files(1).unwrap();
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let mut incoming = listener.incoming_fixed();
+            let mut ctx = Context::from_waker(Waker::noop());
+
+            let mut next = || loop {
+                if let Poll::Ready(next) = incoming.poll_next(&mut ctx) {
+                    return next;
+                }
+                sim.run(Some(Duration::MAX));
+            };
+
+            let _first = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let _second = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+
+            // The second connection finds the table full, which finishes the
+            // op like the kernel does
+            let mut stream = next().unwrap();
+            assert_eq!(next().err().unwrap().raw_os_error(), Some(libc::ENFILE));
+            assert_eq!(sim.inflight(), 0);
+
+            block_on(&sim, async move { stream.close().await }).unwrap();
+            let _third = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            assert!(next().is_ok());
+            assert_eq!(sim.inflight(), 1);
+        });
+    }
+
+    #[test]
+    fn send_zc_hands_the_buffer_back() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let mut stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            let (res, buf, mut stream) = block_on(&sim, async move {
+                let (res, buf) = stream.send_zc(b"zero-copy".to_vec()).await;
+                (res, buf, stream)
+            });
+            assert_eq!(res.unwrap(), 9);
+            assert_eq!(buf, b"zero-copy");
+
+            let mut received = [0; 16];
+            assert_eq!(peer.recv(&mut received).unwrap(), 9);
+            assert_eq!(&received[..9], b"zero-copy");
+
+            // A failed send hands the buffer back as well
+            drop(peer);
+            let (res, buf) = block_on(&sim, async move { stream.send_zc(buf).await });
+            assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EPIPE));
+            assert_eq!(buf, b"zero-copy");
+            assert_eq!(sim.inflight(), 0);
+        });
+    }
+
+    #[test]
+    fn recv_stream_reuses_the_buffers() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            // The ring holds far less than what is sent, the buffers go back
+            // to it as the leases are dropped
+            let ring = BufRing::register(0, 2, 4).unwrap();
+            let mut recv = stream.recv_stream(ring);
+            let mut ctx = Context::from_waker(Waker::noop());
+            let mut next = || loop {
+                if let Poll::Ready(next) = recv.poll_next(&mut ctx) {
+                    return next;
+                }
+                sim.run(Some(Duration::MAX));
+            };
+
+            let sent: Vec<u8> = (0..40).collect();
+            let mut received = Vec::new();
+            for chunk in sent.chunks(8) {
+                peer.send(chunk).unwrap();
+
+                let end = received.len() + chunk.len();
+                while received.len() < end {
+                    received.extend_from_slice(&next().unwrap().unwrap());
+                }
+            }
+            assert_eq!(received, sent);
+
+            drop(peer);
+            assert!(next().is_none());
+        });
+    }
+
+    #[test]
+    fn dropped_recv_stream_gives_the_buffers_back() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            let ring = BufRing::register(0, 2, 4).unwrap();
+            let mut recv = stream.recv_stream(ring);
+            let mut ctx = Context::from_waker(Waker::noop());
+            assert!(recv.poll_next(&mut ctx).is_pending());
+
+            // Both buffers are picked but never collected
+            peer.send(&[1; 8]).unwrap();
+            sim.run(Some(Duration::MAX));
+            drop(recv);
+
+            // Both are back in the ring, they are leased at the same time
+            peer.send(&[2; 8]).unwrap();
+            let mut recv = stream.recv_stream(ring);
+            let (first, second) = block_on(&sim, async move {
+                let first = recv.next().await.unwrap().unwrap();
+                let second = recv.next().await.unwrap().unwrap();
+                (first.to_vec(), second.to_vec())
+            });
+            assert_eq!((first, second), (vec![2; 4], vec![2; 4]));
+        });
+    }
+
+    #[test]
+    fn recv_stream_waits_for_leases() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            let ring = BufRing::register(0, 2, 4).unwrap();
+            let mut recv = stream.recv_stream(ring);
+            let mut ctx = Context::from_waker(Waker::noop());
+
+            peer.send(&[1; 12]).unwrap();
+            let mut next = || loop {
+                if let Poll::Ready(next) = recv.poll_next(&mut ctx) {
+                    return Some(next);
+                }
+                if sim.inflight() == 0 {
+                    return None;
+                }
+                sim.run(Some(Duration::MAX));
+            };
+
+            let first = next().unwrap().unwrap().unwrap();
+            let _second = next().unwrap().unwrap().unwrap();
+            let Some(Err(err)) = next().unwrap() else {
+                panic!("the ring should have run dry");
+            };
+            assert_eq!(err.raw_os_error(), Some(libc::ENOBUFS));
+
+            // The receive is not armed again while every buffer is leased
+            assert!(next().is_none());
+
+            drop(first);
+            assert_eq!(&*next().unwrap().unwrap().unwrap(), &[1; 4]);
+        });
+    }
+
+    #[test]
+    fn vectored_file_io() {
+        simulate(SimOptions::new(), |sim| {
+            let data = block_on(&sim, async {
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .open("wal")
+                    .await
+                    .unwrap();
+                let header = 7u32.to_le_bytes();
+                let bufs = [IoSlice::new(&header), IoSlice::new(b"payload")];
+                let n = file.write_vectored_at(&bufs, 0, libc::RWF_DSYNC).await;
+                assert_eq!(n.unwrap(), 11);
+
+                // The append goes to the end of the file whatever the offset
+                let bufs = [IoSlice::new(b"tail")];
+                let n = file.write_vectored_at(&bufs, 0, libc::RWF_APPEND).await;
+                assert_eq!(n.unwrap(), 4);
+
+                let (mut header, mut rest) = ([0; 4], [0; 16]);
+                let mut bufs = [IoSliceMut::new(&mut header), IoSliceMut::new(&mut rest)];
+                let n = file.read_vectored_at(&mut bufs, 0, 0).await.unwrap();
+                assert_eq!(n, 15);
+                assert_eq!(u32::from_le_bytes(header), 7);
+                rest[..n - 4].to_vec()
+            });
+            assert_eq!(data, b"payloadtail");
+
+            // Only the data written along with RWF_DSYNC survives the crash
+            sim.crash();
+            assert_eq!(sim.read_file("wal").unwrap(), b"\x07\0\0\0payload");
+        });
+    }
+
+    #[test]
+    fn vectored_stream_io() {
+        simulate(SimOptions::new(), |sim| {
+            let listener = block_on(&sim, TcpListner::bind("127.0.0.1:4000", 16)).unwrap();
+            let peer = sim.connect("127.0.0.1:4000".parse().unwrap()).unwrap();
+            let mut stream = block_on(&sim, async move { listener.accept().await }).unwrap();
+
+            peer.send(b"scattered").unwrap();
+            let data = block_on(&sim, async move {
+                let bufs = [
+                    IoSlice::new(b"gat"),
+                    IoSlice::new(b""),
+                    IoSlice::new(b"hered"),
+                ];
+                assert_eq!(stream.send_vectored(&bufs).await.unwrap(), 8);
+
+                let (mut head, mut rest) = ([0; 4], [0; 8]);
+                let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut rest)];
+                let (n, ()) = stream.recv_vectored(&mut bufs).await.unwrap();
+                assert_eq!(n, 9);
+                [&head[..], &rest[..n - 4]].concat()
+            });
+            assert_eq!(data, b"scattered");
+
+            let mut buf = [0; 16];
+            assert_eq!(peer.recv(&mut buf).unwrap(), 8);
+            assert_eq!(&buf[..8], b"gathered");
+        });
+    }
+
+    #[test]
+    fn direct_io_is_checked_upfront() {
+        simulate(SimOptions::new(), |sim| {
+            block_on(&sim, async {
+                let plain = File::create("plain").await.unwrap();
+                assert_eq!(plain.alignment(), None);
+
+                let mut file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .direct(true)
+                    .open("direct")
+                    .await
+                    .unwrap();
+                // The kernel knows nothing of the simulated file, it gets the
+                // page alignment.
+                let align = file.alignment().unwrap();
+                assert_eq!((align.mem, align.offset), (4096, 4096));
+
+                let mut buf = AlignedBuf::for_file(&file, 100);
+                assert_eq!(buf.capacity(), 4096);
+                buf.fill(7);
+                assert_eq!(file.write_at(&buf, 0).await.unwrap(), 4096);
+
+                let misaligned = |res: stdio::Result<usize>| {
+                    let err = res.unwrap_err();
+                    assert_eq!(err.kind(), stdio::ErrorKind::InvalidInput);
+                    err.to_string()
+                };
+                assert!(misaligned(file.write_at(&buf, 512).await).contains("offset"));
+                assert!(misaligned(file.read_at(&mut buf[..512], 0).await).contains("length"));
+                assert!(misaligned(file.read_at(&mut buf[1..], 0).await).contains("buffer"));
+
+                let mut bufs = [IoSliceMut::new(&mut buf)];
+                let res = file.read_vectored_at(&mut bufs, 8, 0).await;
+                assert!(misaligned(res).contains("offset"));
+
+                // The rented buffer comes back along with the error
+                buf.set_len(100);
+                let (res, mut buf) = AsyncReadRent::read(&mut file, buf).await;
+                assert!(misaligned(res).contains("length"));
+
+                buf.set_len(4096);
+                buf.fill(0);
+                let (res, buf) = AsyncReadRent::read(&mut file, buf).await;
+                assert_eq!(res.unwrap(), 4096);
+                assert_eq!(&*buf, &[7; 4096]);
+            });
+            assert_eq!(sim.inflight(), 0);
+            assert_eq!(sim.read_file("direct").unwrap(), [7; 4096]);
+        });
+    }
+}
@@ -0,0 +1,65 @@
+/// Slab is a minimal slab allocator, it hands out stable `usize` keys for the
+/// values inserted into it and reuses the keys of removed values.
+pub(crate) struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    /// next_free is the key of the first vacant entry, `entries.len()` if
+    /// there is none.
+    next_free: usize,
+}
+
+enum Entry<T> {
+    Vacant(usize),
+    Occupied(T),
+}
+
+impl<T> Slab<T> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_free: 0,
+        }
+    }
+
+    /// insert stores the value in the slab and returns its key
+    pub fn insert(&mut self, val: T) -> usize {
+        let key = self.next_free;
+
+        if key == self.entries.len() {
+            self.entries.push(Entry::Occupied(val));
+            self.next_free = key + 1;
+        } else {
+            match std::mem::replace(&mut self.entries[key], Entry::Occupied(val)) {
+                Entry::Vacant(next) => self.next_free = next,
+                Entry::Occupied(_) => unreachable!("free list points to an occupied entry"),
+            }
+        }
+
+        key
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.entries.get_mut(key) {
+            Some(Entry::Occupied(val)) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// remove takes the value out of the slab and frees the key for reuse
+    ///
+    /// # Panics
+    /// Panics if there is no value stored under the key.
+    pub fn remove(&mut self, key: usize) -> T {
+        let entry = std::mem::replace(&mut self.entries[key], Entry::Vacant(self.next_free));
+
+        match entry {
+            Entry::Occupied(val) => {
+                self.next_free = key;
+                val
+            }
+            Entry::Vacant(next) => {
+                self.entries[key] = Entry::Vacant(next);
+                panic!("removing a vacant slab entry");
+            }
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! btree implements a B+-tree over the pages of a [BufferManager].
+//!
+//! Keys and values are byte strings, the keys are ordered lexicographically.
+//! The inner nodes keep the swips of their children, hence the buffer manager
+//! has to be built with [BTreeChildren].
+//!
+//! The tree uses optimistic lock coupling. A page is only modified while no
+//! await is in progress and the executor runs a single task at a time, hence
+//! a modification is never observed half way. The only thing that can happen
+//! while a task waits for a page to be loaded is that other tasks modify the
+//! pages it already traversed, which the task detects by comparing the
+//! versions of the pages before and after the await. The operation is then
+//! restarted from the root.
+//!
+//! Full pages are split one at a time: a leaf is split if its parent has
+//! space for another separator, otherwise the lowest ancestor that can be
+//! split is split first. The pages are not merged, a removal only frees the
+//! space in its leaf.
+
+mod node;
+
+pub use node::{MAX_KEY_SIZE, MAX_VALUE_SIZE};
+
+use std::{cell::Cell, collections::VecDeque, io as stdio};
+
+use crate::buffer::{BufferManager, Children, Page, PageGuard, PageId, Swip};
+
+/// BTreeChildren finds the swips in the pages of the B+-trees, the buffer
+/// manager holding the trees must be built with it (see
+/// [crate::buffer::BufferOptions::children]).
+pub struct BTreeChildren;
+
+impl Children for BTreeChildren {
+    fn for_each_child(&self, page: &Page, f: &mut dyn FnMut(usize)) {
+        node::child_offsets(page, f)
+    }
+}
+
+/// Path holds the pages from the root to a leaf together with their versions
+/// at the time they were traversed.
+type Path = Vec<(PageGuard<'static>, u64)>;
+
+pub struct BTree {
+    bm: &'static BufferManager,
+    root: Cell<Swip>,
+    root_id: Cell<PageId>,
+}
+
+/// Spare holds the pages an insert allocated for its splits which have not
+/// been used yet, the allocation may have to wait for the eviction and the
+/// split is then retried with a fresh path. The pages left over once the
+/// insert is done (or dropped) are discarded.
+struct Spare {
+    bm: &'static BufferManager,
+    pages: Vec<PageGuard<'static>>,
+}
+
+impl Drop for Spare {
+    fn drop(&mut self) {
+        for page in self.pages.drain(..) {
+            self.bm.discard(page);
+        }
+    }
+}
+
+impl BTree {
+    /// create creates an empty tree
+    pub async fn create(bm: &'static BufferManager) -> stdio::Result<Self> {
+        let root = bm.allocate().await?;
+        node::init_leaf(&mut root.page_mut());
+
+        Ok(Self::open(bm, root.page_id()))
+    }
+
+    /// open opens the tree whose root is the page
+    pub fn open(bm: &'static BufferManager, root: PageId) -> Self {
+        Self {
+            bm,
+            root: Cell::new(Swip::new(root)),
+            root_id: Cell::new(root),
+        }
+    }
+
+    /// root returns the page id of the root, it changes every time the root
+    /// is split and should be persisted to open the tree again.
+    pub fn root(&self) -> PageId {
+        self.root_id.get()
+    }
+
+    /// lookup returns the value of the key
+    pub async fn lookup(&self, key: &[u8]) -> stdio::Result<Option<Vec<u8>>> {
+        let path = self.descend(key).await?;
+        let page = path.last().unwrap().0.page();
+
+        let (idx, found) = node::lower_bound(&page, key);
+        Ok(found.then(|| node::value(&page, idx).to_vec()))
+    }
+
+    /// insert inserts the key or replaces its value
+    ///
+    /// # Panics
+    /// Panics if the key is larger than [MAX_KEY_SIZE] or the value is larger
+    /// than [MAX_VALUE_SIZE].
+    pub async fn insert(&self, key: &[u8], value: &[u8]) -> stdio::Result<()> {
+        assert!(key.len() <= MAX_KEY_SIZE, "key too large");
+        assert!(value.len() <= MAX_VALUE_SIZE, "value too large");
+
+        let mut spare = Spare {
+            bm: self.bm,
+            pages: Vec::new(),
+        };
+
+        loop {
+            let path = self.descend(key).await?;
+            if Self::insert_into_leaf(&path.last().unwrap().0, key, value) {
+                return Ok(());
+            }
+
+            self.split(path, &mut spare).await?;
+        }
+    }
+
+    /// remove removes the key, it returns false if the key does not exist
+    pub async fn remove(&self, key: &[u8]) -> stdio::Result<bool> {
+        let path = self.descend(key).await?;
+        let leaf = &path.last().unwrap().0;
+
+        let (idx, found) = node::lower_bound(&leaf.page(), key);
+        if found {
+            node::remove(&mut leaf.page_mut(), idx);
+        }
+
+        Ok(found)
+    }
+
+    /// scan returns the entries with keys greater than or equal to `from` in
+    /// the order of the keys.
+    ///
+    /// The entries are copied out one leaf at a time. Keys which exist for the
+    /// whole scan are returned exactly once, the keys inserted or removed
+    /// while the scan is in progress may or may not be returned.
+    pub fn scan(&self, from: &[u8]) -> Scan<'_> {
+        Scan {
+            tree: self,
+            from: from.to_vec(),
+            inclusive: true,
+            entries: VecDeque::new(),
+            leaf: None,
+            done: false,
+        }
+    }
+
+    /// descend returns the path to the leaf which covers the key
+    async fn descend(&self, key: &[u8]) -> stdio::Result<Path> {
+        'restart: loop {
+            let root = self.bm.fix(&self.root).await?;
+            // The root may have been split while it was being loaded
+            if root.page_id() != self.root_id.get() {
+                continue;
+            }
+
+            let version = root.version();
+            let mut path = vec![(root, version)];
+
+            loop {
+                let offset = {
+                    let page = path.last().unwrap().0.page();
+                    if node::is_leaf(&page) {
+                        None
+                    } else {
+                        Some(node::child_offset_for(&page, key))
+                    }
+                };
+                let Some(offset) = offset else {
+                    return Ok(path);
+                };
+
+                let child = self.bm.fix_child(&path.last().unwrap().0, offset).await?;
+                if !Self::validate(&path) {
+                    continue 'restart;
+                }
+
+                let version = child.version();
+                path.push((child, version));
+            }
+        }
+    }
+
+    /// validate tells whether none of the pages changed since they were
+    /// traversed.
+    fn validate(path: &Path) -> bool {
+        path.iter()
+            .all(|(guard, version)| guard.version() == *version)
+    }
+
+    /// insert_into_leaf inserts the entry if there is enough space in the leaf
+    fn insert_into_leaf(leaf: &PageGuard<'_>, key: &[u8], value: &[u8]) -> bool {
+        let (idx, found) = {
+            let page = leaf.page();
+            let (idx, found) = node::lower_bound(&page, key);
+
+            let mut free = node::free_space(&page);
+            if found {
+                free += node::leaf_entry_size(node::key(&page, idx), node::value(&page, idx));
+            }
+
+            if free < node::leaf_entry_size(key, value) {
+                return false;
+            }
+
+            (idx, found)
+        };
+
+        let mut page = leaf.page_mut();
+        if found {
+            node::remove(&mut page, idx);
+        }
+        node::insert_leaf(&mut page, idx, key, value);
+
+        true
+    }
+
+    /// split splits the last page of the path or, if its parent has no space
+    /// for another separator, the lowest ancestor whose parent has enough
+    /// space.
+    ///
+    /// Nothing is split if the pages needed had to be allocated first, the
+    /// path may be outdated by then. The caller retries in any case.
+    async fn split(&self, path: Path, spare: &mut Spare) -> stdio::Result<()> {
+        let mut level = path.len() - 1;
+        while level > 0 && node::free_space(&path[level - 1].0.page()) < node::MAX_INNER_ENTRY {
+            level -= 1;
+        }
+
+        // Splitting the root needs another page for the new root
+        let needed = if level == 0 { 2 } else { 1 };
+        if spare.pages.len() < needed {
+            while spare.pages.len() < needed {
+                spare.pages.push(self.bm.allocate().await?);
+            }
+
+            return Ok(());
+        }
+
+        let left = &path[level].0;
+        let right = spare.pages.pop().unwrap();
+
+        let separator = {
+            let mut left_page = left.page_mut();
+            let mut right_page = right.page_mut();
+
+            let mid = (node::count(&left_page) - 1) / 2;
+            let separator = node::key(&left_page, mid).to_vec();
+
+            if node::is_leaf(&left_page) {
+                node::split_leaf(&mut left_page, &mut right_page, right.page_id(), mid);
+            } else {
+                node::split_inner(&mut left_page, &mut right_page, mid, &mut |swip| {
+                    self.bm.detach(swip)
+                });
+            }
+
+            separator
+        };
+
+        // The swip of the split page keeps pointing to it (the left half),
+        // the right half takes over its place in the parent.
+        let right_swip = Swip::new(right.page_id());
+
+        if level == 0 {
+            let root = spare.pages.pop().unwrap();
+            let left_swip = self.bm.detach(self.root.get());
+
+            let mut page = root.page_mut();
+            node::init_inner(&mut page, right_swip);
+            node::insert_inner(&mut page, 0, &separator, left_swip);
+
+            self.root.set(Swip::new(root.page_id()));
+            self.root_id.set(root.page_id());
+        } else {
+            let mut page = path[level - 1].0.page_mut();
+
+            let (idx, _) = node::lower_bound(&page, &separator);
+            let offset = if idx == node::count(&page) {
+                node::UPPER
+            } else {
+                node::child_offset(&page, idx)
+            };
+
+            let left_swip = page.swip(offset);
+            page.set_swip(offset, right_swip);
+            node::insert_inner(&mut page, idx, &separator, left_swip);
+        }
+
+        Ok(())
+    }
+}
+
+/// Scan iterates over the entries of a tree, see [BTree::scan]
+pub struct Scan<'a> {
+    tree: &'a BTree,
+
+    /// from is the key the scan continues from
+    from: Vec<u8>,
+    inclusive: bool,
+
+    /// entries holds the entries copied out of the current leaf
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// leaf is the current leaf and its version at the time it was copied
+    leaf: Option<(PageGuard<'static>, u64)>,
+    done: bool,
+}
+
+impl Scan<'_> {
+    /// next returns the next entry, None once the scan is over
+    pub async fn next(&mut self) -> stdio::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            if let Some((key, value)) = self.entries.pop_front() {
+                self.from.clone_from(&key);
+                self.inclusive = false;
+
+                return Ok(Some((key, value)));
+            }
+
+            if self.done {
+                return Ok(None);
+            }
+
+            let leaf = match self.leaf.take() {
+                Some((leaf, version)) if leaf.version() == version => {
+                    let Some(next) = node::next_leaf(&leaf.page()) else {
+                        self.done = true;
+                        continue;
+                    };
+
+                    let next = self.tree.bm.fix_page(next).await?;
+                    // The leaf may have been split meanwhile, its new right
+                    // half would be skipped.
+                    if leaf.version() == version {
+                        next
+                    } else {
+                        self.locate().await?
+                    }
+                }
+                _ => self.locate().await?,
+            };
+
+            self.copy(leaf);
+        }
+    }
+
+    /// locate returns the leaf which covers the key the scan continues from
+    async fn locate(&self) -> stdio::Result<PageGuard<'static>> {
+        let mut path = self.tree.descend(&self.from).await?;
+        Ok(path.pop().unwrap().0)
+    }
+
+    /// copy copies the entries the scan has not returned yet out of the leaf
+    fn copy(&mut self, leaf: PageGuard<'static>) {
+        {
+            let page = leaf.page();
+
+            let (mut idx, found) = node::lower_bound(&page, &self.from);
+            if found && !self.inclusive {
+                idx += 1;
+            }
+
+            for idx in idx..node::count(&page) {
+                self.entries.push_back((
+                    node::key(&page, idx).to_vec(),
+                    node::value(&page, idx).to_vec(),
+                ));
+            }
+        }
+
+        let version = leaf.version();
+        self.leaf = Some((leaf, version));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{poll_fn, Future},
+        pin::pin,
+        task::Poll,
+    };
+
+    use reika::executor::PerThreadExecutor;
+
+    use super::*;
+    use crate::{
+        buffer::{BufferOptions, PAGE_SIZE},
+        sim::{self, simulate},
+    };
+
+    const FILE: &str = "btree";
+
+    /// key returns a long key for the number, the inner nodes fill up after a
+    /// few separators. The keys are ordered like the numbers.
+    fn key(n: u32) -> Vec<u8> {
+        let mut key = format!("{n:08}").into_bytes();
+        key.resize(200, b'.');
+        key
+    }
+
+    fn value(n: u32) -> Vec<u8> {
+        n.to_le_bytes().repeat(25)
+    }
+
+    /// shuffled returns the numbers below `n` in an order derived from the
+    /// seed.
+    fn shuffled(n: u32, seed: u64) -> Vec<u32> {
+        let mut nums: Vec<u32> = (0..n).collect();
+        let mut state = seed | 1;
+
+        for i in (1..nums.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            nums.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+
+        nums
+    }
+
+    async fn build(frames: usize, pages: u64) -> &'static BufferManager {
+        let file = sim::open(FILE).await;
+        BufferOptions::new()
+            .frames(frames)
+            .pages(pages)
+            .children(&BTreeChildren)
+            .build(file)
+            .unwrap()
+    }
+
+    async fn depth(tree: &BTree, key: &[u8]) -> usize {
+        tree.descend(key).await.unwrap().len()
+    }
+
+    /// reachable returns the number of pages of the tree
+    async fn reachable(tree: &BTree) -> u64 {
+        let mut pages = vec![tree.root()];
+        let mut count = 0;
+
+        while let Some(page_id) = pages.pop() {
+            let page = tree.bm.fix_page(page_id).await.unwrap();
+
+            let mut offsets = Vec::new();
+            node::child_offsets(&page.page(), &mut |offset| offsets.push(offset));
+            for offset in offsets {
+                let child = tree.bm.fix_child(&page, offset).await.unwrap();
+                pages.push(child.page_id());
+            }
+
+            count += 1;
+        }
+
+        count
+    }
+
+    #[test]
+    fn splits_across_levels() {
+        for seed in 0..3 {
+            simulate(seed, move |_| async move {
+                // Few frames, the pages keep being evicted and read back
+                let bm = build(32, 0).await;
+                let tree = BTree::create(bm).await.unwrap();
+
+                let nums = shuffled(3000, seed);
+                for n in &nums {
+                    tree.insert(&key(*n), &value(*n)).await.unwrap();
+                }
+
+                assert!(depth(&tree, &key(0)).await >= 3);
+                for n in nums {
+                    assert_eq!(tree.lookup(&key(n)).await.unwrap(), Some(value(n)));
+                }
+                assert_eq!(tree.lookup(&key(3000)).await.unwrap(), None);
+
+                bm.close();
+            });
+        }
+    }
+
+    #[test]
+    fn spare_pages_are_discarded() {
+        simulate(0, |_| async {
+            // The splits wait for the eviction, the inserts into the same
+            // leaf allocate pages only one of them uses.
+            let bm = build(16, 0).await;
+            let tree: &'static BTree = Box::leak(Box::new(BTree::create(bm).await.unwrap()));
+
+            let inserts: Vec<_> = (0..4)
+                .map(|task| {
+                    PerThreadExecutor::spawn(async move {
+                        for n in (task..1200).step_by(4) {
+                            tree.insert(&key(n), &value(n)).await.unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for insert in inserts {
+                insert.await.unwrap();
+            }
+
+            assert_eq!(bm.pages(), reachable(tree).await);
+
+            // An insert dropped while it waits for a page drops its spare
+            // pages as well.
+            let mut n = 2000;
+            loop {
+                let (key, value) = (key(n), value(n));
+                let mut insert = pin!(tree.insert(&key, &value));
+                let pending =
+                    poll_fn(|ctx| Poll::Ready(insert.as_mut().poll(ctx).is_pending())).await;
+                if pending {
+                    break;
+                }
+
+                n += 1;
+            }
+            assert_eq!(bm.pages(), reachable(tree).await);
+
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn scan_follows_leaf_links() {
+        simulate(0, |_| async {
+            let bm = build(16, 0).await;
+            let tree = BTree::create(bm).await.unwrap();
+
+            for n in shuffled(500, 7) {
+                tree.insert(&key(n), &value(n)).await.unwrap();
+            }
+            for n in (0..500).step_by(3) {
+                assert!(tree.remove(&key(n)).await.unwrap());
+            }
+            assert!(depth(&tree, &key(0)).await >= 2);
+
+            let mut scan = tree.scan(&key(100));
+            let mut scanned = Vec::new();
+            while let Some(entry) = scan.next().await.unwrap() {
+                scanned.push(entry);
+            }
+
+            let expected: Vec<_> = (100..500)
+                .filter(|n| n % 3 != 0)
+                .map(|n| (key(n), value(n)))
+                .collect();
+            assert!(scanned == expected, "scan returned the wrong entries");
+
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn lookup_restarts_after_split() {
+        simulate(0, |sim| async move {
+            let bm = build(256, 0).await;
+            let tree = BTree::create(bm).await.unwrap();
+            for n in 0..60 {
+                tree.insert(&key(n), &value(n)).await.unwrap();
+            }
+            assert_eq!(depth(&tree, &key(0)).await, 2);
+
+            bm.sync().await.unwrap();
+            let root = tree.root();
+            bm.close();
+
+            // Reopen the tree with only the root and the last leaf in memory
+            let pages = sim.read_file(FILE).unwrap().len() / PAGE_SIZE;
+            let bm = build(256, pages as u64).await;
+            let tree = BTree::open(bm, root);
+            assert!(tree.lookup(&key(59)).await.unwrap().is_some());
+
+            // The lookup stops at the first leaf, which is read from the disk
+            let first = key(0);
+            let mut descend = pin!(tree.descend(&first));
+            let pending = poll_fn(|ctx| Poll::Ready(descend.as_mut().poll(ctx).is_pending())).await;
+            assert!(pending);
+
+            // Meanwhile the last leaf is split, which changes the root
+            let root = bm.fix(&tree.root).await.unwrap();
+            let version = root.version();
+            let mut n = 1000;
+            while root.version() == version {
+                tree.insert(&key(n), &value(n)).await.unwrap();
+                n += 1;
+            }
+
+            let path = descend.await.unwrap();
+            assert_ne!(path[0].1, version, "lookup did not restart");
+            assert_eq!(path[0].1, root.version());
+            assert!(node::lower_bound(&path[1].0.page(), &first).1);
+
+            drop((path, root));
+            assert_eq!(tree.lookup(&key(0)).await.unwrap(), Some(value(0)));
+            bm.close();
+        });
+    }
+}
@@ -0,0 +1,314 @@
+//! node implements the layout of the B+-tree pages.
+//!
+//! Both leaves and inner nodes are slotted pages:
+//!
+//! ```text
+//! | lsn (8) | kind (1) | - (1) | count (2) | heap (2) | dead (2) | link (8) |
+//! | slots ... ->                            <- ... keys and values (heap) |
+//! ```
+//!
+//! The slots are kept sorted by key. A leaf slot points to the key and the
+//! value in the heap, an inner slot points to the key and holds the swip of
+//! the child with the keys less than or equal to the key. The link is the
+//! page id of the next leaf for leaves and the swip of the child with the
+//! keys greater than every key (the upper child) for inner nodes.
+
+use std::cmp::Ordering;
+
+use crate::buffer::{Page, PageId, Swip, PAGE_SIZE};
+
+const KIND: usize = 8;
+const COUNT: usize = 10;
+const HEAP: usize = 12;
+const DEAD: usize = 14;
+const LINK: usize = 16;
+const SLOTS: usize = 24;
+
+const KIND_LEAF: u8 = 1;
+const KIND_INNER: u8 = 2;
+
+const LEAF_SLOT_SIZE: usize = 8;
+const INNER_SLOT_SIZE: usize = 16;
+
+/// NO_PAGE marks the last leaf
+const NO_PAGE: PageId = u64::MAX;
+
+/// UPPER is the offset of the swip of the upper child in inner nodes
+pub(crate) const UPPER: usize = LINK;
+
+/// MAX_KEY_SIZE is the maximum size of a key
+pub const MAX_KEY_SIZE: usize = 512;
+
+/// MAX_VALUE_SIZE is the maximum size of a value
+pub const MAX_VALUE_SIZE: usize = 512;
+
+/// MAX_INNER_ENTRY is the space an inner entry takes at most
+pub(crate) const MAX_INNER_ENTRY: usize = INNER_SLOT_SIZE + MAX_KEY_SIZE;
+
+pub(crate) fn init_leaf(page: &mut Page) {
+    init(page, KIND_LEAF);
+    set_next_leaf(page, None);
+}
+
+pub(crate) fn init_inner(page: &mut Page, upper: Swip) {
+    init(page, KIND_INNER);
+    page.set_swip(UPPER, upper);
+}
+
+fn init(page: &mut Page, kind: u8) {
+    page[KIND] = kind;
+    set_u16(page, COUNT, 0);
+    set_u16(page, HEAP, PAGE_SIZE as u16);
+    set_u16(page, DEAD, 0);
+}
+
+pub(crate) fn is_leaf(page: &Page) -> bool {
+    page[KIND] == KIND_LEAF
+}
+
+pub(crate) fn count(page: &Page) -> usize {
+    get_u16(page, COUNT) as usize
+}
+
+fn slot_size(page: &Page) -> usize {
+    if is_leaf(page) {
+        LEAF_SLOT_SIZE
+    } else {
+        INNER_SLOT_SIZE
+    }
+}
+
+fn slot(page: &Page, idx: usize) -> usize {
+    SLOTS + idx * slot_size(page)
+}
+
+pub(crate) fn key(page: &Page, idx: usize) -> &[u8] {
+    let slot = slot(page, idx);
+    let (off, len) = (
+        get_u16(page, slot) as usize,
+        get_u16(page, slot + 2) as usize,
+    );
+
+    &page[off..off + len]
+}
+
+/// value returns the value of the entry of a leaf
+pub(crate) fn value(page: &Page, idx: usize) -> &[u8] {
+    let slot = slot(page, idx);
+    let off = (get_u16(page, slot) + get_u16(page, slot + 2)) as usize;
+    let len = get_u16(page, slot + 4) as usize;
+
+    &page[off..off + len]
+}
+
+/// child_offset returns the offset of the swip of the child of an inner node
+pub(crate) fn child_offset(page: &Page, idx: usize) -> usize {
+    slot(page, idx) + 8
+}
+
+/// child_offsets calls `f` with the offset of the swip of every child
+pub(crate) fn child_offsets(page: &Page, f: &mut dyn FnMut(usize)) {
+    if is_leaf(page) {
+        return;
+    }
+
+    for idx in 0..count(page) {
+        f(child_offset(page, idx));
+    }
+    f(UPPER);
+}
+
+pub(crate) fn next_leaf(page: &Page) -> Option<PageId> {
+    match page.read_u64(LINK) {
+        NO_PAGE => None,
+        page_id => Some(page_id),
+    }
+}
+
+pub(crate) fn set_next_leaf(page: &mut Page, next: Option<PageId>) {
+    page.write_u64(LINK, next.unwrap_or(NO_PAGE));
+}
+
+/// lower_bound returns the index of the first key that is not less than the
+/// key and whether the key is equal to it.
+pub(crate) fn lower_bound(page: &Page, key: &[u8]) -> (usize, bool) {
+    let (mut lo, mut hi) = (0, count(page));
+
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match self::key(page, mid).cmp(key) {
+            Ordering::Less => lo = mid + 1,
+            Ordering::Equal => return (mid, true),
+            Ordering::Greater => hi = mid,
+        }
+    }
+
+    (lo, false)
+}
+
+/// child_offset_for returns the offset of the swip of the child which covers
+/// the key.
+pub(crate) fn child_offset_for(page: &Page, key: &[u8]) -> usize {
+    let (idx, _) = lower_bound(page, key);
+    if idx == count(page) {
+        UPPER
+    } else {
+        child_offset(page, idx)
+    }
+}
+
+/// free_space returns the space available for new entries, including the
+/// space that becomes available once the page is compacted.
+pub(crate) fn free_space(page: &Page) -> usize {
+    let slots_end = slot(page, count(page));
+    get_u16(page, HEAP) as usize - slots_end + get_u16(page, DEAD) as usize
+}
+
+pub(crate) fn leaf_entry_size(key: &[u8], value: &[u8]) -> usize {
+    LEAF_SLOT_SIZE + key.len() + value.len()
+}
+
+/// insert_leaf inserts the entry at `idx`, the caller needs to make sure that
+/// there is enough space.
+pub(crate) fn insert_leaf(page: &mut Page, idx: usize, key: &[u8], value: &[u8]) {
+    let off = alloc(page, LEAF_SLOT_SIZE, key.len() + value.len());
+    page[off..off + key.len()].copy_from_slice(key);
+    page[off + key.len()..off + key.len() + value.len()].copy_from_slice(value);
+
+    let slot = open_slot(page, idx);
+    set_u16(page, slot, off as u16);
+    set_u16(page, slot + 2, key.len() as u16);
+    set_u16(page, slot + 4, value.len() as u16);
+}
+
+/// insert_inner inserts the entry at `idx`, the caller needs to make sure that
+/// there is enough space.
+pub(crate) fn insert_inner(page: &mut Page, idx: usize, key: &[u8], child: Swip) {
+    let off = alloc(page, INNER_SLOT_SIZE, key.len());
+    page[off..off + key.len()].copy_from_slice(key);
+
+    let slot = open_slot(page, idx);
+    set_u16(page, slot, off as u16);
+    set_u16(page, slot + 2, key.len() as u16);
+    page.set_swip(slot + 8, child);
+}
+
+/// remove removes the entry at `idx`
+pub(crate) fn remove(page: &mut Page, idx: usize) {
+    let size = slot_size(page);
+    let (slot, count) = (slot(page, idx), count(page));
+
+    let mut len = get_u16(page, slot + 2);
+    if is_leaf(page) {
+        len += get_u16(page, slot + 4);
+    }
+    set_u16(page, DEAD, get_u16(page, DEAD) + len);
+
+    let end = self::slot(page, count);
+    page.copy_within(slot + size..end, slot);
+    set_u16(page, COUNT, count as u16 - 1);
+}
+
+/// alloc reserves space for a slot and `len` bytes in the heap, it returns
+/// the offset of the bytes.
+fn alloc(page: &mut Page, slot_size: usize, len: usize) -> usize {
+    let slots_end = slot(page, count(page)) + slot_size;
+    if (get_u16(page, HEAP) as usize) < slots_end + len {
+        compact(page);
+    }
+
+    let heap = get_u16(page, HEAP) as usize - len;
+    assert!(heap >= slots_end, "page overflow");
+
+    set_u16(page, HEAP, heap as u16);
+    heap
+}
+
+/// open_slot makes room for a slot at `idx`
+fn open_slot(page: &mut Page, idx: usize) -> usize {
+    let size = slot_size(page);
+    let (slot, count) = (slot(page, idx), count(page));
+
+    let end = self::slot(page, count);
+    page.copy_within(slot..end, slot + size);
+    set_u16(page, COUNT, count as u16 + 1);
+
+    slot
+}
+
+/// compact moves the keys and values to the end of the page, reclaiming the
+/// space of the removed entries.
+fn compact(page: &mut Page) {
+    let mut copy = Page::zeroed();
+    copy.copy_from_slice(page);
+
+    let mut heap = PAGE_SIZE;
+    for idx in 0..count(page) {
+        let slot = slot(page, idx);
+
+        let mut len = get_u16(page, slot + 2) as usize;
+        if is_leaf(page) {
+            len += get_u16(page, slot + 4) as usize;
+        }
+
+        let off = get_u16(page, slot) as usize;
+        heap -= len;
+        page[heap..heap + len].copy_from_slice(&copy[off..off + len]);
+        set_u16(page, slot, heap as u16);
+    }
+
+    set_u16(page, HEAP, heap as u16);
+    set_u16(page, DEAD, 0);
+}
+
+/// split_leaf moves the entries after `mid` to the empty leaf `right`, the
+/// leaves stay linked.
+pub(crate) fn split_leaf(left: &mut Page, right: &mut Page, right_id: PageId, mid: usize) {
+    init_leaf(right);
+
+    for idx in mid + 1..count(left) {
+        insert_leaf(right, idx - mid - 1, key(left, idx), value(left, idx));
+    }
+    truncate(left, mid + 1);
+
+    set_next_leaf(right, next_leaf(left));
+    set_next_leaf(left, Some(right_id));
+}
+
+/// split_inner moves the entries after `mid` to the empty inner node
+/// `right`, the child of `mid` becomes the upper child of `left`.
+///
+/// `detach` is called with every swip moved to `right`, the swip it returns
+/// is stored instead.
+pub(crate) fn split_inner(
+    left: &mut Page,
+    right: &mut Page,
+    mid: usize,
+    detach: &mut dyn FnMut(Swip) -> Swip,
+) {
+    init_inner(right, detach(left.swip(UPPER)));
+
+    for idx in mid + 1..count(left) {
+        let child = detach(left.swip(child_offset(left, idx)));
+        insert_inner(right, idx - mid - 1, key(left, idx), child);
+    }
+
+    let upper = left.swip(child_offset(left, mid));
+    left.set_swip(UPPER, upper);
+    truncate(left, mid);
+}
+
+/// truncate removes the entries from `len` onwards
+fn truncate(page: &mut Page, len: usize) {
+    while count(page) > len {
+        remove(page, count(page) - 1);
+    }
+}
+
+fn get_u16(page: &Page, offset: usize) -> u16 {
+    u16::from_le_bytes([page[offset], page[offset + 1]])
+}
+
+fn set_u16(page: &mut Page, offset: usize, value: u16) {
+    page[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,145 @@
+use std::{
+    cell::{Cell, RefCell},
+    future::poll_fn,
+    task::Poll,
+};
+
+use super::page::{Page, PageId};
+use crate::{wait::WaitList, wal::Lsn};
+
+/// FrameId is the index of a frame in the buffer pool
+pub(crate) type FrameId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameState {
+    /// The frame holds no page
+    Free,
+    /// The page is being read from the disk
+    Loading,
+    /// The page is in memory
+    Resident,
+    /// The page is in memory but it is about to be evicted, no swip is
+    /// swizzled to it. Accessing the page makes it resident again.
+    Cooling,
+}
+
+/// Parent tells where the swip which is swizzled to a frame lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Parent {
+    /// No swip points to the frame, the page is found via its page id
+    None,
+    /// A swip kept outside of the buffer pool (a root) points to the frame
+    Root,
+    /// A swip stored in the page of the given frame points to the frame
+    Frame(FrameId),
+}
+
+/// Frame is a slot of the buffer pool, it holds a single page at a time
+pub(crate) struct Frame {
+    pub(crate) page: RefCell<Box<Page>>,
+    pub(crate) page_id: Cell<PageId>,
+    pub(crate) state: Cell<FrameState>,
+
+    /// pins is the number of guards referring to the frame, a pinned frame
+    /// is never evicted.
+    pub(crate) pins: Cell<u32>,
+    pub(crate) dirty: Cell<bool>,
+    /// version is bumped every time the page is handed out for writing
+    pub(crate) version: Cell<u64>,
+    /// writing is set while the page is being written back
+    pub(crate) writing: Cell<bool>,
+    /// rec_lsn is the LSN of the first log record applied to the page since
+    /// it was last written back, 0 if there is none.
+    pub(crate) rec_lsn: Cell<Lsn>,
+
+    pub(crate) parent: Cell<Parent>,
+    /// swizzled_children is the number of swips in the page which are
+    /// swizzled to other frames.
+    pub(crate) swizzled_children: Cell<u32>,
+
+    /// waiters are woken once the page finishes loading or being written
+    /// back.
+    waiters: WaitList,
+}
+
+impl Frame {
+    pub(crate) fn new() -> Self {
+        Self {
+            page: RefCell::new(Page::zeroed()),
+            page_id: Cell::new(0),
+            state: Cell::new(FrameState::Free),
+            pins: Cell::new(0),
+            dirty: Cell::new(false),
+            version: Cell::new(0),
+            writing: Cell::new(false),
+            rec_lsn: Cell::new(0),
+            parent: Cell::new(Parent::None),
+            swizzled_children: Cell::new(0),
+            waiters: WaitList::new(),
+        }
+    }
+
+    /// reset prepares a free frame to hold the page
+    pub(crate) fn reset(&self, page_id: PageId, state: FrameState) {
+        debug_assert_eq!(self.state.get(), FrameState::Free);
+        debug_assert_eq!(self.pins.get(), 0);
+
+        self.page_id.set(page_id);
+        self.state.set(state);
+        self.dirty.set(false);
+        self.writing.set(false);
+        self.rec_lsn.set(0);
+        self.parent.set(Parent::None);
+        self.swizzled_children.set(0);
+    }
+
+    pub(crate) fn pin(&self) {
+        self.pins.set(self.pins.get() + 1);
+    }
+
+    pub(crate) fn unpin(&self) {
+        debug_assert!(self.pins.get() > 0, "unpinning an unpinned frame");
+        self.pins.set(self.pins.get() - 1);
+    }
+
+    /// wake_waiters wakes everyone waiting for the load (or the write-back)
+    /// of the page.
+    pub(crate) fn wake_waiters(&self) {
+        self.waiters.wake_all();
+    }
+
+    /// is_evictable tells whether the page can leave the frame right now
+    pub(crate) fn is_evictable(&self) -> bool {
+        self.pins.get() == 0
+            && !self.writing.get()
+            && self.swizzled_children.get() == 0
+            && self.parent.get() != Parent::Root
+    }
+
+    /// loaded resolves once the frame is no longer loading a page, the
+    /// load may have failed hence the frame needs to be looked up again.
+    pub(crate) async fn loaded(&self) {
+        poll_fn(|ctx| {
+            if self.state.get() != FrameState::Loading {
+                return Poll::Ready(());
+            }
+
+            self.waiters.register(ctx);
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// written resolves once the page is no longer being written back
+    pub(crate) async fn written(&self) {
+        poll_fn(|ctx| {
+            if !self.writing.get() {
+                return Poll::Ready(());
+            }
+
+            self.waiters.register(ctx);
+            Poll::Pending
+        })
+        .await
+    }
+}
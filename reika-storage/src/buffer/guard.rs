@@ -0,0 +1,90 @@
+use std::cell::{Ref, RefMut};
+
+use super::{
+    frame::{Frame, FrameId},
+    page::{Page, PageId},
+    BufferManager,
+};
+use crate::wal::Lsn;
+
+/// PageGuard pins a page in the buffer pool, the page is not evicted as long
+/// as a guard for it exists.
+///
+/// The guard only keeps the page in memory, the page itself is borrowed via
+/// [PageGuard::page] and [PageGuard::page_mut]. The borrows follow the rules
+/// of a [std::cell::RefCell] and hence should not be held across an await.
+pub struct PageGuard<'a> {
+    pub(crate) bm: &'a BufferManager,
+    pub(crate) frame: FrameId,
+}
+
+impl<'a> PageGuard<'a> {
+    /// new pins the frame
+    pub(crate) fn new(bm: &'a BufferManager, frame: FrameId) -> Self {
+        bm.frame(frame).pin();
+        Self { bm, frame }
+    }
+
+    pub(crate) fn frame(&self) -> &'a Frame {
+        self.bm.frame(self.frame)
+    }
+
+    pub fn page_id(&self) -> PageId {
+        self.frame().page_id.get()
+    }
+
+    /// version returns the version of the page, the version changes every
+    /// time the page is borrowed for writing.
+    pub fn version(&self) -> u64 {
+        self.frame().version.get()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.frame().dirty.get()
+    }
+
+    pub fn page(&self) -> Ref<'_, Page> {
+        Ref::map(self.frame().page.borrow(), |page| &**page)
+    }
+
+    /// page_mut borrows the page for writing and marks it dirty
+    pub fn page_mut(&self) -> RefMut<'_, Page> {
+        let frame = self.frame();
+        let page = frame.page.borrow_mut();
+
+        frame.dirty.set(true);
+        frame.version.set(frame.version.get() + 1);
+
+        RefMut::map(page, |page| &mut **page)
+    }
+
+    /// rec_lsn returns the LSN of the first log record applied to the page
+    /// since it was last written back, 0 if there is none.
+    pub(crate) fn rec_lsn(&self) -> Lsn {
+        self.frame().rec_lsn.get()
+    }
+
+    /// set_lsn stamps the page with the LSN of the log record describing its
+    /// latest modification. The page is not written back before the log is
+    /// durable up to the record (see [super::BufferOptions::wal]).
+    pub fn set_lsn(&self, lsn: Lsn) {
+        self.page_mut().write_u64(0, lsn);
+
+        let frame = self.frame();
+        if frame.rec_lsn.get() == 0 {
+            frame.rec_lsn.set(lsn);
+        }
+    }
+}
+
+impl Clone for PageGuard<'_> {
+    fn clone(&self) -> Self {
+        Self::new(self.bm, self.frame)
+    }
+}
+
+impl Drop for PageGuard<'_> {
+    fn drop(&mut self) {
+        self.frame().unpin();
+    }
+}
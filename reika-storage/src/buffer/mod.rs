@@ -0,0 +1,946 @@
+//! buffer implements a LeanStore style buffer manager.
+//!
+//! Pages are cached in a fixed number of frames and are referred by [Swip]s.
+//! A swip of a page which is in memory is swizzled, it points straight to the
+//! frame holding the page, hence a hot page is reached without any hash table
+//! lookup. A page that is not in memory is read from the backing file on the
+//! first access and its swip is swizzled afterwards.
+//!
+//! Data structures keep the swips of their child pages inside their pages, the
+//! buffer manager needs to know where to find them and asks the [Children]
+//! the manager was created with.
+//!
+//! Pages leave the memory in two steps. Once the number of free frames drops
+//! below the low watermark, the eviction system task unswizzles a few pages
+//! and puts them in the cooling stage. A cooling page that is accessed again
+//! is simply made resident again (second chance), otherwise it is evicted
+//! once it is clean. Dirty cooling pages are written back by the write-back
+//! system task. Eviction goes on till the high watermark of free frames is
+//! reached.
+
+mod frame;
+mod guard;
+mod page;
+mod tasks;
+
+pub use guard::PageGuard;
+pub use page::{Page, PageId, Swip, PAGE_LSN_SIZE, PAGE_SIZE};
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    io as stdio,
+    task::Poll,
+};
+
+use crate::{
+    wait::{Notify, WaitList},
+    wal::{Lsn, Wal},
+};
+use frame::{Frame, FrameId, FrameState, Parent};
+use reika::{
+    executor::{core::AbortHandle, PerThreadExecutor},
+    reactor::io::File,
+};
+
+/// Children is implemented by the data structures which store swips in their
+/// pages.
+pub trait Children {
+    /// for_each_child calls `f` with the offset of every swip in the page
+    fn for_each_child(&self, page: &Page, f: &mut dyn FnMut(usize));
+}
+
+#[derive(Clone, Copy)]
+pub struct BufferOptions {
+    frames: usize,
+    pages: u64,
+    children: Option<&'static dyn Children>,
+    watermarks: Option<(usize, usize)>,
+    wal: Option<&'static Wal>,
+}
+
+impl BufferOptions {
+    pub fn new() -> Self {
+        Self {
+            frames: 1024,
+            pages: 0,
+            children: None,
+            watermarks: None,
+            wal: None,
+        }
+    }
+
+    /// frames sets the number of pages that can be cached at the same time
+    pub fn frames(&mut self, frames: usize) -> &mut Self {
+        self.frames = frames;
+        self
+    }
+
+    /// pages sets the number of pages that already exist in the file, new
+    /// pages are allocated after them.
+    pub fn pages(&mut self, pages: u64) -> &mut Self {
+        self.pages = pages;
+        self
+    }
+
+    /// children sets how the swips are found in the pages, it is required
+    /// for swizzling swips stored in pages (see [BufferManager::fix_child]).
+    pub fn children(&mut self, children: &'static dyn Children) -> &mut Self {
+        self.children = Some(children);
+        self
+    }
+
+    /// watermarks sets the number of free frames below which the eviction
+    /// starts (`low`) and the number of free frames at which it stops (`high`).
+    ///
+    /// The wider the gap the more pages are evicted in one go. By default the
+    /// watermarks are 10% and 20% of the frames.
+    pub fn watermarks(&mut self, low: usize, high: usize) -> &mut Self {
+        self.watermarks = Some((low, high));
+        self
+    }
+
+    /// wal sets the log describing the modifications of the pages. A page is
+    /// written back only once the log is durable up to the LSN of the page.
+    pub fn wal(&mut self, wal: &'static Wal) -> &mut Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// build creates the buffer manager and spawns its system tasks to the
+    /// executor of the current thread. It fails if [MAX_BUFFER_MANAGERS] are
+    /// running already, see [BufferManager::close].
+    ///
+    /// NOTE: The buffer manager is leaked and its memory (the frames included)
+    /// is NEVER released. The system tasks and the guards refer to it without
+    /// counting the references, it has to outlive every one of them.
+    pub fn build(&self, file: File) -> stdio::Result<&'static BufferManager> {
+        assert!(self.frames > 0, "buffer pool needs at least one frame");
+
+        let (low, high) = self.watermarks.unwrap_or((
+            (self.frames / 10).max(1),
+            (self.frames / 5).max(2).min(self.frames),
+        ));
+        assert!(
+            low <= high && high <= self.frames,
+            "watermarks must satisfy low <= high <= frames"
+        );
+
+        let raw = Box::into_raw(Box::new(BufferManager {
+            file,
+            frames: (0..self.frames).map(|_| Frame::new()).collect(),
+            free: RefCell::new((0..self.frames).rev().collect()),
+            resident: RefCell::new(HashMap::new()),
+            next_page_id: Cell::new(self.pages),
+            free_pages: RefCell::new(Vec::new()),
+            children: self.children,
+            wal: self.wal,
+            low_watermark: low,
+            high_watermark: high,
+            cooling: RefCell::new(VecDeque::new()),
+            clock: Cell::new(0),
+            evict: Notify::new(),
+            write: Notify::new(),
+            free_waiters: WaitList::new(),
+            exhausted: Cell::new(0),
+            tasks: Cell::new(None),
+        }));
+
+        // SAFETY: the manager is leaked unless its tasks cannot be spawned
+        let bm: &'static BufferManager = unsafe { &*raw };
+
+        let Some((evictor, writer)) = tasks::evictor(bm).zip(tasks::writer(bm)) else {
+            // SAFETY: the tokens are dropped along with their tasks, nothing
+            // refers to the manager anymore.
+            drop(unsafe { Box::from_raw(raw) });
+            return Err(stdio::Error::other("too many buffer managers are running"));
+        };
+
+        bm.tasks
+            .set(Some([evictor.abort_handle(), writer.abort_handle()]));
+        PerThreadExecutor::spawn_system_task(evictor);
+        PerThreadExecutor::spawn_system_task(writer);
+
+        Ok(bm)
+    }
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// MAX_BUFFER_MANAGERS is the number of buffer managers that can run, it is
+/// bound by the size of the pools of the system tasks.
+pub const MAX_BUFFER_MANAGERS: usize = 8;
+
+/// BufferManager caches the pages of a file in a fixed set of frames
+pub struct BufferManager {
+    file: File,
+    frames: Box<[Frame]>,
+
+    free: RefCell<Vec<FrameId>>,
+    /// resident maps the pages which own a frame (including the ones being
+    /// loaded) to the frame.
+    resident: RefCell<HashMap<PageId, FrameId>>,
+
+    next_page_id: Cell<PageId>,
+    /// free_pages are the ids of the discarded pages, they are handed out
+    /// again before new pages are appended to the file.
+    free_pages: RefCell<Vec<PageId>>,
+    children: Option<&'static dyn Children>,
+    wal: Option<&'static Wal>,
+
+    low_watermark: usize,
+    high_watermark: usize,
+    /// cooling holds the cooling frames in the order they started cooling, a
+    /// frame which has been made resident again is skipped.
+    cooling: RefCell<VecDeque<FrameId>>,
+    /// clock is the next frame considered for cooling
+    clock: Cell<usize>,
+
+    /// evict wakes the eviction task
+    evict: Notify,
+    /// write wakes the write-back task
+    write: Notify,
+    /// free_waiters are woken once frames are freed (or the eviction failed)
+    free_waiters: WaitList,
+    /// exhausted is bumped every time the eviction finds nothing to evict
+    exhausted: Cell<u64>,
+    /// tasks are the system tasks of the manager, None once it is closed
+    tasks: Cell<Option<[AbortHandle; 2]>>,
+}
+
+impl BufferManager {
+    pub fn options() -> BufferOptions {
+        BufferOptions::new()
+    }
+
+    pub(crate) fn frame(&self, frame: FrameId) -> &Frame {
+        &self.frames[frame]
+    }
+
+    pub(crate) fn wal(&self) -> Option<&'static Wal> {
+        self.wal
+    }
+
+    /// close stops the system tasks of the manager, their slots are then free
+    /// for another manager (see [MAX_BUFFER_MANAGERS]). The dirty pages are
+    /// not written back and no page is evicted anymore.
+    pub fn close(&self) {
+        for task in self.tasks.take().into_iter().flatten() {
+            task.abort();
+        }
+    }
+
+    /// pages returns the number of pages in use, that is allocated (or in the
+    /// file already) and not discarded.
+    pub fn pages(&self) -> u64 {
+        self.next_page_id.get() - self.free_pages.borrow().len() as u64
+    }
+
+    /// free_frames returns the number of frames not holding any page
+    pub fn free_frames(&self) -> usize {
+        self.free.borrow().len()
+    }
+
+    /// allocate creates a new (zeroed) page
+    pub async fn allocate(&self) -> stdio::Result<PageGuard<'_>> {
+        let frame = self.take_free().await?;
+
+        let page_id = self.free_pages.borrow_mut().pop().unwrap_or_else(|| {
+            let page_id = self.next_page_id.get();
+            self.next_page_id.set(page_id + 1);
+            page_id
+        });
+
+        let f = self.frame(frame);
+        f.reset(page_id, FrameState::Resident);
+        f.page.borrow_mut().fill(0);
+        f.dirty.set(true);
+
+        self.resident.borrow_mut().insert(page_id, frame);
+        Ok(PageGuard::new(self, frame))
+    }
+
+    /// discard frees a page returned by [BufferManager::allocate] that never
+    /// became reachable, the page id is allocated again.
+    ///
+    /// The content of the page is dropped. If the page is pinned elsewhere
+    /// (or being written back) it stays in the pool till it is evicted and its
+    /// page id is not reused.
+    pub fn discard(&self, guard: PageGuard<'_>) {
+        let frame = guard.frame;
+        drop(guard);
+
+        let f = self.frame(frame);
+        f.dirty.set(false);
+        f.rec_lsn.set(0);
+
+        if f.pins.get() > 0 || f.writing.get() || f.parent.get() != Parent::None {
+            return;
+        }
+
+        self.resident.borrow_mut().remove(&f.page_id.get());
+        self.free_pages.borrow_mut().push(f.page_id.get());
+        f.state.set(FrameState::Free);
+        self.free.borrow_mut().push(frame);
+        self.free_waiters.wake_all();
+    }
+
+    /// fix_page pins the page, reading it from the disk if necessary.
+    ///
+    /// No swip is swizzled by this method, pages reachable via swips should be
+    /// fixed via [BufferManager::fix] or [BufferManager::fix_child].
+    pub async fn fix_page(&self, page_id: PageId) -> stdio::Result<PageGuard<'_>> {
+        let frame = self.load(page_id).await?;
+        Ok(PageGuard::new(self, frame))
+    }
+
+    /// fix pins the page the swip refers to, the swip is swizzled if the
+    /// page had to be looked up.
+    ///
+    /// The swip is meant to be kept outside of the buffer pool, for example
+    /// the root of a tree. The page of such a swip is never evicted.
+    pub async fn fix(&self, swip: &Cell<Swip>) -> stdio::Result<PageGuard<'_>> {
+        let current = swip.get();
+        if let Some(frame) = current.frame() {
+            return Ok(PageGuard::new(self, frame));
+        }
+
+        let frame = self.load(current.page_id().unwrap()).await?;
+        let f = self.frame(frame);
+
+        // The swip may have changed while the page was being loaded
+        if swip.get() == current && f.parent.get() == Parent::None {
+            swip.set(Swip::swizzled(frame));
+            f.parent.set(Parent::Root);
+        }
+
+        Ok(PageGuard::new(self, frame))
+    }
+
+    /// fix_child pins the page the swip at `offset` of the parent page refers
+    /// to, the swip is swizzled if the page had to be looked up.
+    ///
+    /// # Panics
+    /// Panics if the manager was built without [BufferOptions::children].
+    pub async fn fix_child<'a>(
+        &'a self,
+        parent: &PageGuard<'a>,
+        offset: usize,
+    ) -> stdio::Result<PageGuard<'a>> {
+        assert!(
+            self.children.is_some(),
+            "swizzling swips stored in pages requires BufferOptions::children"
+        );
+
+        let current = parent.page().swip(offset);
+        if let Some(frame) = current.frame() {
+            return Ok(PageGuard::new(self, frame));
+        }
+
+        let frame = self.load(current.page_id().unwrap()).await?;
+        let f = self.frame(frame);
+
+        // The parent may have changed while the page was being loaded, the
+        // page stays reachable via its page id in that case.
+        let mut page = parent.frame().page.borrow_mut();
+        if page.swip(offset) == current && f.parent.get() == Parent::None {
+            page.set_swip(offset, Swip::swizzled(frame));
+            f.parent.set(Parent::Frame(parent.frame));
+
+            let parent = parent.frame();
+            parent
+                .swizzled_children
+                .set(parent.swizzled_children.get() + 1);
+        }
+
+        Ok(PageGuard::new(self, frame))
+    }
+
+    /// detach unswizzles the swip before a data structure moves it to another
+    /// page (or from a root into a page), the returned swip must be stored
+    /// instead. The page is swizzled again on its next access.
+    ///
+    /// Swips moved around within the same page need not be detached.
+    pub fn detach(&self, swip: Swip) -> Swip {
+        let Some(frame) = swip.frame() else {
+            return swip;
+        };
+
+        let f = self.frame(frame);
+        if let Parent::Frame(parent) = f.parent.get() {
+            let p = self.frame(parent);
+            p.swizzled_children.set(p.swizzled_children.get() - 1);
+        }
+        f.parent.set(Parent::None);
+
+        Swip::new(f.page_id.get())
+    }
+
+    /// flush_page writes the page back to the disk if it is dirty
+    pub async fn flush_page(&self, guard: &PageGuard<'_>) -> stdio::Result<()> {
+        self.write_back(guard.frame).await
+    }
+
+    /// flush writes every dirty page back to the disk
+    pub async fn flush(&self) -> stdio::Result<()> {
+        for frame in 0..self.frames.len() {
+            let f = self.frame(frame);
+            let state = f.state.get();
+            if (state == FrameState::Resident || state == FrameState::Cooling) && f.dirty.get() {
+                self.write_back(frame).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// sync flushes the dirty pages and makes them durable
+    pub async fn sync(&self) -> stdio::Result<()> {
+        self.flush().await?;
+        self.file.sync_data().await
+    }
+
+    /// sync_file makes the pages written back so far durable
+    pub(crate) async fn sync_file(&self) -> stdio::Result<()> {
+        self.file.sync_data().await
+    }
+
+    /// take_free takes a free frame, waiting for the eviction if there are
+    /// none. It fails if the eviction cannot free any frame.
+    async fn take_free(&self) -> stdio::Result<FrameId> {
+        loop {
+            let frame = self.free.borrow_mut().pop();
+            if let Some(frame) = frame {
+                if self.free_frames() < self.low_watermark {
+                    self.evict.notify();
+                }
+
+                return Ok(frame);
+            }
+
+            let exhausted = self.exhausted.get();
+            self.evict.notify();
+
+            poll_fn(|ctx| {
+                if self.free_frames() > 0 || self.exhausted.get() != exhausted {
+                    return Poll::Ready(());
+                }
+
+                self.free_waiters.register(ctx);
+                Poll::Pending
+            })
+            .await;
+
+            if self.free_frames() == 0 {
+                return Err(stdio::Error::new(
+                    stdio::ErrorKind::OutOfMemory,
+                    "no evictable frame in the buffer pool",
+                ));
+            }
+        }
+    }
+
+    /// load returns the frame holding the page, the page is read from the disk
+    /// if it is not in memory.
+    async fn load(&self, page_id: PageId) -> stdio::Result<FrameId> {
+        loop {
+            let frame = self.resident.borrow().get(&page_id).copied();
+
+            if let Some(frame) = frame {
+                let f = self.frame(frame);
+                match f.state.get() {
+                    FrameState::Loading => {
+                        // Someone else is reading the page already
+                        f.loaded().await;
+                        continue;
+                    }
+                    // Second chance, the page stays in memory
+                    FrameState::Cooling => f.state.set(FrameState::Resident),
+                    FrameState::Resident => {}
+                    FrameState::Free => unreachable!("free frame holds a page"),
+                }
+
+                return Ok(frame);
+            }
+
+            let frame = self.take_free().await?;
+
+            // Someone else may have started loading the page while we were
+            // waiting for the frame.
+            if self.resident.borrow().contains_key(&page_id) {
+                self.free.borrow_mut().push(frame);
+                self.free_waiters.wake_all();
+                continue;
+            }
+
+            self.frame(frame).reset(page_id, FrameState::Loading);
+            self.resident.borrow_mut().insert(page_id, frame);
+
+            let load = LoadGuard { bm: self, frame };
+            self.read_page(frame, page_id).await?;
+            load.finish();
+
+            return Ok(frame);
+        }
+    }
+
+    /// read_page reads the page into the frame, the part of the page beyond
+    /// the end of the file is zeroed.
+    ///
+    /// The page is read into a page of its own which then replaces the page
+    /// of the frame, the frame is never handed to the reads. If the load is
+    /// abandoned midway the frame goes back to the free list right away
+    /// while the reads may still be in-flight.
+    async fn read_page(&self, frame: FrameId, page_id: PageId) -> stdio::Result<()> {
+        let mut page = Page::zeroed();
+        let offset = page_id * PAGE_SIZE as u64;
+
+        let mut read = 0;
+        while read < PAGE_SIZE {
+            let n = self
+                .file
+                .read_at(&mut page[read..], offset + read as u64)
+                .await?;
+            if n == 0 {
+                break;
+            }
+
+            read += n;
+        }
+
+        page[read..].fill(0);
+        *self.frame(frame).page.borrow_mut() = page;
+        Ok(())
+    }
+
+    /// write_back writes the page held by the frame to the disk. If the page
+    /// is being written back already, that write is waited for and the page
+    /// is written back again if it is still dirty.
+    pub(crate) async fn write_back(&self, frame: FrameId) -> stdio::Result<()> {
+        let f = self.frame(frame);
+        while f.writing.get() {
+            f.written().await;
+        }
+
+        if !f.dirty.get() {
+            return Ok(());
+        }
+
+        // The page is written from a copy so that it can be modified while the
+        // write is in progress, such modifications keep the page dirty.
+        let version = f.version.get();
+        let mut copy = Page::zeroed();
+        copy.copy_from_slice(&f.page.borrow());
+        self.unswizzle_children(frame, &mut copy);
+
+        let _writing = WritingGuard::new(f);
+        let _guard = PageGuard::new(self, frame);
+
+        self.write_page(f.page_id.get(), &copy).await?;
+
+        if f.version.get() == version {
+            f.dirty.set(false);
+            f.rec_lsn.set(0);
+        }
+
+        Ok(())
+    }
+
+    /// write_page writes the page to the disk, after the log records applied
+    /// to it (write-ahead logging).
+    async fn write_page(&self, page_id: PageId, page: &Page) -> stdio::Result<()> {
+        if let Some(wal) = self.wal {
+            wal.commit(page.lsn()).await?;
+        }
+
+        let offset = page_id * PAGE_SIZE as u64;
+
+        let mut written = 0;
+        while written < PAGE_SIZE {
+            let n = self
+                .file
+                .write_at(&page[written..], offset + written as u64)
+                .await?;
+            if n == 0 {
+                return Err(stdio::ErrorKind::WriteZero.into());
+            }
+
+            written += n;
+        }
+
+        Ok(())
+    }
+
+    /// cool moves unswizzled pages to the cooling stage till `wanted` pages
+    /// are cooling, it returns the number of pages moved.
+    pub(crate) fn cool(&self, wanted: usize) -> usize {
+        let mut cooled = 0;
+
+        for _ in 0..self.frames.len() {
+            if self.cooling.borrow().len() >= wanted {
+                break;
+            }
+
+            let frame = self.clock.get();
+            self.clock.set((frame + 1) % self.frames.len());
+
+            let f = self.frame(frame);
+            if f.state.get() != FrameState::Resident || !f.is_evictable() {
+                continue;
+            }
+
+            if self.unswizzle(frame) {
+                f.state.set(FrameState::Cooling);
+                self.cooling.borrow_mut().push_back(frame);
+                cooled += 1;
+            }
+        }
+
+        cooled
+    }
+
+    /// evict_cooled evicts up to `wanted` clean pages from the cooling stage,
+    /// it returns the number of pages evicted and the number of cooling pages
+    /// that need to be written back first.
+    pub(crate) fn evict_cooled(&self, wanted: usize) -> (usize, usize) {
+        let mut cooling = self.cooling.borrow_mut();
+        let (mut evicted, mut dirty) = (0, 0);
+
+        for _ in 0..cooling.len() {
+            if evicted >= wanted {
+                break;
+            }
+
+            let frame = cooling.pop_front().unwrap();
+            let f = self.frame(frame);
+            if f.state.get() != FrameState::Cooling {
+                continue;
+            }
+
+            if f.dirty.get() || !f.is_evictable() {
+                dirty += f.dirty.get() as usize;
+                cooling.push_back(frame);
+                continue;
+            }
+
+            self.resident.borrow_mut().remove(&f.page_id.get());
+            f.state.set(FrameState::Free);
+            self.free.borrow_mut().push(frame);
+            evicted += 1;
+        }
+
+        if evicted > 0 {
+            self.free_waiters.wake_all();
+        }
+
+        (evicted, dirty)
+    }
+
+    /// dirty_cooling returns the cooling frames which need to be written back
+    pub(crate) fn dirty_cooling(&self) -> Vec<FrameId> {
+        self.cooling
+            .borrow()
+            .iter()
+            .copied()
+            .filter(|frame| {
+                let f = self.frame(*frame);
+                f.state.get() == FrameState::Cooling && f.dirty.get()
+            })
+            .collect()
+    }
+
+    /// dirty_pages returns the dirty pages which were modified through the
+    /// log together with the LSN of the first record applied to them since
+    /// they were last written back.
+    pub(crate) fn dirty_pages(&self) -> Vec<(PageId, Lsn)> {
+        self.frames
+            .iter()
+            .filter(|f| f.state.get() != FrameState::Free && f.dirty.get() && f.rec_lsn.get() != 0)
+            .map(|f| (f.page_id.get(), f.rec_lsn.get()))
+            .collect()
+    }
+
+    /// out_of_frames fails the tasks waiting for a free frame
+    pub(crate) fn out_of_frames(&self) {
+        self.exhausted.set(self.exhausted.get() + 1);
+        self.free_waiters.wake_all();
+    }
+
+    /// unswizzle replaces the swip swizzled to the frame with the page id, it
+    /// returns false if the swip cannot be unswizzled.
+    fn unswizzle(&self, frame: FrameId) -> bool {
+        let f = self.frame(frame);
+
+        let parent = match f.parent.get() {
+            Parent::None => return true,
+            Parent::Root => return false,
+            Parent::Frame(parent) => parent,
+        };
+
+        let p = self.frame(parent);
+        let Ok(mut page) = p.page.try_borrow_mut() else {
+            return false;
+        };
+
+        let mut offsets = Vec::new();
+        self.children
+            .unwrap()
+            .for_each_child(&page, &mut |offset| offsets.push(offset));
+
+        let swizzled = Swip::swizzled(frame);
+        let Some(offset) = offsets.into_iter().find(|o| page.swip(*o) == swizzled) else {
+            return false;
+        };
+
+        page.set_swip(offset, Swip::new(f.page_id.get()));
+        p.swizzled_children.set(p.swizzled_children.get() - 1);
+        f.parent.set(Parent::None);
+
+        true
+    }
+
+    /// unswizzle_children replaces the swizzled swips in the copy of the page
+    /// held by the frame with the page ids of the children.
+    fn unswizzle_children(&self, frame: FrameId, copy: &mut Page) {
+        if self.frame(frame).swizzled_children.get() == 0 {
+            return;
+        }
+
+        let mut offsets = Vec::new();
+        self.children
+            .unwrap()
+            .for_each_child(copy, &mut |offset| offsets.push(offset));
+
+        for offset in offsets {
+            if let Some(child) = copy.swip(offset).frame() {
+                copy.set_swip(offset, Swip::new(self.frame(child).page_id.get()));
+            }
+        }
+    }
+}
+
+/// LoadGuard releases the frame of a page whose load failed (or was
+/// abandoned), the waiters are woken in any case.
+struct LoadGuard<'a> {
+    bm: &'a BufferManager,
+    frame: FrameId,
+}
+
+impl LoadGuard<'_> {
+    fn finish(self) {
+        let f = self.bm.frame(self.frame);
+        f.state.set(FrameState::Resident);
+        f.wake_waiters();
+
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for LoadGuard<'_> {
+    fn drop(&mut self) {
+        let f = self.bm.frame(self.frame);
+
+        self.bm.resident.borrow_mut().remove(&f.page_id.get());
+        f.state.set(FrameState::Free);
+        self.bm.free.borrow_mut().push(self.frame);
+
+        f.wake_waiters();
+    }
+}
+
+/// WritingGuard marks the frame as being written back, the mark is cleared
+/// once the write is done or abandoned.
+struct WritingGuard<'a> {
+    f: &'a Frame,
+}
+
+impl<'a> WritingGuard<'a> {
+    fn new(f: &'a Frame) -> Self {
+        f.writing.set(true);
+        Self { f }
+    }
+}
+
+impl Drop for WritingGuard<'_> {
+    fn drop(&mut self) {
+        self.f.writing.set(false);
+        self.f.wake_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reika::reactor::core::yield_now;
+
+    use super::*;
+    use crate::sim::{self, simulate};
+
+    const FILE: &str = "pages";
+
+    /// SWIP is the offset of the only swip of the test pages
+    const SWIP: usize = PAGE_LSN_SIZE;
+    /// DATA is the offset of the data of the test pages
+    const DATA: usize = SWIP + 8;
+
+    struct OneChild;
+
+    impl Children for OneChild {
+        fn for_each_child(&self, _page: &Page, f: &mut dyn FnMut(usize)) {
+            f(SWIP)
+        }
+    }
+
+    /// build creates a manager whose evictor only runs when no frame is left
+    async fn build(frames: usize) -> &'static BufferManager {
+        BufferOptions::new()
+            .frames(frames)
+            .watermarks(0, 1)
+            .children(&OneChild)
+            .build(sim::open(FILE).await)
+            .unwrap()
+    }
+
+    fn state(guard: &PageGuard<'_>) -> FrameState {
+        guard.frame().state.get()
+    }
+
+    #[test]
+    fn swips_are_swizzled_and_unswizzled() {
+        simulate(0, |sim| async move {
+            let bm = build(8).await;
+
+            let parent = bm.allocate().await.unwrap();
+            let child = bm.allocate().await.unwrap();
+            let child_id = child.page_id();
+            child.page_mut().write_u64(DATA, 42);
+            parent.page_mut().set_swip(SWIP, Swip::new(child_id));
+            drop(child);
+
+            // The root swip points straight to the frame after the first fix
+            let root = Cell::new(Swip::new(parent.page_id()));
+            drop(parent);
+            let parent = bm.fix(&root).await.unwrap();
+            assert_eq!(root.get(), Swip::swizzled(parent.frame));
+
+            let child = bm.fix_child(&parent, SWIP).await.unwrap();
+            assert_eq!(parent.page().swip(SWIP), Swip::swizzled(child.frame));
+            assert_eq!(parent.frame().swizzled_children.get(), 1);
+
+            // The swips never reach the disk swizzled
+            bm.flush().await.unwrap();
+            let data = sim.read_file(FILE).unwrap();
+            let offset = parent.page_id() as usize * PAGE_SIZE + SWIP;
+            let on_disk = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+            assert_eq!(Swip::from_raw(on_disk), Swip::new(child_id));
+
+            // Cooling the child unswizzles its swip, the root is not cooled
+            drop(child);
+            assert_eq!(bm.cool(2), 1);
+            assert_eq!(parent.page().swip(SWIP), Swip::new(child_id));
+            assert_eq!(parent.frame().swizzled_children.get(), 0);
+            assert_eq!(state(&parent), FrameState::Resident);
+
+            let free = bm.free_frames();
+            assert_eq!(bm.evict_cooled(1), (1, 0));
+            assert_eq!(bm.free_frames(), free + 1);
+
+            // The child is read back and swizzled again
+            let child = bm.fix_child(&parent, SWIP).await.unwrap();
+            assert_eq!(child.page_id(), child_id);
+            assert_eq!(child.page().read_u64(DATA), 42);
+            assert!(parent.page().swip(SWIP).is_swizzled());
+
+            drop((child, parent));
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn pinned_pages_are_not_evicted() {
+        simulate(0, |_| async {
+            let bm = build(4).await;
+
+            let pinned = bm.allocate().await.unwrap();
+            let unpinned = bm.allocate().await.unwrap().page_id();
+            bm.flush().await.unwrap();
+
+            assert_eq!(bm.cool(4), 1);
+            assert_eq!(bm.evict_cooled(4), (1, 0));
+            assert_eq!(state(&pinned), FrameState::Resident);
+            assert!(!bm.resident.borrow().contains_key(&unpinned));
+
+            // Every frame is pinned, there is nothing the evictor could free
+            let mut guards = vec![pinned];
+            while bm.free_frames() > 0 {
+                guards.push(bm.allocate().await.unwrap());
+            }
+            let err = bm.allocate().await.err().unwrap();
+            assert_eq!(err.kind(), stdio::ErrorKind::OutOfMemory);
+
+            // The dirty page is written back before its frame is reused
+            let page_id = guards[1].page_id();
+            guards[1].page_mut().write_u64(DATA, 7);
+            guards.remove(1);
+            let guard = bm.allocate().await.unwrap();
+            assert_ne!(guard.page_id(), page_id);
+
+            guards.push(guard);
+            guards.remove(0);
+            let reread = bm.fix_page(page_id).await.unwrap();
+            assert_eq!(reread.page().read_u64(DATA), 7);
+
+            drop((guards, reread));
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn cooling_page_is_promoted() {
+        simulate(0, |_| async {
+            let bm = build(4).await;
+
+            let page_id = bm.allocate().await.unwrap().page_id();
+            bm.flush().await.unwrap();
+            assert_eq!(bm.cool(1), 1);
+
+            // Accessing a cooling page makes it resident again
+            let guard = bm.fix_page(page_id).await.unwrap();
+            assert_eq!(state(&guard), FrameState::Resident);
+            drop(guard);
+
+            assert_eq!(bm.evict_cooled(1), (0, 0));
+            assert!(bm.cooling.borrow().is_empty());
+            assert!(bm.resident.borrow().contains_key(&page_id));
+
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn managers_are_limited() {
+        simulate(0, |_| async {
+            let mut bms = Vec::new();
+            for _ in 0..MAX_BUFFER_MANAGERS {
+                bms.push(build(1).await);
+            }
+
+            let file = sim::open(FILE).await;
+            assert!(BufferOptions::new().frames(1).build(file).is_err());
+
+            // The slots of a closed manager are freed once its tasks are
+            // dropped by the executor.
+            bms.pop().unwrap().close();
+            yield_now().await;
+            bms.push(build(1).await);
+
+            for bm in bms {
+                bm.close();
+            }
+        });
+    }
+}
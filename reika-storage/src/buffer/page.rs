@@ -0,0 +1,122 @@
+use std::ops::{Deref, DerefMut};
+
+use super::frame::FrameId;
+use crate::wal::Lsn;
+
+/// PAGE_SIZE is the size of every page, both on disk and in memory
+pub const PAGE_SIZE: usize = 4096;
+
+/// PageId identifies a page, the page lives at `page_id * PAGE_SIZE` in the
+/// file backing the buffer manager.
+pub type PageId = u64;
+
+/// PAGE_LSN_SIZE is the size of the LSN kept at the start of every page
+pub const PAGE_LSN_SIZE: usize = 8;
+
+/// Page is the in-memory copy of a page
+///
+/// Every page starts with the LSN of the last log record that was applied to
+/// the page, those bytes are not available to the data structures.
+#[repr(C, align(8))]
+pub struct Page([u8; PAGE_SIZE]);
+
+impl Page {
+    pub(crate) fn zeroed() -> Box<Self> {
+        Box::new(Self([0; PAGE_SIZE]))
+    }
+
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.0[offset..offset + 8].try_into().unwrap())
+    }
+
+    pub fn write_u64(&mut self, offset: usize, value: u64) {
+        self.0[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// lsn returns the LSN of the last log record applied to the page, see
+    /// [super::PageGuard::set_lsn].
+    pub fn lsn(&self) -> Lsn {
+        self.read_u64(0)
+    }
+
+    /// swip reads the swip stored at `offset`
+    pub fn swip(&self, offset: usize) -> Swip {
+        Swip::from_raw(self.read_u64(offset))
+    }
+
+    /// set_swip stores the swip at `offset`
+    pub fn set_swip(&mut self, offset: usize, swip: Swip) {
+        self.write_u64(offset, swip.into_raw())
+    }
+}
+
+impl Deref for Page {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Page {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// UNSWIZZLED_TAG marks a swip which holds a page id
+const UNSWIZZLED_TAG: u64 = 1 << 63;
+
+/// Swip is a reference to a page, it is either swizzled and points straight
+/// to the frame holding the page or unswizzled and holds the id of the page.
+///
+/// Following a swizzled swip is as cheap as following a pointer, the buffer
+/// manager swizzles a swip once it has loaded the page and unswizzles it
+/// before the page leaves the memory.
+///
+/// Swizzled swips never reach the disk, the buffer manager unswizzles them
+/// in the copy of the page that it writes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Swip(u64);
+
+impl Swip {
+    pub const fn new(page_id: PageId) -> Self {
+        assert!(page_id & UNSWIZZLED_TAG == 0, "page id is out of range");
+        Self(page_id | UNSWIZZLED_TAG)
+    }
+
+    pub(crate) const fn swizzled(frame: FrameId) -> Self {
+        Self(frame as u64)
+    }
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub const fn into_raw(self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_swizzled(&self) -> bool {
+        self.0 & UNSWIZZLED_TAG == 0
+    }
+
+    /// page_id returns the page id of an unswizzled swip
+    pub const fn page_id(&self) -> Option<PageId> {
+        if self.is_swizzled() {
+            None
+        } else {
+            Some(self.0 & !UNSWIZZLED_TAG)
+        }
+    }
+
+    /// frame returns the frame a swizzled swip points to
+    pub(crate) const fn frame(&self) -> Option<FrameId> {
+        if self.is_swizzled() {
+            Some(self.0 as FrameId)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! tasks holds the system tasks of the buffer manager, they are spawned by
+//! [super::BufferOptions::build].
+
+use reika::reactor::core::yield_now;
+
+use super::{BufferManager, MAX_BUFFER_MANAGERS};
+
+/// evictor keeps enough frames free. It is woken once the number of free
+/// frames drops below the low watermark and then evicts pages till the high
+/// watermark is reached, yielding to the other tasks between the rounds.
+///
+/// Every round first evicts the pages that have been cooling since the
+/// previous rounds and then refills the cooling stage, hence a cooling page
+/// always gets a chance to be accessed again before it is evicted.
+#[reika::macros::task(pool_size = MAX_BUFFER_MANAGERS)]
+pub(crate) async fn evictor(bm: &'static BufferManager) -> ! {
+    loop {
+        bm.evict.notified().await;
+
+        while bm.free_frames() < bm.high_watermark {
+            let wanted = bm.high_watermark - bm.free_frames();
+
+            let (evicted, dirty) = bm.evict_cooled(wanted);
+            let cooled = bm.cool(wanted);
+
+            if dirty > 0 {
+                bm.write.notify();
+            }
+
+            if evicted == 0 && cooled == 0 {
+                // The writer wakes us up again once it is done, otherwise
+                // there is nothing left that could be evicted.
+                if dirty == 0 {
+                    bm.out_of_frames();
+                }
+
+                break;
+            }
+
+            yield_now().await;
+        }
+    }
+}
+
+/// writer writes back the dirty cooling pages so that they can be evicted
+///
+/// A page which fails to be written stays dirty and is retried the next time
+/// the writer is woken up.
+#[reika::macros::task(pool_size = MAX_BUFFER_MANAGERS)]
+pub(crate) async fn writer(bm: &'static BufferManager) -> ! {
+    loop {
+        bm.write.notified().await;
+
+        let (mut written, mut failed) = (0, 0);
+        for frame in bm.dirty_cooling() {
+            match bm.write_back(frame).await {
+                Ok(()) => written += 1,
+                Err(_) => failed += 1,
+            }
+        }
+
+        // The tasks waiting for a frame fail rather than wait for the disk
+        if written == 0 && failed > 0 {
+            bm.out_of_frames();
+        } else {
+            bm.evict.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buffer::BufferOptions,
+        sim::{self, simulate},
+    };
+
+    const FILE: &str = "pages";
+
+    /// settle yields till the system tasks are done with their work
+    async fn settle() {
+        for _ in 0..64 {
+            yield_now().await;
+        }
+    }
+
+    #[test]
+    fn eviction_respects_watermarks() {
+        simulate(0, |_| async {
+            let bm = BufferOptions::new()
+                .frames(16)
+                .watermarks(4, 8)
+                .build(sim::open(FILE).await)
+                .unwrap();
+
+            // Nothing is evicted while the free frames stay above the low
+            // watermark.
+            for _ in 0..12 {
+                drop(bm.allocate().await.unwrap());
+            }
+            settle().await;
+            assert_eq!(bm.free_frames(), 4);
+            assert!(bm.cooling.borrow().is_empty());
+
+            // Dropping below it evicts pages till the high watermark
+            drop(bm.allocate().await.unwrap());
+            settle().await;
+            assert_eq!(bm.free_frames(), 8);
+
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn dirty_pages_are_written_back_before_reuse() {
+        simulate(0, |sim| async move {
+            let bm = BufferOptions::new()
+                .frames(4)
+                .build(sim::open(FILE).await)
+                .unwrap();
+
+            let mut pages = Vec::new();
+            for n in 0..32 {
+                let guard = bm.allocate().await.unwrap();
+                guard.page_mut().write_u64(8, n);
+                pages.push(guard.page_id());
+            }
+
+            // The evicted pages made it to the disk
+            let data = sim.read_file(FILE).unwrap();
+            let first = u64::from_le_bytes(data[8..16].try_into().unwrap());
+            assert_eq!(first, 0);
+
+            for (n, page_id) in pages.into_iter().enumerate() {
+                let guard = bm.fix_page(page_id).await.unwrap();
+                assert_eq!(guard.page().read_u64(8), n as u64);
+            }
+
+            bm.close();
+        });
+    }
+}
@@ -0,0 +1,9 @@
+#![feature(type_alias_impl_trait)]
+pub mod btree;
+pub mod buffer;
+pub mod crc32c;
+pub mod recovery;
+#[cfg(test)]
+mod sim;
+mod wait;
+pub mod wal;
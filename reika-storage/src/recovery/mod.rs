@@ -0,0 +1,856 @@
+//! recovery implements ARIES style transactions over the pages of a
+//! [BufferManager] logged to a [Wal].
+//!
+//! Every modification of a page is logged with its before and after image and
+//! the page is stamped with the LSN of the record. The buffer manager writes a
+//! page back only once the log is durable up to the LSN of the page, and a
+//! transaction is durable once its commit record is. On open the pages are
+//! brought back to a consistent state in three passes over the log:
+//!
+//! - analysis finds the transactions that were active at the time of the
+//!   crash and the pages that may have been dirty, starting at the last
+//!   checkpoint.
+//! - redo repeats the history, every update (or compensation) whose record is
+//!   newer than the LSN of the page is applied again.
+//! - undo rolls back the transactions which did not commit. Every undone
+//!   update is logged as a compensation record, hence an undo which is
+//!   interrupted by another crash is never repeated.
+//!
+//! A write of a page which is cut by a crash leaves the page torn, part of it
+//! is newer than its LSN tells. Hence the first update of a page since it was
+//! last written back is preceded by an image of the whole page, the redo of a
+//! page starts by restoring the image (whatever the LSN of the page is) and
+//! the torn part is then rewritten. It costs a page worth of log for every
+//! page written back.
+//!
+//! Checkpoints are fuzzy, they only log the active transactions and the dirty
+//! pages and do not write back any page. A system task writes a checkpoint
+//! every time the log grows by the checkpoint interval.
+//!
+//! Transactions are not isolated from each other, concurrent transactions must
+//! not modify the same parts of the pages.
+
+mod record;
+mod tasks;
+
+pub use record::TxnId;
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    io as stdio,
+};
+
+use crate::{
+    buffer::{BufferManager, PageGuard, PAGE_LSN_SIZE, PAGE_SIZE},
+    wait::Notify,
+    wal::{Lsn, Wal, FIRST_LSN},
+};
+use record::Record;
+use reika::executor::{core::AbortHandle, PerThreadExecutor};
+
+#[derive(Clone, Copy)]
+pub struct RecoveryOptions {
+    checkpoint_interval: u64,
+}
+
+impl RecoveryOptions {
+    pub fn new() -> Self {
+        Self {
+            checkpoint_interval: 16 << 20,
+        }
+    }
+
+    /// checkpoint_interval sets the number of bytes appended to the log after
+    /// which another checkpoint is written.
+    pub fn checkpoint_interval(&mut self, bytes: u64) -> &mut Self {
+        self.checkpoint_interval = bytes;
+        self
+    }
+
+    /// open recovers the pages of the buffer manager from the log and spawns
+    /// the checkpoint system task to the executor of the current thread.
+    ///
+    /// NOTE: Like the buffer manager, the recovery lives as long as the thread.
+    /// At most one exists per buffer manager, see [Recovery::close]. It fails
+    /// if [crate::buffer::MAX_BUFFER_MANAGERS] recoveries are running already.
+    ///
+    /// # Panics
+    /// Panics if the buffer manager was not built with the log (see
+    /// [crate::buffer::BufferOptions::wal]).
+    pub async fn open(
+        &self,
+        bm: &'static BufferManager,
+        wal: &'static Wal,
+    ) -> stdio::Result<&'static Recovery> {
+        assert!(
+            bm.wal().is_some_and(|bm_wal| std::ptr::eq(bm_wal, wal)),
+            "the buffer manager must be built with BufferOptions::wal"
+        );
+
+        let rm: &'static Recovery = Box::leak(Box::new(Recovery {
+            bm,
+            wal,
+            txns: RefCell::new(HashMap::new()),
+            next_txn: Cell::new(1),
+            checkpoint_interval: self.checkpoint_interval,
+            last_checkpoint: Cell::new(wal.checkpoint_lsn()),
+            checkpoint: Notify::new(),
+            task: Cell::new(None),
+        }));
+
+        // The task is dropped (and its slot released) if the recovery fails
+        let Some(checkpointer) = tasks::checkpointer(rm) else {
+            return Err(stdio::Error::other("too many recoveries are running"));
+        };
+        rm.recover().await?;
+
+        rm.task.set(Some(checkpointer.abort_handle()));
+        PerThreadExecutor::spawn_system_task(checkpointer);
+
+        Ok(rm)
+    }
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Recovery {
+    bm: &'static BufferManager,
+    wal: &'static Wal,
+
+    /// txns maps the active transactions to the LSN of their last record, 0
+    /// if they have not logged anything yet.
+    txns: RefCell<HashMap<TxnId, Lsn>>,
+    next_txn: Cell<TxnId>,
+
+    checkpoint_interval: u64,
+    /// last_checkpoint is the LSN of the last checkpoint record
+    last_checkpoint: Cell<Lsn>,
+    /// checkpoint wakes the checkpoint task
+    checkpoint: Notify,
+    /// task is the checkpoint task, None once the recovery is closed
+    task: Cell<Option<AbortHandle>>,
+}
+
+impl Recovery {
+    pub fn options() -> RecoveryOptions {
+        RecoveryOptions::new()
+    }
+
+    /// close stops the checkpoint task, its slot is then free for another
+    /// recovery. The transactions can go on but no checkpoint is written
+    /// anymore.
+    pub fn close(&self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+
+    /// begin starts a transaction
+    pub fn begin(&self) -> Txn<'_> {
+        let id = self.next_txn.get();
+        self.next_txn.set(id + 1);
+        self.txns.borrow_mut().insert(id, 0);
+
+        Txn { rm: self, id }
+    }
+
+    /// checkpoint writes a fuzzy checkpoint, the recovery starts at the last
+    /// checkpoint once it is durable.
+    pub async fn checkpoint(&self) -> stdio::Result<()> {
+        let begin = self.wal.end_lsn();
+        let txns = self.txns.borrow().iter().map(|(t, l)| (*t, *l)).collect();
+        let dirty = self.bm.dirty_pages();
+
+        // The pages which are not dirty have been written back but the writes
+        // are durable only once the file is synced.
+        self.bm.sync_file().await?;
+
+        let record = Record::Checkpoint {
+            begin,
+            next_txn: self.next_txn.get(),
+            txns,
+            dirty,
+        };
+
+        let lsn = self.wal.append(&record.encode());
+        self.last_checkpoint.set(lsn);
+
+        self.wal.commit(lsn).await?;
+        self.wal.set_checkpoint_lsn(lsn).await
+    }
+
+    /// recover runs the analysis, redo and undo passes over the log
+    async fn recover(&self) -> stdio::Result<()> {
+        let mut txns = HashMap::new();
+        let mut dirty = HashMap::new();
+        let mut next_txn = 1;
+
+        // The analysis starts with the tables of the last checkpoint, as of
+        // the time it started.
+        let mut start = FIRST_LSN;
+        let checkpoint = self.wal.checkpoint_lsn();
+        if checkpoint != 0 {
+            match self.read(checkpoint).await? {
+                Record::Checkpoint {
+                    begin,
+                    next_txn: next,
+                    txns: active,
+                    dirty: pages,
+                } => {
+                    start = begin;
+                    next_txn = next;
+                    txns.extend(active);
+                    dirty.extend(pages);
+                }
+                _ => return Err(invalid("not a checkpoint log record")),
+            }
+        }
+
+        let mut reader = self.wal.reader(start);
+        while let Some(rec) = reader.next().await? {
+            match Record::decode(&rec.payload)? {
+                // The records tell everything the checkpoints do
+                Record::Checkpoint { next_txn: next, .. } => {
+                    next_txn = next_txn.max(next);
+                }
+                Record::Update { txn, page, .. } | Record::Compensation { txn, page, .. } => {
+                    txns.insert(txn, rec.lsn);
+                    dirty.entry(page).or_insert(rec.lsn);
+                    next_txn = next_txn.max(txn + 1);
+                }
+                Record::Image { page, .. } => {
+                    dirty.entry(page).or_insert(rec.lsn);
+                }
+                Record::Commit { txn, .. } | Record::Abort { txn, .. } => {
+                    txns.remove(&txn);
+                    next_txn = next_txn.max(txn + 1);
+                }
+            }
+        }
+
+        if let Some(start) = dirty.values().min().copied() {
+            let mut reader = self.wal.reader(start);
+            while let Some(rec) = reader.next().await? {
+                let (page, offset, after, image) = match Record::decode(&rec.payload)? {
+                    Record::Update {
+                        page,
+                        offset,
+                        after,
+                        ..
+                    }
+                    | Record::Compensation {
+                        page,
+                        offset,
+                        after,
+                        ..
+                    } => (page, offset as usize, after, false),
+                    Record::Image { page, data } => (page, PAGE_LSN_SIZE, data, true),
+                    _ => continue,
+                };
+
+                // The page has been written back since the record
+                if dirty.get(&page).is_none_or(|rec_lsn| *rec_lsn > rec.lsn) {
+                    continue;
+                }
+
+                // The LSN of a torn page cannot be trusted, the image is
+                // restored in any case.
+                let guard = self.bm.fix_page(page).await?;
+                if image || guard.page().lsn() < rec.lsn {
+                    guard.page_mut()[offset..offset + after.len()].copy_from_slice(&after);
+                    guard.set_lsn(rec.lsn);
+                }
+            }
+        }
+
+        let losers: Vec<TxnId> = txns.keys().copied().collect();
+        *self.txns.borrow_mut() = txns;
+        self.next_txn.set(next_txn);
+
+        self.undo(&losers).await?;
+
+        // The next recovery starts from here
+        self.checkpoint().await
+    }
+
+    /// undo rolls back the transactions, the updates are undone from the
+    /// newest to the oldest.
+    async fn undo(&self, txns: &[TxnId]) -> stdio::Result<()> {
+        // The records are read back from the log file
+        self.wal.flush().await?;
+
+        let mut next = BTreeMap::new();
+        for txn in txns {
+            let lsn = self.txns.borrow()[txn];
+            if lsn == 0 {
+                self.end(*txn);
+            } else {
+                next.insert(lsn, *txn);
+            }
+        }
+
+        while let Some((lsn, txn)) = next.pop_last() {
+            let undo_next = match self.read(lsn).await? {
+                Record::Update {
+                    prev_lsn,
+                    page,
+                    offset,
+                    before,
+                    ..
+                } => {
+                    let guard = self.bm.fix_page(page).await?;
+                    self.image(&guard);
+
+                    let offset = offset as usize;
+                    guard.page_mut()[offset..offset + before.len()].copy_from_slice(&before);
+
+                    let lsn = self.log(txn, |last| Record::Compensation {
+                        txn,
+                        prev_lsn: last,
+                        page,
+                        offset: offset as u16,
+                        after: before,
+                        undo_next: prev_lsn,
+                    });
+                    guard.set_lsn(lsn);
+
+                    prev_lsn
+                }
+                Record::Compensation { undo_next, .. } => undo_next,
+                _ => return Err(invalid("not an undoable log record")),
+            };
+
+            if undo_next == 0 {
+                self.end(txn);
+            } else {
+                next.insert(undo_next, txn);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// end ends the rolled back transaction
+    fn end(&self, txn: TxnId) {
+        self.log(txn, |last| Record::Abort {
+            txn,
+            prev_lsn: last,
+        });
+        self.txns.borrow_mut().remove(&txn);
+    }
+
+    /// log appends the record of the transaction, `record` is called with the
+    /// LSN of the previous record of the transaction.
+    fn log(&self, txn: TxnId, record: impl FnOnce(Lsn) -> Record) -> Lsn {
+        let last = self.txns.borrow()[&txn];
+        let lsn = self.append(&record(last));
+        self.txns.borrow_mut().insert(txn, lsn);
+
+        lsn
+    }
+
+    /// image logs the image of the page before its first update since it was
+    /// last written back, see [Record::Image].
+    fn image(&self, page: &PageGuard<'_>) {
+        if page.rec_lsn() != 0 {
+            return;
+        }
+
+        let data = page.page()[PAGE_LSN_SIZE..].to_vec();
+        let lsn = self.append(&Record::Image {
+            page: page.page_id(),
+            data,
+        });
+        page.set_lsn(lsn);
+    }
+
+    fn append(&self, record: &Record) -> Lsn {
+        let lsn = self.wal.append(&record.encode());
+        if lsn - self.last_checkpoint.get() >= self.checkpoint_interval {
+            self.checkpoint.notify();
+        }
+
+        lsn
+    }
+
+    async fn read(&self, lsn: Lsn) -> stdio::Result<Record> {
+        match self.wal.reader(lsn).next().await? {
+            Some(rec) => Record::decode(&rec.payload),
+            None => Err(invalid("log record is missing")),
+        }
+    }
+}
+
+/// Txn is a transaction, it ends with either [Txn::commit] or [Txn::abort].
+///
+/// A transaction which is dropped before it ends stays active and is rolled
+/// back by the next recovery, its modifications stay visible till then.
+pub struct Txn<'a> {
+    rm: &'a Recovery,
+    id: TxnId,
+}
+
+impl Txn<'_> {
+    pub fn id(&self) -> TxnId {
+        self.id
+    }
+
+    /// write copies the data into the page at `offset` and logs the
+    /// modification.
+    ///
+    /// # Panics
+    /// Panics if the data does not fit in the page or overlaps with the LSN
+    /// of the page.
+    pub fn write(&self, page: &PageGuard<'_>, offset: usize, data: &[u8]) {
+        assert!(
+            offset >= PAGE_LSN_SIZE && offset + data.len() <= PAGE_SIZE,
+            "write is out of the page"
+        );
+        self.rm.image(page);
+
+        let before = {
+            let mut page = page.page_mut();
+            let before = page[offset..offset + data.len()].to_vec();
+            page[offset..offset + data.len()].copy_from_slice(data);
+
+            before
+        };
+
+        let lsn = self.rm.log(self.id, |last| Record::Update {
+            txn: self.id,
+            prev_lsn: last,
+            page: page.page_id(),
+            offset: offset as u16,
+            before,
+            after: data.to_vec(),
+        });
+        page.set_lsn(lsn);
+    }
+
+    /// commit commits the transaction, it returns once the transaction is
+    /// durable.
+    pub async fn commit(self) -> stdio::Result<()> {
+        let lsn = self.rm.log(self.id, |last| Record::Commit {
+            txn: self.id,
+            prev_lsn: last,
+        });
+        self.rm.txns.borrow_mut().remove(&self.id);
+
+        self.rm.wal.commit(lsn).await
+    }
+
+    /// abort rolls back the modifications of the transaction
+    pub async fn abort(self) -> stdio::Result<()> {
+        self.rm.undo(&[self.id]).await
+    }
+}
+
+fn invalid(msg: &str) -> stdio::Error {
+    stdio::Error::new(stdio::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        future::{poll_fn, Future},
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        buffer::{BufferOptions, PageId},
+        sim::{self, simulate},
+        wal::LOG_BLOCK_SIZE,
+    };
+
+    const WAL: &str = "wal";
+    const DATA: &str = "data";
+
+    /// PAGES is the number of pages the transactions write to
+    const PAGES: u64 = 16;
+    const SLOT_SIZE: usize = 16;
+    /// TXN_SLOTS is the number of slots every transaction writes to, they are
+    /// on different pages.
+    const TXN_SLOTS: usize = 2;
+
+    const WORKERS: usize = 4;
+    const TXNS: usize = 90;
+
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    async fn open_wal() -> &'static Wal {
+        let wal = Wal::open(sim::open(WAL).await).await.unwrap();
+        Box::leak(Box::new(wal))
+    }
+
+    /// open opens the pages logged to the log, the buffer manager is returned
+    /// even if the recovery failed so that it can be closed.
+    async fn open(
+        wal: &'static Wal,
+        frames: usize,
+    ) -> (&'static BufferManager, stdio::Result<&'static Recovery>) {
+        let bm = BufferOptions::new()
+            .frames(frames)
+            .pages(PAGES)
+            .wal(wal)
+            .build(sim::open(DATA).await)
+            .unwrap();
+
+        let rm = Recovery::options()
+            .checkpoint_interval(16 * 1024)
+            .open(bm, wal)
+            .await;
+
+        (bm, rm)
+    }
+
+    /// slot returns the page and the offset of the slot
+    fn slot(slot: usize) -> (PageId, usize) {
+        let page = slot as u64 % PAGES;
+        let offset = PAGE_LSN_SIZE + slot / PAGES as usize * SLOT_SIZE;
+
+        (page, offset)
+    }
+
+    /// value returns what the transaction writes to its slots
+    fn value(txn: usize) -> Vec<u8> {
+        (txn as u32 + 1).to_le_bytes().repeat(SLOT_SIZE / 4)
+    }
+
+    async fn read_slot(bm: &BufferManager, n: usize) -> Vec<u8> {
+        let (page, offset) = slot(n);
+        let guard = bm.fix_page(page).await.unwrap();
+        let data = guard.page()[offset..offset + SLOT_SIZE].to_vec();
+
+        data
+    }
+
+    /// Outcome is what the workload learnt about a transaction
+    #[derive(Clone, Copy, Debug)]
+    enum Outcome {
+        Committed,
+        /// InDoubt is a transaction whose commit failed
+        InDoubt,
+        RolledBack,
+    }
+
+    /// workload runs the transactions, each of them writes its value to slots
+    /// of its own. Every third one commits, every third one aborts and the
+    /// others are left active. It stops at the first failure.
+    async fn workload(
+        rm: &'static Recovery,
+        txns: impl Iterator<Item = usize>,
+    ) -> Vec<(usize, Outcome)> {
+        let mut outcomes = Vec::new();
+
+        for i in txns {
+            let txn = rm.begin();
+            for n in i * TXN_SLOTS..(i + 1) * TXN_SLOTS {
+                let (page, offset) = slot(n);
+                let Ok(guard) = rm.bm.fix_page(page).await else {
+                    outcomes.push((i, Outcome::RolledBack));
+                    return outcomes;
+                };
+
+                txn.write(&guard, offset, &value(i));
+            }
+
+            let (outcome, res) = match i % 3 {
+                0 => match txn.commit().await {
+                    Ok(()) => (Outcome::Committed, Ok(())),
+                    Err(err) => (Outcome::InDoubt, Err(err)),
+                },
+                1 => (Outcome::RolledBack, txn.abort().await),
+                _ => (Outcome::RolledBack, Ok(())),
+            };
+
+            outcomes.push((i, outcome));
+            if res.is_err() {
+                break;
+            }
+        }
+
+        outcomes
+    }
+
+    /// check checks that the transactions which committed survived and the
+    /// others were rolled back, the ones in doubt went either way as a whole.
+    async fn check(bm: &BufferManager, outcomes: &[(usize, Outcome)]) {
+        for (i, outcome) in outcomes {
+            let mut values = Vec::new();
+            for n in i * TXN_SLOTS..(i + 1) * TXN_SLOTS {
+                values.push(read_slot(bm, n).await);
+            }
+
+            let committed = values.iter().all(|v| *v == value(*i));
+            let rolled_back = values.iter().all(|v| v.iter().all(|b| *b == 0));
+            match outcome {
+                Outcome::Committed => assert!(committed, "txn {i} is lost"),
+                Outcome::RolledBack => assert!(rolled_back, "txn {i} is not rolled back"),
+                Outcome::InDoubt => assert!(committed || rolled_back, "txn {i} is torn"),
+            }
+        }
+    }
+
+    /// compensations returns the `undo_next` of the durable compensation
+    /// records of the transaction.
+    async fn compensations(wal: &Wal, txn: TxnId) -> Vec<Lsn> {
+        let mut reader = wal.reader(FIRST_LSN);
+        let mut undo = Vec::new();
+
+        while let Some(rec) = reader.next().await.unwrap() {
+            if let Record::Compensation {
+                txn: t, undo_next, ..
+            } = Record::decode(&rec.payload).unwrap()
+            {
+                if t == txn {
+                    undo.push(undo_next);
+                }
+            }
+        }
+
+        undo
+    }
+
+    #[test]
+    fn committed_txns_survive_cuts() {
+        for seed in 0..16 {
+            simulate(seed, move |sim| async move {
+                let wal = open_wal().await;
+                let (bm, rm) = open(wal, 8).await;
+                let rm = rm.unwrap();
+
+                // The power is cut somewhere during the workload
+                let mut state = seed + 1;
+                sim.fail_at(WAL, wal.end_lsn() + next(&mut state) % (128 << 10));
+
+                let workers: Vec<_> = (0..WORKERS)
+                    .map(|w| PerThreadExecutor::spawn(workload(rm, (w..TXNS).step_by(WORKERS))))
+                    .collect();
+
+                let mut outcomes = Vec::new();
+                for worker in workers {
+                    outcomes.extend(worker.await.unwrap());
+                }
+                assert!(outcomes.len() < TXNS, "the log was not cut");
+
+                rm.close();
+                bm.close();
+                sim.crash();
+
+                let wal = open_wal().await;
+                let (bm, rm) = open(wal, 8).await;
+                rm.unwrap().close();
+
+                check(bm, &outcomes).await;
+                bm.close();
+            });
+        }
+    }
+
+    #[test]
+    fn partial_log_block_is_dropped() {
+        simulate(0, |sim| async move {
+            let wal = open_wal().await;
+            let (bm, rm) = open(wal, 32).await;
+            let rm = rm.unwrap();
+
+            let mut outcomes = workload(rm, 0..6).await;
+            wal.flush().await.unwrap();
+
+            // Only the start of the next block of the log reaches the disk
+            let block = wal.end_lsn();
+            sim.fail_at(WAL, block + 100);
+            outcomes.extend(workload(rm, 6..TXNS).await);
+            assert!(outcomes.len() < TXNS, "the log was not cut");
+
+            rm.close();
+            bm.close();
+            sim.crash();
+
+            let wal = open_wal().await;
+            assert_eq!(wal.end_lsn(), block);
+            let (bm, rm) = open(wal, 32).await;
+            rm.unwrap().close();
+
+            check(bm, &outcomes).await;
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn torn_page_is_restored() {
+        simulate(0, |sim| async move {
+            let wal = open_wal().await;
+            // The pages fit in the pool, none is written back on its own
+            let (bm, rm) = open(wal, 32).await;
+            let rm = rm.unwrap();
+
+            let outcomes = workload(rm, 0..TXNS).await;
+            wal.flush().await.unwrap();
+
+            // The write of the first page is cut after its first slots, its
+            // LSN makes it to the disk but most of the slots do not.
+            let cut = PAGE_LSN_SIZE + 4 * SLOT_SIZE;
+            sim.fail_at(DATA, cut as u64);
+            assert!(bm.flush().await.is_err());
+
+            rm.close();
+            bm.close();
+            sim.crash();
+
+            let torn = sim.read_file(DATA).unwrap();
+            assert_eq!(torn.len(), cut);
+            assert_ne!(torn[..PAGE_LSN_SIZE], [0; PAGE_LSN_SIZE]);
+
+            let wal = open_wal().await;
+            let (bm, rm) = open(wal, 32).await;
+            rm.unwrap().close();
+
+            check(bm, &outcomes).await;
+            bm.close();
+        });
+    }
+
+    #[test]
+    fn crash_during_undo() {
+        let partial = Arc::new(AtomicUsize::new(0));
+
+        for seed in 0..16 {
+            let partial = partial.clone();
+
+            simulate(seed, move |sim| async move {
+                // A loser, which is left active, updates every page. There
+                // are fewer frames than pages hence the undo writes back the
+                // pages (and flushes the log) as it goes.
+                let wal = open_wal().await;
+                let (bm, rm) = open(wal, 4).await;
+                let rm = rm.unwrap();
+
+                let txn = rm.begin();
+                let loser = txn.id();
+                for n in 0..PAGES as usize {
+                    let (page, offset) = slot(n);
+                    let guard = bm.fix_page(page).await.unwrap();
+                    txn.write(&guard, offset, &value(n));
+                }
+
+                wal.flush().await.unwrap();
+                rm.close();
+                bm.close();
+                sim.crash();
+
+                // The power is cut while the recovery rolls the loser back
+                let wal = open_wal().await;
+                let mut state = seed + 1;
+                let cut = next(&mut state) % (PAGES * LOG_BLOCK_SIZE as u64);
+                sim.fail_at(WAL, wal.end_lsn() + cut);
+
+                let (bm, rm) = open(wal, 4).await;
+                if let Ok(rm) = rm {
+                    rm.close();
+                }
+                bm.close();
+                sim.crash();
+
+                let wal = open_wal().await;
+                let undone = compensations(wal, loser).await.len();
+                if undone > 0 && undone < PAGES as usize {
+                    partial.fetch_add(1, Ordering::Relaxed);
+                }
+
+                let (bm, rm) = open(wal, 4).await;
+                rm.unwrap().close();
+
+                for n in 0..PAGES as usize {
+                    assert_eq!(read_slot(bm, n).await, [0; SLOT_SIZE]);
+                }
+
+                let undone = compensations(wal, loser).await;
+                let distinct: HashSet<_> = undone.iter().collect();
+                assert_eq!(distinct.len(), undone.len(), "an update is undone twice");
+                assert_eq!(undone.len(), PAGES as usize);
+
+                bm.close();
+            });
+        }
+
+        assert!(partial.load(Ordering::Relaxed) > 0, "no undo was cut");
+    }
+
+    #[test]
+    fn crash_before_checkpoint_header() {
+        simulate(0, |sim| async move {
+            let wal = open_wal().await;
+            let (bm, rm) = open(wal, 8).await;
+            let rm = rm.unwrap();
+            // The checkpoint is written by hand
+            rm.close();
+            let header = wal.checkpoint_lsn();
+
+            // A loser is left active across the checkpoint
+            let outcomes = workload(rm, 0..2).await;
+            let txn = rm.begin();
+            for n in 0..PAGES as usize {
+                let (page, offset) = slot(2 * TXN_SLOTS + n);
+                let guard = bm.fix_page(page).await.unwrap();
+                txn.write(&guard, offset, &value(2));
+            }
+
+            // The power is cut once the checkpoint record is durable, before
+            // the header points to it.
+            let before = rm.last_checkpoint.get();
+            let mut checkpoint = pin!(rm.checkpoint());
+            let res = poll_fn(|ctx| {
+                let poll = checkpoint.as_mut().poll(ctx);
+
+                let lsn = rm.last_checkpoint.get();
+                if poll.is_pending() && lsn != before && wal.durable_lsn() > lsn {
+                    sim.fail_at(WAL, 0);
+                }
+
+                poll
+            })
+            .await;
+            assert!(res.is_err());
+
+            let lost = rm.last_checkpoint.get();
+            bm.close();
+            sim.crash();
+
+            let wal = open_wal().await;
+            assert_eq!(wal.checkpoint_lsn(), header);
+            let rec = wal.reader(lost).next().await.unwrap().unwrap();
+            assert!(matches!(
+                Record::decode(&rec.payload).unwrap(),
+                Record::Checkpoint { .. }
+            ));
+
+            let (bm, rm) = open(wal, 8).await;
+            rm.unwrap().close();
+
+            check(bm, &outcomes).await;
+            for n in 0..PAGES as usize {
+                assert_eq!(read_slot(bm, 2 * TXN_SLOTS + n).await, [0; SLOT_SIZE]);
+            }
+            assert!(wal.checkpoint_lsn() > lost);
+
+            bm.close();
+        });
+    }
+}
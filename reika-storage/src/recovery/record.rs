@@ -0,0 +1,257 @@
+//! record implements the encoding of the log records of the transactions.
+
+use std::io as stdio;
+
+use crate::{buffer::PageId, wal::Lsn};
+
+/// TxnId identifies a transaction
+pub type TxnId = u64;
+
+const UPDATE: u8 = 1;
+const COMPENSATION: u8 = 2;
+const COMMIT: u8 = 3;
+const ABORT: u8 = 4;
+const CHECKPOINT: u8 = 5;
+const IMAGE: u8 = 6;
+
+/// Record is a log record. The records of a transaction are chained via
+/// `prev_lsn`, the first record of a transaction has no previous record (0).
+#[derive(Debug)]
+pub(crate) enum Record {
+    /// Update describes the modification of a part of a page
+    Update {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        page: PageId,
+        offset: u16,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    /// Compensation describes the undo of an update, `undo_next` is the next
+    /// record of the transaction to be undone. It is never undone itself.
+    Compensation {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        page: PageId,
+        offset: u16,
+        after: Vec<u8>,
+        undo_next: Lsn,
+    },
+    Commit {
+        txn: TxnId,
+        prev_lsn: Lsn,
+    },
+    /// Abort ends a transaction whose updates have all been undone
+    Abort {
+        txn: TxnId,
+        prev_lsn: Lsn,
+    },
+    /// Image holds the whole page (but its LSN) as it was before its first
+    /// update since it was last written back. It belongs to no transaction,
+    /// the redo of the page starts by restoring it.
+    Image {
+        page: PageId,
+        data: Vec<u8>,
+    },
+    /// Checkpoint holds the active transactions (with the LSN of their last
+    /// record) and the dirty pages (with the LSN of the first record which
+    /// dirtied them) as of `begin`, the end of the log when the checkpoint
+    /// started.
+    Checkpoint {
+        begin: Lsn,
+        next_txn: TxnId,
+        txns: Vec<(TxnId, Lsn)>,
+        dirty: Vec<(PageId, Lsn)>,
+    },
+}
+
+impl Record {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        match self {
+            Record::Update {
+                txn,
+                prev_lsn,
+                page,
+                offset,
+                before,
+                after,
+            } => {
+                buf.push(UPDATE);
+                put_u64(&mut buf, *txn);
+                put_u64(&mut buf, *prev_lsn);
+                put_u64(&mut buf, *page);
+                put_u16(&mut buf, *offset);
+                put_bytes(&mut buf, before);
+                put_bytes(&mut buf, after);
+            }
+            Record::Compensation {
+                txn,
+                prev_lsn,
+                page,
+                offset,
+                after,
+                undo_next,
+            } => {
+                buf.push(COMPENSATION);
+                put_u64(&mut buf, *txn);
+                put_u64(&mut buf, *prev_lsn);
+                put_u64(&mut buf, *page);
+                put_u16(&mut buf, *offset);
+                put_bytes(&mut buf, after);
+                put_u64(&mut buf, *undo_next);
+            }
+            Record::Commit { txn, prev_lsn } => {
+                buf.push(COMMIT);
+                put_u64(&mut buf, *txn);
+                put_u64(&mut buf, *prev_lsn);
+            }
+            Record::Abort { txn, prev_lsn } => {
+                buf.push(ABORT);
+                put_u64(&mut buf, *txn);
+                put_u64(&mut buf, *prev_lsn);
+            }
+            Record::Image { page, data } => {
+                buf.push(IMAGE);
+                put_u64(&mut buf, *page);
+                put_bytes(&mut buf, data);
+            }
+            Record::Checkpoint {
+                begin,
+                next_txn,
+                txns,
+                dirty,
+            } => {
+                buf.push(CHECKPOINT);
+                put_u64(&mut buf, *begin);
+                put_u64(&mut buf, *next_txn);
+                put_u64(&mut buf, txns.len() as u64);
+                for (txn, lsn) in txns {
+                    put_u64(&mut buf, *txn);
+                    put_u64(&mut buf, *lsn);
+                }
+                put_u64(&mut buf, dirty.len() as u64);
+                for (page, lsn) in dirty {
+                    put_u64(&mut buf, *page);
+                    put_u64(&mut buf, *lsn);
+                }
+            }
+        }
+
+        buf
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> stdio::Result<Self> {
+        let mut dec = Decoder { buf };
+
+        let record = match dec.u8()? {
+            UPDATE => Record::Update {
+                txn: dec.u64()?,
+                prev_lsn: dec.u64()?,
+                page: dec.u64()?,
+                offset: dec.u16()?,
+                before: dec.bytes()?,
+                after: dec.bytes()?,
+            },
+            COMPENSATION => Record::Compensation {
+                txn: dec.u64()?,
+                prev_lsn: dec.u64()?,
+                page: dec.u64()?,
+                offset: dec.u16()?,
+                after: dec.bytes()?,
+                undo_next: dec.u64()?,
+            },
+            COMMIT => Record::Commit {
+                txn: dec.u64()?,
+                prev_lsn: dec.u64()?,
+            },
+            ABORT => Record::Abort {
+                txn: dec.u64()?,
+                prev_lsn: dec.u64()?,
+            },
+            IMAGE => Record::Image {
+                page: dec.u64()?,
+                data: dec.bytes()?,
+            },
+            CHECKPOINT => {
+                let begin = dec.u64()?;
+                let next_txn = dec.u64()?;
+
+                let mut txns = Vec::new();
+                for _ in 0..dec.u64()? {
+                    txns.push((dec.u64()?, dec.u64()?));
+                }
+
+                let mut dirty = Vec::new();
+                for _ in 0..dec.u64()? {
+                    dirty.push((dec.u64()?, dec.u64()?));
+                }
+
+                Record::Checkpoint {
+                    begin,
+                    next_txn,
+                    txns,
+                    dirty,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+
+        if !dec.buf.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(record)
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u16(buf, bytes.len() as u16);
+    buf.extend_from_slice(bytes);
+}
+
+fn invalid() -> stdio::Error {
+    stdio::Error::new(stdio::ErrorKind::InvalidData, "malformed log record")
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> stdio::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(invalid());
+        }
+
+        let (head, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> stdio::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> stdio::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> stdio::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> stdio::Result<Vec<u8>> {
+        let len = self.u16()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
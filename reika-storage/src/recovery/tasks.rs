@@ -0,0 +1,17 @@
+//! tasks holds the system task of the recovery, it is spawned by
+//! [super::RecoveryOptions::open].
+
+use super::Recovery;
+use crate::buffer::MAX_BUFFER_MANAGERS;
+
+/// checkpointer writes a checkpoint every time the log has grown by the
+/// checkpoint interval.
+///
+/// A checkpoint which fails is retried once the log grows by another interval.
+#[reika::macros::task(pool_size = MAX_BUFFER_MANAGERS)]
+pub(crate) async fn checkpointer(rm: &'static Recovery) -> ! {
+    loop {
+        rm.checkpoint.notified().await;
+        let _ = rm.checkpoint().await;
+    }
+}
@@ -0,0 +1,85 @@
+//! sim runs the tests of the crate on the simulator of the reactor, see
+//! [reika::reactor::sim].
+
+use std::{
+    cell::Cell,
+    future::Future,
+    rc::Rc,
+    sync::{Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use reika::{
+    executor::PerThreadExecutor,
+    reactor::{
+        core::yield_now,
+        io::File,
+        sim::{SimBackend, SimOptions},
+        PerThreadReactor,
+    },
+};
+
+/// STUCK is the virtual time after which a simulation is considered stuck,
+/// the clock jumps ahead whenever every task waits.
+const STUCK: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// SIMULATIONS runs the simulations one at a time, the pools of the system
+/// tasks are shared by the threads.
+static SIMULATIONS: Mutex<()> = Mutex::new(());
+
+/// simulate runs the future returned by `f` on a thread of its own, whose
+/// ops are executed by a simulation seeded with `seed`.
+///
+/// The buffer managers (and recoveries) opened by the future must be closed
+/// by the time it returns, the next simulations need their system tasks.
+pub(crate) fn simulate<F, Fut>(seed: u64, f: F)
+where
+    F: FnOnce(SimBackend) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    let _running = SIMULATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let res = thread::spawn(move || {
+        let sim = SimOptions::new().seed(seed).build();
+        PerThreadReactor::install(Box::new(sim.clone()))
+            .ok()
+            .unwrap();
+
+        let fut = f(sim.clone());
+        let res = Rc::new(Cell::new(None));
+        let r = res.clone();
+        PerThreadExecutor::spawn(async move {
+            r.set(Some(PerThreadExecutor::spawn(fut).await));
+            // The system tasks which were closed are dropped once the
+            // executor gets to them.
+            yield_now().await;
+        });
+
+        PerThreadExecutor::run(Some(|| {
+            PerThreadReactor::run(u32::MAX).unwrap();
+            assert!(sim.now() < STUCK, "the simulation is stuck");
+        }));
+
+        // The executor catches the panics of the tasks
+        if let Some(Err(err)) = res.take() {
+            panic!("the simulation failed: {err}");
+        }
+    })
+    .join();
+
+    if let Err(panic) = res {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+/// open opens (or creates) a file of the simulation for reading and writing
+pub(crate) async fn open(path: &str) -> File {
+    File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)
+        .await
+        .unwrap()
+}
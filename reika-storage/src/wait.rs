@@ -0,0 +1,68 @@
+use std::{
+    cell::{Cell, RefCell},
+    future::poll_fn,
+    task::{Context, Poll, Waker},
+};
+
+/// WaitList holds the wakers of the tasks waiting for some event
+pub(crate) struct WaitList {
+    wakers: RefCell<Vec<Waker>>,
+}
+
+impl WaitList {
+    pub(crate) const fn new() -> Self {
+        Self {
+            wakers: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// register adds the waker of `ctx` to the list (once)
+    pub(crate) fn register(&self, ctx: &Context<'_>) {
+        let mut wakers = self.wakers.borrow_mut();
+        if !wakers.iter().any(|waker| waker.will_wake(ctx.waker())) {
+            wakers.push(ctx.waker().clone());
+        }
+    }
+
+    pub(crate) fn wake_all(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.borrow_mut());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Notify wakes up a task which is waiting for work, a notification sent
+/// while the task is busy is not lost but handled once the task waits again.
+pub(crate) struct Notify {
+    notified: Cell<bool>,
+    waiters: WaitList,
+}
+
+impl Notify {
+    pub(crate) const fn new() -> Self {
+        Self {
+            notified: Cell::new(false),
+            waiters: WaitList::new(),
+        }
+    }
+
+    pub(crate) fn notify(&self) {
+        self.notified.set(true);
+        self.waiters.wake_all();
+    }
+
+    /// notified resolves once [Notify::notify] has been called, the
+    /// notification is consumed.
+    pub(crate) async fn notified(&self) {
+        poll_fn(|ctx| {
+            if self.notified.replace(false) {
+                return Poll::Ready(());
+            }
+
+            self.waiters.register(ctx);
+            Poll::Pending
+        })
+        .await
+    }
+}
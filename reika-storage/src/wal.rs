@@ -0,0 +1,842 @@
+//! wal implements a write-ahead log with group commit.
+//!
+//! Records are appended to an in-memory tail and made durable by
+//! [Wal::commit]. The first committer that finds no flush in progress becomes
+//! the leader, it waits for a round of the executor so that the other tasks
+//! can append their records and join, then it writes the whole tail
+//! with a single `write_at` followed by a single `sync_data`. Everybody whose
+//! record made it into the write is done once the sync completes.
+//!
+//! The log file starts with two header blocks, which also hold the LSN of the
+//! last checkpoint (see [Wal::set_checkpoint_lsn]). The header is written to
+//! the blocks in turn and the newest valid one is used, a torn write of the
+//! header leaves the other one intact. A record is laid out as
+//!
+//! ```text
+//! | crc32c (u32) | len (u32) | payload (len bytes) |
+//! ```
+//!
+//! where the checksum covers the length and the payload. Every flush ends with
+//! a padding record, whose length is [PADDING], which fills the rest of the
+//! block. The next flush starts on a fresh block hence a durable block is
+//! never written again. On open the log ends after the last padding record,
+//! the records after it belong to a flush which did not complete and they are
+//! zeroed.
+//!
+//! The padding costs up to a block per flush, a lone committer of a small
+//! record writes (and uses up) a whole block of the log. Group commit keeps
+//! it in check under load, a flush carries the records of every committer
+//! that joined it.
+
+use std::{
+    cell::{Cell, RefCell},
+    future::poll_fn,
+    io as stdio,
+    task::Poll,
+};
+
+use reika::reactor::{core::yield_now, io::File};
+
+use crate::{
+    crc32c::{crc32c, crc32c_append},
+    wait::WaitList,
+};
+
+/// Lsn is the log sequence number of a record, it is the offset of the record
+/// in the log file.
+pub type Lsn = u64;
+
+/// LOG_BLOCK_SIZE is the size (and the alignment) of the writes to the log
+pub const LOG_BLOCK_SIZE: usize = 4096;
+
+/// FIRST_LSN is the LSN of the first record. The first blocks hold the header
+/// of the log hence the LSN 0 never refers to a record.
+pub const FIRST_LSN: Lsn = (HEADER_SLOTS * LOG_BLOCK_SIZE) as Lsn;
+
+/// MAX_RECORD_SIZE is the maximum size of the payload of a record
+pub const MAX_RECORD_SIZE: usize = 1 << 24;
+
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// PADDING is the length of the padding record, it tells the reader to skip to
+/// the next block.
+const PADDING: u32 = u32::MAX;
+
+/// HEADER_SLOTS is the number of header blocks, see [Header]
+const HEADER_SLOTS: usize = 2;
+
+const MAGIC: &[u8; 8] = b"REIKAWAL";
+const VERSION: u32 = 2;
+/// HEADER_CRC is the offset of the checksum in the header, it covers the
+/// bytes before and the bytes after it up to [HEADER_SIZE].
+const HEADER_CRC: usize = 12;
+/// SEQ is the offset of the sequence number in the header
+const SEQ: usize = 16;
+/// CHECKPOINT is the offset of the checkpoint LSN in the header
+const CHECKPOINT: usize = 24;
+const HEADER_SIZE: usize = 32;
+
+/// PREALLOCATE is the minimum amount of space reserved for the log at once.
+/// The space is reserved without changing the size of the file, `sync_data`
+/// still has to sync the size but it does not have to allocate blocks.
+const PREALLOCATE: u64 = 1 << 20;
+
+/// FALLOC_FL_KEEP_SIZE keeps `fallocate` from extending the size of the file
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+
+/// READ_CHUNK is the size of the reads done by [LogReader]
+const READ_CHUNK: usize = 16 * LOG_BLOCK_SIZE;
+
+pub struct Wal {
+    file: File,
+
+    tail: RefCell<Tail>,
+    /// end is the LSN of the next record
+    end: Cell<Lsn>,
+    /// durable is the LSN up to which the log is durable
+    durable: Cell<Lsn>,
+    /// allocated is the size of the log file that has been reserved
+    allocated: Cell<u64>,
+    /// checkpoint is the LSN of the last checkpoint record, 0 if none
+    checkpoint: Cell<Lsn>,
+    /// header_seq is the sequence number of the last header written
+    header_seq: Cell<u64>,
+
+    /// flushing is set while a leader is writing the tail
+    flushing: Cell<bool>,
+    /// flushes is bumped every time a leader is done
+    flushes: Cell<u64>,
+    /// flushed are woken once the leader is done
+    flushed: WaitList,
+}
+
+/// Tail holds the part of the log which is still to be written, starting at
+/// the durable end of the log.
+struct Tail {
+    buf: LogBuffer,
+    /// start is the (block aligned) offset of `buf` in the log file
+    start: u64,
+    /// sealed is the LSN up to which the records are followed by a padding
+    /// record, see [Wal::seal].
+    sealed: Lsn,
+    /// spare is the buffer the leader writes from, it is kept around to avoid
+    /// an allocation per flush.
+    spare: LogBuffer,
+}
+
+impl Wal {
+    /// open opens the log stored in the file, an empty file gets initialized
+    /// with a new log.
+    ///
+    /// The existing records are scanned to find the end of the log, anything
+    /// after the last complete flush is discarded.
+    pub async fn open(file: File) -> stdio::Result<Self> {
+        let mut slots = LogBuffer::new();
+        slots.pad(HEADER_SLOTS * LOG_BLOCK_SIZE);
+        read_full(&file, slots.bytes_mut(), 0).await?;
+
+        let slots = slots.bytes();
+        let newest = slots
+            .chunks(LOG_BLOCK_SIZE)
+            .filter_map(Header::parse)
+            .max_by_key(|header| header.seq);
+
+        let header = match newest {
+            Some(header) => header,
+            None if slots.iter().all(|byte| *byte == 0) => {
+                let header = Header {
+                    seq: 0,
+                    checkpoint: 0,
+                };
+                header.write(&file).await?;
+                header
+            }
+            None => {
+                return Err(stdio::Error::new(
+                    stdio::ErrorKind::InvalidData,
+                    "not a reika log file",
+                ))
+            }
+        };
+
+        let mut reader = LogReader::new(&file, FIRST_LSN, Lsn::MAX);
+        while reader.next().await?.is_some() {}
+        let end = reader.sealed();
+        discard(&file, end).await?;
+
+        // The checkpoint is lost if the log was cut before it
+        let mut checkpoint = header.checkpoint;
+        if checkpoint >= end {
+            checkpoint = 0;
+        }
+
+        Ok(Self {
+            file,
+            tail: RefCell::new(Tail {
+                buf: LogBuffer::new(),
+                start: end,
+                sealed: end,
+                spare: LogBuffer::new(),
+            }),
+            end: Cell::new(end),
+            durable: Cell::new(end),
+            allocated: Cell::new(end),
+            checkpoint: Cell::new(checkpoint),
+            header_seq: Cell::new(header.seq),
+            flushing: Cell::new(false),
+            flushes: Cell::new(0),
+            flushed: WaitList::new(),
+        })
+    }
+
+    /// append adds a record to the tail of the log and returns its LSN, the
+    /// record is durable once [Wal::commit] for the LSN returns.
+    ///
+    /// # Panics
+    /// Panics if the payload is empty or larger than [MAX_RECORD_SIZE].
+    pub fn append(&self, payload: &[u8]) -> Lsn {
+        assert!(
+            !payload.is_empty() && payload.len() <= MAX_RECORD_SIZE,
+            "invalid record size"
+        );
+
+        let len = (payload.len() as u32).to_le_bytes();
+        let crc = crc32c_append(crc32c(&len), payload);
+
+        let mut tail = self.tail.borrow_mut();
+        tail.buf.extend(&crc.to_le_bytes());
+        tail.buf.extend(&len);
+        tail.buf.extend(payload);
+
+        let lsn = self.end.get();
+        self.end
+            .set(lsn + (RECORD_HEADER_SIZE + payload.len()) as u64);
+
+        lsn
+    }
+
+    /// end_lsn returns the LSN the next record is going to get
+    pub fn end_lsn(&self) -> Lsn {
+        self.end.get()
+    }
+
+    /// durable_lsn returns the LSN up to which (exclusive) the log is durable
+    pub fn durable_lsn(&self) -> Lsn {
+        self.durable.get()
+    }
+
+    /// commit waits till the record at `lsn` (and everything before it) is
+    /// durable, the concurrent committers are batched into a single write.
+    ///
+    /// It fails with `InvalidInput` if no record was appended at `lsn`.
+    pub async fn commit(&self, lsn: Lsn) -> stdio::Result<()> {
+        if lsn >= self.end.get() {
+            return Err(stdio::Error::new(
+                stdio::ErrorKind::InvalidInput,
+                "committing a record past the end of the log",
+            ));
+        }
+
+        while self.durable.get() <= lsn {
+            if self.flushing.get() {
+                // The record may or may not be part of the ongoing flush
+                self.flush_done().await;
+                continue;
+            }
+
+            self.group_flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// flush makes every record appended so far durable
+    pub async fn flush(&self) -> stdio::Result<()> {
+        let end = self.end.get();
+        if end > self.durable.get() {
+            self.commit(end - 1).await?;
+        }
+
+        Ok(())
+    }
+
+    /// checkpoint_lsn returns the LSN of the last checkpoint record, 0 if
+    /// there is none.
+    pub fn checkpoint_lsn(&self) -> Lsn {
+        self.checkpoint.get()
+    }
+
+    /// set_checkpoint_lsn stores the LSN of the last checkpoint record in the
+    /// header of the log, the record must be durable already.
+    pub async fn set_checkpoint_lsn(&self, lsn: Lsn) -> stdio::Result<()> {
+        assert!(lsn < self.durable.get(), "checkpoint record is not durable");
+
+        // The sequence number is taken before the write so that concurrent
+        // writes go to different slots.
+        let seq = self.header_seq.get() + 1;
+        self.header_seq.set(seq);
+
+        Header {
+            seq,
+            checkpoint: lsn,
+        }
+        .write(&self.file)
+        .await?;
+        self.checkpoint.set(self.checkpoint.get().max(lsn));
+
+        Ok(())
+    }
+
+    /// reader returns a reader for the durable records starting at `lsn`
+    pub fn reader(&self, lsn: Lsn) -> LogReader<'_> {
+        LogReader::new(&self.file, lsn, self.durable.get())
+    }
+
+    /// flush_done waits till the flush in progress is done, the next one may
+    /// have started already by then.
+    async fn flush_done(&self) {
+        let flushes = self.flushes.get();
+
+        poll_fn(|ctx| {
+            if self.flushes.get() != flushes {
+                return Poll::Ready(());
+            }
+
+            self.flushed.register(ctx);
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// group_flush is run by the leader of a group commit
+    async fn group_flush(&self) -> stdio::Result<()> {
+        self.flushing.set(true);
+        let _leader = Leader { wal: self };
+
+        // Let the other tasks append their records and join. The executor
+        // does not run the tasks in the order they were woken, the tasks woken
+        // by the previous flush may run after us. Hence we wait for a full
+        // round of the executor by yielding twice.
+        yield_now().await;
+        yield_now().await;
+
+        self.write_tail().await
+    }
+
+    /// write_tail writes the records appended so far and syncs them
+    async fn write_tail(&self) -> stdio::Result<()> {
+        if self.durable.get() >= self.end.get() {
+            return Ok(());
+        }
+
+        // The records appended while the write is in progress go to the tail
+        // hence it is written from a copy.
+        let (out, start, end) = {
+            let mut tail = self.tail.borrow_mut();
+            let end = self.seal(&mut tail);
+            let mut out = std::mem::replace(&mut tail.spare, LogBuffer::new());
+
+            out.clear();
+            out.extend(&tail.buf.bytes()[..(end - tail.start) as usize]);
+            (out, tail.start, end)
+        };
+
+        let res = self.write_and_sync(out.bytes(), start).await;
+        self.tail.borrow_mut().spare = out;
+        res?;
+
+        self.durable.set(end);
+
+        // The sealed blocks are never written again
+        let mut tail = self.tail.borrow_mut();
+        let written_blocks = (end - tail.start) / LOG_BLOCK_SIZE as u64;
+        tail.buf.drain_blocks(written_blocks as usize);
+        tail.start = end;
+
+        Ok(())
+    }
+
+    /// seal appends a padding record after the records of the tail, the next
+    /// records start on a fresh block. It returns the (block aligned) end of
+    /// the padding.
+    fn seal(&self, tail: &mut Tail) -> Lsn {
+        let end = self.end.get();
+        if tail.sealed == end {
+            // A flush which failed sealed the tail already
+            return end;
+        }
+
+        let len = PADDING.to_le_bytes();
+        tail.buf.extend(&crc32c(&len).to_le_bytes());
+        tail.buf.extend(&len);
+
+        let sealed = align_up(end + RECORD_HEADER_SIZE as u64);
+        tail.buf.pad((sealed - tail.start) as usize);
+        tail.sealed = sealed;
+        self.end.set(sealed);
+
+        sealed
+    }
+
+    async fn write_and_sync(&self, buf: &[u8], offset: u64) -> stdio::Result<()> {
+        let upto = offset + buf.len() as u64;
+        let allocated = self.allocated.get();
+        if upto > allocated {
+            let len = align_up((upto - allocated).max(PREALLOCATE));
+            self.file
+                .fallocate(allocated, len, FALLOC_FL_KEEP_SIZE)
+                .await?;
+            self.allocated.set(allocated + len);
+        }
+
+        write_full(&self.file, buf, offset).await?;
+        self.file.sync_data().await
+    }
+}
+
+/// Leader ends the flush even if the leader is dropped midway
+struct Leader<'a> {
+    wal: &'a Wal,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.wal.flushing.set(false);
+        self.wal.flushes.set(self.wal.flushes.get() + 1);
+        self.wal.flushed.wake_all();
+    }
+}
+
+pub struct LogRecord {
+    pub lsn: Lsn,
+    pub payload: Vec<u8>,
+}
+
+/// LogReader reads the records of a log one by one
+pub struct LogReader<'a> {
+    file: &'a File,
+    /// lsn is the LSN of the next record
+    lsn: Lsn,
+    /// end is the LSN the reader stops at
+    end: Lsn,
+    /// sealed is the LSN after the last padding record read
+    sealed: Lsn,
+
+    chunk: Vec<u8>,
+    chunk_start: u64,
+}
+
+impl<'a> LogReader<'a> {
+    /// new returns a reader for the records from `lsn` up to `end`
+    pub fn new(file: &'a File, lsn: Lsn, end: Lsn) -> Self {
+        Self {
+            file,
+            lsn,
+            end,
+            sealed: lsn,
+            chunk: Vec::new(),
+            chunk_start: 0,
+        }
+    }
+
+    /// lsn returns the LSN of the next record, once the reader reached the
+    /// end of the log it is the end of the log.
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    /// sealed returns the LSN after the last padding record read, that is the
+    /// end of the last flush.
+    pub fn sealed(&self) -> Lsn {
+        self.sealed
+    }
+
+    /// next returns the next record or `None` at the end of the log
+    pub async fn next(&mut self) -> stdio::Result<Option<LogRecord>> {
+        let mut header = [0; RECORD_HEADER_SIZE];
+        let (crc, len) = loop {
+            if self.lsn >= self.end || self.read(&mut header, self.lsn).await? < RECORD_HEADER_SIZE
+            {
+                return Ok(None);
+            }
+
+            let crc = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+            if len != PADDING {
+                break (crc, len as usize);
+            }
+
+            if crc32c(&header[4..]) != crc {
+                return Ok(None);
+            }
+
+            self.lsn = align_up(self.lsn + RECORD_HEADER_SIZE as u64);
+            self.sealed = self.lsn;
+        };
+
+        if len == 0 || len > MAX_RECORD_SIZE {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0; len];
+        let offset = self.lsn + RECORD_HEADER_SIZE as u64;
+        if self.read(&mut payload, offset).await? < len {
+            return Ok(None);
+        }
+
+        if crc32c_append(crc32c(&header[4..]), &payload) != crc {
+            return Ok(None);
+        }
+
+        let lsn = self.lsn;
+        self.lsn = offset + len as u64;
+
+        Ok(Some(LogRecord { lsn, payload }))
+    }
+
+    /// read reads from the log at the offset through the chunk cache, it only
+    /// returns less than asked for at the end of the file.
+    async fn read(&mut self, buf: &mut [u8], offset: u64) -> stdio::Result<usize> {
+        let mut copied = 0;
+
+        while copied < buf.len() {
+            let at = offset + copied as u64;
+            let chunk_end = self.chunk_start + self.chunk.len() as u64;
+
+            if at < self.chunk_start || at >= chunk_end {
+                self.chunk_start = align_down(at);
+                self.chunk.resize(READ_CHUNK, 0);
+
+                let n = read_full(self.file, &mut self.chunk, self.chunk_start).await?;
+                self.chunk.truncate(n);
+
+                if at >= self.chunk_start + n as u64 {
+                    break;
+                }
+
+                continue;
+            }
+
+            let from = (at - self.chunk_start) as usize;
+            let n = (buf.len() - copied).min(self.chunk.len() - from);
+            buf[copied..copied + n].copy_from_slice(&self.chunk[from..from + n]);
+            copied += n;
+        }
+
+        Ok(copied)
+    }
+}
+
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct Block([u8; LOG_BLOCK_SIZE]);
+
+/// LogBuffer is a growable buffer whose memory is block aligned
+struct LogBuffer {
+    blocks: Vec<Block>,
+    len: usize,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn raw(&mut self) -> &mut [u8] {
+        // # Safety
+        // Blocks are plain bytes without any padding.
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.blocks.as_mut_ptr() as *mut u8,
+                self.blocks.len() * LOG_BLOCK_SIZE,
+            )
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // # Safety
+        // Same as [LogBuffer::raw].
+        unsafe { std::slice::from_raw_parts(self.blocks.as_ptr() as *const u8, self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        &mut self.raw()[..len]
+    }
+
+    fn reserve(&mut self, len: usize) {
+        let blocks = len.div_ceil(LOG_BLOCK_SIZE);
+        if blocks > self.blocks.len() {
+            self.blocks.resize(blocks, Block([0; LOG_BLOCK_SIZE]));
+        }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        let (start, end) = (self.len, self.len + data.len());
+
+        self.reserve(end);
+        self.raw()[start..end].copy_from_slice(data);
+        self.len = end;
+    }
+
+    /// pad extends the buffer with zeros till it is `len` bytes long
+    fn pad(&mut self, len: usize) {
+        let start = self.len;
+
+        self.reserve(len);
+        self.raw()[start..len].fill(0);
+        self.len = len;
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// drain_blocks removes the first `n` blocks from the buffer
+    fn drain_blocks(&mut self, n: usize) {
+        self.blocks.drain(..n);
+        self.len -= n * LOG_BLOCK_SIZE;
+    }
+}
+
+fn align_down(offset: u64) -> u64 {
+    offset - offset % LOG_BLOCK_SIZE as u64
+}
+
+fn align_up(offset: u64) -> u64 {
+    align_down(offset + LOG_BLOCK_SIZE as u64 - 1)
+}
+
+/// read_full reads till the buffer is full or the end of the file is reached
+async fn read_full(file: &File, buf: &mut [u8], offset: u64) -> stdio::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        let n = file.read_at(&mut buf[read..], offset + read as u64).await?;
+        if n == 0 {
+            break;
+        }
+
+        read += n;
+    }
+
+    Ok(read)
+}
+
+/// discard zeroes the log from `end` to the end of the file. What is there is
+/// left from a flush which did not complete, the reader could not tell it
+/// apart from the next flushes otherwise.
+async fn discard(file: &File, end: Lsn) -> stdio::Result<()> {
+    let mut chunk = LogBuffer::new();
+    chunk.pad(READ_CHUNK);
+
+    let mut offset = end;
+    let mut zeroed = false;
+    loop {
+        let n = read_full(file, chunk.bytes_mut(), offset).await?;
+        if n == 0 {
+            break;
+        }
+
+        let bytes = &mut chunk.bytes_mut()[..n];
+        if bytes.iter().any(|byte| *byte != 0) {
+            bytes.fill(0);
+            write_full(file, bytes, offset).await?;
+            zeroed = true;
+        }
+
+        offset += n as u64;
+    }
+
+    if zeroed {
+        file.sync_data().await?;
+    }
+
+    Ok(())
+}
+
+/// Header is the header of the log. It is stored in the block `seq %
+/// HEADER_SLOTS`, the valid one with the highest sequence number is current.
+struct Header {
+    seq: u64,
+    checkpoint: Lsn,
+}
+
+impl Header {
+    /// parse parses the header block, None if it is not valid
+    fn parse(block: &[u8]) -> Option<Self> {
+        let crc = u32::from_le_bytes(block[HEADER_CRC..HEADER_CRC + 4].try_into().unwrap());
+        if &block[..8] != MAGIC
+            || u32::from_le_bytes(block[8..12].try_into().unwrap()) != VERSION
+            || crc32c_append(crc32c(&block[..HEADER_CRC]), &block[SEQ..HEADER_SIZE]) != crc
+        {
+            return None;
+        }
+
+        Some(Self {
+            seq: u64::from_le_bytes(block[SEQ..SEQ + 8].try_into().unwrap()),
+            checkpoint: u64::from_le_bytes(block[CHECKPOINT..CHECKPOINT + 8].try_into().unwrap()),
+        })
+    }
+
+    /// write writes (and syncs) the header to its block
+    async fn write(&self, file: &File) -> stdio::Result<()> {
+        let mut header = LogBuffer::new();
+        header.pad(LOG_BLOCK_SIZE);
+
+        let bytes = header.bytes_mut();
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[SEQ..SEQ + 8].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[CHECKPOINT..CHECKPOINT + 8].copy_from_slice(&self.checkpoint.to_le_bytes());
+
+        let crc = crc32c_append(crc32c(&bytes[..HEADER_CRC]), &bytes[SEQ..HEADER_SIZE]);
+        bytes[HEADER_CRC..HEADER_CRC + 4].copy_from_slice(&crc.to_le_bytes());
+
+        let slot = self.seq % HEADER_SLOTS as u64;
+        write_full(file, bytes, slot * LOG_BLOCK_SIZE as u64).await?;
+        file.sync_data().await
+    }
+}
+
+async fn write_full(file: &File, buf: &[u8], offset: u64) -> stdio::Result<()> {
+    let mut written = 0;
+    while written < buf.len() {
+        let n = file
+            .write_at(&buf[written..], offset + written as u64)
+            .await?;
+        if n == 0 {
+            return Err(stdio::ErrorKind::WriteZero.into());
+        }
+
+        written += n;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reika::executor::PerThreadExecutor;
+
+    use super::*;
+    use crate::sim::{self, simulate};
+
+    const FILE: &str = "wal";
+
+    async fn open() -> &'static Wal {
+        let wal = Wal::open(sim::open(FILE).await).await.unwrap();
+        Box::leak(Box::new(wal))
+    }
+
+    async fn records(wal: &Wal) -> Vec<Vec<u8>> {
+        let mut reader = wal.reader(FIRST_LSN);
+        let mut records = Vec::new();
+        while let Some(record) = reader.next().await.unwrap() {
+            records.push(record.payload);
+        }
+
+        records
+    }
+
+    #[test]
+    fn group_commit_batches() {
+        simulate(0, |sim| async move {
+            let wal = open().await;
+
+            let committers: Vec<_> = (0..8u8)
+                .map(|n| {
+                    PerThreadExecutor::spawn(async move {
+                        let lsn = wal.append(&[n; 16]);
+                        wal.commit(lsn).await
+                    })
+                })
+                .collect();
+            for committer in committers {
+                committer.await.unwrap().unwrap();
+            }
+
+            // A single flush wrote every record to a single block
+            assert_eq!(wal.flushes.get(), 1);
+            assert_eq!(wal.durable_lsn(), FIRST_LSN + LOG_BLOCK_SIZE as u64);
+            assert_eq!(records(wal).await.len(), 8);
+
+            // The space is reserved without growing the file
+            assert!(wal.allocated.get() >= PREALLOCATE);
+            let len = sim.read_file(FILE).unwrap().len() as u64;
+            assert_eq!(len, wal.durable_lsn());
+        });
+    }
+
+    #[test]
+    fn commit_past_the_end_fails() {
+        simulate(0, |_| async {
+            let wal = open().await;
+
+            let end = wal.end_lsn();
+            let err = wal.commit(end).await.unwrap_err();
+            assert_eq!(err.kind(), stdio::ErrorKind::InvalidInput);
+
+            let lsn = wal.append(b"record");
+            wal.commit(lsn).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn torn_tail_is_rejected() {
+        simulate(0, |sim| async move {
+            let wal = open().await;
+            wal.append(b"first");
+            wal.flush().await.unwrap();
+
+            let torn = wal.append(b"second");
+            wal.append(b"third");
+            wal.flush().await.unwrap();
+
+            // A byte of the last flush did not make it to the disk
+            let mut data = sim.read_file(FILE).unwrap();
+            data[torn as usize + RECORD_HEADER_SIZE] ^= 0xff;
+            sim.write_file(FILE, &data);
+
+            let wal = open().await;
+            assert_eq!(records(wal).await, [b"first"]);
+            assert_eq!(wal.end_lsn(), torn);
+
+            // The rest of the flush is zeroed, new records do not mix with it
+            let data = sim.read_file(FILE).unwrap();
+            assert!(data[torn as usize..].iter().all(|byte| *byte == 0));
+
+            wal.append(b"fourth");
+            wal.flush().await.unwrap();
+            assert_eq!(records(wal).await, [&b"first"[..], b"fourth"]);
+        });
+    }
+
+    #[test]
+    fn header_falls_back_to_the_other_slot() {
+        simulate(0, |sim| async move {
+            let wal = open().await;
+            let first = wal.append(b"first");
+            let second = wal.append(b"second");
+            wal.flush().await.unwrap();
+
+            // The headers go to slot 1 and then to slot 0
+            wal.set_checkpoint_lsn(first).await.unwrap();
+            wal.set_checkpoint_lsn(second).await.unwrap();
+            assert_eq!(open().await.checkpoint_lsn(), second);
+
+            let mut data = sim.read_file(FILE).unwrap();
+            data[SEQ] ^= 0xff;
+            sim.write_file(FILE, &data);
+            assert_eq!(open().await.checkpoint_lsn(), first);
+
+            // Without any valid header the file is not taken for a log
+            data[LOG_BLOCK_SIZE + SEQ] ^= 0xff;
+            sim.write_file(FILE, &data);
+            let res = Wal::open(sim::open(FILE).await).await;
+            assert_eq!(res.err().unwrap().kind(), stdio::ErrorKind::InvalidData);
+        });
+    }
+}
@@ -26,16 +26,30 @@ pub mod executor {
         /// spawn_task consumes a task and spawns it to an executor
         /// running on the current thread.
         ///
-        /// NOTE: TaskRef can be created from [TaskStorage] which can be
+        /// NOTE: SpawnToken can be created from [TaskStorage] which can be
         /// created statically, this allows to create spawn tasks with
         /// zero runtime memory allocation.
-        pub fn spawn_task(task: core::TaskRef) {
+        ///
+        /// The returned [core::JoinHandle] can be awaited for the output of
+        /// the task or dropped to detach it.
+        pub fn spawn_task<T>(task: core::SpawnToken<T>) -> core::JoinHandle<T> {
             Self::EXECUTOR.with(|ex: &core::Executor| {
                 // # Safety: This is safe because this static is never
                 // going to outlive the running thread.
                 let static_ex = unsafe { _make_static(ex) };
-                static_ex.spawn_task(task);
-            });
+                static_ex.spawn_task(task)
+            })
+        }
+
+        /// spawn_system_task spawns a system task to the executor running on
+        /// the current thread, see [core::Executor::spawn_system_task].
+        pub fn spawn_system_task<T>(task: core::SpawnToken<T>) -> core::JoinHandle<T> {
+            Self::EXECUTOR.with(|ex: &core::Executor| {
+                // # Safety: This is safe because this static is never
+                // going to outlive the running thread.
+                let static_ex = unsafe { _make_static(ex) };
+                static_ex.spawn_system_task(task)
+            })
         }
 
         /// spawn takes any future and spawns it to an executor running
@@ -44,7 +58,7 @@ pub mod executor {
         /// NOTE: This method does runtime memory allocation and will NEVER
         /// release the memory aquired for the storage of the future. This
         /// method should be used with care!
-        pub fn spawn(fut: impl Future<Output = ()> + 'static) {
+        pub fn spawn<F: Future + 'static>(fut: F) -> core::JoinHandle<F::Output> {
             Self::EXECUTOR.with(|ex: &core::Executor| {
                 // # Safety: This is safe because this static is never
                 // going to outlive the running thread.
@@ -54,8 +68,8 @@ pub mod executor {
                 let leaked = Box::leak(boxed);
                 let task = leaked.prepare_task(|| fut);
 
-                static_ex.spawn_task(task);
-            });
+                static_ex.spawn_task(task)
+            })
         }
 
         /// run is the function that actually starts the executor